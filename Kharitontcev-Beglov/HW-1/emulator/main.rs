@@ -1,56 +1,289 @@
-mod emulator;
 extern crate klang_lib;
 
 use std::fs::File;
 use std::io;
 use std::io::Read;
-use std::path::PathBuf;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use clap::Parser;
-use crate::emulator::{Emulator, MEMORY_SIZE};
+use klang_lib::binary::debug_info::{DebugInfo, VariableLocation};
+use klang_lib::emulator::{Emulator, ExitStatus};
+use klang_lib::emulator::cp0::Coprocessor0;
+use klang_lib::emulator::io::{FileInput, FileOutput, Input, Output, StdinInput, StdoutOutput};
+use klang_lib::emulator::memory::MemoryMap;
+use klang_lib::emulator::microcode::Microprogram;
+use klang_lib::emulator::snapshot::Snapshot;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
     /// Set the compiled code file to run
-    #[arg(short, long, value_name = "INPUT_CODE_FILE")]
-    code: PathBuf,
+    #[arg(short, long, value_name = "INPUT_CODE_FILE", required_unless_present = "dump_fsm")]
+    code: Option<PathBuf>,
 
     /// Set the compiled memory file to run
-    #[arg(short, long, value_name = "INPUT_MEMORY_FILE")]
-    memory: PathBuf,
-    
+    #[arg(short, long, value_name = "INPUT_MEMORY_FILE", required_unless_present = "dump_fsm")]
+    memory: Option<PathBuf>,
+
+    /// Set the debug information file produced by the compiler
+    #[arg(short, long, value_name = "DEBUG_INFO_FILE")]
+    debug_info: Option<PathBuf>,
+
+    /// Print every executed Klang statement with the values of the variables into stderr
+    #[arg(short, long, requires = "debug_info")]
+    trace: bool,
+
+    /// Print the number of executed cycles and instructions into stderr after the program finishes
+    #[arg(short, long)]
+    stats: bool,
+
+    /// Write the output of the program into the file instead of stdout
+    #[arg(short, long, value_name = "OUTPUT_FILE")]
+    output: Option<PathBuf>,
+
+    /// Read the input of the program from the file instead of stdin
+    #[arg(short, long, value_name = "INPUT_FILE")]
+    input: Option<PathBuf>,
+
+    /// Stop the program with an error after the number of cycles
+    #[arg(short = 'l', long, value_name = "CYCLES")]
+    cycle_limit: Option<u64>,
+
+    /// Load the code at the address, the code should be compiled for it
+    #[arg(long, value_name = "ADDRESS", value_parser = parse_address)]
+    text_base: Option<u32>,
+
+    /// Load the memory file at the address
+    #[arg(long, value_name = "ADDRESS", value_parser = parse_address)]
+    data_base: Option<u32>,
+
+    /// Place the heap segment at the address
+    #[arg(long, value_name = "ADDRESS", value_parser = parse_address)]
+    heap_base: Option<u32>,
+
+    /// Place the stack segment at the address
+    #[arg(long, value_name = "ADDRESS", value_parser = parse_address)]
+    stack_base: Option<u32>,
+
+    /// Handle the exceptions with the coprocessor 0 by jumping to the handler at the address
+    #[arg(long, value_name = "ADDRESS", value_parser = parse_address)]
+    exception_handler: Option<u32>,
+
+    /// Raise the timer interrupt every number of cycles
+    #[arg(long, value_name = "CYCLES", requires = "exception_handler", value_parser = clap::value_parser!(u64).range(1..))]
+    timer: Option<u64>,
+
+    /// Run the control unit from the microcode file instead of the built-in one
+    #[arg(long, value_name = "MICROCODE_FILE")]
+    microcode: Option<PathBuf>,
+
+    /// Write the state diagram of the control unit in the Graphviz format into the file, the program is run only
+    /// when the code and the memory files are set
+    #[arg(long, value_name = "DOT_FILE")]
+    dump_fsm: Option<PathBuf>,
+
+    /// Continue the emulation from the snapshot file saved with the same code, memory and options
+    #[arg(long, value_name = "SNAPSHOT_FILE")]
+    load_snapshot: Option<PathBuf>,
+
+    /// Save the snapshot of the emulation into the file when it stops, so a run stopped by `--cycle-limit` may be
+    /// resumed with `--load-snapshot`
+    #[arg(long, value_name = "SNAPSHOT_FILE")]
+    save_snapshot: Option<PathBuf>,
+}
+
+/// Decimal or hexadecimal with `0x` address
+fn parse_address(text: &str) -> Result<u32, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|error| format!("Invalid address {}: {}", text, error))
 }
 
-fn read_checks(file: &PathBuf) {
+/// Exit status of the emulator when the program exceeds the cycle limit, as of `timeout`
+const CYCLE_LIMIT_EXIT_CODE: u8 = 124;
+
+/// Exit status of the emulator on the arithmetic overflow without `--exception-handler`, as of a process killed by `SIGFPE`
+const OVERFLOW_EXIT_CODE: u8 = 136;
+
+/// Exit status of the emulator on an access outside of the memory map without `--exception-handler`, as of a process
+/// killed by `SIGSEGV`
+const ADDRESS_ERROR_EXIT_CODE: u8 = 139;
+
+/// Exit status of the emulator when the program can't read its input, as of `EX_DATAERR` of `sysexits.h`
+const INPUT_ERROR_EXIT_CODE: u8 = 65;
+
+fn read_checks(file: &Path) {
     if !file.exists() {
-        panic!("File {} is not exists", file.as_path().to_str().unwrap())
+        panic!("File {} is not exists", file.to_str().unwrap())
     }
     if !file.is_file() {
-        panic!("File {} is not a file", file.as_path().to_str().unwrap())
+        panic!("File {} is not a file", file.to_str().unwrap())
+    }
+}
+
+fn read_debug_info(file: &Path) -> io::Result<DebugInfo> {
+    read_checks(file);
+    let text = std::fs::read_to_string(file)?;
+    DebugInfo::from_text(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Parses and validates the microprogram, every problem found is reported
+fn read_microcode(file: &Path) -> io::Result<Microprogram> {
+    read_checks(file);
+    let text = std::fs::read_to_string(file)?;
+    let program = Microprogram::from_text(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    program.validate().map_err(|errors| {
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        io::Error::new(io::ErrorKind::InvalidData, errors.join("\n"))
+    })?;
+    Ok(program)
+}
+
+fn read_snapshot(file: &Path) -> io::Result<Snapshot> {
+    read_checks(file);
+    let text = std::fs::read_to_string(file)?;
+    Snapshot::from_text(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Formats the variables available at the pc, a register is shared by variables with disjoint live ranges
+fn format_variables(emulator: &Emulator, debug_info: &DebugInfo, pc: u32) -> String {
+    debug_info.variables_at(pc)
+        .map(|var| match var.location {
+            VariableLocation::Memory(address) => match emulator.read_word(address as usize) {
+                Some(value) => format!("{}={}", var.name, value),
+                None => format!("{}=?", var.name),
+            },
+            VariableLocation::Register { register, .. } => format!("{}={}", var.name, emulator.register(register as usize)),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Reports the emulation stopped at the current instruction together with the variables
+fn report_stop(emulator: &Emulator, debug_info: &Option<DebugInfo>, reason: &str) {
+    let pc = emulator.instruction_address() as u32;
+    match debug_info.as_ref().and_then(|debug_info| debug_info.location_of(pc)) {
+        Some(span) => eprintln!("{} at line {} (pc={:#x})", reason, span, pc),
+        None => eprintln!("{} at pc={:#x}", reason, pc),
+    }
+    if let Some(debug_info) = debug_info {
+        eprintln!("Variables: {}", format_variables(emulator, debug_info, pc));
     }
 }
-fn main() -> io::Result<()> {
+
+fn main() -> io::Result<ExitCode> {
     let cli = Cli::parse();
-    read_checks(&cli.code);
-    read_checks(&cli.memory);
-    let mut code_file = File::open(cli.code)?;
+    let microprogram = cli.microcode.as_deref().map(read_microcode).transpose()?;
+    if let Some(path) = &cli.dump_fsm {
+        let dot = match &microprogram {
+            Some(program) => program.to_dot(),
+            None => Microprogram::default().to_dot(),
+        };
+        std::fs::write(path, dot)?;
+    }
+    let (Some(code), Some(memory)) = (cli.code, cli.memory) else {
+        return Ok(ExitCode::SUCCESS);
+    };
+    read_checks(&code);
+    read_checks(&memory);
+    let mut code_file = File::open(code)?;
     let mut code_buffer = Vec::new();
     code_file.read_to_end(&mut code_buffer)?;
     
-    let mut memory_file = File::open(cli.memory)?;
+    let mut memory_file = File::open(memory)?;
     let mut memory_buffer = Vec::new();
     memory_file.read_to_end(&mut memory_buffer)?;
-    let mut memory = Vec::new();
-    memory.resize(MEMORY_SIZE, 0);
-    memory[0..memory_buffer.len()].copy_from_slice(&memory_buffer);
-    
-    let mut emulator = Emulator::new(code_buffer, memory);
-    
-    loop {
+
+    let mut map = MemoryMap::default();
+    for (base, segment) in [
+        (cli.text_base, &mut map.text),
+        (cli.data_base, &mut map.data),
+        (cli.heap_base, &mut map.heap),
+        (cli.stack_base, &mut map.stack),
+    ] {
+        segment.base = base.unwrap_or(segment.base);
+    }
+    let debug_info = cli.debug_info.as_deref().map(read_debug_info).transpose()?;
+    let snapshot = cli.load_snapshot.as_deref().map(read_snapshot).transpose()?;
+    let output: Box<dyn Output> = match &cli.output {
+        Some(path) => Box::new(FileOutput::create(path)?),
+        None => Box::new(StdoutOutput),
+    };
+    let input: Box<dyn Input> = match &cli.input {
+        Some(path) => {
+            read_checks(path);
+            Box::new(FileInput::open(path)?)
+        }
+        None => Box::new(StdinInput),
+    };
+    let mut emulator = Emulator::with_memory_map(code_buffer, memory_buffer, map, output, input)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error.to_string()))?;
+    if let Some(handler) = cli.exception_handler {
+        let cp0 = Coprocessor0::new(handler);
+        emulator = emulator.with_coprocessor(match cli.timer {
+            Some(period) => cp0.with_timer(period),
+            None => cp0,
+        });
+    }
+    if let Some(program) = microprogram {
+        emulator = emulator.with_microprogram(program);
+    }
+    if let Some(limit) = cli.cycle_limit {
+        emulator = emulator.with_cycle_limit(limit);
+    }
+    if let Some(snapshot) = &snapshot {
+        emulator.restore(snapshot).map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error.to_string()))?;
+    }
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| loop {
+        if let (true, Some(debug_info)) = (cli.trace, &debug_info) {
+            if emulator.is_instruction_start() {
+                let pc = emulator.pc() as u32;
+                if let Some(span) = debug_info.statement_at(pc) {
+                    eprintln!("[line {}] {}", span, format_variables(&emulator, debug_info, pc));
+                }
+            }
+        }
         if emulator.clock() {
             break;
         }
+    }));
+    if let Err(error) = result {
+        if debug_info.is_some() {
+            report_stop(&emulator, &debug_info, "Emulation failed");
+        }
+        panic::resume_unwind(error);
+    }
+    if let Some(path) = &cli.save_snapshot {
+        std::fs::write(path, emulator.snapshot().to_text())?;
+    }
+    if cli.stats {
+        eprintln!("Cycles: {}", emulator.cycles());
+        eprintln!("Instructions: {}", emulator.instructions());
+    }
+
+    match emulator.status().unwrap() {
+        // The exit code is truncated to a byte, as by the operating system
+        ExitStatus::Exited(code) => Ok(ExitCode::from(code as u8)),
+        status @ ExitStatus::CycleLimitExceeded(_) => {
+            report_stop(&emulator, &debug_info, &status.to_string());
+            Ok(ExitCode::from(CYCLE_LIMIT_EXIT_CODE))
+        }
+        status @ ExitStatus::Overflow { .. } => {
+            report_stop(&emulator, &debug_info, &status.to_string());
+            Ok(ExitCode::from(OVERFLOW_EXIT_CODE))
+        }
+        status @ ExitStatus::InputError { .. } => {
+            report_stop(&emulator, &debug_info, &status.to_string());
+            Ok(ExitCode::from(INPUT_ERROR_EXIT_CODE))
+        }
+        status @ ExitStatus::AddressError { .. } => {
+            report_stop(&emulator, &debug_info, &status.to_string());
+            Ok(ExitCode::from(ADDRESS_ERROR_EXIT_CODE))
+        }
     }
-    
-    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,171 @@
+//! Linear scan allocation of the Klang variables to registers.
+//!
+//! A variable either gets a register for its whole live interval or stays in the memory, so the values
+//! never have to be written back or reloaded. When registers run out, the variable with the lowest
+//! weight is kept in the memory, where the weight counts accesses with `10^depth` for the loop depth.
+
+use std::collections::HashMap;
+use crate::parser::ast::Ident;
+use crate::stack_machine::liveness::{LiveInterval, Liveness};
+use crate::stack_machine::sm::{Label, StackCommand};
+
+/// Number of loops containing every command, a loop is a backward jump together with the commands it jumps over
+fn loop_depths(program: &[StackCommand]) -> Vec<u32> {
+    let labels: HashMap<Label, usize> = program.iter()
+        .enumerate()
+        .filter_map(|(index, cmd)| match cmd {
+            StackCommand::Label(label) => Some((*label, index)),
+            _ => None
+        })
+        .collect();
+    let mut depths = vec![0; program.len()];
+    for (index, cmd) in program.iter().enumerate() {
+        if let StackCommand::Jmp(label) | StackCommand::ConditionalJump(_, label) = cmd {
+            match labels.get(label) {
+                Some(&target) if target <= index => depths[target..=index].iter_mut().for_each(|depth| *depth += 1),
+                _ => {}
+            }
+        }
+    }
+    depths
+}
+
+fn weights(program: &[StackCommand]) -> HashMap<Ident, u64> {
+    let mut result = HashMap::new();
+    for (cmd, depth) in program.iter().zip(loop_depths(program)) {
+        if let StackCommand::Load(id) | StackCommand::Store(id) = cmd {
+            *result.entry(id.clone()).or_insert(0) += 10u64.saturating_pow(depth);
+        }
+    }
+    result
+}
+
+/// Assigns registers to the variables of the program, variables without a register are kept in the memory
+pub fn allocate_registers(program: &[StackCommand], registers: &[u8]) -> HashMap<Ident, u8> {
+    let intervals = Liveness::analyze(program).intervals(program);
+    let weights = weights(program);
+    let weight = |interval: &LiveInterval| weights.get(&interval.variable).copied().unwrap_or(0);
+
+    let mut result = HashMap::new();
+    let mut free: Vec<u8> = registers.iter().rev().copied().collect();
+    let mut active: Vec<(&LiveInterval, u8)> = Vec::new();
+    for interval in &intervals {
+        active.retain(|(other, register)| {
+            let expired = other.end < interval.start;
+            if expired {
+                free.push(*register);
+            }
+            !expired
+        });
+        if let Some(register) = free.pop() {
+            active.push((interval, register));
+            result.insert(interval.variable.clone(), register);
+            continue;
+        }
+        let lightest = active.iter()
+            .enumerate()
+            .min_by_key(|(_, (other, _))| weight(other))
+            .map(|(index, (other, _))| (index, weight(other)));
+        if let Some((index, lightest_weight)) = lightest {
+            if lightest_weight < weight(interval) {
+                let (spilled, register) = active.swap_remove(index);
+                result.remove(&spilled.variable);
+                active.push((interval, register));
+                result.insert(interval.variable.clone(), register);
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::binary::SMTransformer;
+    use crate::binary::debug_info::VariableLocation;
+    use crate::stack_machine::text::parse_program;
+    use super::*;
+
+    fn ident(name: &str) -> Ident {
+        Ident(name.to_string())
+    }
+
+    #[test]
+    fn test_loop_without_memory_accesses() {
+        let program = parse_program("
+            const 5
+            store c
+            jmp L1
+        L0:
+            load s
+            load c
+            op add
+            store s
+            load c
+            const 1
+            op sub
+            store c
+        L1:
+            load c
+            jnz L0
+            load s
+            print
+        ").unwrap();
+        let (_, code) = SMTransformer::with_variable_registers().transform_program(&program).unwrap();
+        // Variables are accessed relative to $10, the stack and constants are not
+        let variable_accesses = code.chunks(4)
+            .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+            .filter(|instr| matches!(instr >> 26, 34 | 43) && (instr >> 21) & 0x1f == 10)
+            .count();
+        assert_eq!(0, variable_accesses);
+    }
+
+    #[test]
+    fn test_registers_reused() {
+        let program = parse_program("const 1\nstore a\nload a\nprint\nconst 2\nstore b\nload b\nprint").unwrap();
+        let allocation = allocate_registers(&program, &[16, 17]);
+        assert_eq!(Some(&16), allocation.get(&ident("a")));
+        assert_eq!(Some(&16), allocation.get(&ident("b")));
+    }
+
+    #[test]
+    fn test_shared_register_ranges() {
+        let program = parse_program("const 1\nstore a\nload a\nprint\nconst 2\nstore b\nload b\nprint").unwrap();
+        let mut transformer = SMTransformer::with_variable_registers();
+        transformer.transform_program(&program).unwrap();
+        let ranges: Vec<(&str, u8, u32, u32)> = transformer.debug_info().variables().iter()
+            .filter_map(|entry| match entry.location {
+                VariableLocation::Register { register, start, end } => Some((entry.name.as_str(), register, start, end)),
+                VariableLocation::Memory(_) => None,
+            })
+            .collect();
+        let [("a", 16, a_start, a_end), ("b", 16, b_start, b_end)] = ranges[..] else {
+            panic!("Expected `a` and `b` sharing $16, got {:?}", ranges)
+        };
+        assert!(a_start < a_end && a_end <= b_start && b_start < b_end);
+        let names = |pc| transformer.debug_info().variables_at(pc).map(|entry| entry.name.clone()).collect::<Vec<_>>();
+        assert_eq!(vec!["a"], names(a_start));
+        assert_eq!(vec!["b"], names(b_start));
+    }
+
+    #[test]
+    fn test_loop_variables_preferred() {
+        let program = parse_program("
+            const 1
+            store a
+            const 2
+            store b
+            load a
+            load b
+            op add
+            print
+        L0:
+            load c
+            print
+            load a
+            jnz L0
+        ").unwrap();
+        let allocation = allocate_registers(&program, &[16]);
+        assert_eq!(HashMap::from([(ident("a"), 16)]), allocation);
+        assert_eq!(vec![0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1], loop_depths(&program));
+    }
+}
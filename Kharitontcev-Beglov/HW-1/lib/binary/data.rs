@@ -0,0 +1,125 @@
+//! Constants of the data segment, shared by the backends.
+//!
+//! The data segment of a program starts with the constants and the string literals, which are addressed from `$0`.
+//! The variables, the stack or the memory slots of the backend follow them.
+
+use std::collections::HashMap;
+
+use crate::binary::link::LinkError;
+
+#[derive(Clone, Debug, Default)]
+pub struct ConstantPool {
+    // Indices of the words holding the constants and of the first words of the strings
+    constants: HashMap<i32, usize>,
+    strings: HashMap<String, usize>,
+    words: Vec<i32>,
+}
+
+/// Offset of the word with the index from the base register of `lw` and `sw`
+pub fn word_offset(index: usize) -> u16 {
+    (index as u16) << 2
+}
+
+/// Checks that the offsets of all the `words` words from the base register fit into the signed 16-bit offset of `lw`
+/// and `sw`, the emulator sign-extends larger ones
+pub fn check_offsets(words: usize) -> Result<(), LinkError> {
+    match words.checked_sub(1).map(|last| last as u64 * 4) {
+        Some(offset) if offset > i16::MAX as u64 => Err(LinkError::OffsetOutOfRange(offset)),
+        _ => Ok(()),
+    }
+}
+
+impl ConstantPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index of the word with the constant, it is stored the first time it is used
+    pub fn constant(&mut self, constant: i32) -> usize {
+        match self.constants.get(&constant) {
+            Some(index) => *index,
+            None => self.push(constant),
+        }
+    }
+
+    /// Stores the constant after the others even if the pool has it already, later lookups find this copy.
+    /// The backends store the offsets of the memory layout this way, since they depend on the size of the pool
+    pub fn push(&mut self, constant: i32) -> usize {
+        self.constants.insert(constant, self.words.len());
+        self.words.push(constant);
+        self.words.len() - 1
+    }
+
+    /// Index of the word with a constant stored before
+    pub fn index(&self, constant: i32) -> usize {
+        self.constants[&constant]
+    }
+
+    /// Index of the first word of the string, it is stored the first time it is used.
+    /// The UTF-8 bytes of the string are followed by the terminating zero and padded to whole words
+    pub fn string(&mut self, value: &str) -> usize {
+        if let Some(index) = self.strings.get(value) {
+            return *index;
+        }
+        let index = self.words.len();
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.resize(bytes.len() / 4 * 4 + 4, 0);
+        self.words.extend(bytes.chunks(4).map(|word| i32::from_be_bytes(word.try_into().unwrap())));
+        self.strings.insert(value.to_string(), index);
+        index
+    }
+
+    /// Index of the first word of a string stored before
+    pub fn string_index(&self, value: &str) -> usize {
+        self.strings[value]
+    }
+
+    /// Number of the words
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Contents of the pool in the big-endian order of the memory
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.words.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constants_and_strings_are_stored_once() {
+        let mut pool = ConstantPool::new();
+        assert_eq!(0, pool.constant(4));
+        assert_eq!(1, pool.string("abcd"));
+        assert_eq!(3, pool.constant(7));
+        assert_eq!(0, pool.constant(4));
+        assert_eq!(1, pool.string("abcd"));
+        assert_eq!(4, pool.string(""));
+        assert_eq!(5, pool.len());
+        assert_eq!(vec![0, 0, 0, 4, b'a', b'b', b'c', b'd', 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0], pool.to_bytes());
+    }
+
+    #[test]
+    fn test_pushed_constant_is_found_later() {
+        let mut pool = ConstantPool::new();
+        pool.constant(8);
+        assert_eq!(1, pool.push(8));
+        assert_eq!(1, pool.index(8));
+        assert_eq!(1, pool.constant(8));
+        assert_eq!(2, pool.len());
+    }
+
+    #[test]
+    fn test_offsets_at_the_sign_bit() {
+        assert_eq!(Ok(()), check_offsets(0));
+        assert_eq!(Ok(()), check_offsets(0x2000));
+        assert_eq!(Err(LinkError::OffsetOutOfRange(0x8000)), check_offsets(0x2001));
+    }
+}
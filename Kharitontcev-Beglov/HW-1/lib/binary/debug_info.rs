@@ -0,0 +1,212 @@
+use std::fmt::{Display, Formatter};
+use crate::lexer::tokens::Span;
+
+/// Maps the code address of the first instruction of a statement to its position in the source
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct LineEntry {
+    pub pc: u32,
+    pub span: Span,
+}
+
+/// Where the value of a variable is kept
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum VariableLocation {
+    /// Address in the data memory, during the whole program
+    Memory(u32),
+    /// The register holds the variable for the instructions at `start <= pc < end`, it is shared with other variables elsewhere
+    Register { register: u8, start: u32, end: u32 },
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VariableEntry {
+    pub name: String,
+    pub location: VariableLocation,
+}
+
+/// Debug information produced by the compiler alongside the binary.
+///
+/// Text format is line based, each line is either `line <pc> <line>:<column>`, `var <name> <address>`
+/// or `reg <name> <register> <start> <end>`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DebugInfo {
+    // Sorted by pc
+    lines: Vec<LineEntry>,
+    variables: Vec<VariableEntry>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseDebugInfoError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ParseDebugInfoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid debug info at line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseDebugInfoError {}
+
+impl DebugInfo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_line(&mut self, pc: u32, span: Span) {
+        // Several statements may start at the same address (e.g. `var` declarations), the last one is executed
+        match self.lines.last_mut() {
+            Some(last) if last.pc == pc => last.span = span,
+            last => {
+                debug_assert!(last.is_none_or(|last| last.pc < pc), "lines should be added in increasing pc order");
+                self.lines.push(LineEntry { pc, span })
+            }
+        }
+    }
+
+    pub fn add_variable(&mut self, name: &str, address: u32) {
+        self.variables.push(VariableEntry { name: name.to_string(), location: VariableLocation::Memory(address) })
+    }
+
+    pub fn add_register_variable(&mut self, name: &str, register: u8, start: u32, end: u32) {
+        let location = VariableLocation::Register { register, start, end };
+        self.variables.push(VariableEntry { name: name.to_string(), location })
+    }
+
+    pub fn lines(&self) -> &[LineEntry] {
+        &self.lines
+    }
+
+    pub fn variables(&self) -> &[VariableEntry] {
+        &self.variables
+    }
+
+    /// Returns the variables whose values are available at `pc`
+    pub fn variables_at(&self, pc: u32) -> impl Iterator<Item = &VariableEntry> {
+        self.variables.iter().filter(move |entry| match entry.location {
+            VariableLocation::Memory(_) => true,
+            VariableLocation::Register { start, end, .. } => (start..end).contains(&pc),
+        })
+    }
+
+    /// Returns the source position of the statement the instruction at `pc` belongs to
+    pub fn location_of(&self, pc: u32) -> Option<Span> {
+        let index = self.lines.partition_point(|entry| entry.pc <= pc);
+        if index == 0 { None } else { Some(self.lines[index - 1].span) }
+    }
+
+    /// Returns the source position if a statement starts exactly at `pc`
+    pub fn statement_at(&self, pc: u32) -> Option<Span> {
+        self.lines.binary_search_by_key(&pc, |entry| entry.pc)
+            .ok()
+            .map(|index| self.lines[index].span)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut result = String::new();
+        for entry in &self.lines {
+            result.push_str(&format!("line {} {}\n", entry.pc, entry.span));
+        }
+        for entry in &self.variables {
+            match entry.location {
+                VariableLocation::Memory(address) => result.push_str(&format!("var {} {}\n", entry.name, address)),
+                VariableLocation::Register { register, start, end } => {
+                    result.push_str(&format!("reg {} {} {} {}\n", entry.name, register, start, end))
+                }
+            }
+        }
+        result
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, ParseDebugInfoError> {
+        let mut result = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: &str| ParseDebugInfoError { line: index + 1, message: message.to_string() };
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.as_slice() {
+                [] => {}
+                ["line", pc, position] => {
+                    let pc = pc.parse().map_err(|_| error("invalid pc"))?;
+                    if result.lines.last().is_some_and(|last| last.pc > pc) {
+                        return Err(error("lines should be sorted by pc"));
+                    }
+                    let (line, column) = position.split_once(':').ok_or_else(|| error("expected <line>:<column>"))?;
+                    let span = Span {
+                        line: line.parse().map_err(|_| error("invalid line number"))?,
+                        column: column.parse().map_err(|_| error("invalid column number"))?,
+                    };
+                    result.add_line(pc, span)
+                }
+                ["var", name, address] => {
+                    result.add_variable(name, address.parse().map_err(|_| error("invalid address"))?)
+                }
+                ["reg", name, register, start, end] => {
+                    let register = register.parse().ok().filter(|register| *register < 32).ok_or_else(|| error("invalid register"))?;
+                    let start = start.parse().map_err(|_| error("invalid pc"))?;
+                    let end = end.parse().map_err(|_| error("invalid pc"))?;
+                    result.add_register_variable(name, register, start, end)
+                }
+                _ => return Err(error("expected `line <pc> <line>:<column>`, `var <name> <address>` or `reg <name> <register> <start> <end>`"))
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(line: usize, column: usize) -> Span {
+        Span { line, column }
+    }
+
+    #[test]
+    fn test_location_lookup() {
+        let mut info = DebugInfo::new();
+        info.add_line(8, span(2, 5));
+        info.add_line(24, span(3, 5));
+        info.add_line(24, span(4, 1));
+        assert_eq!(None, info.location_of(0));
+        assert_eq!(Some(span(2, 5)), info.location_of(8));
+        assert_eq!(Some(span(2, 5)), info.location_of(20));
+        assert_eq!(Some(span(4, 1)), info.location_of(100));
+        assert_eq!(None, info.statement_at(20));
+        assert_eq!(Some(span(4, 1)), info.statement_at(24));
+    }
+
+    #[test]
+    fn test_variables_at() {
+        let mut info = DebugInfo::new();
+        info.add_variable("a", 20);
+        info.add_register_variable("b", 16, 8, 24);
+        info.add_register_variable("c", 16, 24, 40);
+        let names = |pc| info.variables_at(pc).map(|entry| entry.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["a"], names(4));
+        assert_eq!(vec!["a", "b"], names(20));
+        assert_eq!(vec!["a", "c"], names(24));
+        assert_eq!(vec!["a"], names(40));
+    }
+
+    #[test]
+    fn test_text_roundtrip() {
+        let mut info = DebugInfo::new();
+        info.add_line(8, span(2, 5));
+        info.add_line(44, span(3, 1));
+        info.add_variable("a", 20);
+        info.add_variable("b", 24);
+        info.add_register_variable("c", 16, 8, 44);
+        let text = info.to_text();
+        assert_eq!("line 8 2:5\nline 44 3:1\nvar a 20\nvar b 24\nreg c 16 8 44\n", text);
+        assert_eq!(info, DebugInfo::from_text(&text).unwrap());
+    }
+
+    #[test]
+    fn test_invalid_text() {
+        let error = DebugInfo::from_text("line 8 2:5\nline 8 2").unwrap_err();
+        assert_eq!(2, error.line);
+        assert!(DebugInfo::from_text("reg a 32 0 4").is_err());
+        assert!(DebugInfo::from_text("reg a 16").is_err());
+        assert_eq!(2, DebugInfo::from_text("line 8 2:5\nline 4 3:1").unwrap_err().line);
+    }
+}
@@ -1,8 +1,11 @@
+use crate::parser::ast::{Ops, PrefixOps};
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Instr {
     R(RType),
     J(JType),
     I(IType),
+    C0(Cop0),
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -16,9 +19,124 @@ pub struct RType {
     pub funct: u8, // 6
 }
 
+impl RType {
+    /// Functs of the additions and the subtractions: `add` and `sub` stop on the signed overflow,
+    /// `addu` and `subu` wrap around
+    pub const ADD: u8 = 32;
+    pub const ADDU: u8 = 33;
+    pub const SUB: u8 = 34;
+    pub const SUBU: u8 = 35;
+
+    /// Funct of the `halt $rs` pseudo-instruction, which stops the program with the exit code in `$rs`
+    pub const HALT: u8 = 13;
+
+    pub fn halt(rs: u8) -> Self {
+        Self { rs, rt: 0, rd: 0, funct: Self::HALT }
+    }
+
+    /// Funct of `syscall`, which runs the [`Service`] selected by `$2`
+    pub const SYSCALL: u8 = 12;
+
+    pub fn syscall() -> Self {
+        Self { rs: 0, rt: 0, rd: 0, funct: Self::SYSCALL }
+    }
+}
+
+/// How the compiled `+`, `-` and unary minus of Klang treat the signed overflow. The stack pointer and the runtime
+/// routines always wrap around
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Arithmetic {
+    /// Wraps around as the interpreters do, with `addu` and `subu`
+    #[default]
+    Wrapping,
+    /// Stops the program on the overflow, with `add` and `sub`
+    Checked,
+}
+
+impl Arithmetic {
+    pub fn add(self) -> u8 {
+        match self {
+            Arithmetic::Wrapping => RType::ADDU,
+            Arithmetic::Checked => RType::ADD,
+        }
+    }
+
+    pub fn sub(self) -> u8 {
+        match self {
+            Arithmetic::Wrapping => RType::SUBU,
+            Arithmetic::Checked => RType::SUB,
+        }
+    }
+
+    /// Funct of the binary operation
+    pub fn funct(self, op: &Ops) -> u8 {
+        match op {
+            Ops::Add => self.add(),
+            Ops::Sub => self.sub(),
+            Ops::BitwiseAnd => 36,
+            Ops::BitwiseOr => 37,
+            Ops::BitwiseNor => 39,
+        }
+    }
+
+    /// Whether the operation may stop the program, then it can be neither removed nor moved by the optimizations
+    pub fn may_trap(self, op: &Ops) -> bool {
+        self == Arithmetic::Checked && matches!(op, Ops::Add | Ops::Sub)
+    }
+
+    pub fn may_trap_prefix(self, op: &PrefixOps) -> bool {
+        self == Arithmetic::Checked && *op == PrefixOps::UnaryMinus
+    }
+
+    /// Computes the operation as the compiled code does, `None` if the program stops on the overflow
+    pub fn apply(self, op: &Ops, lhs: i32, rhs: i32) -> Option<i32> {
+        match (self, op) {
+            (Arithmetic::Checked, Ops::Add) => lhs.checked_add(rhs),
+            (Arithmetic::Checked, Ops::Sub) => lhs.checked_sub(rhs),
+            _ => Some(op.apply(lhs, rhs)),
+        }
+    }
+
+    /// Computes the prefix operation as the compiled code does, the unary minus is a subtraction from zero
+    pub fn apply_prefix(self, op: &PrefixOps, value: i32) -> Option<i32> {
+        match (self, op) {
+            (Arithmetic::Checked, PrefixOps::UnaryMinus) => value.checked_neg(),
+            _ => Some(op.apply(value)),
+        }
+    }
+}
+
+/// Services of `syscall`, numbered as in MARS and SPIM. The number is in `$v0` (`$2`), the argument in `$a0` (`$4`)
+/// and the result in `$v0`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Service {
+    /// Prints the integer without a line break
+    PrintInt = 1,
+    /// Prints the zero-terminated string at the address
+    PrintString = 4,
+    /// Reads a line with an integer
+    ReadInt = 5,
+    /// Stops the program with the zero exit code
+    Exit = 10,
+    /// Prints the character with the code
+    PrintChar = 11,
+    /// Stops the program with the exit code in `$a0`
+    Exit2 = 17,
+}
+
+impl Service {
+    pub const ALL: [Service; 6] = [Service::PrintInt, Service::PrintString, Service::ReadInt, Service::Exit, Service::PrintChar, Service::Exit2];
+
+    pub fn from_code(code: i32) -> Option<Self> {
+        Self::ALL.into_iter().find(|service| *service as i32 == code)
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum JType {
     Jmp { address: u32 },
+    /// Jumps as `Jmp` and stores the address of the next instruction to $31
+    Jal { address: u32 },
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -27,6 +145,29 @@ pub enum IType {
     Beq { rs: u8, rt: u8, imm: u16 },
     Lw { rs: u8, rt: u8, imm: u16 },
     Sw { rs: u8, rt: u8, imm: u16 },
+    /// Prints the zero-terminated string at `$rs + imm`
+    Prints { rs: u8, imm: u16 },
+}
+
+/// Instructions of the coprocessor 0, see [`crate::emulator::cp0`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Cop0 {
+    /// Moves the coprocessor register `rd` to `$rt`
+    Mfc0 { rt: u8, rd: u8 },
+    /// Moves `$rt` to the coprocessor register `rd`
+    Mtc0 { rt: u8, rd: u8 },
+    /// Returns from the exception handler to the address in `EPC`
+    Eret,
+}
+
+impl Cop0 {
+    pub const OPCODE: u8 = 16;
+    /// The `rs` field selecting the operation
+    pub const MFC0: u8 = 0;
+    pub const MTC0: u8 = 4;
+    pub const CO: u8 = 16;
+    /// Funct of `eret`, the `rs` field is [`Cop0::CO`]
+    pub const ERET: u8 = 24;
 }
 
 fn truncate_register(reg: u8) -> u8 {
@@ -37,16 +178,17 @@ fn transform_r_type(r_type: &RType) -> u32 {
     let rs = truncate_register(r_type.rs) as u32;
     let rt = truncate_register(r_type.rt) as u32;
     let rd = truncate_register(r_type.rd) as u32;
-    (0u32 << 26) | (rs << 21) | (rt << 16) | (rd << 11) | (0u32 << 6) | (r_type.funct as u32)
+    (rs << 21) | (rt << 16) | (rd << 11) | (r_type.funct as u32) // opcode and shamt are zero
 }
 
 fn transform_j_type(j_type: &JType) -> u32 {
-    match j_type {
-        JType::Jmp { address } => {
-            let opcode = 2u32;
-            (opcode << 26) | (address & 0x3ffffff)
-        }
-    }
+    let (opcode, address) = match j_type {
+        JType::Jmp { address } => (2u32, *address),
+        JType::Jal { address } => (3u32, *address),
+    };
+    // The linker checks the targets, an address out of the field is a bug in the backend
+    assert!(address <= 0x3ffffff, "Jump address {:#x} does not fit into 26 bits", address);
+    (opcode << 26) | address
 }
 
 fn transform_i_type(i_type: &IType) -> u32 {
@@ -63,14 +205,25 @@ fn transform_i_type(i_type: &IType) -> u32 {
         IType::Lw { rs, rt, imm } => {
             (34u32, truncate_register(*rs) as u32, truncate_register(*rt) as u32, *imm as u32)
         }
+        IType::Prints { rs, imm } => (63u32, truncate_register(*rs) as u32, 0, *imm as u32),
     };
     (opcode << 26) | (rs << 21) | (rt << 16) | imm
 }
 
+fn transform_cop0(cop0: &Cop0) -> u32 {
+    let (rs, rt, rd, funct) = match cop0 {
+        Cop0::Mfc0 { rt, rd } => (Cop0::MFC0, truncate_register(*rt), truncate_register(*rd), 0),
+        Cop0::Mtc0 { rt, rd } => (Cop0::MTC0, truncate_register(*rt), truncate_register(*rd), 0),
+        Cop0::Eret => (Cop0::CO, 0, 0, Cop0::ERET),
+    };
+    ((Cop0::OPCODE as u32) << 26) | ((rs as u32) << 21) | ((rt as u32) << 16) | ((rd as u32) << 11) | funct as u32
+}
+
 pub fn transform_to_bytes(instr: &Instr) -> u32 {
     match instr {
         Instr::R(r_type) => transform_r_type(r_type),
         Instr::J(j_type) => transform_j_type(j_type),
-        Instr::I(i_type) => transform_i_type(i_type)
+        Instr::I(i_type) => transform_i_type(i_type),
+        Instr::C0(cop0) => transform_cop0(cop0),
     }
 }
\ No newline at end of file
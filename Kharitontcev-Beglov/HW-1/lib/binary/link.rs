@@ -0,0 +1,433 @@
+//! Linker-like stage resolving the jump targets of the generated code.
+//!
+//! Backends emit instructions together with symbolic jumps and labels. Only here the labels get their byte
+//! addresses, computed from the load address of the code and the instruction size, and the jumps are encoded.
+//! `j` keeps the bits 2..28 of the target and takes the upper four bits from the address of the next
+//! instruction, so the target should be in the same 256 MB region. A conditional jump is a `beq`/`bne` with
+//! the offset to the target if it fits into 16 bits, and an inverted branch over `j` otherwise.
+//!
+//! Calls of the runtime routines are `jal` to the code of the routines (see [`runtime`]), which is appended to
+//! the program after the `halt` ending it. The code of an object is not ended, the next module follows it,
+//! so the routines in an object are preceded by a jump over them.
+//!
+//! The same stage records the relocations of an object file (see [`object`](crate::binary::object)): every `j`
+//! depends on the placement of the code and the [`Item::Relocated`] instructions on the placement of the data.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::hash::Hash;
+
+use crate::binary::debug_info::DebugInfo;
+use crate::binary::instructions::{Instr, IType, JType, RType, transform_to_bytes};
+use crate::binary::object::{Relocation, RelocationTarget};
+use crate::binary::runtime::{self, RuntimeLabel};
+use crate::lexer::tokens::Span;
+use crate::parser::ast::Routine;
+use crate::stack_machine::sm::Condition;
+
+pub const INSTRUCTION_SIZE: u32 = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Item<L> {
+    Instr(Instr),
+    /// `lw`/`sw` with the offset relative to the target, which is known only after linking the objects
+    Relocated(Instr, RelocationTarget),
+    Jump(L),
+    /// Calls the runtime routine, the return address is stored to `$31`
+    Call(Routine),
+    /// Jumps to the label if the value of the register satisfies the condition
+    ConditionalJump(Condition, u8, L),
+    Label(L),
+    /// The following code belongs to the statement at the position
+    Location(Span),
+    /// The variable is kept in the register from here until its [`Item::VariableEnd`]
+    VariableStart(String, u8),
+    VariableEnd(String),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LinkError {
+    UndefinedLabel(String),
+    DuplicateLabel(String),
+    UnalignedLoadAddress(u32),
+    /// The target of `j` is out of the 256 MB region of the jump
+    JumpOutOfRange { from: u64, target: u64 },
+    /// The code does not fit into the 32-bit address space after the load address
+    CodeTooLarge { load_address: u32, size: u64 },
+    /// No linked module exports the variable used by the module
+    UndefinedSymbol { symbol: String, module: String },
+    DuplicateSymbol { symbol: String, first: String, second: String },
+    DuplicateModule(String),
+    /// The imported module is not among the linked ones
+    MissingModule { module: String, imported_by: String },
+    ImportCycle(String),
+    /// The relocated data address of `lw`/`sw` does not fit into its signed 16-bit offset
+    DataOutOfRange { module: String, address: u64 },
+    /// The offset of the data from the base register of `lw`/`sw` does not fit into its signed 16-bit offset
+    OffsetOutOfRange(u64),
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkError::UndefinedLabel(label) => write!(f, "Label {} is not defined", label),
+            LinkError::DuplicateLabel(label) => write!(f, "Label {} is defined more than once", label),
+            LinkError::UnalignedLoadAddress(address) => {
+                write!(f, "Load address {:#x} is not a multiple of the instruction size", address)
+            }
+            LinkError::JumpOutOfRange { from, target } => {
+                write!(f, "Jump at {:#x} can't reach {:#x} outside of its 256 MB region", from, target)
+            }
+            LinkError::CodeTooLarge { load_address, size } => {
+                write!(f, "Code of {} bytes does not fit into the memory after {:#x}", size, load_address)
+            }
+            LinkError::UndefinedSymbol { symbol, module } => {
+                write!(f, "Variable {} used by {} is not exported by any module", symbol, module)
+            }
+            LinkError::DuplicateSymbol { symbol, first, second } => {
+                write!(f, "Variable {} is exported by both {} and {}", symbol, first, second)
+            }
+            LinkError::DuplicateModule(module) => write!(f, "Module {} is linked more than once", module),
+            LinkError::MissingModule { module, imported_by } => {
+                write!(f, "Module {} imported by {} is not linked", module, imported_by)
+            }
+            LinkError::ImportCycle(module) => write!(f, "Module {} imports itself through its imports", module),
+            LinkError::DataOutOfRange { module, address } => {
+                write!(f, "Data address {:#x} of {} does not fit into the 16-bit offset", address, module)
+            }
+            LinkError::OffsetOutOfRange(offset) => {
+                write!(f, "Data offset {:#x} does not fit into the 16-bit offset", offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+impl<L> Item<L> {
+    pub fn map_label<M>(self, f: impl Fn(L) -> M) -> Item<M> {
+        match self {
+            Item::Instr(instr) => Item::Instr(instr),
+            Item::Relocated(instr, target) => Item::Relocated(instr, target),
+            Item::Jump(label) => Item::Jump(f(label)),
+            Item::Call(routine) => Item::Call(routine),
+            Item::ConditionalJump(condition, rs, label) => Item::ConditionalJump(condition, rs, f(label)),
+            Item::Label(label) => Item::Label(f(label)),
+            Item::Location(span) => Item::Location(span),
+            Item::VariableStart(name, register) => Item::VariableStart(name, register),
+            Item::VariableEnd(name) => Item::VariableEnd(name),
+        }
+    }
+}
+
+/// Label of the linked code, which consists of the program, a `halt` or a jump over the runtime and the runtime routines
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum Target<L> {
+    Program(L),
+    Runtime(RuntimeLabel),
+    End,
+}
+
+impl<L: Display> Display for Target<L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Program(label) => write!(f, "{}", label),
+            Target::Runtime(label) => write!(f, "{}", label),
+            Target::End => write!(f, "end"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Linker {
+    load_address: u32,
+}
+
+impl Default for Linker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Byte addresses of the items and of the labels
+struct Layout<L> {
+    addresses: Vec<u64>,
+    labels: HashMap<L, u64>,
+    end: u64,
+}
+
+impl Linker {
+    /// Start of the text segment of the emulator, as in MARS
+    pub const DEFAULT_LOAD_ADDRESS: u32 = 0x0040_0000;
+
+    pub fn new() -> Self {
+        Self::with_load_address(Self::DEFAULT_LOAD_ADDRESS)
+    }
+
+    pub fn with_load_address(load_address: u32) -> Self {
+        Self { load_address }
+    }
+
+    pub fn load_address(&self) -> u32 {
+        self.load_address
+    }
+
+    fn layout<L: Copy + Eq + Hash + Display>(&self, items: &[Item<L>], long: &HashSet<usize>) -> Result<Layout<L>, LinkError> {
+        let mut addresses = Vec::with_capacity(items.len());
+        let mut labels = HashMap::new();
+        let mut address = self.load_address as u64;
+        for (index, item) in items.iter().enumerate() {
+            addresses.push(address);
+            let size = match item {
+                Item::Instr(_) | Item::Relocated(_, _) | Item::Jump(_) | Item::Call(_) => 1,
+                Item::ConditionalJump(_, _, _) if long.contains(&index) => 2,
+                Item::ConditionalJump(_, _, _) => 1,
+                Item::Label(label) => {
+                    if labels.insert(*label, address).is_some() {
+                        return Err(LinkError::DuplicateLabel(label.to_string()));
+                    }
+                    0
+                }
+                Item::Location(_) | Item::VariableStart(_, _) | Item::VariableEnd(_) => 0,
+            };
+            address += size * INSTRUCTION_SIZE as u64;
+        }
+        Ok(Layout { addresses, labels, end: address })
+    }
+
+    fn target<L: Eq + Hash + Display>(layout: &Layout<L>, label: &L) -> Result<u64, LinkError> {
+        layout.labels.get(label).copied().ok_or_else(|| LinkError::UndefinedLabel(label.to_string()))
+    }
+
+    /// Offset of the branch target in instructions after the branch
+    fn branch_offset(branch: u64, target: u64) -> i64 {
+        (target as i64 - (branch + INSTRUCTION_SIZE as u64) as i64) / INSTRUCTION_SIZE as i64
+    }
+
+    /// Address field of `j` and `jal`
+    fn jump_address(from: u64, target: u64) -> Result<u32, LinkError> {
+        let next = from + INSTRUCTION_SIZE as u64;
+        if next >> 28 != target >> 28 {
+            return Err(LinkError::JumpOutOfRange { from, target });
+        }
+        Ok(((target >> 2) & 0x3ff_ffff) as u32)
+    }
+
+    fn jump(from: u64, target: u64) -> Result<Instr, LinkError> {
+        Ok(Instr::J(JType::Jmp { address: Self::jump_address(from, target)? }))
+    }
+
+    /// The program followed by the called runtime routines, each routine is linked once.
+    /// A whole program is ended with `halt $0`, otherwise the code continues after the routines
+    fn with_runtime<L: Clone>(items: &[Item<L>], halt: bool) -> Vec<Item<Target<L>>> {
+        let mut result: Vec<Item<Target<L>>> = items.iter().cloned().map(|item| item.map_label(Target::Program)).collect();
+        let called: Vec<Routine> = Routine::ALL.into_iter()
+            .filter(|routine| items.iter().any(|item| matches!(item, Item::Call(called) if called == routine)))
+            .collect();
+        if halt {
+            result.push(Item::Instr(Instr::R(RType::halt(0))));
+        } else if !called.is_empty() {
+            result.push(Item::Jump(Target::End));
+        }
+        for routine in &called {
+            result.extend(runtime::routine_code(*routine).into_iter().map(|item| item.map_label(Target::Runtime)));
+        }
+        if !halt && !called.is_empty() {
+            result.push(Item::Label(Target::End));
+        }
+        result
+    }
+
+    /// Conditional jumps start in the short form. Each long one moves the labels after it, so the out of range
+    /// branches are searched again until there are no new ones; branches only become longer, so this terminates.
+    fn relax<L: Copy + Eq + Hash + Display>(&self, items: &[Item<L>]) -> Result<(Layout<L>, HashSet<usize>), LinkError> {
+        let mut long = HashSet::new();
+        loop {
+            let layout = self.layout(items, &long)?;
+            let mut changed = false;
+            for (index, item) in items.iter().enumerate() {
+                let Item::ConditionalJump(_, _, label) = item else { continue };
+                let offset = Self::branch_offset(layout.addresses[index], Self::target(&layout, label)?);
+                if i16::try_from(offset).is_err() && long.insert(index) {
+                    changed = true;
+                }
+            }
+            if !changed {
+                return Ok((layout, long));
+            }
+        }
+    }
+
+    /// Encodes the program placed at the load address, positions of the statements and the ranges of the register
+    /// variables are added to the debug information
+    pub fn link<L: Copy + Eq + Hash + Display>(&self, items: &[Item<L>], debug_info: &mut DebugInfo) -> Result<Vec<u8>, LinkError> {
+        self.encode(items, true, debug_info).map(|(code, _)| code)
+    }
+
+    /// Encodes the code of an object as [`Linker::link`] does, but without the `halt`,
+    /// and also returns the relocations at the offsets from the load address
+    pub fn link_relocatable<L: Copy + Eq + Hash + Display>(
+        &self,
+        items: &[Item<L>],
+        debug_info: &mut DebugInfo,
+    ) -> Result<(Vec<u8>, Vec<Relocation>), LinkError> {
+        self.encode(items, false, debug_info)
+    }
+
+    fn encode<L: Copy + Eq + Hash + Display>(
+        &self,
+        items: &[Item<L>],
+        halt: bool,
+        debug_info: &mut DebugInfo,
+    ) -> Result<(Vec<u8>, Vec<Relocation>), LinkError> {
+        if !self.load_address.is_multiple_of(INSTRUCTION_SIZE) {
+            return Err(LinkError::UnalignedLoadAddress(self.load_address));
+        }
+        let items = Self::with_runtime(items, halt);
+        let (layout, long) = self.relax(&items)?;
+        if layout.end > u32::MAX as u64 + 1 {
+            let size = layout.end - self.load_address as u64;
+            return Err(LinkError::CodeTooLarge { load_address: self.load_address, size });
+        }
+        let mut code = Vec::new();
+        let mut relocations = Vec::new();
+        let mut register_variables: HashMap<&str, (u8, u32)> = HashMap::new();
+        let mut relocate = |address: u64, target: RelocationTarget| {
+            relocations.push(Relocation { offset: (address - self.load_address as u64) as u32, target });
+        };
+        for (index, item) in items.iter().enumerate() {
+            let address = layout.addresses[index];
+            let instructions = match item {
+                Item::Instr(instr) => vec![*instr],
+                Item::Relocated(instr, target) => {
+                    relocate(address, target.clone());
+                    vec![*instr]
+                }
+                Item::Jump(label) => {
+                    relocate(address, RelocationTarget::Code);
+                    vec![Self::jump(address, Self::target(&layout, label)?)?]
+                }
+                Item::Call(routine) => {
+                    relocate(address, RelocationTarget::Code);
+                    let target = Self::target(&layout, &Target::Runtime(RuntimeLabel::entry(*routine)))?;
+                    vec![Instr::J(JType::Jal { address: Self::jump_address(address, target)? })]
+                }
+                Item::ConditionalJump(condition, rs, label) => {
+                    let target = Self::target(&layout, label)?;
+                    let (rs, rt) = (*rs, 0);
+                    if long.contains(&index) {
+                        // Skips the jump if the condition does not hold
+                        let imm = 1;
+                        let branch = match condition {
+                            Condition::EqualsZero => IType::Bne { rs, rt, imm },
+                            Condition::NotEqualsZero => IType::Beq { rs, rt, imm },
+                        };
+                        relocate(address + INSTRUCTION_SIZE as u64, RelocationTarget::Code);
+                        vec![Instr::I(branch), Self::jump(address + INSTRUCTION_SIZE as u64, target)?]
+                    } else {
+                        let imm = Self::branch_offset(address, target) as i16 as u16;
+                        vec![Instr::I(match condition {
+                            Condition::EqualsZero => IType::Beq { rs, rt, imm },
+                            Condition::NotEqualsZero => IType::Bne { rs, rt, imm },
+                        })]
+                    }
+                }
+                Item::Label(_) => vec![],
+                Item::Location(span) => {
+                    debug_info.add_line(address as u32, *span);
+                    vec![]
+                }
+                Item::VariableStart(name, register) => {
+                    register_variables.insert(name, (*register, address as u32));
+                    vec![]
+                }
+                Item::VariableEnd(name) => {
+                    if let Some((register, start)) = register_variables.remove(name.as_str()) {
+                        debug_info.add_register_variable(name, register, start, address as u32);
+                    }
+                    vec![]
+                }
+            };
+            code.extend(instructions.iter().flat_map(|instr| transform_to_bytes(instr).to_be_bytes()));
+        }
+        Ok((code, relocations))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOP: Item<u32> = Item::Instr(Instr::R(RType { rs: 0, rt: 0, rd: 0, funct: 32 }));
+    const HALT: u32 = RType::HALT as u32;
+    /// Word address of the default load address, as in the `j` targets
+    const BASE: u32 = Linker::DEFAULT_LOAD_ADDRESS >> 2;
+
+    fn words(code: &[u8]) -> Vec<u32> {
+        code.chunks(4).map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap())).collect()
+    }
+
+    #[test]
+    fn test_load_address() {
+        let items = [Item::Label(0), NOP, Item::Jump(1), Item::Jump(0), Item::Label(1)];
+        let code = Linker::with_load_address(0x100).link(&items, &mut DebugInfo::new()).unwrap();
+        // Targets are encoded as word addresses
+        assert_eq!(vec![32, (2 << 26) | (0x10c >> 2), (2 << 26) | (0x100 >> 2), HALT], words(&code));
+        let code = Linker::new().link(&items, &mut DebugInfo::new()).unwrap();
+        assert_eq!(vec![32, (2 << 26) | (BASE + 3), (2 << 26) | BASE, HALT], words(&code));
+    }
+
+    #[test]
+    fn test_branches() {
+        let mut items = vec![Item::Label(0), Item::ConditionalJump(Condition::NotEqualsZero, 8, 0), Item::ConditionalJump(Condition::EqualsZero, 8, 1)];
+        items.extend(std::iter::repeat_n(NOP, 40000));
+        items.push(Item::Label(1));
+        let code = words(&Linker::new().link(&items, &mut DebugInfo::new()).unwrap());
+        // bne $8, $0, -1
+        assert_eq!((5 << 26) | (8 << 21) | 0xffff, code[0]);
+        // The second target is too far: bne $8, $0, 1; j end, where the halt is
+        assert_eq!((5 << 26) | (8 << 21) | 1, code[1]);
+        assert_eq!((2 << 26) | (BASE + 40003), code[2]);
+        assert_eq!(vec![HALT], code[40003..]);
+    }
+
+    #[test]
+    fn test_runtime_calls() {
+        let items = [Item::Call(Routine::Abs), NOP, Item::Call(Routine::Abs)];
+        let (code, relocations) = Linker::with_load_address(0).link_relocatable(&items, &mut DebugInfo::new()).unwrap();
+        let code = words(&code);
+        // jal abs; nop; jal abs; j end; abs: ...
+        assert_eq!(vec![(3 << 26) | 4, 32, (3 << 26) | 4, (2 << 26) | code.len() as u32], code[..4]);
+        assert_eq!(runtime::routine_code(Routine::Abs).len() - 2, code.len() - 4);
+        assert_eq!(vec![0, 8, 12], relocations.iter().map(|relocation| relocation.offset).take(3).collect::<Vec<_>>());
+        // A program is ended with the halt instead of the jump, no runtime without calls
+        let code = words(&Linker::new().link(&items, &mut DebugInfo::new()).unwrap());
+        assert_eq!(vec![(3 << 26) | (BASE + 4), 32, (3 << 26) | (BASE + 4), HALT], code[..4]);
+        assert_eq!(runtime::routine_code(Routine::Abs).len() - 2, code.len() - 4);
+        assert_eq!(vec![32, HALT], words(&Linker::new().link(&[NOP], &mut DebugInfo::new()).unwrap()));
+    }
+
+    #[test]
+    fn test_errors() {
+        let linker = Linker::new();
+        assert_eq!(Err(LinkError::UndefinedLabel(String::from("7"))), linker.link(&[Item::Jump(7)], &mut DebugInfo::new()));
+        assert_eq!(
+            Err(LinkError::DuplicateLabel(String::from("1"))),
+            linker.link(&[Item::Label(1), NOP, Item::Label(1)], &mut DebugInfo::new())
+        );
+        assert_eq!(
+            Err(LinkError::UnalignedLoadAddress(2)),
+            Linker::with_load_address(2).link(&[NOP], &mut DebugInfo::new())
+        );
+        // The jump is the last instruction of its region
+        let linker = Linker::with_load_address(0x0fff_fffc);
+        assert_eq!(
+            Err(LinkError::JumpOutOfRange { from: 0x0fff_fffc, target: 0x0fff_fffc }),
+            linker.link(&[Item::Label(0), Item::Jump(0)], &mut DebugInfo::new())
+        );
+        assert!(matches!(
+            Linker::with_load_address(0xffff_fff8).link(&[NOP, NOP, NOP], &mut DebugInfo::new()),
+            Err(LinkError::CodeTooLarge { size: 16, .. })
+        ));
+    }
+}
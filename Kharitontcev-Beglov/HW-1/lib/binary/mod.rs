@@ -1,24 +1,58 @@
-use std::collections::{HashMap, HashSet, LinkedList};
+use std::collections::{HashMap, HashSet};
 
-use crate::binary::instructions::{Instr, IType, JType, RType, transform_to_bytes};
+use crate::binary::data::{check_offsets, word_offset, ConstantPool};
+use crate::binary::debug_info::DebugInfo;
+use crate::binary::instructions::{Arithmetic, Instr, IType, RType, Service};
+use crate::binary::link::{Item, LinkError, Linker};
+use crate::binary::object::{ModuleInterface, Object, RelocationTarget, Symbol};
 use crate::parser::ast::{Ident, Ops};
-use crate::stack_machine::sm::{Condition, Label, StackCommand};
+use crate::stack_machine::liveness::Liveness;
+use crate::stack_machine::sm::{Label, StackCommand};
 
 pub mod instructions;
+pub mod data;
+pub mod debug_info;
+pub mod link;
+pub mod object;
+pub mod register;
+pub mod runtime;
+pub mod allocation;
+pub mod ssa;
 
 #[derive(Clone, Debug)]
 pub struct SMTransformer {
-    // The constants map maps all possible `const` instructions into the offset in the data array
-    constants: HashMap<i32, usize>,
-    constants_order: Vec<i32>,
-    next_free_constant_offset: usize,
+    // All possible `const` instructions and the string literals, placed at the start of the data array
+    constants: ConstantPool,
 
     // The variables map maps all variable identifiers to the memory address, since there is no scopes
     variables: HashMap<Ident, usize>,
     next_free_variable_offset: usize,
 
-    // The labels map maps all labels to the index of the next instructions
-    labels: HashMap<Label, usize>,
+    // Places the code and resolves the labels
+    linker: Linker,
+
+    // Variables kept in the registers instead of the memory, filled only if `allocate_registers` is set
+    variable_registers: HashMap<Ident, u8>,
+    allocate_registers: bool,
+
+    // Set while compiling an object file: the data is addressed from the zero register and relocated by the linker,
+    // the variables exported by the imported modules are not allocated
+    relocatable: bool,
+    externals: HashSet<Ident>,
+
+    // Prints with the `syscall` services instead of the print pseudo-instructions
+    syscalls: bool,
+
+    arithmetic: Arithmetic,
+
+    // Source positions of the statements and addresses of the variables
+    debug_info: DebugInfo,
+}
+
+impl Default for SMTransformer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SMTransformer {
@@ -28,51 +62,91 @@ impl SMTransformer {
     const VARIABLE_LOAD_TMP: u8 = 10;
     const STACK_INCREMENT: u8 = 26;
     const ZERO: u8 = 0; // Zero register
+    const ARGUMENT_0: u8 = 4;
+    const ARGUMENT_1: u8 = 5;
+    const RESULT: u8 = 2;
+    const SERVICE: u8 = 2;
+    const VARIABLE_REGISTERS: [u8; 8] = [16, 17, 18, 19, 20, 21, 22, 23];
 
     pub fn new() -> Self {
         let mut res = Self {
-            constants: HashMap::new(),
-            constants_order: Vec::new(),
+            constants: ConstantPool::new(),
             variables: HashMap::new(),
-            labels: HashMap::new(),
+            linker: Linker::new(),
+            variable_registers: HashMap::new(),
+            allocate_registers: false,
+            relocatable: false,
+            externals: HashSet::new(),
+            syscalls: false,
+            arithmetic: Arithmetic::default(),
+            debug_info: DebugInfo::new(),
             next_free_variable_offset: 0,
-            next_free_constant_offset: 0,
         };
-        res.push_constant(&4);
+        res.constants.constant(4);
         res
     }
 
-    fn push_constant(&mut self, constant: &i32) {
-        if self.constants.contains_key(constant) {
-            return;
-        }
-        self.force_push_constant(constant);
+    /// Keeps the most used variables in the registers `$16`-`$23`, see [`allocation`]
+    pub fn with_variable_registers() -> Self {
+        Self { allocate_registers: true, ..Self::new() }
+    }
+
+    /// Prints with the MARS and SPIM `syscall` services and ends the program with the exit service, so the same code
+    /// runs there. Not for object files: the printed strings are addressed by their absolute addresses
+    pub fn with_syscalls(self) -> Self {
+        Self { syscalls: true, ..self }
     }
-    fn force_push_constant(&mut self, constant: &i32) {
-        self.constants.insert(*constant, self.next_free_constant_offset);
-        self.constants_order.push(*constant);
-        self.next_free_constant_offset += 1
+
+    /// Compiles `+`, `-` and unary minus with the overflow handling
+    pub fn with_arithmetic(self, arithmetic: Arithmetic) -> Self {
+        Self { arithmetic, ..self }
     }
 
     fn push_identifier(&mut self, ident: &Ident) {
-        if self.variables.contains_key(ident) {
+        if self.variables.contains_key(ident) || self.variable_registers.contains_key(ident) || self.externals.contains(ident) {
             return;
         }
         self.variables.insert(ident.clone(), self.next_free_variable_offset);
         self.next_free_variable_offset += 1
     }
 
-    fn push_label(&mut self, label: &Label, id: usize) {
-        if self.labels.contains_key(label) {
-            println!("Overwriting label {}", label)
+    fn collect_constants(&mut self, program: &Vec<StackCommand>) {
+        for instr in program {
+            if let StackCommand::Const(x) = instr {
+                self.constants.constant(*x);
+            }
+        }
+    }
+
+    fn collect_strings(&mut self, program: &Vec<StackCommand>) {
+        for instr in program {
+            if let StackCommand::PrintString(value) = instr {
+                self.constants.string(value);
+            }
         }
-        self.labels.insert(*label, id);
     }
 
-    fn collect_constants(&mut self, program: &Vec<StackCommand>) {
+    fn string_address(&self, value: &str) -> i32 {
+        self.constants.string_index(value) as i32 * 4
+    }
+
+    /// The service numbers and the arguments of the syscalls, the strings should be collected already
+    fn collect_syscall_constants(&mut self, program: &Vec<StackCommand>) {
+        self.constants.constant(Service::Exit as i32);
         for instr in program {
             match instr {
-                StackCommand::Const(x) => self.push_constant(x),
+                StackCommand::Print => {
+                    self.constants.constant(Service::PrintInt as i32);
+                    self.constants.constant(Service::PrintChar as i32);
+                    self.constants.constant('\n' as i32);
+                }
+                StackCommand::PrintChar => {
+                    self.constants.constant(Service::PrintChar as i32);
+                }
+                StackCommand::PrintString(value) => {
+                    self.constants.constant(Service::PrintString as i32);
+                    self.constants.constant(self.string_address(value));
+                }
                 _ => {}
             }
         }
@@ -97,255 +171,346 @@ impl SMTransformer {
                 _ => {}
             }
         }
+        result.retain(|id| !self.variable_registers.contains_key(id) && !self.externals.contains(id));
         result.len()
     }
     
-    fn compiled_size(instr: &StackCommand) -> usize {
-        let stack_pop_size: usize = 3;
-        let stack_push_size: usize = 3;
-        match instr {
-            StackCommand::Print => stack_pop_size + 1,
-            StackCommand::Op(_) => 2 * stack_pop_size + 1 + stack_push_size,
-            StackCommand::Load(_) => stack_push_size + 1,
-            StackCommand::Store(_) => stack_pop_size + 1,
-            StackCommand::Const(_) => stack_push_size + 1,
-            StackCommand::Label(_) => 0,
-            StackCommand::Jmp(_) => 1,
-            StackCommand::ConditionalJump(_, _) => stack_pop_size + 2
-        }
-    }
-
-    fn collect_labels(&mut self, program: &Vec<StackCommand>) {
-        let mut meaningful_instructions: usize = 2; // boot code
-        for instr in program {
-            match instr {
-                StackCommand::Label(l) => self.push_label(l, meaningful_instructions),
-                _ => meaningful_instructions += Self::compiled_size(instr)
-            }
-        }
-    }
-
-    fn pop_from_stack_into(&self, reg: u8) -> LinkedList<u8> {
+    fn pop_from_stack_into(&self, reg: u8) -> Vec<Instr> {
         let load = Instr::I(IType::Lw {
             rs: Self::SP,
             rt: reg,
             imm: 0,
         });
-        let four_index = *self.constants.get(&4).unwrap() as u16;
         let load_one = Instr::I(IType::Lw {
             rs: Self::ZERO,
             rt: Self::STACK_INCREMENT,
-            imm: four_index << 2,
+            imm: word_offset(self.constants.index(4)),
         });
         let sub = Instr::R(RType {
             rs: Self::SP,
             rt: Self::STACK_INCREMENT,
             rd: Self::SP,
-            funct: 34,
+            funct: RType::SUBU,
         });
-        let order = [load_one, sub, load];
-        let mut result = LinkedList::new();
-        for inst in order {
-            result.append(&mut LinkedList::from(transform_to_bytes(&inst).to_be_bytes()));
-        }
-        result
+        vec![load_one, sub, load]
     }
-    fn push_into_stack(&self, reg: u8) -> LinkedList<u8> {
+    fn push_into_stack(&self, reg: u8) -> Vec<Instr> {
         let save = Instr::I(IType::Sw {
             rs: Self::SP,
             rt: reg,
             imm: 0,
         });
-        let four_index = *self.constants.get(&4).unwrap() as u16;
         let load_one = Instr::I(IType::Lw {
             rs: Self::ZERO,
             rt: Self::STACK_INCREMENT,
-            imm: four_index << 2,
+            imm: word_offset(self.constants.index(4)),
         });
         let add = Instr::R(RType {
             rs: Self::SP,
             rt: Self::STACK_INCREMENT,
             rd: Self::SP,
-            funct: 32,
+            funct: RType::ADDU,
         });
-        let order = [save, load_one, add];
-        let mut result = LinkedList::new();
-        for inst in order {
-            result.append(&mut LinkedList::from(transform_to_bytes(&inst).to_be_bytes()));
-        }
-        result
+        vec![save, load_one, add]
     }
 
-    fn load_const_to(&self, reg: u8, x: i32) -> LinkedList<u8> {
-        let index = *self.constants.get(&x).unwrap() as u16;
-        let load = Instr::I(IType::Lw {
+    fn duplicate_stack_top(&self) -> Vec<Instr> {
+        // The top of the stack is read through OPERAND_2, so the stack pointer is changed only once
+        let load_one = Instr::I(IType::Lw {
             rs: Self::ZERO,
-            rt: reg,
-            imm: index << 2,
+            rt: Self::STACK_INCREMENT,
+            imm: word_offset(self.constants.index(4)),
+        });
+        let top_address = Instr::R(RType {
+            rs: Self::SP,
+            rt: Self::STACK_INCREMENT,
+            rd: Self::OPERAND_2,
+            funct: RType::SUBU,
         });
-        LinkedList::from(transform_to_bytes(&load).to_be_bytes())
-    }
-    fn load_variable_to(&self, reg: u8, ident: &Ident) -> LinkedList<u8> {
-        let index = *self.variables.get(ident).unwrap() as u16;
         let load = Instr::I(IType::Lw {
-            rs: Self::VARIABLE_LOAD_TMP,
-            rt: reg,
-            imm: index << 2,
+            rs: Self::OPERAND_2,
+            rt: Self::OPERAND_1,
+            imm: 0,
+        });
+        let save = Instr::I(IType::Sw {
+            rs: Self::SP,
+            rt: Self::OPERAND_1,
+            imm: 0,
+        });
+        let add = Instr::R(RType {
+            rs: Self::SP,
+            rt: Self::STACK_INCREMENT,
+            rd: Self::SP,
+            funct: RType::ADDU,
         });
-        LinkedList::from(transform_to_bytes(&load).to_be_bytes())
+        vec![load_one, top_address, load, save, add]
     }
 
-    fn save_variable_from(&self, reg: u8, ident: &Ident) -> LinkedList<u8> {
-        let index = *self.variables.get(ident).unwrap() as u16;
-        let save = Instr::I(IType::Sw {
-            rs: Self::VARIABLE_LOAD_TMP,
+    fn load_const_to(&self, reg: u8, x: i32) -> Instr {
+        Instr::I(IType::Lw {
+            rs: Self::ZERO,
             rt: reg,
-            imm: index << 2,
-        });
-        LinkedList::from(transform_to_bytes(&save).to_be_bytes())
+            imm: word_offset(self.constants.index(x)),
+        })
     }
-    fn get_r_type_operation(op: &Ops, rs: u8, rt: u8, rd: u8) -> LinkedList<u8> {
-        let funct = match op {
-            Ops::Add => 32u8,
-            Ops::Sub => 34u8,
-            Ops::BitwiseAnd => 36u8,
-            Ops::BitwiseOr => 37u8,
-            Ops::BitwiseNor => 39u8,
-        };
-        let op = Instr::R(RType {
+    fn syscall(&self, service: Service) -> Vec<Instr> {
+        vec![self.load_const_to(Self::SERVICE, service as i32), Instr::R(RType::syscall())]
+    }
+
+    /// Base register and offset of the variable in the memory
+    fn variable_address(&self, ident: &Ident) -> (u8, u16) {
+        let index = *self.variables.get(ident).unwrap() as u16;
+        if self.relocatable {
+            (Self::ZERO, self.variables_offset() as u16 + (index << 2))
+        } else {
+            (Self::VARIABLE_LOAD_TMP, index << 2)
+        }
+    }
+
+    fn load_variable_to(&self, reg: u8, ident: &Ident) -> Instr {
+        let (rs, imm) = self.variable_address(ident);
+        Instr::I(IType::Lw { rs, rt: reg, imm })
+    }
+
+    fn save_variable_from(&self, reg: u8, ident: &Ident) -> Instr {
+        let (rs, imm) = self.variable_address(ident);
+        Instr::I(IType::Sw { rs, rt: reg, imm })
+    }
+    fn get_r_type_operation(&self, op: &Ops, rs: u8, rt: u8, rd: u8) -> Instr {
+        let funct = self.arithmetic.funct(op);
+        Instr::R(RType {
             rs,
             rt,
             rd,
             funct,
-        });
-        LinkedList::from(transform_to_bytes(&op).to_be_bytes())
-    }
-
-    fn get_label_address(&self, l: &Label) -> u32 {
-        let next_instruction_index = *self.labels.get(l).unwrap() as u32;
-        next_instruction_index
+        })
     }
 
-
-    fn transform_instruction(&self, instruction: &StackCommand) -> LinkedList<u8> {
-        match instruction {
+    fn transform_instruction(&self, instruction: &StackCommand) -> Vec<Item<Label>> {
+        let instructions = match instruction {
+            StackCommand::Print if self.syscalls => {
+                let mut result = self.pop_from_stack_into(Self::ARGUMENT_0);
+                result.extend(self.syscall(Service::PrintInt));
+                result.push(self.load_const_to(Self::ARGUMENT_0, '\n' as i32));
+                result.extend(self.syscall(Service::PrintChar));
+                result
+            }
+            StackCommand::PrintChar if self.syscalls => {
+                let mut result = self.pop_from_stack_into(Self::ARGUMENT_0);
+                result.extend(self.syscall(Service::PrintChar));
+                result
+            }
+            StackCommand::PrintString(value) if self.syscalls => {
+                let mut result = vec![self.load_const_to(Self::ARGUMENT_0, self.string_address(value))];
+                result.extend(self.syscall(Service::PrintString));
+                result
+            }
             StackCommand::Print => {
                 let mut result = self.pop_from_stack_into(Self::OPERAND_1);
-                result.append(&mut LinkedList::from(transform_to_bytes(
-                    &Instr::R(RType {
-                        rs: Self::OPERAND_1,
-                        rt: 0,
-                        rd: 0,
-                        funct: 0,
-                    })
-                ).to_be_bytes()));
+                result.push(Instr::R(RType {
+                    rs: Self::OPERAND_1,
+                    rt: 0,
+                    rd: 0,
+                    funct: 0,
+                }));
+                result
+            }
+            StackCommand::PrintChar => {
+                let mut result = self.pop_from_stack_into(Self::OPERAND_1);
+                result.push(Instr::R(RType {
+                    rs: Self::OPERAND_1,
+                    rt: 0,
+                    rd: 0,
+                    funct: 1,
+                }));
                 result
             }
+            StackCommand::PrintString(value) => {
+                vec![Instr::I(IType::Prints { rs: Self::ZERO, imm: word_offset(self.constants.string_index(value)) })]
+            }
             StackCommand::Op(op) => {
                 let load_1 = self.pop_from_stack_into(Self::OPERAND_1);
                 let load_2 = self.pop_from_stack_into(Self::OPERAND_2);
-                let op = Self::get_r_type_operation(
+                let op = self.get_r_type_operation(
                     op,
                     Self::OPERAND_2,
                     Self::OPERAND_1,
                     Self::OPERAND_1,
                 );
                 let push = self.push_into_stack(Self::OPERAND_1);
-                let order = [load_1, load_2, op, push];
-                let mut result = LinkedList::new();
-                for mut cmd in order {
-                    result.append(&mut cmd);
-                }
-                result
+                [load_1, load_2, vec![op], push].concat()
+            }
+            StackCommand::Load(id) if self.externals.contains(id) => {
+                let load = Instr::I(IType::Lw { rs: Self::ZERO, rt: Self::OPERAND_1, imm: 0 });
+                let mut result = vec![Item::Relocated(load, RelocationTarget::Symbol(id.0.clone()))];
+                result.extend(self.push_into_stack(Self::OPERAND_1).into_iter().map(|instr| self.item(instr)));
+                return result;
+            }
+            StackCommand::Store(id) if self.externals.contains(id) => {
+                let mut result: Vec<Item<Label>> = self.pop_from_stack_into(Self::OPERAND_1).into_iter().map(|instr| self.item(instr)).collect();
+                let save = Instr::I(IType::Sw { rs: Self::ZERO, rt: Self::OPERAND_1, imm: 0 });
+                result.push(Item::Relocated(save, RelocationTarget::Symbol(id.0.clone())));
+                return result;
+            }
+            StackCommand::Load(id) if self.variable_registers.contains_key(id) => {
+                self.push_into_stack(self.variable_registers[id])
+            }
+            StackCommand::Store(id) if self.variable_registers.contains_key(id) => {
+                self.pop_from_stack_into(self.variable_registers[id])
             }
             StackCommand::Load(id) => {
-                let mut result = self.load_variable_to(Self::OPERAND_1, id);
-                let mut push = self.push_into_stack(Self::OPERAND_1);
-                result.append(&mut push);
+                let mut result = vec![self.load_variable_to(Self::OPERAND_1, id)];
+                result.extend(self.push_into_stack(Self::OPERAND_1));
                 result
             }
             StackCommand::Store(id) => {
                 let mut pop = self.pop_from_stack_into(Self::OPERAND_1);
-                let mut save = self.save_variable_from(Self::OPERAND_1, id);
-                pop.append(&mut save);
+                pop.push(self.save_variable_from(Self::OPERAND_1, id));
                 pop
             }
             StackCommand::Const(num) => {
-                let mut result = self.load_const_to(Self::OPERAND_1, *num);
-                let mut push = self.push_into_stack(Self::OPERAND_1);
-                result.append(&mut push);
+                let mut result = vec![self.load_const_to(Self::OPERAND_1, *num)];
+                result.extend(self.push_into_stack(Self::OPERAND_1));
                 result
             }
-            StackCommand::Label(_) => LinkedList::new(),
-            StackCommand::Jmp(l) => {
-                LinkedList::from(transform_to_bytes(&Instr::J(JType::Jmp {
-                    address: self.get_label_address(l)
-                })).to_be_bytes())
-            }
-            StackCommand::ConditionalJump(Condition::EqualsZero, l) => {
-                // if equals, then jump
-                let pop = self.pop_from_stack_into(Self::OPERAND_1);
-                let branch = LinkedList::from(transform_to_bytes(&Instr::I(IType::Bne {
-                    rs: Self::OPERAND_1,
-                    rt: Self::ZERO,
-                    imm: 1,
-                })).to_be_bytes());
-                let jump = LinkedList::from(transform_to_bytes(&Instr::J(JType::Jmp {
-                    address: self.get_label_address(l)
-                })).to_be_bytes());
-                let mut result = LinkedList::new();
-                let order = [pop, branch, jump];
-                for mut instr in order {
-                    result.append(&mut instr);
+            StackCommand::Dup => self.duplicate_stack_top(),
+            StackCommand::Call(routine) => {
+                // The arguments are popped in the reverse order, see the calling convention in `runtime`
+                let mut result = Vec::new();
+                for register in [Self::ARGUMENT_1, Self::ARGUMENT_0].into_iter().skip(2 - routine.arity()) {
+                    result.extend(self.pop_from_stack_into(register).into_iter().map(|instr| self.item(instr)));
                 }
-                result
+                result.push(Item::Call(*routine));
+                result.extend(self.push_into_stack(Self::RESULT).into_iter().map(|instr| self.item(instr)));
+                return result;
             }
-            StackCommand::ConditionalJump(Condition::NotEqualsZero, l) => {
-                // if not equals, then jump
-                let pop = self.pop_from_stack_into(Self::OPERAND_1);
-                let branch = LinkedList::from(transform_to_bytes(&Instr::I(IType::Beq {
-                    rs: Self::OPERAND_1,
-                    rt: Self::ZERO,
-                    imm: 1,
-                })).to_be_bytes());
-                let jump = LinkedList::from(transform_to_bytes(&Instr::J(JType::Jmp {
-                    address: self.get_label_address(l)
-                })).to_be_bytes());
-                let mut result = LinkedList::new();
-                let order = [pop, branch, jump];
-                for mut instr in order {
-                    result.append(&mut instr);
-                }
-                result
+            StackCommand::Label(l) => return vec![Item::Label(*l)],
+            StackCommand::Location(span) => return vec![Item::Location(*span)],
+            StackCommand::Jmp(l) => return vec![Item::Jump(*l)],
+            StackCommand::ConditionalJump(condition, l) => {
+                let mut result: Vec<Item<Label>> = self.pop_from_stack_into(Self::OPERAND_1).into_iter().map(|instr| self.item(instr)).collect();
+                result.push(Item::ConditionalJump(*condition, Self::OPERAND_1, *l));
+                return result;
+            }
+        };
+        instructions.into_iter().map(|instr| self.item(instr)).collect()
+    }
+
+    /// In an object file the memory accesses from the zero register address the module data
+    fn item(&self, instr: Instr) -> Item<Label> {
+        match instr {
+            Instr::I(IType::Lw { rs, .. } | IType::Sw { rs, .. } | IType::Prints { rs, .. }) if rs == Self::ZERO && self.relocatable => {
+                Item::Relocated(instr, RelocationTarget::Data)
+            }
+            _ => Item::Instr(instr),
+        }
+    }
+
+    fn variables_offset(&self) -> i32 {
+        self.constants.len() as i32 * 4
+    }
+
+    fn collect_debug_variables(&mut self) {
+        let mut variables: Vec<(&Ident, &usize)> = self.variables.iter().collect();
+        variables.sort_by_key(|(_, index)| **index);
+        for (ident, index) in variables {
+            let address = self.variables_offset() as u32 + (*index as u32) * 4;
+            self.debug_info.add_variable(&ident.0, address);
+        }
+    }
+
+    /// Transforms the commands, the live intervals of the register variables are marked for the debug information
+    fn transform_commands(&self, program: &[StackCommand]) -> Vec<Item<Label>> {
+        let mut starts: HashMap<usize, Vec<&Ident>> = HashMap::new();
+        let mut ends: HashMap<usize, Vec<&Ident>> = HashMap::new();
+        let intervals = if self.variable_registers.is_empty() { vec![] } else { Liveness::analyze(program).intervals(program) };
+        for interval in intervals.iter().filter(|interval| self.variable_registers.contains_key(&interval.variable)) {
+            starts.entry(interval.start).or_default().push(&interval.variable);
+            ends.entry(interval.end).or_default().push(&interval.variable);
+        }
+        let mut items = Vec::new();
+        for (index, instr) in program.iter().enumerate() {
+            for ident in starts.get(&index).into_iter().flatten() {
+                items.push(Item::VariableStart(ident.0.clone(), self.variable_registers[*ident]));
+            }
+            items.extend(self.transform_instruction(instr));
+            for ident in ends.get(&index).into_iter().flatten() {
+                items.push(Item::VariableEnd(ident.0.clone()));
             }
         }
+        items
     }
 
-    fn get_loader_code(&self, variables: usize) -> LinkedList<u8> {
-        let variables_offset = self.constants_order.len() as i32 * 4;
-        let stack_offset = (variables + self.constants_order.len()) as i32 * 4;
-        let mut variables = self.load_const_to(Self::VARIABLE_LOAD_TMP, variables_offset);
-        let mut stack = self.load_const_to(Self::SP, stack_offset);
-        variables.append(&mut stack);
-        variables
+    fn get_loader_code(&self, variables: usize) -> Vec<Instr> {
+        let variables_offset = self.variables_offset();
+        let stack_offset = (variables + self.constants.len()) as i32 * 4;
+        vec![
+            self.load_const_to(Self::VARIABLE_LOAD_TMP, variables_offset),
+            self.load_const_to(Self::SP, stack_offset),
+        ]
     }
 
-    pub fn transform_program(&mut self, program: &Vec<StackCommand>) -> (Vec<u8>, Vec<u8>) {
+    /// Compiles the program and returns the memory and the code, fails if the labels can't be resolved
+    pub fn transform_program(&mut self, program: &Vec<StackCommand>) -> Result<(Vec<u8>, Vec<u8>), LinkError> {
+        if self.allocate_registers {
+            self.variable_registers = allocation::allocate_registers(program, &Self::VARIABLE_REGISTERS);
+        }
         let variables = self.number_of_identifiers(program);
         self.collect_constants(program);
-        self.force_push_constant(&((self.constants_order.len() + 2 + variables) as i32 * 4)); // Stack offset
-        self.force_push_constant(&((self.constants_order.len() as i32 + 1) * 4)); // variables offset
+        self.collect_strings(program);
+        if self.syscalls {
+            self.collect_syscall_constants(program);
+        }
+        self.constants.push((self.constants.len() + 2 + variables) as i32 * 4); // Stack offset
+        self.constants.push((self.constants.len() as i32 + 1) * 4); // variables offset
         self.collect_identifiers(program);
-        self.collect_labels(program);
-        let mut constants_result = LinkedList::new();
-        for constant in &self.constants_order {
-            constants_result.append(&mut LinkedList::from(constant.to_be_bytes()))
+        check_offsets(self.constants.len())?;
+        check_offsets(self.variables.len())?;
+        let constants_result = self.constants.to_bytes();
+        self.collect_debug_variables();
+        let mut items: Vec<Item<Label>> = self.get_loader_code(variables).into_iter().map(Item::Instr).collect();
+        items.extend(self.transform_commands(program));
+        if self.syscalls {
+            items.extend(self.syscall(Service::Exit).into_iter().map(Item::Instr));
         }
-        let mut code_result = self.get_loader_code(variables);
-        for instr in program {
-            code_result.append(&mut self.transform_instruction(instr))
+        let code_result = self.linker.link(&items, &mut self.debug_info)?;
+        Ok((constants_result, code_result))
+    }
+
+    /// Compiles the module into an object file for [`object::link_objects`], the stack pointer is set by the linker
+    pub fn transform_object(&mut self, program: &Vec<StackCommand>, module: &ModuleInterface) -> Result<Object, LinkError> {
+        assert!(!self.syscalls, "Object files are compiled without syscalls");
+        self.relocatable = true;
+        self.externals = module.externals.clone();
+        if self.allocate_registers {
+            // Other modules access the exported and the imported variables in the memory
+            self.variable_registers = allocation::allocate_registers(program, &Self::VARIABLE_REGISTERS);
+            self.variable_registers.retain(|id, _| !module.exports.contains(id) && !module.externals.contains(id));
+        }
+        for id in &module.exports {
+            self.externals.remove(id);
+        }
+        self.collect_constants(program);
+        self.collect_strings(program);
+        self.collect_identifiers(program);
+        for id in &module.exports {
+            self.push_identifier(id);
         }
-        (constants_result.into_iter().collect(), code_result.into_iter().collect())
+        self.collect_debug_variables();
+        let items = self.transform_commands(program);
+        // The code of an object starts at the zero address, `link_objects` moves it into the text segment
+        let (code, relocations) = Linker::with_load_address(0).link_relocatable(&items, &mut self.debug_info)?;
+        let mut data = self.constants.to_bytes();
+        data.resize(data.len() + self.variables.len() * 4, 0);
+        let symbols = module.exports.iter()
+            .map(|id| Symbol { name: id.0.clone(), offset: self.variables_offset() as u32 + self.variables[id] as u32 * 4 })
+            .collect();
+        Ok(Object { name: module.name.clone(), imports: module.imports.clone(), code, data, symbols, relocations })
+    }
+
+    /// Debug information of the last transformed program
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug_info
     }
 }
 
@@ -0,0 +1,364 @@
+//! Relocatable object files of separately compiled Klang modules and linking them into one program.
+//!
+//! An object keeps the code and the data of a module as if both were placed at the zero address, together with
+//! the exported variables and the relocations: the instructions whose fields change when the module is moved.
+//! [`link_objects`] places the modules one after another, the imported modules first, so each module is run
+//! after the ones it imports. The stack pointer is set by a single `lw` before the first module, and `halt $0`
+//! follows the last one.
+//!
+//! Text format is line based, each line is either `module <name>`, `import <name>`, `export <name> <offset>`,
+//! `code <word>`, `data <word>` or `reloc <offset> data|code|symbol <name>`, words are 8 hex digits.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+use crate::binary::instructions::{Instr, IType, RType, transform_to_bytes};
+use crate::binary::link::{INSTRUCTION_SIZE, LinkError, Linker};
+use crate::parser::ast::Ident;
+
+/// What the compiler knows about a module besides its program
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ModuleInterface {
+    /// File name of the module, imports refer to the modules by it
+    pub name: String,
+    pub imports: Vec<String>,
+    pub exports: Vec<Ident>,
+    /// Variables exported by the imported modules
+    pub externals: HashSet<Ident>,
+}
+
+/// What the relocated field of the instruction is relative to
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum RelocationTarget {
+    /// Offset of `lw`/`sw` from the start of the module data
+    Data,
+    /// Target of `j` from the start of the module code
+    Code,
+    /// Offset of `lw`/`sw` from the variable exported by another module
+    Symbol(String),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Relocation {
+    /// Offset of the instruction in the module code
+    pub offset: u32,
+    pub target: RelocationTarget,
+}
+
+/// Exported variable at the offset in the module data
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub offset: u32,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Object {
+    pub name: String,
+    pub imports: Vec<String>,
+    pub code: Vec<u8>,
+    pub data: Vec<u8>,
+    pub symbols: Vec<Symbol>,
+    pub relocations: Vec<Relocation>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseObjectError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ParseObjectError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid object file at line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseObjectError {}
+
+fn words(bytes: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    bytes.chunks(4).map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+}
+
+impl Object {
+    pub fn to_text(&self) -> String {
+        let mut result = format!("module {}\n", self.name);
+        for import in &self.imports {
+            result.push_str(&format!("import {}\n", import));
+        }
+        for symbol in &self.symbols {
+            result.push_str(&format!("export {} {}\n", symbol.name, symbol.offset));
+        }
+        for word in words(&self.code) {
+            result.push_str(&format!("code {:08x}\n", word));
+        }
+        for word in words(&self.data) {
+            result.push_str(&format!("data {:08x}\n", word));
+        }
+        for relocation in &self.relocations {
+            match &relocation.target {
+                RelocationTarget::Data => result.push_str(&format!("reloc {} data\n", relocation.offset)),
+                RelocationTarget::Code => result.push_str(&format!("reloc {} code\n", relocation.offset)),
+                RelocationTarget::Symbol(name) => result.push_str(&format!("reloc {} symbol {}\n", relocation.offset, name)),
+            }
+        }
+        result
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, ParseObjectError> {
+        let mut result = Self::default();
+        let mut has_name = false;
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: &str| ParseObjectError { line: index + 1, message: message.to_string() };
+            let word = |word: &str| u32::from_str_radix(word, 16).map_err(|_| error("invalid word"));
+            let offset = |offset: &str| offset.parse::<u32>()
+                .ok()
+                .filter(|offset| offset % INSTRUCTION_SIZE == 0)
+                .ok_or_else(|| error("invalid offset"));
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.as_slice() {
+                [] => {}
+                ["module", name] if !has_name => {
+                    result.name = name.to_string();
+                    has_name = true;
+                }
+                ["import", name] => result.imports.push(name.to_string()),
+                ["export", name, symbol_offset] => {
+                    result.symbols.push(Symbol { name: name.to_string(), offset: offset(symbol_offset)? })
+                }
+                ["code", instr] => result.code.extend(word(instr)?.to_be_bytes()),
+                ["data", value] => result.data.extend(word(value)?.to_be_bytes()),
+                ["reloc", instr, target @ ..] => {
+                    let target = match target {
+                        ["data"] => RelocationTarget::Data,
+                        ["code"] => RelocationTarget::Code,
+                        ["symbol", name] => RelocationTarget::Symbol(name.to_string()),
+                        _ => return Err(error("expected `data`, `code` or `symbol <name>` relocation")),
+                    };
+                    result.relocations.push(Relocation { offset: offset(instr)?, target })
+                }
+                _ => return Err(error("expected `module`, `import`, `export`, `code`, `data` or `reloc` line"))
+            }
+        }
+        if !has_name {
+            return Err(ParseObjectError { line: 1, message: String::from("missing `module <name>` line") });
+        }
+        if let Some(relocation) = result.relocations.iter().find(|relocation| relocation.offset as usize >= result.code.len()) {
+            return Err(ParseObjectError { line: 1, message: format!("relocation at {} is out of the code", relocation.offset) });
+        }
+        Ok(result)
+    }
+}
+
+/// Indices of the objects in the order of running, every module is placed after the modules it imports
+fn initialization_order(objects: &[Object]) -> Result<Vec<usize>, LinkError> {
+    let mut indices = HashMap::new();
+    for (index, object) in objects.iter().enumerate() {
+        if indices.insert(object.name.as_str(), index).is_some() {
+            return Err(LinkError::DuplicateModule(object.name.clone()));
+        }
+    }
+    fn visit(
+        index: usize,
+        objects: &[Object],
+        indices: &HashMap<&str, usize>,
+        visiting: &mut HashSet<usize>,
+        order: &mut Vec<usize>,
+    ) -> Result<(), LinkError> {
+        if order.contains(&index) {
+            return Ok(());
+        }
+        if !visiting.insert(index) {
+            return Err(LinkError::ImportCycle(objects[index].name.clone()));
+        }
+        for import in &objects[index].imports {
+            let imported = *indices.get(import.as_str()).ok_or_else(|| LinkError::MissingModule {
+                module: import.clone(),
+                imported_by: objects[index].name.clone(),
+            })?;
+            visit(imported, objects, indices, visiting, order)?;
+        }
+        visiting.remove(&index);
+        order.push(index);
+        Ok(())
+    }
+    let mut order = Vec::new();
+    for index in 0..objects.len() {
+        visit(index, objects, &indices, &mut HashSet::new(), &mut order)?;
+    }
+    Ok(order)
+}
+
+fn data_offset(address: u64, module: &str) -> Result<u16, LinkError> {
+    if address > i16::MAX as u64 {
+        return Err(LinkError::DataOutOfRange { module: module.to_string(), address });
+    }
+    Ok(address as u16)
+}
+
+/// Links the objects into the memory and the code for the emulator
+pub fn link_objects(objects: &[Object]) -> Result<(Vec<u8>, Vec<u8>), LinkError> {
+    const SP: u8 = 29;
+    let order = initialization_order(objects)?;
+
+    let load_address = Linker::DEFAULT_LOAD_ADDRESS as u64;
+    let startup_size = INSTRUCTION_SIZE as u64;
+    let (mut code_bases, mut data_bases) = (HashMap::new(), HashMap::new());
+    let (mut code_end, mut data_size) = (load_address + startup_size, 0u64);
+    for &index in &order {
+        code_bases.insert(index, code_end);
+        data_bases.insert(index, data_size);
+        code_end += objects[index].code.len() as u64;
+        data_size += objects[index].data.len() as u64;
+    }
+
+    let mut symbols: HashMap<&str, (u64, &str)> = HashMap::new();
+    for &index in &order {
+        let object = &objects[index];
+        for symbol in &object.symbols {
+            let address = data_bases[&index] + symbol.offset as u64;
+            if let Some((_, first)) = symbols.insert(&symbol.name, (address, &object.name)) {
+                return Err(LinkError::DuplicateSymbol {
+                    symbol: symbol.name.clone(),
+                    first: first.to_string(),
+                    second: object.name.clone(),
+                });
+            }
+        }
+    }
+
+    // The stack starts right after the word keeping its address
+    let stack_word = data_size;
+    let startup = Instr::I(IType::Lw { rs: 0, rt: SP, imm: data_offset(stack_word, "the startup code")? });
+    let mut code: Vec<u8> = transform_to_bytes(&startup).to_be_bytes().to_vec();
+    let mut memory = Vec::new();
+    for &index in &order {
+        let object = &objects[index];
+        let mut module_code = object.code.clone();
+        for relocation in &object.relocations {
+            let offset = relocation.offset as usize;
+            let instr = u32::from_be_bytes(module_code[offset..offset + 4].try_into().unwrap());
+            let relocated = match &relocation.target {
+                RelocationTarget::Data | RelocationTarget::Symbol(_) => {
+                    let base = match &relocation.target {
+                        RelocationTarget::Symbol(name) => symbols.get(name.as_str()).ok_or_else(|| LinkError::UndefinedSymbol {
+                            symbol: name.clone(),
+                            module: object.name.clone(),
+                        })?.0,
+                        _ => data_bases[&index],
+                    };
+                    let address = base + (instr & 0xffff) as u64;
+                    (instr & !0xffff) | data_offset(address, &object.name)? as u32
+                }
+                RelocationTarget::Code => {
+                    let from = code_bases[&index] + offset as u64;
+                    let target = code_bases[&index] + ((instr & 0x3ff_ffff) << 2) as u64;
+                    if (from + INSTRUCTION_SIZE as u64) >> 28 != target >> 28 {
+                        return Err(LinkError::JumpOutOfRange { from, target });
+                    }
+                    (instr & !0x3ff_ffff) | ((target >> 2) & 0x3ff_ffff) as u32
+                }
+            };
+            module_code[offset..offset + 4].copy_from_slice(&relocated.to_be_bytes());
+        }
+        code.extend(module_code);
+        memory.extend(&object.data);
+    }
+    // The modules are not ended, the last one continues here
+    code.extend(transform_to_bytes(&Instr::R(RType::halt(0))).to_be_bytes());
+    memory.extend(((stack_word + 4) as u32).to_be_bytes());
+    if load_address + code.len() as u64 > u32::MAX as u64 {
+        return Err(LinkError::CodeTooLarge { load_address: load_address as u32, size: code.len() as u64 });
+    }
+    Ok((memory, code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(name: &str, imports: &[&str]) -> Object {
+        Object { name: name.to_string(), imports: imports.iter().map(|import| import.to_string()).collect(), ..Object::default() }
+    }
+
+    fn words(bytes: &[u8]) -> Vec<u32> {
+        super::words(bytes).collect()
+    }
+
+    #[test]
+    fn test_text() {
+        let object = Object {
+            name: String::from("main.k"),
+            imports: vec![String::from("util.k")],
+            code: vec![0x88, 0x08, 0x00, 0x04, 0x08, 0x00, 0x00, 0x00],
+            data: vec![0, 0, 0, 4, 0, 0, 0, 0],
+            symbols: vec![Symbol { name: String::from("total"), offset: 4 }],
+            relocations: vec![
+                Relocation { offset: 0, target: RelocationTarget::Symbol(String::from("limit")) },
+                Relocation { offset: 4, target: RelocationTarget::Code },
+            ],
+        };
+        let text = object.to_text();
+        assert!(text.contains("code 88080004\n"));
+        assert!(text.contains("reloc 0 symbol limit\n"));
+        assert_eq!(Ok(object), Object::from_text(&text));
+        assert_eq!(2, Object::from_text("module a\nreloc 3 data").unwrap_err().line);
+        assert!(Object::from_text("module a\nreloc 0 data").is_err());
+        assert!(Object::from_text("import a").is_err());
+    }
+
+    #[test]
+    fn test_relocations() {
+        let mut util = object("util.k", &[]);
+        // lw $8, 0($0); j 0
+        util.code = [0x8808_0000u32, 0x0800_0000].iter().flat_map(|word| word.to_be_bytes()).collect();
+        util.data = vec![0, 0, 0, 7];
+        util.symbols.push(Symbol { name: String::from("x"), offset: 0 });
+        util.relocations = vec![Relocation { offset: 0, target: RelocationTarget::Data }, Relocation { offset: 4, target: RelocationTarget::Code }];
+        let mut main = object("main.k", &["util.k"]);
+        // lw $8, 4($0); sw $8, 0($0)
+        main.code = [0x8808_0004u32, 0xac08_0000].iter().flat_map(|word| word.to_be_bytes()).collect();
+        main.data = vec![0; 8];
+        main.relocations = vec![
+            Relocation { offset: 0, target: RelocationTarget::Data },
+            Relocation { offset: 4, target: RelocationTarget::Symbol(String::from("x")) },
+        ];
+        // The imported module is placed first regardless of the order of the objects
+        let (memory, code) = link_objects(&[main, util]).unwrap();
+        // The code is placed at the default load address, the jump goes to the second word of it
+        let j = 0x0800_0000 | ((Linker::DEFAULT_LOAD_ADDRESS + 4) >> 2);
+        assert_eq!(vec![0x881d_000c, 0x8808_0000, j, 0x8808_0008, 0xac08_0000, 0x0000_000d], words(&code));
+        assert_eq!(vec![7, 0, 0, 0x10], words(&memory));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut user = object("main.k", &["util.k"]);
+        user.code = vec![0; 4];
+        user.relocations.push(Relocation { offset: 0, target: RelocationTarget::Symbol(String::from("x")) });
+        assert_eq!(
+            Err(LinkError::MissingModule { module: String::from("util.k"), imported_by: String::from("main.k") }),
+            link_objects(&[user.clone()])
+        );
+        assert_eq!(
+            Err(LinkError::UndefinedSymbol { symbol: String::from("x"), module: String::from("main.k") }),
+            link_objects(&[user, object("util.k", &[])])
+        );
+        let mut first = object("a.k", &[]);
+        first.symbols.push(Symbol { name: String::from("x"), offset: 0 });
+        let mut second = object("b.k", &["a.k"]);
+        second.symbols.push(Symbol { name: String::from("x"), offset: 4 });
+        assert_eq!(
+            Err(LinkError::DuplicateSymbol { symbol: String::from("x"), first: String::from("a.k"), second: String::from("b.k") }),
+            link_objects(&[second, first])
+        );
+        assert_eq!(Err(LinkError::DuplicateModule(String::from("a.k"))), link_objects(&[object("a.k", &[]), object("a.k", &[])]));
+        assert_eq!(
+            Err(LinkError::ImportCycle(String::from("a.k"))),
+            link_objects(&[object("a.k", &["b.k"]), object("b.k", &["a.k"])])
+        );
+        let mut large = object("large.k", &[]);
+        large.data = vec![0; 0x8000];
+        assert!(matches!(link_objects(&[large]), Err(LinkError::DataOutOfRange { .. })));
+    }
+}
@@ -0,0 +1,337 @@
+//! Backend compiling the AST directly into MIPS code, expressions are evaluated in registers.
+//!
+//! Operands are ordered with the Sethi–Ullman numbering, so an expression needs as few registers as possible.
+//! If the registers run out, intermediate values are spilled into the memory stack.
+//! The memory layout is the same as in [`SMTransformer`](crate::binary::SMTransformer): constants, variables, stack.
+
+use std::collections::HashMap;
+
+use crate::binary::data::{check_offsets, word_offset, ConstantPool};
+use crate::binary::debug_info::DebugInfo;
+use crate::binary::instructions::{Arithmetic, Instr, IType, RType};
+use crate::binary::link::{Item, LinkError, Linker};
+use crate::lexer::tokens::Span;
+use crate::parser::ast::{Block, Expr, Ident, PrefixOps, Stmt};
+use crate::stack_machine::sm::{Condition, Label};
+
+#[derive(Clone, Debug)]
+pub struct RegisterTransformer {
+    // Constants and string literals
+    constants: ConstantPool,
+    variables: HashMap<Ident, usize>,
+    variables_order: Vec<Ident>,
+    // Registers for the intermediate values of the expressions
+    temporaries: Vec<u8>,
+    code: Vec<Item<Label>>,
+    last_label: i32,
+    debug_info: DebugInfo,
+    arithmetic: Arithmetic,
+}
+
+impl Default for RegisterTransformer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Sethi–Ullman number: the number of registers needed to evaluate the expression without spilling
+fn registers_needed(expr: &Expr) -> usize {
+    match expr {
+        Expr::IntLiteral(_) | Expr::Var(_) => 1,
+        Expr::PrefixOperation(_, expr) => registers_needed(expr),
+        Expr::InfixOperation(lhs, _, rhs) => operands_needed(lhs, rhs),
+        Expr::Call(_, args) => match args.as_slice() {
+            [lhs, rhs] => operands_needed(lhs, rhs),
+            args => args.iter().map(registers_needed).max().unwrap_or(1),
+        },
+    }
+}
+
+fn operands_needed(lhs: &Expr, rhs: &Expr) -> usize {
+    let (lhs, rhs) = (registers_needed(lhs), registers_needed(rhs));
+    if lhs == rhs { lhs + 1 } else { lhs.max(rhs) }
+}
+
+impl RegisterTransformer {
+    const ZERO: u8 = 0;
+    // Always holds 4, the size of the stack slot
+    const STACK_INCREMENT: u8 = 1;
+    const VARIABLES: u8 = 28;
+    const SP: u8 = 29;
+    const ARGUMENT_0: u8 = 4;
+    const ARGUMENT_1: u8 = 5;
+    const RESULT: u8 = 2;
+    const TEMPORARIES: [u8; 10] = [8, 9, 10, 11, 12, 13, 14, 15, 24, 25];
+    const BOOT_CODE_SIZE: usize = 3;
+
+    pub fn new() -> Self {
+        Self::with_register_limit(Self::TEMPORARIES.len())
+    }
+
+    /// Compiles `+`, `-` and unary minus with the overflow handling
+    pub fn with_arithmetic(self, arithmetic: Arithmetic) -> Self {
+        Self { arithmetic, ..self }
+    }
+
+    /// Uses only `limit` registers for the expressions, at least two are needed for a binary operation
+    pub fn with_register_limit(limit: usize) -> Self {
+        assert!((2..=Self::TEMPORARIES.len()).contains(&limit), "Register limit should be in 2..={}", Self::TEMPORARIES.len());
+        let mut res = Self {
+            constants: ConstantPool::new(),
+            variables: HashMap::new(),
+            variables_order: Vec::new(),
+            temporaries: Self::TEMPORARIES[..limit].to_vec(),
+            code: Vec::new(),
+            last_label: 0,
+            debug_info: DebugInfo::new(),
+            arithmetic: Arithmetic::default(),
+        };
+        res.constants.constant(4);
+        res
+    }
+
+    fn variable_offset(&mut self, ident: &Ident) -> u16 {
+        let next = self.variables_order.len();
+        let index = *self.variables.entry(ident.clone()).or_insert(next);
+        if index == next {
+            self.variables_order.push(ident.clone());
+        }
+        word_offset(index)
+    }
+
+    fn generate_label(&mut self) -> Label {
+        let label = Label { id: self.last_label };
+        self.last_label += 1;
+        label
+    }
+
+    fn emit(&mut self, instr: Instr) {
+        self.code.push(Item::Instr(instr))
+    }
+
+    fn emit_r_type(&mut self, funct: u8, rs: u8, rt: u8, rd: u8) {
+        self.emit(Instr::R(RType { rs, rt, rd, funct }))
+    }
+
+    fn push_into_stack(&mut self, reg: u8) {
+        self.emit(Instr::I(IType::Sw { rs: Self::SP, rt: reg, imm: 0 }));
+        self.emit_r_type(RType::ADDU, Self::SP, Self::STACK_INCREMENT, Self::SP);
+    }
+
+    fn pop_from_stack_into(&mut self, reg: u8) {
+        self.emit_r_type(RType::SUBU, Self::SP, Self::STACK_INCREMENT, Self::SP);
+        self.emit(Instr::I(IType::Lw { rs: Self::SP, rt: reg, imm: 0 }));
+    }
+
+    /// Evaluates the expression into `registers[0]`, the other registers may be overwritten
+    fn transform_expr(&mut self, expr: &Expr, registers: &[u8]) {
+        let target = registers[0];
+        match expr {
+            Expr::IntLiteral(x) => {
+                let imm = word_offset(self.constants.constant(*x));
+                self.emit(Instr::I(IType::Lw { rs: Self::ZERO, rt: target, imm }))
+            }
+            Expr::Var(id) => {
+                let imm = self.variable_offset(id);
+                self.emit(Instr::I(IType::Lw { rs: Self::VARIABLES, rt: target, imm }))
+            }
+            Expr::PrefixOperation(op, expr) => {
+                self.transform_expr(expr, registers);
+                match op {
+                    PrefixOps::BitwiseNot => self.emit_r_type(39, target, Self::ZERO, target),
+                    PrefixOps::UnaryMinus => self.emit_r_type(self.arithmetic.sub(), Self::ZERO, target, target),
+                }
+            }
+            Expr::InfixOperation(lhs, op, rhs) => {
+                let (lhs_reg, rhs_reg) = self.transform_operands(lhs, rhs, registers);
+                self.emit_r_type(self.arithmetic.funct(op), lhs_reg, rhs_reg, target)
+            }
+            Expr::Call(routine, args) => {
+                // The routines keep the temporaries, see the calling convention in `runtime`
+                match args.as_slice() {
+                    [lhs, rhs] => {
+                        let (lhs_reg, rhs_reg) = self.transform_operands(lhs, rhs, registers);
+                        self.emit_r_type(37, lhs_reg, Self::ZERO, Self::ARGUMENT_0);
+                        self.emit_r_type(37, rhs_reg, Self::ZERO, Self::ARGUMENT_1);
+                    }
+                    [arg] => {
+                        self.transform_expr(arg, registers);
+                        self.emit_r_type(37, target, Self::ZERO, Self::ARGUMENT_0);
+                    }
+                    _ => unreachable!("The parser checks the number of arguments of {}", routine.name()),
+                }
+                self.code.push(Item::Call(*routine));
+                self.emit_r_type(37, Self::RESULT, Self::ZERO, target)
+            }
+        }
+    }
+
+    /// Evaluates both operands and returns their registers, `registers[0]` is one of them
+    fn transform_operands(&mut self, lhs: &Expr, rhs: &Expr, registers: &[u8]) -> (u8, u8) {
+        // The operand needing more registers is evaluated first, while all registers are free
+        let rhs_first = registers_needed(rhs) > registers_needed(lhs);
+        let (first, second) = if rhs_first { (rhs, lhs) } else { (lhs, rhs) };
+        let (first_reg, second_reg) = if registers_needed(second) < registers.len() {
+            self.transform_expr(first, registers);
+            self.transform_expr(second, &registers[1..]);
+            (registers[0], registers[1])
+        } else {
+            self.transform_expr(first, registers);
+            self.push_into_stack(registers[0]);
+            self.transform_expr(second, registers);
+            self.pop_from_stack_into(registers[1]);
+            (registers[1], registers[0])
+        };
+        if rhs_first { (second_reg, first_reg) } else { (first_reg, second_reg) }
+    }
+
+    /// Evaluates the condition and jumps to the label if the condition holds for its value
+    fn transform_condition_jump(&mut self, condition: &Expr, jump_if: Condition, label: Label) {
+        let registers = self.temporaries.clone();
+        self.transform_expr(condition, &registers);
+        self.code.push(Item::ConditionalJump(jump_if, registers[0], label))
+    }
+
+    fn transform_stmt(&mut self, stmt: &Stmt, span: Span) {
+        let registers = self.temporaries.clone();
+        match stmt {
+            Stmt::VarDeclaration(_) => {}
+            Stmt::VarAssign(id, expr) => {
+                self.code.push(Item::Location(span));
+                self.transform_expr(expr, &registers);
+                let imm = self.variable_offset(id);
+                self.emit(Instr::I(IType::Sw { rs: Self::VARIABLES, rt: registers[0], imm }))
+            }
+            Stmt::Print(expr) => {
+                self.code.push(Item::Location(span));
+                self.transform_expr(expr, &registers);
+                self.emit_r_type(0, registers[0], 0, 0)
+            }
+            Stmt::PrintChar(expr) => {
+                self.code.push(Item::Location(span));
+                self.transform_expr(expr, &registers);
+                self.emit_r_type(1, registers[0], 0, 0)
+            }
+            Stmt::PrintString(value) => {
+                self.code.push(Item::Location(span));
+                let imm = word_offset(self.constants.string(value));
+                self.emit(Instr::I(IType::Prints { rs: Self::ZERO, imm }))
+            }
+            Stmt::If { condition, true_branch, false_branch } => {
+                self.code.push(Item::Location(span));
+                let false_label = self.generate_label();
+                self.transform_condition_jump(condition, Condition::EqualsZero, false_label);
+                self.transform_block(true_branch);
+                match false_branch {
+                    Some(false_branch) => {
+                        let end_label = self.generate_label();
+                        self.code.push(Item::Jump(end_label));
+                        self.code.push(Item::Label(false_label));
+                        self.transform_block(false_branch);
+                        self.code.push(Item::Label(end_label));
+                    }
+                    None => self.code.push(Item::Label(false_label)),
+                }
+            }
+            Stmt::While(condition, body) => {
+                let body_label = self.generate_label();
+                let condition_label = self.generate_label();
+                self.code.push(Item::Location(span));
+                self.code.push(Item::Jump(condition_label));
+                self.code.push(Item::Label(body_label));
+                self.transform_block(body);
+                self.code.push(Item::Label(condition_label));
+                self.code.push(Item::Location(span));
+                self.transform_condition_jump(condition, Condition::NotEqualsZero, body_label);
+            }
+        }
+    }
+
+    fn transform_block(&mut self, block: &Block) {
+        for stmt in block {
+            self.transform_stmt(&stmt.node, stmt.span)
+        }
+    }
+
+    fn variables_offset(&self) -> i32 {
+        self.constants.len() as i32 * 4
+    }
+
+    fn boot_code(&mut self) -> [Instr; Self::BOOT_CODE_SIZE] {
+        // Same as in the stack backend, the offsets are forced to be the last constants
+        let constants = self.constants.len();
+        let variables = self.variables_order.len();
+        let stack_index = self.constants.push(((constants + 2 + variables) * 4) as i32);
+        let variables_index = self.constants.push(((constants + 2) * 4) as i32);
+        let four = word_offset(self.constants.index(4));
+        [
+            Instr::I(IType::Lw { rs: Self::ZERO, rt: Self::STACK_INCREMENT, imm: four }),
+            Instr::I(IType::Lw { rs: Self::ZERO, rt: Self::VARIABLES, imm: word_offset(variables_index) }),
+            Instr::I(IType::Lw { rs: Self::ZERO, rt: Self::SP, imm: word_offset(stack_index) }),
+        ]
+    }
+
+    /// Compiles the program and returns the memory and the code, as `SMTransformer::transform_program` does
+    pub fn transform_program(&mut self, program: &Block) -> Result<(Vec<u8>, Vec<u8>), LinkError> {
+        self.transform_block(program);
+        let mut items: Vec<Item<Label>> = self.boot_code().into_iter().map(Item::Instr).collect();
+        items.append(&mut self.code);
+        check_offsets(self.constants.len())?;
+        check_offsets(self.variables_order.len())?;
+        let code = Linker::new().link(&items, &mut self.debug_info)?;
+        for (index, ident) in self.variables_order.iter().enumerate() {
+            let address = self.variables_offset() as u32 + index as u32 * 4;
+            self.debug_info.add_variable(&ident.0, address);
+        }
+        let memory = self.constants.to_bytes();
+        Ok((memory, code))
+    }
+
+    /// Debug information of the last transformed program
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug_info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_source;
+    use super::*;
+
+    fn parse_expr(source: &str) -> Expr {
+        let parsed = parse_source(&format!("{{ x = {} }}", source));
+        match &parsed[0].node {
+            Stmt::VarAssign(_, expr) => expr.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn spills(transformer: &RegisterTransformer) -> usize {
+        transformer.code.iter()
+            .filter(|item| matches!(item, Item::Instr(Instr::I(IType::Sw { rs: RegisterTransformer::SP, .. }))))
+            .count()
+    }
+
+    #[test]
+    fn test_registers_needed() {
+        assert_eq!(1, registers_needed(&parse_expr("a")));
+        assert_eq!(2, registers_needed(&parse_expr("a + 1")));
+        assert_eq!(2, registers_needed(&parse_expr("((a + 1) - b) & c")));
+        assert_eq!(3, registers_needed(&parse_expr("(a + 1) - (b | c)")));
+        assert_eq!(3, registers_needed(&parse_expr("a - ((a + 1) - (b | c))")));
+    }
+
+    #[test]
+    fn test_spilling() {
+        let expr = parse_expr("(a + 1) - ((b | c) & (d + 2))");
+        let mut transformer = RegisterTransformer::new();
+        transformer.transform_expr(&expr, &RegisterTransformer::TEMPORARIES);
+        assert_eq!(0, spills(&transformer));
+        // 6 loads and 5 operations
+        assert_eq!(11, transformer.code.len());
+
+        let mut transformer = RegisterTransformer::with_register_limit(2);
+        transformer.transform_expr(&expr, &RegisterTransformer::TEMPORARIES[..2]);
+        assert_eq!(2, spills(&transformer));
+    }
+}
@@ -0,0 +1,254 @@
+//! Runtime routines of the Klang standard library, see [`Routine`].
+//!
+//! The processor has no multiplication or division, so the calls compile to `jal` into the routines written
+//! here, and the [`Linker`](crate::binary::link::Linker) appends the code of every called routine to the program.
+//! The routines follow one calling convention for all the backends:
+//! - the arguments are passed in `$4` and `$5`, the result is returned in `$2`;
+//! - the return address is in `$31`, a routine returns with `jr $31`;
+//! - `$2`-`$7`, `$26` and `$27` may be changed by the routine, all other registers are preserved.
+//!
+//! The code is position independent apart from the jumps, which are resolved by the linker as usual.
+
+use std::fmt::{Display, Formatter};
+
+use crate::binary::instructions::{Instr, RType};
+use crate::binary::link::Item;
+use crate::parser::ast::Routine;
+use crate::stack_machine::sm::Condition;
+
+/// Label inside the code of a routine, the label with the zero id is the entry point
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct RuntimeLabel {
+    pub routine: Routine,
+    pub id: u8,
+}
+
+impl RuntimeLabel {
+    pub fn entry(routine: Routine) -> Self {
+        Self { routine, id: 0 }
+    }
+}
+
+impl Display for RuntimeLabel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.id {
+            0 => write!(f, "{}", self.routine.name()),
+            id => write!(f, "{}.{}", self.routine.name(), id),
+        }
+    }
+}
+
+const RESULT: u8 = 2;
+const REMAINDER: u8 = 3;
+const LHS: u8 = 4;
+const RHS: u8 = 5;
+const TMP: u8 = 6;
+const MASK: u8 = 7;
+const LHS_SIGN: u8 = 26;
+const RHS_SIGN: u8 = 27;
+const RETURN_ADDRESS: u8 = 31;
+
+/// Label before the return of the routines with a single branch
+const DONE: u8 = 1;
+
+struct Assembler {
+    routine: Routine,
+    items: Vec<Item<RuntimeLabel>>,
+}
+
+impl Assembler {
+    fn new(routine: Routine) -> Self {
+        Self { routine, items: vec![Item::Label(RuntimeLabel::entry(routine))] }
+    }
+
+    fn label(&self, id: u8) -> RuntimeLabel {
+        RuntimeLabel { routine: self.routine, id }
+    }
+
+    fn r_type(&mut self, funct: u8, rd: u8, rs: u8, rt: u8) {
+        self.items.push(Item::Instr(Instr::R(RType { rs, rt, rd, funct })))
+    }
+
+    fn add(&mut self, rd: u8, rs: u8, rt: u8) {
+        self.r_type(RType::ADDU, rd, rs, rt)
+    }
+
+    fn sub(&mut self, rd: u8, rs: u8, rt: u8) {
+        self.r_type(RType::SUBU, rd, rs, rt)
+    }
+
+    fn and(&mut self, rd: u8, rs: u8, rt: u8) {
+        self.r_type(36, rd, rs, rt)
+    }
+
+    fn nor(&mut self, rd: u8, rs: u8, rt: u8) {
+        self.r_type(39, rd, rs, rt)
+    }
+
+    fn slt(&mut self, rd: u8, rs: u8, rt: u8) {
+        self.r_type(42, rd, rs, rt)
+    }
+
+    fn mov(&mut self, rd: u8, rs: u8) {
+        self.r_type(37, rd, rs, 0)
+    }
+
+    fn one(&mut self, rd: u8) {
+        self.nor(rd, 0, 0);
+        self.sub(rd, 0, rd);
+    }
+
+    fn jz(&mut self, rs: u8, id: u8) {
+        self.items.push(Item::ConditionalJump(Condition::EqualsZero, rs, self.label(id)))
+    }
+
+    fn jnz(&mut self, rs: u8, id: u8) {
+        self.items.push(Item::ConditionalJump(Condition::NotEqualsZero, rs, self.label(id)))
+    }
+
+    fn jmp(&mut self, id: u8) {
+        self.items.push(Item::Jump(self.label(id)))
+    }
+
+    fn place(&mut self, id: u8) {
+        self.items.push(Item::Label(self.label(id)))
+    }
+
+    fn ret(&mut self) {
+        self.r_type(8, 0, RETURN_ADDRESS, 0)
+    }
+}
+
+/// Adds the left operand shifted by the bit number for every set bit of the right one
+fn mul(code: &mut Assembler) {
+    const LOOP: u8 = 2;
+    const SKIP: u8 = 3;
+    code.mov(RESULT, 0);
+    code.one(MASK);
+    code.place(LOOP);
+    code.jz(RHS, DONE);
+    code.and(TMP, RHS, MASK);
+    code.jz(TMP, SKIP);
+    code.add(RESULT, RESULT, LHS);
+    // Clears the processed bit, so the loop stops after the highest set bit
+    code.sub(RHS, RHS, TMP);
+    code.place(SKIP);
+    code.add(LHS, LHS, LHS);
+    code.add(MASK, MASK, MASK);
+    code.jmp(LOOP);
+    code.place(DONE);
+    code.ret();
+}
+
+/// Truncating division of `$4` by `$5` to the quotient in `$2` and the remainder in `$3`, continues at `DONE`.
+/// The division by zero gives -1 and the dividend as the remainder.
+fn divide(code: &mut Assembler) {
+    const NON_ZERO: u8 = 9;
+    const GENERAL: u8 = 10;
+    const LHS_POSITIVE: u8 = 11;
+    const RHS_POSITIVE: u8 = 12;
+    const LOOP: u8 = 13;
+    const SUBTRACT: u8 = 14;
+    const NEXT: u8 = 15;
+    const SIGNS: u8 = 16;
+    const QUOTIENT_POSITIVE: u8 = 17;
+    code.jnz(RHS, NON_ZERO);
+    code.nor(RESULT, 0, 0);
+    code.mov(REMAINDER, LHS);
+    code.jmp(DONE);
+
+    // The absolute values are unsigned, the absolute value of the minimal number is 2^31
+    code.place(NON_ZERO);
+    code.slt(LHS_SIGN, LHS, 0);
+    code.slt(RHS_SIGN, RHS, 0);
+    code.jz(LHS_SIGN, LHS_POSITIVE);
+    code.sub(LHS, 0, LHS);
+    code.place(LHS_POSITIVE);
+    code.jz(RHS_SIGN, RHS_POSITIVE);
+    code.sub(RHS, 0, RHS);
+    code.place(RHS_POSITIVE);
+    code.mov(RESULT, 0);
+    code.mov(REMAINDER, 0);
+
+    // The divisor 2^31 goes into the dividend at most once, and only if they are equal
+    code.slt(TMP, RHS, 0);
+    code.jz(TMP, GENERAL);
+    code.mov(REMAINDER, LHS);
+    code.slt(TMP, LHS, 0);
+    code.jz(TMP, SIGNS);
+    code.mov(RESULT, TMP);
+    code.mov(REMAINDER, 0);
+    code.jmp(SIGNS);
+
+    // Restoring division, one bit of the dividend per iteration. The divisor is below 2^31, so the remainder
+    // fits into 32 unsigned bits after shifting and is not less than the divisor if its highest bit is set
+    code.place(GENERAL);
+    code.one(MASK);
+    code.place(LOOP);
+    code.slt(TMP, LHS, 0);
+    code.add(LHS, LHS, LHS);
+    code.add(REMAINDER, REMAINDER, REMAINDER);
+    code.add(REMAINDER, REMAINDER, TMP);
+    code.add(RESULT, RESULT, RESULT);
+    code.slt(TMP, REMAINDER, 0);
+    code.jnz(TMP, SUBTRACT);
+    code.slt(TMP, REMAINDER, RHS);
+    code.jnz(TMP, NEXT);
+    code.place(SUBTRACT);
+    code.sub(REMAINDER, REMAINDER, RHS);
+    code.nor(TMP, 0, 0);
+    code.sub(RESULT, RESULT, TMP);
+    code.place(NEXT);
+    code.add(MASK, MASK, MASK);
+    code.jnz(MASK, LOOP);
+
+    // The quotient is negative if the signs differ, the remainder has the sign of the dividend
+    code.place(SIGNS);
+    code.sub(TMP, LHS_SIGN, RHS_SIGN);
+    code.jz(TMP, QUOTIENT_POSITIVE);
+    code.sub(RESULT, 0, RESULT);
+    code.place(QUOTIENT_POSITIVE);
+    code.jz(LHS_SIGN, DONE);
+    code.sub(REMAINDER, 0, REMAINDER);
+    code.place(DONE);
+}
+
+fn abs(code: &mut Assembler) {
+    code.mov(RESULT, LHS);
+    code.slt(TMP, LHS, 0);
+    code.jz(TMP, DONE);
+    code.sub(RESULT, 0, LHS);
+    code.place(DONE);
+    code.ret();
+}
+
+/// Returns the right operand if `slt` of the operands in the given order is set, the left one otherwise
+fn select(code: &mut Assembler, rs: u8, rt: u8) {
+    code.mov(RESULT, LHS);
+    code.slt(TMP, rs, rt);
+    code.jz(TMP, DONE);
+    code.mov(RESULT, RHS);
+    code.place(DONE);
+    code.ret();
+}
+
+/// Code of the routine starting at its entry label
+pub fn routine_code(routine: Routine) -> Vec<Item<RuntimeLabel>> {
+    let mut code = Assembler::new(routine);
+    match routine {
+        Routine::Mul => mul(&mut code),
+        Routine::Div => {
+            divide(&mut code);
+            code.ret();
+        }
+        Routine::Mod => {
+            divide(&mut code);
+            code.mov(RESULT, REMAINDER);
+            code.ret();
+        }
+        Routine::Abs => abs(&mut code),
+        Routine::Min => select(&mut code, RHS, LHS),
+        Routine::Max => select(&mut code, LHS, RHS),
+    }
+    code.items
+}
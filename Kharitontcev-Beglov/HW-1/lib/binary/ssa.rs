@@ -0,0 +1,492 @@
+//! Backend compiling the SSA form into MIPS code.
+//!
+//! Values get registers with the linear scan over the blocks in the reverse postorder, the values which do not
+//! fit into the registers are kept in the memory slots after the constants. Phis are replaced with copies at the
+//! end of the predecessors, the critical edges are split beforehand so the copies run only on their edge.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+use crate::binary::data::{check_offsets, word_offset, ConstantPool};
+use crate::binary::debug_info::DebugInfo;
+use crate::binary::instructions::{Arithmetic, Instr, IType, RType};
+use crate::binary::link::{Item, LinkError, Linker};
+use crate::parser::ast::PrefixOps;
+use crate::ssa::{Function, Instruction, Operation, Terminator, Value};
+use crate::stack_machine::cfg::BlockId;
+use crate::stack_machine::sm::Condition;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Location {
+    Register(u8),
+    /// Index of the memory slot
+    Slot(usize),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum Target {
+    Block(BlockId),
+    /// Address right after the code, where the emulator stops
+    End,
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Block(block) => write!(f, "b{}", block),
+            Target::End => write!(f, "end"),
+        }
+    }
+}
+
+/// Positions of the values in the code where they are live, ordered by the start
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct LiveInterval {
+    value: Value,
+    start: usize,
+    end: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct SsaTransformer {
+    // Constants and string literals
+    constants: ConstantPool,
+    // Registers for the values
+    registers: Vec<u8>,
+    locations: HashMap<Value, Location>,
+    slots: usize,
+    code: Vec<Item<Target>>,
+    debug_info: DebugInfo,
+    arithmetic: Arithmetic,
+}
+
+impl Default for SsaTransformer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Orders the simultaneous copies `(destination, source)` so no source is overwritten before it is read.
+///
+/// Cycles are broken by saving one of the sources into `temporary`.
+fn sequentialize_copies(mut copies: Vec<(Location, Location)>, temporary: Location) -> Vec<(Location, Location)> {
+    copies.retain(|(destination, source)| destination != source);
+    let mut result = Vec::new();
+    while !copies.is_empty() {
+        let ready = copies.iter().position(|(destination, _)| copies.iter().all(|(_, source)| source != destination));
+        match ready {
+            Some(index) => result.push(copies.remove(index)),
+            None => {
+                // Only cycles are left
+                let saved = copies[0].1;
+                result.push((temporary, saved));
+                copies.iter_mut().filter(|(_, source)| *source == saved).for_each(|(_, source)| *source = temporary);
+            }
+        }
+    }
+    result
+}
+
+/// Values live at the start and at the end of every block, phi inputs are live at the end of their predecessor
+fn liveness(function: &Function, order: &[BlockId]) -> (Vec<HashSet<Value>>, Vec<HashSet<Value>>) {
+    let blocks = &function.blocks;
+    let mut upward_uses = vec![HashSet::new(); blocks.len()];
+    let mut definitions = vec![HashSet::new(); blocks.len()];
+    let mut phi_uses = vec![HashSet::new(); blocks.len()];
+    for &id in order {
+        let block = &blocks[id];
+        definitions[id].extend(block.phis.iter().map(|phi| phi.value));
+        let mut uses = Vec::new();
+        for instruction in &block.instructions {
+            match instruction {
+                Instruction::Assign(value, operation) => {
+                    uses.extend(operation.operands().into_iter().filter(|operand| !definitions[id].contains(operand)));
+                    definitions[id].insert(*value);
+                }
+                Instruction::Print(value) | Instruction::PrintChar(value) if !definitions[id].contains(value) => uses.push(*value),
+                _ => {}
+            }
+        }
+        if let Terminator::Branch(condition, _, _) = block.terminator {
+            if !definitions[id].contains(&condition) {
+                uses.push(condition);
+            }
+        }
+        upward_uses[id].extend(uses);
+        for successor in block.terminator.successors() {
+            for phi in &blocks[successor].phis {
+                phi_uses[id].extend(phi.incoming.iter().filter(|(predecessor, _)| *predecessor == id).map(|(_, value)| *value));
+            }
+        }
+    }
+    let mut live_in: Vec<HashSet<Value>> = vec![HashSet::new(); blocks.len()];
+    let mut live_out: Vec<HashSet<Value>> = vec![HashSet::new(); blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &id in order.iter().rev() {
+            let mut out = phi_uses[id].clone();
+            for successor in blocks[id].terminator.successors() {
+                out.extend(&live_in[successor]);
+            }
+            let mut input = upward_uses[id].clone();
+            input.extend(out.difference(&definitions[id]));
+            if input != live_in[id] || out != live_out[id] {
+                live_in[id] = input;
+                live_out[id] = out;
+                changed = true;
+            }
+        }
+    }
+    (live_in, live_out)
+}
+
+/// Numbers the block starts, the instructions and the terminators in the order, and collects the ranges of them
+/// where every value is live
+fn live_intervals(function: &Function, order: &[BlockId]) -> Vec<LiveInterval> {
+    let (live_in, live_out) = liveness(function, order);
+    let mut ranges: HashMap<Value, (usize, usize)> = HashMap::new();
+    let mut extend = |value: Value, position: usize| {
+        let range = ranges.entry(value).or_insert((position, position));
+        range.0 = range.0.min(position);
+        range.1 = range.1.max(position);
+    };
+    let mut position = 0;
+    for &id in order {
+        let block = &function.blocks[id];
+        live_in[id].iter().for_each(|value| extend(*value, position));
+        block.phis.iter().for_each(|phi| extend(phi.value, position));
+        for instruction in &block.instructions {
+            position += 1;
+            match instruction {
+                Instruction::Assign(value, operation) => {
+                    extend(*value, position);
+                    operation.operands().into_iter().for_each(|operand| extend(operand, position));
+                }
+                Instruction::Print(value) | Instruction::PrintChar(value) => extend(*value, position),
+                Instruction::PrintString(_) | Instruction::Location(_) => {}
+            }
+        }
+        // The phi copies are made right before the terminator, so the phis are live there as well
+        position += 1;
+        live_out[id].iter().for_each(|value| extend(*value, position));
+        for successor in block.terminator.successors() {
+            function.blocks[successor].phis.iter().for_each(|phi| extend(phi.value, position));
+        }
+        if let Terminator::Branch(condition, _, _) = block.terminator {
+            extend(condition, position);
+        }
+        position += 1;
+    }
+    let mut result: Vec<LiveInterval> = ranges.into_iter()
+        .map(|(value, (start, end))| LiveInterval { value, start, end })
+        .collect();
+    result.sort_by_key(|interval| (interval.start, interval.end, interval.value));
+    result
+}
+
+impl SsaTransformer {
+    const ZERO: u8 = 0;
+    // Temporary for the cycles of the phi copies
+    const COPY_TEMPORARY: u8 = 1;
+    // Values loaded from the slots and results stored into them
+    const SCRATCH_1: u8 = 26;
+    const SCRATCH_2: u8 = 27;
+    const SLOTS: u8 = 28;
+    const ARGUMENTS: [u8; 2] = [4, 5];
+    const RESULT: u8 = 2;
+    const REGISTERS: [u8; 18] = [8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25];
+    const BOOT_CODE_SIZE: usize = 1;
+
+    pub fn new() -> Self {
+        Self::with_register_limit(Self::REGISTERS.len())
+    }
+
+    /// Compiles `+`, `-` and unary minus with the overflow handling
+    pub fn with_arithmetic(self, arithmetic: Arithmetic) -> Self {
+        Self { arithmetic, ..self }
+    }
+
+    /// Uses only `limit` registers for the values, the rest of them are kept in the memory
+    pub fn with_register_limit(limit: usize) -> Self {
+        assert!((1..=Self::REGISTERS.len()).contains(&limit), "Register limit should be in 1..={}", Self::REGISTERS.len());
+        Self {
+            constants: ConstantPool::new(),
+            registers: Self::REGISTERS[..limit].to_vec(),
+            locations: HashMap::new(),
+            slots: 0,
+            code: Vec::new(),
+            debug_info: DebugInfo::new(),
+            arithmetic: Arithmetic::default(),
+        }
+    }
+
+    /// Linear scan: when the registers run out, the value live for the longest time goes into a slot.
+    /// A spilled value has no register at all, its register is given to the value which caused the spill.
+    /// Slots are assigned afterward with the same scan, so they are reused as well.
+    fn allocate(&mut self, intervals: &[LiveInterval]) {
+        let mut free: Vec<u8> = self.registers.iter().rev().copied().collect();
+        let mut active: Vec<(LiveInterval, u8)> = Vec::new();
+        let mut spilled = Vec::new();
+        for interval in intervals {
+            active.retain(|(other, register)| {
+                let expired = other.end < interval.start;
+                if expired {
+                    free.push(*register);
+                }
+                !expired
+            });
+            if let Some(register) = free.pop() {
+                active.push((*interval, register));
+                self.locations.insert(interval.value, Location::Register(register));
+                continue;
+            }
+            let (index, longest) = active.iter().enumerate().max_by_key(|(_, (other, _))| other.end).unwrap();
+            if longest.0.end > interval.end {
+                let register = longest.1;
+                spilled.push(longest.0);
+                active[index] = (*interval, register);
+                self.locations.insert(interval.value, Location::Register(register));
+            } else {
+                spilled.push(*interval);
+            }
+        }
+
+        spilled.sort_by_key(|interval| (interval.start, interval.end, interval.value));
+        let mut free_slots: Vec<usize> = Vec::new();
+        let mut active: Vec<(usize, usize)> = Vec::new();
+        for interval in spilled {
+            active.retain(|(end, slot)| {
+                let expired = *end < interval.start;
+                if expired {
+                    free_slots.push(*slot);
+                }
+                !expired
+            });
+            let slot = free_slots.pop().unwrap_or_else(|| {
+                self.slots += 1;
+                self.slots - 1
+            });
+            active.push((interval.end, slot));
+            self.locations.insert(interval.value, Location::Slot(slot));
+        }
+    }
+
+    fn emit(&mut self, instr: Instr) {
+        self.code.push(Item::Instr(instr))
+    }
+
+    fn emit_r_type(&mut self, funct: u8, rs: u8, rt: u8, rd: u8) {
+        self.emit(Instr::R(RType { rs, rt, rd, funct }))
+    }
+
+    fn slot_offset(slot: usize) -> u16 {
+        word_offset(slot)
+    }
+
+    /// Register holding the value, values from the slots are loaded into `scratch`
+    fn operand(&mut self, value: Value, scratch: u8) -> u8 {
+        match self.locations[&value] {
+            Location::Register(register) => register,
+            Location::Slot(slot) => {
+                self.emit(Instr::I(IType::Lw { rs: Self::SLOTS, rt: scratch, imm: Self::slot_offset(slot) }));
+                scratch
+            }
+        }
+    }
+
+    /// Register to compute the value into, followed by [`Self::store_result`]
+    fn result_register(&self, value: Value) -> u8 {
+        match self.locations[&value] {
+            Location::Register(register) => register,
+            Location::Slot(_) => Self::SCRATCH_1,
+        }
+    }
+
+    fn store_result(&mut self, value: Value) {
+        if let Location::Slot(slot) = self.locations[&value] {
+            self.emit(Instr::I(IType::Sw { rs: Self::SLOTS, rt: Self::SCRATCH_1, imm: Self::slot_offset(slot) }))
+        }
+    }
+
+    fn transform_operation(&mut self, value: Value, operation: &Operation) {
+        match operation {
+            Operation::Const(x) => {
+                let imm = word_offset(self.constants.constant(*x));
+                let rt = self.result_register(value);
+                self.emit(Instr::I(IType::Lw { rs: Self::ZERO, rt, imm }))
+            }
+            Operation::Binary(op, lhs, rhs) => {
+                let lhs = self.operand(*lhs, Self::SCRATCH_1);
+                let rhs = self.operand(*rhs, Self::SCRATCH_2);
+                let rd = self.result_register(value);
+                self.emit_r_type(self.arithmetic.funct(op), lhs, rhs, rd)
+            }
+            Operation::Unary(op, operand) => {
+                let operand = self.operand(*operand, Self::SCRATCH_1);
+                let rd = self.result_register(value);
+                match op {
+                    PrefixOps::BitwiseNot => self.emit_r_type(39, operand, Self::ZERO, rd),
+                    PrefixOps::UnaryMinus => self.emit_r_type(self.arithmetic.sub(), Self::ZERO, operand, rd),
+                }
+            }
+            Operation::Call(routine, args) => {
+                // The routines keep the registers of the values, see the calling convention in `runtime`
+                for (arg, register) in args.iter().zip(Self::ARGUMENTS) {
+                    let arg = self.operand(*arg, Self::SCRATCH_1);
+                    self.emit_r_type(37, arg, Self::ZERO, register);
+                }
+                self.code.push(Item::Call(*routine));
+                let rd = self.result_register(value);
+                self.emit_r_type(37, Self::RESULT, Self::ZERO, rd)
+            }
+        }
+        self.store_result(value)
+    }
+
+    fn transform_copy(&mut self, destination: Location, source: Location) {
+        match (destination, source) {
+            (Location::Register(rd), Location::Register(rs)) => self.emit_r_type(RType::ADDU, rs, Self::ZERO, rd),
+            (Location::Register(rt), Location::Slot(slot)) => {
+                self.emit(Instr::I(IType::Lw { rs: Self::SLOTS, rt, imm: Self::slot_offset(slot) }))
+            }
+            (Location::Slot(slot), Location::Register(rt)) => {
+                self.emit(Instr::I(IType::Sw { rs: Self::SLOTS, rt, imm: Self::slot_offset(slot) }))
+            }
+            (Location::Slot(destination), Location::Slot(source)) => {
+                self.emit(Instr::I(IType::Lw { rs: Self::SLOTS, rt: Self::SCRATCH_1, imm: Self::slot_offset(source) }));
+                self.emit(Instr::I(IType::Sw { rs: Self::SLOTS, rt: Self::SCRATCH_1, imm: Self::slot_offset(destination) }))
+            }
+        }
+    }
+
+    /// Copies the phi inputs coming from the block into the phis of the successor
+    fn transform_phi_copies(&mut self, function: &Function, block: BlockId, successor: BlockId) {
+        let copies = function.blocks[successor].phis.iter()
+            .filter_map(|phi| {
+                let (_, input) = phi.incoming.iter().find(|(predecessor, _)| *predecessor == block)?;
+                Some((self.locations[&phi.value], self.locations[input]))
+            })
+            .collect();
+        for (destination, source) in sequentialize_copies(copies, Location::Register(Self::COPY_TEMPORARY)) {
+            self.transform_copy(destination, source)
+        }
+    }
+
+    fn transform_terminator(&mut self, terminator: &Terminator, next: Option<BlockId>) {
+        match *terminator {
+            Terminator::Jump(target) if Some(target) != next => self.code.push(Item::Jump(Target::Block(target))),
+            Terminator::Jump(_) => {}
+            Terminator::Branch(condition, not_zero, zero) => {
+                let rs = self.operand(condition, Self::SCRATCH_1);
+                if Some(not_zero) == next {
+                    self.code.push(Item::ConditionalJump(Condition::EqualsZero, rs, Target::Block(zero)));
+                } else {
+                    self.code.push(Item::ConditionalJump(Condition::NotEqualsZero, rs, Target::Block(not_zero)));
+                    if Some(zero) != next {
+                        self.code.push(Item::Jump(Target::Block(zero)));
+                    }
+                }
+            }
+            Terminator::Return if next.is_some() => self.code.push(Item::Jump(Target::End)),
+            Terminator::Return => {}
+        }
+    }
+
+    fn transform_function(&mut self, function: &Function) {
+        let order = function.reverse_postorder();
+        self.allocate(&live_intervals(function, &order));
+        for (index, &id) in order.iter().enumerate() {
+            let block = &function.blocks[id];
+            self.code.push(Item::Label(Target::Block(id)));
+            for instruction in &block.instructions {
+                match instruction {
+                    Instruction::Assign(value, operation) => self.transform_operation(*value, operation),
+                    Instruction::Print(value) => {
+                        let rs = self.operand(*value, Self::SCRATCH_1);
+                        self.emit_r_type(0, rs, 0, 0)
+                    }
+                    Instruction::PrintChar(value) => {
+                        let rs = self.operand(*value, Self::SCRATCH_1);
+                        self.emit_r_type(1, rs, 0, 0)
+                    }
+                    Instruction::PrintString(value) => {
+                        let imm = word_offset(self.constants.string(value));
+                        self.code.push(Item::Instr(Instr::I(IType::Prints { rs: Self::ZERO, imm })))
+                    }
+                    Instruction::Location(span) => self.code.push(Item::Location(*span)),
+                }
+            }
+            // After splitting the critical edges, only the blocks with a single successor have phi copies
+            if let Terminator::Jump(successor) = block.terminator {
+                self.transform_phi_copies(function, id, successor);
+            }
+            self.transform_terminator(&block.terminator, order.get(index + 1).copied());
+        }
+    }
+
+    fn boot_code(&mut self) -> [Instr; Self::BOOT_CODE_SIZE] {
+        // Forced to be the last constant, the slots follow it
+        let slots_index = self.constants.push(((self.constants.len() + 1) * 4) as i32);
+        [Instr::I(IType::Lw { rs: Self::ZERO, rt: Self::SLOTS, imm: word_offset(slots_index) })]
+    }
+
+    /// Compiles the function and returns the memory and the code, as `SMTransformer::transform_program` does
+    pub fn transform_program(&mut self, function: &Function) -> Result<(Vec<u8>, Vec<u8>), LinkError> {
+        let mut function = function.clone();
+        function.split_critical_edges();
+        self.transform_function(&function);
+        self.code.push(Item::Label(Target::End));
+        let mut items: Vec<Item<Target>> = self.boot_code().into_iter().map(Item::Instr).collect();
+        items.append(&mut self.code);
+        check_offsets(self.constants.len())?;
+        check_offsets(self.slots)?;
+        let code = Linker::new().link(&items, &mut self.debug_info)?;
+        let mut memory = self.constants.to_bytes();
+        memory.resize(memory.len() + self.slots * 4, 0);
+        Ok((memory, code))
+    }
+
+    /// Debug information of the last transformed program, values of the SSA form are not tied to the variables
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug_info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_source;
+    use crate::ssa::lower::lower_program;
+    use super::*;
+
+    fn lower(source: &str) -> Function {
+        lower_program(&parse_source(source))
+    }
+
+    #[test]
+    fn test_sequentialize_copies() {
+        let (r1, r2, r3, temporary) = (Location::Register(1), Location::Register(2), Location::Register(3), Location::Register(9));
+        // r2 is read before it is overwritten, r3 is not copied to itself
+        assert_eq!(vec![(r1, r2), (r2, r3)], sequentialize_copies(vec![(r2, r3), (r1, r2), (r3, r3)], temporary));
+        // Swap
+        assert_eq!(
+            vec![(temporary, r2), (r2, r1), (r1, temporary)],
+            sequentialize_copies(vec![(r1, r2), (r2, r1)], temporary)
+        );
+    }
+
+    #[test]
+    fn test_spilled_values_use_slots() {
+        let function = lower("{ a = 1; b = 2; c = 3; while (a) { print(a + b + c); a = a - 1 } }");
+        let mut transformer = SsaTransformer::with_register_limit(2);
+        let (memory, _) = transformer.transform_program(&function).unwrap();
+        let slots = transformer.locations.values().filter(|location| matches!(location, Location::Slot(_))).count();
+        assert!(slots > 0);
+        assert!(transformer.slots > 0 && transformer.slots <= slots);
+        assert_eq!(memory.len(), (transformer.constants.len() + transformer.slots) * 4);
+
+        let mut transformer = SsaTransformer::new();
+        transformer.transform_program(&function).unwrap();
+        assert_eq!(0, transformer.slots);
+    }
+}
@@ -0,0 +1,145 @@
+//! Coprocessor 0 handling the exceptions and the interrupts, as in MIPS32 with a single exception vector.
+//!
+//! On an exception the address of the faulting instruction goes to `EPC`, the cause to `Cause` and `Status.EXL` is set,
+//! then the execution continues at the handler address. `eret` clears `EXL` and returns to `EPC`. The interrupts are
+//! taken between the instructions when `Status.IE` is set, `Status.EXL` is clear and a pending line of `Cause.IP` is
+//! enabled in `Status.IM`. The timer raises the line 7 every period of cycles, the handler acknowledges it by
+//! clearing the bit in `Cause`.
+//!
+//! Unlike the MIPS convention, no register is reserved for the handler: the compiled code keeps values in `$1`, `$26`
+//! and `$27` too, so a handler has to save every register it changes (e.g. with `sw` relative to `$0`) and restore it
+//! before `eret`.
+
+/// Reasons of the exceptions, the values are the `Cause.ExcCode` field
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ExceptionCode {
+    Interrupt = 0,
+    /// Misaligned load or instruction fetch
+    AddressLoad = 4,
+    /// Misaligned store
+    AddressStore = 5,
+    Syscall = 8,
+    /// Unknown opcode or funct
+    ReservedInstruction = 10,
+    /// Signed overflow of `add` or `sub`
+    Overflow = 12,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Coprocessor0 {
+    bad_address: u32,
+    status: u32,
+    cause: u32,
+    epc: u32,
+    handler: u32,
+    timer_period: Option<u64>,
+}
+
+impl Coprocessor0 {
+    /// Address of the last misaligned access
+    pub const BAD_VADDR: u8 = 8;
+    pub const STATUS: u8 = 12;
+    pub const CAUSE: u8 = 13;
+    pub const EPC: u8 = 14;
+
+    /// Interrupts enable bit of `Status`
+    pub const IE: u32 = 1;
+    /// Exception level bit of `Status`, set while the handler runs
+    pub const EXL: u32 = 1 << 1;
+    /// Interrupt line of the timer in `Cause.IP` and `Status.IM`
+    pub const TIMER_LINE: u32 = 1 << 15;
+    const INTERRUPT_LINES: u32 = 0xff00;
+
+    /// The exceptions go to the handler at the address, the interrupts are disabled
+    pub fn new(handler: u32) -> Self {
+        Self { bad_address: 0, status: 0, cause: 0, epc: 0, handler, timer_period: None }
+    }
+
+    /// Raises the timer interrupt line every `period` cycles
+    pub fn with_timer(self, period: u64) -> Self {
+        assert_ne!(0, period, "The timer period should be positive");
+        Self { timer_period: Some(period), ..self }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self { timer_period: self.timer_period, ..Self::new(self.handler) };
+    }
+
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+
+    pub fn cause(&self) -> u32 {
+        self.cause
+    }
+
+    pub fn epc(&self) -> u32 {
+        self.epc
+    }
+
+    pub fn bad_address(&self) -> u32 {
+        self.bad_address
+    }
+
+    /// The `ExcCode` field of `Cause`
+    pub fn exception_code(&self) -> u32 {
+        (self.cause >> 2) & 0x1f
+    }
+
+    /// Sets the registers saved by a snapshot, including the read-only ones
+    pub(crate) fn restore(&mut self, bad_address: u32, status: u32, cause: u32, epc: u32) {
+        *self = Self { bad_address, status, cause, epc, ..self.clone() };
+    }
+
+    /// Value for `mfc0`, the registers which are not modeled read as zero
+    pub fn read(&self, register: u8) -> u32 {
+        match register {
+            Self::BAD_VADDR => self.bad_address,
+            Self::STATUS => self.status,
+            Self::CAUSE => self.cause,
+            Self::EPC => self.epc,
+            _ => 0,
+        }
+    }
+
+    /// Write of `mtc0`. Only the interrupt lines of `Cause` are writable, `BadVAddr` is read-only
+    pub fn write(&mut self, register: u8, value: u32) {
+        match register {
+            Self::STATUS => self.status = value,
+            Self::CAUSE => self.cause = (self.cause & !Self::INTERRUPT_LINES) | (value & Self::INTERRUPT_LINES),
+            Self::EPC => self.epc = value,
+            _ => {}
+        }
+    }
+
+    /// Counts the cycle, raises the timer line at the end of every period
+    pub(crate) fn tick(&mut self, cycles: u64) {
+        if self.timer_period.is_some_and(|period| cycles.is_multiple_of(period)) {
+            self.cause |= Self::TIMER_LINE;
+        }
+    }
+
+    pub(crate) fn interrupt_pending(&self) -> bool {
+        self.status & Self::IE != 0 && self.status & Self::EXL == 0 && self.status & self.cause & Self::INTERRUPT_LINES != 0
+    }
+
+    /// Records the exception of the instruction at the address and returns the handler address. A nested exception
+    /// keeps the `EPC` of the first one
+    pub(crate) fn enter(&mut self, code: ExceptionCode, address: u32, bad_address: Option<u32>) -> u32 {
+        if self.status & Self::EXL == 0 {
+            self.epc = address;
+        }
+        self.status |= Self::EXL;
+        self.cause = (self.cause & !0x7c) | ((code as u32) << 2);
+        if let Some(bad_address) = bad_address {
+            self.bad_address = bad_address;
+        }
+        self.handler
+    }
+
+    /// Leaves the handler, returns the address to continue at
+    pub(crate) fn eret(&mut self) -> u32 {
+        self.status &= !Self::EXL;
+        self.epc
+    }
+}
@@ -0,0 +1,204 @@
+//! Output and input of the emulated program. The emulator prints through an [`Output`] and reads through
+//! an [`Input`], so the program may be run with the console, in-memory buffers or files.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// Where the text printed by the program goes
+pub trait Output {
+    fn print(&mut self, text: &str) -> io::Result<()>;
+}
+
+/// Where the program reads from
+pub trait Input {
+    /// Next byte of the input, `None` at the end of it
+    fn read_byte(&mut self) -> io::Result<Option<u8>>;
+
+    /// Next line without the line break, `None` at the end of the input
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = Vec::new();
+        loop {
+            match self.read_byte()? {
+                Some(b'\n') => break,
+                Some(byte) => line.push(byte),
+                None if line.is_empty() => return Ok(None),
+                None => break,
+            }
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+    }
+
+    /// Integer on the next line, the whitespace around it is ignored
+    fn read_int(&mut self) -> Result<i32, InputError> {
+        let line = self.read_line().map_err(|error| InputError::Failed(error.kind()))?.ok_or(InputError::EndOfInput)?;
+        line.trim().parse().map_err(|_| InputError::InvalidInteger)
+    }
+}
+
+/// Why the program could not read a value, the emulation stops with it
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum InputError {
+    EndOfInput,
+    /// The line is not a decimal integer in the range of a word
+    InvalidInteger,
+    Failed(io::ErrorKind),
+}
+
+impl Display for InputError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputError::EndOfInput => write!(f, "No integer to read at the end of the input"),
+            InputError::InvalidInteger => write!(f, "Invalid integer in the input"),
+            InputError::Failed(kind) => write!(f, "Failed to read the input: {}", kind),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StdoutOutput;
+
+impl Output for StdoutOutput {
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        io::stdout().write_all(text.as_bytes())
+    }
+}
+
+/// Collects the output in memory, the clones share the text, so it is read through a clone given to the emulator
+#[derive(Clone, Debug, Default)]
+pub struct BufferOutput {
+    text: Rc<RefCell<String>>,
+}
+
+impl BufferOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(&self) -> String {
+        self.text.borrow().clone()
+    }
+}
+
+impl Output for BufferOutput {
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        self.text.borrow_mut().push_str(text);
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct FileOutput {
+    writer: BufWriter<File>,
+}
+
+impl FileOutput {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self { writer: BufWriter::new(File::create(path)?) })
+    }
+}
+
+impl Output for FileOutput {
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        // Flushed every time, so the output is complete even if the emulation fails
+        self.writer.write_all(text.as_bytes())?;
+        self.writer.flush()
+    }
+}
+
+/// Reads the bytes one by one from a buffered reader
+fn read_byte(reader: &mut impl BufRead) -> io::Result<Option<u8>> {
+    let byte = reader.fill_buf()?.first().copied();
+    if byte.is_some() {
+        reader.consume(1);
+    }
+    Ok(byte)
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StdinInput;
+
+impl Input for StdinInput {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        read_byte(&mut io::stdin().lock())
+    }
+}
+
+/// Input given in advance, empty by default
+#[derive(Clone, Debug, Default)]
+pub struct BufferInput {
+    data: VecDeque<u8>,
+}
+
+impl BufferInput {
+    pub fn new(data: &[u8]) -> Self {
+        Self { data: data.iter().copied().collect() }
+    }
+}
+
+impl Input for BufferInput {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.data.pop_front())
+    }
+}
+
+#[derive(Debug)]
+pub struct FileInput {
+    reader: BufReader<File>,
+}
+
+impl FileInput {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self { reader: BufReader::new(File::open(path)?) })
+    }
+}
+
+impl Input for FileInput {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        read_byte(&mut self.reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_line() {
+        let mut input = BufferInput::new(b"12\r\nab\n\nlast");
+        assert_eq!(Some(b'1'), input.read_byte().unwrap());
+        assert_eq!(Some(String::from("2")), input.read_line().unwrap());
+        assert_eq!(Some(String::from("ab")), input.read_line().unwrap());
+        assert_eq!(Some(String::new()), input.read_line().unwrap());
+        assert_eq!(Some(String::from("last")), input.read_line().unwrap());
+        assert_eq!(None, input.read_line().unwrap());
+        assert_eq!(None, input.read_byte().unwrap());
+    }
+
+    #[test]
+    fn test_read_int() {
+        let mut input = BufferInput::new(b" -12 \n\n2147483648\n7");
+        assert_eq!(Ok(-12), input.read_int());
+        assert_eq!(Err(InputError::InvalidInteger), input.read_int());
+        assert_eq!(Err(InputError::InvalidInteger), input.read_int());
+        assert_eq!(Ok(7), input.read_int());
+        assert_eq!(Err(InputError::EndOfInput), input.read_int());
+    }
+
+    #[test]
+    fn test_buffer_output_is_shared() {
+        let output = BufferOutput::new();
+        let mut sink: Box<dyn Output> = Box::new(output.clone());
+        sink.print("a").unwrap();
+        sink.print("b\n").unwrap();
+        assert_eq!("ab\n", output.text());
+    }
+}
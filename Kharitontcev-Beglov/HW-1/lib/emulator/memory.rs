@@ -0,0 +1,183 @@
+//! Single address space of the emulator holding both the code and the data. The [`MemoryMap`] places the text,
+//! data, heap and stack segments. An access outside of them stops the emulation with an address error, or raises
+//! the address error exception if the coprocessor 0 is present.
+
+use std::fmt::{Display, Formatter};
+use crate::binary::link::Linker;
+
+/// Range of the addresses `[base, base + size)`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Segment {
+    pub base: u32,
+    pub size: u32,
+}
+
+impl Segment {
+    pub fn new(base: u32, size: u32) -> Self {
+        Self { base, size }
+    }
+
+    /// First address after the segment
+    pub fn end(&self) -> u64 {
+        self.base as u64 + self.size as u64
+    }
+
+    fn contains(&self, address: u64, length: u64) -> bool {
+        self.base as u64 <= address && address + length <= self.end()
+    }
+}
+
+/// Where the segments are placed. The code is loaded at the start of the text segment and the memory file
+/// at the start of the data segment, the stack pointer starts at the end of the stack segment.
+///
+/// The compiled programs address the data with the offsets from `$0`, so the data segment is at zero by default,
+/// and the text segment is where the linker places the code. Their stack grows up from the end of the data,
+/// through the heap and the stack segments following it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MemoryMap {
+    pub text: Segment,
+    pub data: Segment,
+    pub heap: Segment,
+    pub stack: Segment,
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self {
+            text: Segment::new(Linker::DEFAULT_LOAD_ADDRESS, 0x10_0000),
+            data: Segment::new(0, 0x1_0000),
+            heap: Segment::new(0x1_0000, 0x7_0000),
+            stack: Segment::new(0x8_0000, 0x8_0000),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MemoryMapError {
+    /// The segment does not fit into the 32-bit address space
+    OutOfAddressSpace(&'static str),
+    /// The segment base is not a multiple of the word size
+    UnalignedSegment(&'static str),
+    /// The segment size is not a multiple of the word size
+    UnalignedSize(&'static str),
+    Overlap(&'static str, &'static str),
+    /// The contents do not fit into the segment
+    TooLarge { segment: &'static str, size: usize },
+}
+
+impl Display for MemoryMapError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoryMapError::OutOfAddressSpace(segment) => write!(f, "The {} segment does not fit into the address space", segment),
+            MemoryMapError::UnalignedSegment(segment) => write!(f, "The {} segment does not start at a word boundary", segment),
+            MemoryMapError::UnalignedSize(segment) => write!(f, "The size of the {} segment is not a multiple of the word size", segment),
+            MemoryMapError::Overlap(first, second) => write!(f, "The {} and {} segments overlap", first, second),
+            MemoryMapError::TooLarge { segment, size } => write!(f, "{} bytes do not fit into the {} segment", size, segment),
+        }
+    }
+}
+
+impl MemoryMap {
+    pub(crate) fn segments(&self) -> [(&'static str, Segment); 4] {
+        [("text", self.text), ("data", self.data), ("heap", self.heap), ("stack", self.stack)]
+    }
+
+    /// Checks that the bases and the sizes of the segments are aligned, the segments do not overlap and fit into
+    /// the address space
+    pub fn validate(&self) -> Result<(), MemoryMapError> {
+        let segments = self.segments();
+        for (name, segment) in segments {
+            if segment.end() > 1 << 32 {
+                return Err(MemoryMapError::OutOfAddressSpace(name));
+            }
+            if !segment.base.is_multiple_of(4) {
+                return Err(MemoryMapError::UnalignedSegment(name));
+            }
+            if !segment.size.is_multiple_of(4) {
+                return Err(MemoryMapError::UnalignedSize(name));
+            }
+        }
+        for (index, (first, lhs)) in segments.iter().enumerate() {
+            for (second, rhs) in &segments[index + 1..] {
+                if (lhs.base as u64) < rhs.end() && (rhs.base as u64) < lhs.end() {
+                    return Err(MemoryMapError::Overlap(first, second));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Contents of the segments of a memory map, the same for every reset
+#[derive(Clone, Debug)]
+pub(crate) struct Memory {
+    segments: Vec<(Segment, Vec<u8>)>,
+    initial: Vec<Vec<u8>>,
+}
+
+impl Memory {
+    pub fn new(map: &MemoryMap, code: Vec<u8>, data: Vec<u8>) -> Result<Self, MemoryMapError> {
+        map.validate()?;
+        let mut segments = Vec::new();
+        for (name, segment) in map.segments() {
+            let contents = match name {
+                "text" => &code,
+                "data" => &data,
+                _ => &Vec::new(),
+            };
+            if contents.len() as u64 > segment.size as u64 {
+                return Err(MemoryMapError::TooLarge { segment: name, size: contents.len() });
+            }
+            let mut bytes = contents.clone();
+            bytes.resize(segment.size as usize, 0);
+            segments.push((segment, bytes));
+        }
+        let initial = segments.iter().map(|(_, bytes)| bytes.clone()).collect();
+        Ok(Self { segments, initial })
+    }
+
+    pub fn reset(&mut self) {
+        for ((_, bytes), initial) in self.segments.iter_mut().zip(&self.initial) {
+            bytes.copy_from_slice(initial)
+        }
+    }
+
+    /// Current bytes of the segments in the order of the map
+    pub fn contents(&self) -> Vec<Vec<u8>> {
+        self.segments.iter().map(|(_, bytes)| bytes.clone()).collect()
+    }
+
+    /// Replaces the bytes of the segments, the contents should be of the same map
+    pub fn set_contents(&mut self, contents: &[Vec<u8>]) {
+        for ((_, bytes), saved) in self.segments.iter_mut().zip(contents) {
+            bytes.copy_from_slice(saved)
+        }
+    }
+
+    /// Bytes of the segment from the address to the end of the segment, `None` if the `length` bytes at the address
+    /// are outside of the map
+    fn bytes_at(&self, address: u32, length: u64) -> Option<&[u8]> {
+        self.segments.iter()
+            .find(|(segment, _)| segment.contains(address as u64, length))
+            .map(|(segment, bytes)| &bytes[(address - segment.base) as usize..])
+    }
+
+    /// Word at the position, `None` outside of the map
+    pub fn get_word_from_position(&self, position: u32) -> Option<i32> {
+        self.bytes_at(position, 4).map(|bytes| i32::from_be_bytes(bytes[..4].try_into().unwrap()))
+    }
+
+    /// Bytes from the position up to the zero byte or the end of its segment, `None` outside of the map
+    pub fn get_string_from_position(&self, position: u32) -> Option<&[u8]> {
+        let data = self.bytes_at(position, 1)?;
+        Some(&data[..data.iter().position(|byte| *byte == 0).unwrap_or(data.len())])
+    }
+
+    /// Writes the word if it is inside of the map, [`Memory::get_word_from_position`] tells whether it is
+    pub fn set_word_at_position(&mut self, position: u32, word: i32) {
+        if let Some((segment, bytes)) = self.segments.iter_mut().find(|(segment, _)| segment.contains(position as u64, 4)) {
+            let offset = (position - segment.base) as usize;
+            bytes[offset..offset + 4].copy_from_slice(&word.to_be_bytes())
+        }
+    }
+}
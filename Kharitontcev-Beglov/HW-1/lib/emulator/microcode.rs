@@ -0,0 +1,467 @@
+//! Microprogrammed control unit of the emulator. Each state of the [`Microprogram`] asserts its control signals
+//! for one cycle and names the next state, either directly or through a dispatch table indexed by the opcode
+//! (and the funct) of the fetched instruction. The first state fetches the instructions.
+//!
+//! The default microprogram is `microcode.txt`, the same format is loaded with [`Microprogram::from_text`]:
+//!
+//! ```text
+//! state Fetch: ir_write pc_write alu_source_b=1 -> Decode
+//! state Decode: alu_source_b=3 -> dispatch opcode
+//! dispatch opcode 0/8 -> JumpRegister
+//! dispatch opcode 0 -> RTypeExecute
+//! ```
+//!
+//! The flags which are not listed are off, `pc_source` and `alu_source_b` are 0, `alu_control` is 32.
+//! `alu_control=funct` takes the operation from the funct of the instruction.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use crate::emulator::{ALU, FSMDecision};
+
+pub type StateId = usize;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct State {
+    name: String,
+    signals: FSMDecision,
+    next: Next,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Next {
+    State(StateId),
+    /// Index of the dispatch table
+    Dispatch(usize),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct DispatchTable {
+    name: String,
+    /// The entries with a funct take precedence over the entry of the whole opcode
+    entries: Vec<(u8, Option<u8>, StateId)>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Microprogram {
+    states: Vec<State>,
+    tables: Vec<DispatchTable>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseMicrocodeError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ParseMicrocodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid microcode at line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseMicrocodeError {}
+
+/// Problem found by [`Microprogram::validate`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MicrocodeError {
+    /// The first state does not load the instruction register
+    NoFetch(String),
+    /// The state is never entered from the first one
+    Unreachable(String),
+    /// The first state is never entered again from the state, so the instruction never finishes
+    NoReturnToFetch(String),
+    /// No state dispatches through the table
+    UnusedTable(String),
+    ConflictingSignals { state: String, reason: &'static str },
+}
+
+impl Display for MicrocodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MicrocodeError::NoFetch(state) => write!(f, "The first state {} does not fetch the instruction", state),
+            MicrocodeError::Unreachable(state) => write!(f, "The state {} is unreachable", state),
+            MicrocodeError::NoReturnToFetch(state) => write!(f, "The state {} never returns to the fetch", state),
+            MicrocodeError::UnusedTable(table) => write!(f, "The dispatch table {} is unused", table),
+            MicrocodeError::ConflictingSignals { state, reason } => write!(f, "Conflicting signals in the state {}: {}", state, reason),
+        }
+    }
+}
+
+impl std::error::Error for MicrocodeError {}
+
+impl Default for Microprogram {
+    fn default() -> Self {
+        Self::from_text(Self::DEFAULT).expect("The default microprogram is valid")
+    }
+}
+
+fn parse_signals(words: &[&str]) -> Result<FSMDecision, String> {
+    let mut signals = FSMDecision::default();
+    let mut seen = Vec::new();
+    for word in words {
+        let (name, value) = match word.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (*word, None),
+        };
+        if seen.contains(&name) {
+            return Err(format!("signal `{}` is listed twice", name));
+        }
+        seen.push(name);
+        let number = |max: u8| value
+            .and_then(|value| value.parse::<u8>().ok())
+            .filter(|value| *value <= max)
+            .ok_or_else(|| format!("`{}` expects a value from 0 to {}", name, max));
+        let flag = match name {
+            "iord" => &mut signals.iord,
+            "mem_write" => &mut signals.mem_write,
+            "ir_write" => &mut signals.ir_write,
+            "pc_write" => &mut signals.pc_write,
+            "branch" => &mut signals.branch,
+            "alu_src_a_reg" => &mut signals.alu_src_a_reg,
+            "reg_write" => &mut signals.reg_write,
+            "mem_to_reg" => &mut signals.mem_to_reg,
+            "reg_dst" => &mut signals.reg_dst,
+            "negate_zero" => &mut signals.negate_zero,
+            "link" => &mut signals.link,
+            "pc_source" => {
+                signals.pc_source = number(2)?;
+                continue;
+            }
+            "alu_source_b" => {
+                signals.alu_source_b = number(3)?;
+                continue;
+            }
+            "alu_control" => {
+                match value {
+                    Some("funct") => signals.alu_funct = true,
+                    _ => signals.alu_control = value
+                        .and_then(|value| value.parse::<u8>().ok())
+                        .filter(|value| ALU::FUNCTS.contains(value))
+                        .ok_or_else(|| format!("`alu_control` expects `funct` or one of the functs {:?}", ALU::FUNCTS))?,
+                }
+                continue;
+            }
+            _ => return Err(format!("unknown signal `{}`", name)),
+        };
+        if value.is_some() {
+            return Err(format!("`{}` is a flag without a value", name));
+        }
+        *flag = true;
+    }
+    Ok(signals)
+}
+
+/// The asserted signals in the format of the microcode file
+fn format_signals(signals: &FSMDecision) -> Vec<String> {
+    let flags = [
+        ("iord", signals.iord),
+        ("mem_write", signals.mem_write),
+        ("ir_write", signals.ir_write),
+        ("pc_write", signals.pc_write),
+        ("branch", signals.branch),
+        ("negate_zero", signals.negate_zero),
+        ("alu_src_a_reg", signals.alu_src_a_reg),
+        ("reg_write", signals.reg_write),
+        ("mem_to_reg", signals.mem_to_reg),
+        ("reg_dst", signals.reg_dst),
+        ("link", signals.link),
+    ];
+    let mut result: Vec<String> = flags.iter().filter(|(_, on)| *on).map(|(name, _)| name.to_string()).collect();
+    if signals.pc_source != 0 {
+        result.push(format!("pc_source={}", signals.pc_source));
+    }
+    if signals.alu_source_b != 0 {
+        result.push(format!("alu_source_b={}", signals.alu_source_b));
+    }
+    if signals.alu_funct {
+        result.push(String::from("alu_control=funct"));
+    } else if signals.alu_control != FSMDecision::default().alu_control {
+        result.push(format!("alu_control={}", signals.alu_control));
+    }
+    result
+}
+
+fn parse_condition(condition: &str) -> Option<(u8, Option<u8>)> {
+    let field = |value: &str| value.parse::<u8>().ok().filter(|value| *value < 64);
+    match condition.split_once('/') {
+        Some((opcode, funct)) => Some((field(opcode)?, Some(field(funct)?))),
+        None => Some((field(condition)?, None)),
+    }
+}
+
+impl Microprogram {
+    /// The control unit of the instructions in `InstructionSet.md`
+    pub const DEFAULT: &'static str = include_str!("microcode.txt");
+
+    /// Index of the state fetching the instructions
+    pub const FETCH: StateId = 0;
+
+    /// Parses the states and the dispatch tables, the names may be used before they are defined. The program is not
+    /// checked beyond that, see [`Microprogram::validate`]
+    pub fn from_text(text: &str) -> Result<Self, ParseMicrocodeError> {
+        // The targets are resolved when all the names are known
+        let mut states = Vec::new();
+        let mut entries = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |message: String| ParseMicrocodeError { line: line_number, message };
+            let parts: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+            match parts.as_slice() {
+                [] => {}
+                ["state", name, rest @ ..] if name.ends_with(':') => {
+                    let name = name.trim_end_matches(':');
+                    let (signals, next) = match rest {
+                        [signals @ .., "->", "dispatch", table] => (signals, Err(table.to_string())),
+                        [signals @ .., "->", state] => (signals, Ok(state.to_string())),
+                        _ => return Err(error(String::from("expected `-> <state>` or `-> dispatch <table>`"))),
+                    };
+                    let signals = parse_signals(signals).map_err(error)?;
+                    states.push((line_number, name.to_string(), signals, next));
+                }
+                ["dispatch", table, condition, "->", state] => {
+                    let (opcode, funct) = parse_condition(condition)
+                        .ok_or_else(|| error(format!("invalid condition `{}`, expected <opcode>[/<funct>]", condition)))?;
+                    entries.push((line_number, table.to_string(), opcode, funct, state.to_string()));
+                }
+                _ => return Err(error(String::from("expected `state <name>: <signals> -> <next>` or `dispatch <table> <opcode>[/<funct>] -> <state>` line"))),
+            }
+        }
+        if states.is_empty() {
+            return Err(ParseMicrocodeError { line: 1, message: String::from("no states") });
+        }
+
+        let mut ids: HashMap<&str, StateId> = HashMap::new();
+        for (id, (line, name, _, _)) in states.iter().enumerate() {
+            if ids.insert(name.as_str(), id).is_some() {
+                return Err(ParseMicrocodeError { line: *line, message: format!("state `{}` is defined twice", name) });
+            }
+        }
+        let resolve = |line: usize, name: &str| ids.get(name).copied()
+            .ok_or_else(|| ParseMicrocodeError { line, message: format!("unknown state `{}`", name) });
+
+        let mut tables: Vec<DispatchTable> = Vec::new();
+        for (line, table, opcode, funct, state) in &entries {
+            let state = resolve(*line, state)?;
+            let index = match tables.iter().position(|known| &known.name == table) {
+                Some(index) => index,
+                None => {
+                    tables.push(DispatchTable { name: table.clone(), entries: Vec::new() });
+                    tables.len() - 1
+                }
+            };
+            let table = &mut tables[index];
+            if table.entries.iter().any(|(known_opcode, known_funct, _)| known_opcode == opcode && known_funct == funct) {
+                return Err(ParseMicrocodeError { line: *line, message: format!("the condition is already dispatched by `{}`", table.name) });
+            }
+            table.entries.push((*opcode, *funct, state));
+        }
+
+        let states = states.iter()
+            .map(|(line, name, signals, next)| {
+                let next = match next {
+                    Ok(state) => Next::State(resolve(*line, state)?),
+                    Err(table) => Next::Dispatch(tables.iter().position(|known| &known.name == table)
+                        .ok_or_else(|| ParseMicrocodeError { line: *line, message: format!("unknown dispatch table `{}`", table) })?),
+                };
+                Ok(State { name: name.clone(), signals: *signals, next })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { states, tables })
+    }
+
+    /// Checks that every state is reachable and finishes the instruction, and that no state asserts the signals
+    /// contradicting each other
+    pub fn validate(&self) -> Result<(), Vec<MicrocodeError>> {
+        let mut errors = Vec::new();
+        if !self.states[Self::FETCH].signals.ir_write {
+            errors.push(MicrocodeError::NoFetch(self.states[Self::FETCH].name.clone()));
+        }
+        for state in &self.states {
+            let signals = &state.signals;
+            let conflicts = [
+                (signals.mem_write && !signals.iord, "mem_write without iord overwrites the instruction"),
+                (signals.ir_write && signals.iord, "ir_write with iord loads the instruction from the data address"),
+                (signals.branch && signals.pc_write, "pc_write makes the branch unconditional"),
+                (signals.link && (signals.mem_to_reg || signals.reg_dst), "link overrides mem_to_reg and reg_dst"),
+            ];
+            for (_, reason) in conflicts.iter().filter(|(conflict, _)| *conflict) {
+                errors.push(MicrocodeError::ConflictingSignals { state: state.name.clone(), reason });
+            }
+        }
+
+        let reachable = self.reachable_from(Self::FETCH);
+        for (id, state) in self.states.iter().enumerate() {
+            if id != Self::FETCH && !reachable[id] {
+                errors.push(MicrocodeError::Unreachable(state.name.clone()));
+            } else if !self.reachable_from(id)[Self::FETCH] {
+                errors.push(MicrocodeError::NoReturnToFetch(state.name.clone()));
+            }
+        }
+        for (index, table) in self.tables.iter().enumerate() {
+            if !self.states.iter().any(|state| state.next == Next::Dispatch(index)) {
+                errors.push(MicrocodeError::UnusedTable(table.name.clone()));
+            }
+        }
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Graphviz diagram of the states, each labelled with its asserted signals. The dispatch transitions are labelled
+    /// with the table and the opcodes, or the opcodes and the functs, leading to the state
+    pub fn to_dot(&self) -> String {
+        let escape = |text: &str| text.replace('\\', "\\\\").replace('"', "\\\"");
+        let quote = |name: &str| format!("\"{}\"", escape(name));
+        let mut result = String::from("digraph fsm {\n    node [shape=box];\n");
+        for (id, state) in self.states.iter().enumerate() {
+            // The lines are joined by the escape sequence of Graphviz, so they are escaped one by one
+            let label: Vec<String> = [state.name.clone()].into_iter()
+                .chain(format_signals(&state.signals))
+                .map(|line| escape(&line))
+                .collect();
+            let fetch = if id == Self::FETCH { ", peripheries=2" } else { "" };
+            result.push_str(&format!("    {} [label=\"{}\"{}];\n", quote(&state.name), label.join("\\n"), fetch));
+        }
+        for state in &self.states {
+            match state.next {
+                Next::State(next) => result.push_str(&format!("    {} -> {};\n", quote(&state.name), quote(&self.states[next].name))),
+                Next::Dispatch(table) => {
+                    let table = &self.tables[table];
+                    // One edge to every state with all the conditions leading to it
+                    let mut targets: Vec<(StateId, Vec<String>)> = Vec::new();
+                    for (opcode, funct, next) in &table.entries {
+                        let condition = match funct {
+                            Some(funct) => format!("{}/{}", opcode, funct),
+                            None => opcode.to_string(),
+                        };
+                        match targets.iter_mut().find(|(target, _)| target == next) {
+                            Some((_, conditions)) => conditions.push(condition),
+                            None => targets.push((*next, vec![condition])),
+                        }
+                    }
+                    for (next, conditions) in targets {
+                        let label = format!("{} {}", table.name, conditions.join(", "));
+                        result.push_str(&format!(
+                            "    {} -> {} [label={}];\n",
+                            quote(&state.name), quote(&self.states[next].name), quote(&label),
+                        ));
+                    }
+                }
+            }
+        }
+        result.push_str("}\n");
+        result
+    }
+
+    pub fn state_name(&self, state: StateId) -> &str {
+        &self.states[state].name
+    }
+
+    pub fn state_id(&self, name: &str) -> Option<StateId> {
+        self.states.iter().position(|state| state.name == name)
+    }
+
+    pub(super) fn signals(&self, state: StateId) -> FSMDecision {
+        self.states[state].signals
+    }
+
+    /// The state after the state for the instruction, `None` when the dispatch table has no entry for it
+    pub(super) fn next(&self, state: StateId, opcode: u8, funct: u8) -> Option<StateId> {
+        match self.states[state].next {
+            Next::State(next) => Some(next),
+            Next::Dispatch(table) => {
+                let entries = &self.tables[table].entries;
+                entries.iter().find(|(known, known_funct, _)| *known == opcode && *known_funct == Some(funct))
+                    .or_else(|| entries.iter().find(|(known, known_funct, _)| *known == opcode && known_funct.is_none()))
+                    .map(|(_, _, next)| *next)
+            }
+        }
+    }
+
+    fn successors(&self, state: StateId) -> Vec<StateId> {
+        match self.states[state].next {
+            Next::State(next) => vec![next],
+            Next::Dispatch(table) => self.tables[table].entries.iter().map(|(_, _, next)| *next).collect(),
+        }
+    }
+
+    /// The states entered after the state in one or more cycles
+    fn reachable_from(&self, state: StateId) -> Vec<bool> {
+        let mut reached = vec![false; self.states.len()];
+        let mut stack = self.successors(state);
+        while let Some(next) = stack.pop() {
+            if !reached[next] {
+                reached[next] = true;
+                stack.extend(self.successors(next));
+            }
+        }
+        reached
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(text: &str) -> Vec<MicrocodeError> {
+        Microprogram::from_text(text).unwrap().validate().unwrap_err()
+    }
+
+    #[test]
+    fn default_program() {
+        let program = Microprogram::default();
+        assert_eq!(Ok(()), program.validate());
+        assert_eq!("Fetch", program.state_name(Microprogram::FETCH));
+        let decode = program.next(Microprogram::FETCH, 0, 0).unwrap();
+        assert_eq!("JumpRegister", program.state_name(program.next(decode, 0, 8).unwrap()));
+        assert_eq!("RTypeExecute", program.state_name(program.next(decode, 0, 32).unwrap()));
+        assert_eq!("BranchNotEqual", program.state_name(program.next(decode, 5, 0).unwrap()));
+        assert_eq!(None, program.next(decode, 1, 0));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |text: &str| Microprogram::from_text(text).unwrap_err();
+        assert_eq!(1, error("state Fetch: ir_write -> Decode").line);
+        assert!(error("state Fetch: ir_write -> Fetch\nstate Fetch: -> Fetch").message.contains("defined twice"));
+        assert!(error("state Fetch: ir_write pc_source=3 -> Fetch").message.contains("from 0 to 2"));
+        assert!(error("state Fetch: ir_write alu_control=1 -> Fetch").message.contains("alu_control"));
+        assert!(error("state Fetch: ir_write ir_write -> Fetch").message.contains("listed twice"));
+        assert!(error("state Fetch: jump -> Fetch").message.contains("unknown signal"));
+        assert!(error("state Fetch: ir_write -> dispatch opcode").message.contains("unknown dispatch table"));
+        let duplicate = "state Fetch: ir_write -> dispatch opcode\ndispatch opcode 2 -> Fetch\ndispatch opcode 2 -> Fetch";
+        assert_eq!(3, error(duplicate).line);
+        assert_eq!(
+            ParseMicrocodeError { line: 1, message: String::from("no states") },
+            error("# empty"),
+        );
+    }
+
+    #[test]
+    fn dot() {
+        let dot = Microprogram::default().to_dot();
+        assert!(dot.starts_with("digraph fsm {"));
+        assert!(dot.contains("    \"Fetch\" [label=\"Fetch\\nir_write\\npc_write\\nalu_source_b=1\", peripheries=2];\n"), "{}", dot);
+        assert!(dot.contains("    \"RTypeExecute\" [label=\"RTypeExecute\\nalu_src_a_reg\\nalu_control=funct\"];\n"), "{}", dot);
+        assert!(dot.contains("    \"Fetch\" -> \"Decode\";\n"));
+        assert!(dot.contains("    \"Decode\" -> \"JumpRegister\" [label=\"opcode 0/8\"];\n"));
+        assert!(dot.contains("    \"Decode\" -> \"ITypeAddressCompute\" [label=\"opcode 34, 43\"];\n"));
+        assert!(dot.contains("    \"ITypeAddressCompute\" -> \"ITypeMemoryWrite\" [label=\"memory 43\"];\n"));
+        assert!(dot.trim_end().ends_with('}'));
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(vec![MicrocodeError::NoFetch(String::from("Fetch"))], errors("state Fetch: pc_write -> Fetch"));
+        assert_eq!(
+            vec![MicrocodeError::ConflictingSignals { state: String::from("Fetch"), reason: "pc_write makes the branch unconditional" }],
+            errors("state Fetch: ir_write pc_write branch -> Fetch"),
+        );
+        assert_eq!(
+            vec![MicrocodeError::Unreachable(String::from("Store")), MicrocodeError::UnusedTable(String::from("memory"))],
+            errors("state Fetch: ir_write -> Fetch\nstate Store: iord mem_write -> Fetch\ndispatch memory 43 -> Store"),
+        );
+        assert_eq!(
+            vec![MicrocodeError::NoReturnToFetch(String::from("Loop"))],
+            errors("state Fetch: ir_write -> dispatch opcode\nstate Loop: -> Loop\ndispatch opcode 0 -> Fetch\ndispatch opcode 2 -> Loop"),
+        );
+    }
+}
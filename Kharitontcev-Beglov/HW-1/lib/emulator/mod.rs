@@ -0,0 +1,632 @@
+//! Multicycle MIPS emulator, see `InstructionSet.md` for the instructions and `emulator-parts.md` for the datapath.
+
+pub mod cp0;
+pub mod io;
+pub mod memory;
+pub mod microcode;
+pub mod snapshot;
+
+use std::fmt::{Display, Formatter};
+use crate::binary::instructions::{Cop0, RType, Service};
+use crate::emulator::cp0::{Coprocessor0, ExceptionCode};
+use crate::emulator::io::{BufferOutput, Input, InputError, Output};
+use crate::emulator::memory::{Memory, MemoryMap, MemoryMapError};
+use crate::emulator::microcode::{Microprogram, StateId};
+
+const REGISTERS_SIZE: usize = 32;
+
+#[allow(clippy::upper_case_acronyms)]
+struct ALU {
+    zero_flag: bool,
+    // Signed overflow of `add` and `sub`, which stops the program or raises the exception, `addu` and `subu` wrap
+    // around silently
+    overflow_flag: bool,
+}
+
+impl ALU {
+    /// Functs of the operations, the other R-type functs are not computed by the ALU
+    const FUNCTS: [u8; 8] = [32, 33, 34, 35, 36, 37, 39, 42];
+
+    pub fn new() -> Self {
+        ALU { zero_flag: false, overflow_flag: false }
+    }
+    pub fn reset(&mut self) {
+        self.zero_flag = false;
+        self.overflow_flag = false;
+    }
+    pub fn perform_operation(&mut self, lhs: i32, rhs: i32, funct: u8) -> i32 {
+        self.overflow_flag = match funct {
+            RType::ADD => lhs.checked_add(rhs).is_none(),
+            RType::SUB => lhs.checked_sub(rhs).is_none(),
+            _ => false,
+        };
+        let result = match funct {
+            RType::ADD | RType::ADDU => lhs.wrapping_add(rhs),
+            RType::SUB | RType::SUBU => lhs.wrapping_sub(rhs),
+            36 => lhs & rhs,
+            37 => lhs | rhs,
+            39 => !(lhs | rhs),
+            42 => if lhs < rhs { 1 } else { 0 }
+            _ => panic!("Invalid funct code={}", funct)
+        };
+        self.zero_flag = result == 0;
+        result
+    }
+    pub fn get_zero_flag(&mut self) -> bool {
+        self.zero_flag
+    }
+    pub fn get_overflow_flag(&self) -> bool {
+        self.overflow_flag
+    }
+}
+
+struct Registers {
+    /// Each register is able to store a word
+    data: [i32; REGISTERS_SIZE],
+}
+
+impl Registers {
+    const ZERO_REGISTER: usize = 0;
+    const SP: usize = 29;
+    pub fn new() -> Self {
+        Self {
+            data: [0; REGISTERS_SIZE]
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.data.fill(0)
+    }
+    pub fn get_value(&self, id: usize) -> i32 {
+        self.data[id]
+    }
+    pub fn set_value(&mut self, id: usize, new_value: i32) {
+        assert_ne!(Self::ZERO_REGISTER, id, "Can't alternate value for zeroth register");
+        self.data[id] = new_value
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+struct FSM {
+    program: Microprogram,
+    current_state: StateId,
+    opcode: u8,
+    funct: u8,
+}
+
+/// Control signals of a cycle, one state of the [`Microprogram`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct FSMDecision {
+    pub iord: bool,
+    pub mem_write: bool,
+    pub ir_write: bool,
+    pub pc_write: bool,
+    pub branch: bool,
+    pub pc_source: u8,
+    pub alu_control: u8,
+    // The ALU operation is the funct of the instruction instead of `alu_control`, and `add` and `sub` may overflow
+    pub alu_funct: bool,
+    pub alu_src_a_reg: bool,
+    pub alu_source_b: u8,
+    pub reg_write: bool,
+    pub mem_to_reg: bool,
+    pub reg_dst: bool,
+    pub negate_zero: bool,
+    // Writes the return address, which is `pc`, to $31 instead of the ALU or memory result to the `reg_dst` register
+    pub link: bool,
+}
+
+impl Default for FSMDecision {
+    fn default() -> Self {
+        Self {
+            iord: false,
+            mem_write: false,
+            ir_write: false,
+            pc_write: false,
+            branch: false,
+            pc_source: 0,
+            alu_control: RType::ADD,
+            alu_funct: false,
+            alu_src_a_reg: false,
+            alu_source_b: 0,
+            reg_write: false,
+            mem_to_reg: false,
+            reg_dst: false,
+            negate_zero: false,
+            link: false,
+        }
+    }
+}
+
+impl FSM {
+    pub fn new(program: Microprogram) -> Self {
+        Self { program, current_state: Microprogram::FETCH, opcode: 0, funct: 0 }
+    }
+    pub fn set_instruction(&mut self, op: u8, funct: u8) {
+        self.opcode = op;
+        self.funct = funct;
+    }
+    pub fn reset(&mut self) {
+        self.opcode = 0;
+        self.funct = 0;
+        self.current_state = Microprogram::FETCH;
+    }
+    pub fn is_fetch(&self) -> bool {
+        self.current_state == Microprogram::FETCH
+    }
+    pub fn state_name(&self) -> &str {
+        self.program.state_name(self.current_state)
+    }
+    pub fn get_decision(&mut self) -> FSMDecision {
+        let mut decision = self.program.signals(self.current_state);
+        if decision.alu_funct {
+            decision.alu_control = self.funct;
+        }
+        self.current_state = self.program.next(self.current_state, self.opcode, self.funct)
+            .unwrap_or_else(|| panic!("Invalid opcode {}!", self.opcode));
+        decision
+    }
+    pub fn is_print(&self) -> bool {
+        self.opcode == 0 && self.funct == 0
+    }
+    pub fn is_print_char(&self) -> bool {
+        self.opcode == 0 && self.funct == 1
+    }
+    pub fn is_print_string(&self) -> bool {
+        self.opcode == 63
+    }
+    pub fn is_halt(&self) -> bool {
+        self.opcode == 0 && self.funct == RType::HALT
+    }
+    pub fn is_syscall(&self) -> bool {
+        self.opcode == 0 && self.funct == RType::SYSCALL
+    }
+    pub fn is_cop0(&self) -> bool {
+        self.opcode == Cop0::OPCODE
+    }
+    /// Whether the instruction is known, the others raise the reserved instruction exception with the coprocessor
+    pub fn is_valid(&self, instruction: u32) -> bool {
+        let rs = ((instruction >> 21) & 0x1f) as u8;
+        match self.opcode {
+            0 => matches!(self.funct, 0 | 1 | 8 | RType::SYSCALL | RType::HALT) || ALU::FUNCTS.contains(&self.funct),
+            16 => rs == Cop0::MFC0 || rs == Cop0::MTC0 || (rs == Cop0::CO && self.funct == Cop0::ERET),
+            2 | 3 | 4 | 5 | 34 | 43 | 63 => true,
+            _ => false,
+        }
+    }
+}
+
+pub struct Emulator {
+    map: MemoryMap,
+    // End of the loaded code, the program exits when it gets there
+    code_end: usize,
+    pc: usize,
+    // Address of the instruction being executed, `pc` already points to the next one
+    instruction_address: usize,
+    memory: Memory,
+    alu: ALU,
+    registers: Registers,
+    fsm: FSM,
+    current_instruction: u32,
+    alu_output: i32,
+    data: i32,
+    operand_a: i32,
+    operand_b: i32,
+    cycles: u64,
+    instructions: u64,
+    status: Option<ExitStatus>,
+    cycle_limit: Option<u64>,
+    cp0: Option<Coprocessor0>,
+    output: Box<dyn Output>,
+    input: Box<dyn Input>,
+}
+
+/// How the program finished
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ExitStatus {
+    /// The program executed `halt` with the exit code or ran past the end of its code, which exits with zero
+    Exited(i32),
+    /// The program was stopped after the number of cycles
+    CycleLimitExceeded(u64),
+    /// `add` or `sub` at the address overflowed without the coprocessor 0 to handle the exception
+    Overflow { pc: u32 },
+    /// The read service called at the address could not read the value
+    InputError { pc: u32, error: InputError },
+    /// The instruction at `pc` accessed the address outside of the memory map without the coprocessor 0 to handle
+    /// the exception. A fetch outside of the map has its own address as `pc`
+    AddressError { pc: u32, address: u32 },
+}
+
+impl Display for ExitStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExitStatus::Exited(code) => write!(f, "Exited with code {}", code),
+            ExitStatus::CycleLimitExceeded(limit) => write!(f, "Cycle limit of {} exceeded", limit),
+            ExitStatus::Overflow { .. } => write!(f, "Arithmetic overflow"),
+            ExitStatus::InputError { error, .. } => write!(f, "{}", error),
+            ExitStatus::AddressError { address, .. } => write!(f, "Address {:#x} is outside of the memory map", address),
+        }
+    }
+}
+
+/// Result of [`Emulator::run_to_completion`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Completion {
+    pub output: String,
+    pub status: ExitStatus,
+    pub cycles: u64,
+    pub instructions: u64,
+}
+
+impl Emulator {
+    /// Loads the program with the default memory map
+    pub fn new(commands: Vec<u8>, initial_memory: Vec<u8>, output: Box<dyn Output>, input: Box<dyn Input>) -> Self {
+        Self::with_memory_map(commands, initial_memory, MemoryMap::default(), output, input)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Loads the code into the text segment and the memory into the data segment of the map
+    pub fn with_memory_map(
+        commands: Vec<u8>,
+        initial_memory: Vec<u8>,
+        map: MemoryMap,
+        output: Box<dyn Output>,
+        input: Box<dyn Input>,
+    ) -> Result<Self, MemoryMapError> {
+        let code_end = map.text.base as usize + commands.len();
+        let mut emulator = Self {
+            map,
+            code_end,
+            pc: 0,
+            instruction_address: 0,
+            memory: Memory::new(&map, commands, initial_memory)?,
+            alu: ALU::new(),
+            registers: Registers::new(),
+            fsm: FSM::new(Microprogram::default()),
+            current_instruction: 0,
+            alu_output: 0,
+            data: 0,
+            operand_a: 0,
+            operand_b: 0,
+            cycles: 0,
+            instructions: 0,
+            status: None,
+            cycle_limit: None,
+            cp0: None,
+            output,
+            input,
+        };
+        emulator.reset();
+        Ok(emulator)
+    }
+
+    /// Stops the program with [`ExitStatus::CycleLimitExceeded`] instead of running the cycle after the limit
+    pub fn with_cycle_limit(mut self, limit: u64) -> Self {
+        self.cycle_limit = Some(limit);
+        self
+    }
+
+    /// Handles the exceptions and the interrupts with the coprocessor instead of stopping the emulation. `syscall`
+    /// raises the exception as well instead of running the services
+    pub fn with_coprocessor(mut self, cp0: Coprocessor0) -> Self {
+        self.cp0 = Some(cp0);
+        self
+    }
+
+    /// Runs the control unit from the microprogram instead of the default one. The emulator does not check it,
+    /// see [`Microprogram::validate`]
+    pub fn with_microprogram(mut self, program: Microprogram) -> Self {
+        self.fsm = FSM::new(program);
+        self
+    }
+
+    pub fn coprocessor(&self) -> Option<&Coprocessor0> {
+        self.cp0.as_ref()
+    }
+
+    /// Runs the program with the input, the output is collected into the result
+    pub fn run_to_completion(commands: Vec<u8>, initial_memory: Vec<u8>, input: Box<dyn Input>, cycle_limit: Option<u64>) -> Completion {
+        let output = BufferOutput::new();
+        let mut emulator = Self::new(commands, initial_memory, Box::new(output.clone()), input);
+        emulator.cycle_limit = cycle_limit;
+        while !emulator.clock() {}
+        Completion {
+            output: output.text(),
+            status: emulator.status.unwrap(),
+            cycles: emulator.cycles,
+            instructions: emulator.instructions,
+        }
+    }
+
+    /// Restores the state after loading the program: the memory contents, `pc` at the start of the text segment
+    /// and the stack pointer at the end of the stack segment
+    pub fn reset(&mut self) {
+        self.pc = self.map.text.base as usize;
+        self.instruction_address = self.pc;
+        self.memory.reset();
+        self.registers.reset();
+        self.registers.set_value(Registers::SP, self.map.stack.end() as u32 as i32);
+        self.alu.reset();
+        self.fsm.reset();
+        if let Some(cp0) = &mut self.cp0 {
+            cp0.reset();
+        }
+        self.cycles = 0;
+        self.instructions = 0;
+        self.status = None;
+    }
+
+    /// Runs one cycle, returns whether the program has finished, then [`Emulator::status`] tells how
+    pub fn clock(&mut self) -> bool {
+        if self.status.is_some() {
+            return true;
+        }
+        if self.cycle_limit.is_some_and(|limit| self.cycles >= limit) {
+            self.status = Some(ExitStatus::CycleLimitExceeded(self.cycles));
+            return true;
+        }
+        // println!("Clock. PC={}", self.pc);
+        // println!("FSMState: {}", self.fsm.state_name());
+        // println!("Current instruction: {:#032b}. Opcode={}, funct={}", self.current_instruction, self.fsm.opcode, self.fsm.funct);
+        if self.fsm.is_fetch() && self.cp0.as_ref().is_some_and(Coprocessor0::interrupt_pending) {
+            // Taken before the next instruction, which is executed after `eret`
+            self.pc = self.raise(ExceptionCode::Interrupt, self.pc as u32, None) as usize;
+        }
+        let decision = self.fsm.get_decision();
+        self.cycles += 1;
+        if let Some(cp0) = &mut self.cp0 {
+            cp0.tick(self.cycles);
+        }
+        // println!("FSM Decision: {:?}", decision);
+        // println!("operand_a={}, operand_b={}, alu_output={}, data={}", self.operand_a, self.operand_b, self.alu_output, self.data);
+
+        // Instructions and data share the memory, `iord` selects the address
+        let address = if decision.iord { self.alu_output as u32 } else { self.pc as u32 };
+        let accessed = decision.ir_write || decision.iord;
+        // Only the fetch and the data access cycles use the memory, so the others do not touch unmapped addresses
+        let read = if accessed { self.memory.get_word_from_position(address) } else { Some(self.data) };
+        let misaligned = self.cp0.is_some() && accessed && !address.is_multiple_of(4);
+        let Some(mut read) = read.filter(|_| !misaligned) else {
+            let code = if decision.mem_write { ExceptionCode::AddressStore } else { ExceptionCode::AddressLoad };
+            // A faulting fetch faults at the fetched address itself
+            let faulting = if decision.ir_write { address } else { self.instruction_address as u32 };
+            return match self.address_error(code, faulting, address) {
+                Some(handler) => {
+                    self.pc = handler as usize;
+                    false
+                }
+                None => true,
+            };
+        };
+        // Set when the instruction leaves the sequential flow in another way than the datapath does
+        let mut redirect = None;
+
+        if decision.mem_write {
+            // println!("mem[{}]={}", address, self.operand_b);
+            self.memory.set_word_at_position(address, self.operand_b);
+            // The word is read back after the write
+            read = self.operand_b;
+        }
+
+        if decision.ir_write {
+            self.current_instruction = read as u32;
+            self.instruction_address = self.pc;
+            self.instructions += 1;
+            self.fsm.set_instruction(
+                ((self.current_instruction >> 26) & 0x3f) as u8,
+                (self.current_instruction & 0x3f) as u8,
+            );
+            let rs = (self.current_instruction >> 21) & 0x1f;
+            let rt = (self.current_instruction >> 16) & 0x1f;
+            self.operand_a = self.registers.get_value(rs as usize);
+            self.operand_b = self.registers.get_value(rt as usize);
+            // println!("Current instruction (pc={}): {:#034b}. Opcode={}, funct={}, rs={}, rt={}", self.pc, self.current_instruction, self.fsm.opcode, self.fsm.funct, rs, rt);
+            if self.fsm.is_print() {
+                self.print(&format!("{}\n", self.operand_a));
+                // println!("Register: ${}={}", rs, self.operand_a);
+                self.fsm.reset();
+            } else if self.fsm.is_print_char() {
+                self.print_char(self.operand_a);
+                self.fsm.reset();
+            } else if self.fsm.is_print_string() {
+                let immediate = self.current_instruction as u16 as i16 as i32;
+                let address = self.operand_a.wrapping_add(immediate) as u32;
+                if !self.print_string(address) {
+                    redirect = self.address_error(ExceptionCode::AddressLoad, self.instruction_address as u32, address);
+                }
+                self.fsm.reset();
+            } else if self.fsm.is_halt() {
+                self.status = Some(ExitStatus::Exited(self.operand_a));
+                self.fsm.reset();
+            } else if self.cp0.is_some() && !self.fsm.is_valid(self.current_instruction) {
+                redirect = Some(self.raise(ExceptionCode::ReservedInstruction, self.instruction_address as u32, None));
+            } else if self.fsm.is_syscall() {
+                if self.cp0.is_some() {
+                    redirect = Some(self.raise(ExceptionCode::Syscall, self.instruction_address as u32, None));
+                } else {
+                    self.syscall();
+                    self.fsm.reset();
+                }
+            } else if self.fsm.is_cop0() && self.cp0.is_some() {
+                redirect = self.coprocessor_instruction();
+                self.fsm.reset();
+            }
+        }
+
+        if decision.reg_write {
+            let data = if decision.link { self.pc as i32 } else if decision.mem_to_reg { self.data } else { self.alu_output };
+            let res_reg = if decision.link {
+                31
+            } else if decision.reg_dst {
+                (self.current_instruction >> 11) & 0x1f
+            } else {
+                (self.current_instruction >> 16) & 0x1f
+            };
+            // println!("new reg #{}={}", res_reg, data);
+            self.registers.set_value(res_reg as usize, data);
+        }
+
+        let alu_lhs: i32 = if decision.alu_src_a_reg { self.operand_a } else { self.pc as i32 };
+        // The 16-bit immediate is sign extended, so branches may go backward
+        let immediate = self.current_instruction as u16 as i16 as i32;
+        let alu_rhs: i32 = match decision.alu_source_b {
+            0 => self.operand_b,
+            1 => 4,
+            2 => immediate,
+            3 => immediate << 2,
+            _ => panic!("Invalid alu_source_b: {}", decision.alu_source_b)
+        };
+
+        let result = self.alu.perform_operation(alu_lhs, alu_rhs, decision.alu_control);
+        if decision.alu_funct && self.alu.get_overflow_flag() {
+            // The result is not written back
+            match self.cp0 {
+                Some(_) => redirect = Some(self.raise(ExceptionCode::Overflow, self.instruction_address as u32, None)),
+                None => self.status = Some(ExitStatus::Overflow { pc: self.instruction_address as u32 }),
+            }
+        }
+        let pc_en = (decision.branch & (self.alu.get_zero_flag() ^ decision.negate_zero)) | (decision.pc_write);
+        if pc_en {
+            self.pc = match decision.pc_source {
+                0 => result as usize,
+                1 => self.alu_output as usize,
+                2 => ((self.pc >> 28) << 28) | ((self.current_instruction as usize & 0x3ffffff) << 2),
+                _ => panic!("Invalid PC source state: {}", decision.pc_source)
+            };
+            // println!("new pc={}, branch={}, zero={}, negate_zero={}", self.pc,decision.branch, self.alu.get_zero_flag(), decision.negate_zero);;
+        }
+        if let Some(address) = redirect {
+            self.pc = address as usize;
+        }
+        self.alu_output = result;
+        self.data = read;
+        // println!();
+        if self.pc == self.code_end && self.fsm.is_fetch() {
+            self.status.get_or_insert(ExitStatus::Exited(0));
+        }
+        self.status.is_some()
+    }
+
+    /// How the program finished, `None` while it is running
+    pub fn status(&self) -> Option<ExitStatus> {
+        self.status
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn instruction_address(&self) -> usize {
+        self.instruction_address
+    }
+
+    /// Whether the next clock fetches a new instruction at `pc`
+    pub fn is_instruction_start(&self) -> bool {
+        self.fsm.is_fetch()
+    }
+
+    /// Name of the microprogram state running in the next cycle
+    pub fn control_state(&self) -> &str {
+        self.fsm.state_name()
+    }
+
+    /// Number of clock cycles since the start
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Number of fetched instructions since the start
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn register(&self, id: usize) -> i32 {
+        self.registers.get_value(id)
+    }
+
+    /// Word at the address, `None` outside of the memory map
+    pub fn read_word(&self, address: usize) -> Option<i32> {
+        self.memory.get_word_from_position(address as u32)
+    }
+
+    pub fn memory_map(&self) -> &MemoryMap {
+        &self.map
+    }
+
+    fn print(&mut self, text: &str) {
+        if let Err(error) = self.output.print(text) {
+            panic!("Failed to print the output: {}", error)
+        }
+    }
+
+    fn print_char(&mut self, code: i32) {
+        self.print(&char::from_u32(code as u32).unwrap_or(char::REPLACEMENT_CHARACTER).to_string());
+    }
+
+    /// Prints the string at the address, returns whether the address is inside of the memory map
+    fn print_string(&mut self, address: u32) -> bool {
+        let Some(bytes) = self.memory.get_string_from_position(address) else { return false };
+        let text = String::from_utf8_lossy(bytes).into_owned();
+        self.print(&text);
+        true
+    }
+
+    /// Raises the address error exception of the instruction at `pc`, or stops the emulation without the
+    /// coprocessor. Returns the handler address for `pc`
+    fn address_error(&mut self, code: ExceptionCode, pc: u32, address: u32) -> Option<u32> {
+        match self.cp0 {
+            Some(_) => Some(self.raise(code, pc, Some(address))),
+            None => {
+                self.status = Some(ExitStatus::AddressError { pc, address });
+                None
+            }
+        }
+    }
+
+    /// Enters the exception handler, the instruction is abandoned. Returns the handler address for `pc`
+    fn raise(&mut self, code: ExceptionCode, address: u32, bad_address: Option<u32>) -> u32 {
+        self.fsm.reset();
+        self.cp0.as_mut().unwrap().enter(code, address, bad_address)
+    }
+
+    /// Runs `mfc0`, `mtc0` or `eret`, returns the address `eret` returns to
+    fn coprocessor_instruction(&mut self) -> Option<u32> {
+        let rs = ((self.current_instruction >> 21) & 0x1f) as u8;
+        let rt = ((self.current_instruction >> 16) & 0x1f) as usize;
+        let rd = ((self.current_instruction >> 11) & 0x1f) as u8;
+        let cp0 = self.cp0.as_mut().unwrap();
+        match rs {
+            Cop0::MFC0 if rt != Registers::ZERO_REGISTER => self.registers.set_value(rt, cp0.read(rd) as i32),
+            Cop0::MFC0 => {}
+            Cop0::MTC0 => cp0.write(rd, self.operand_b as u32),
+            _ => return Some(cp0.eret()),
+        }
+        None
+    }
+
+    fn syscall(&mut self) {
+        const V0: usize = 2;
+        const A0: usize = 4;
+        let code = self.registers.get_value(V0);
+        let argument = self.registers.get_value(A0);
+        match Service::from_code(code) {
+            Some(Service::PrintInt) => self.print(&argument.to_string()),
+            Some(Service::PrintString) => {
+                if !self.print_string(argument as u32) {
+                    self.address_error(ExceptionCode::AddressLoad, self.instruction_address as u32, argument as u32);
+                }
+            }
+            Some(Service::ReadInt) => match self.input.read_int() {
+                Ok(value) => self.registers.set_value(V0, value),
+                Err(error) => self.status = Some(ExitStatus::InputError { pc: self.instruction_address as u32, error }),
+            },
+            Some(Service::Exit) => self.status = Some(ExitStatus::Exited(0)),
+            Some(Service::PrintChar) => self.print_char(argument),
+            Some(Service::Exit2) => self.status = Some(ExitStatus::Exited(argument)),
+            None => panic!("Unknown syscall service {}", code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
\ No newline at end of file
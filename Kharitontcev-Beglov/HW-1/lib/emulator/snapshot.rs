@@ -0,0 +1,288 @@
+//! Snapshots of a running emulator: the `pc`, the registers, the memory, the state of the control unit, the latches
+//! between the cycles, the counters and the coprocessor registers. Restoring a snapshot continues the emulation
+//! from the same cycle, so the program may be stepped back or resumed later from a file.
+//!
+//! The input and the output are not a part of the snapshot: the printed text stays printed and the read input is not
+//! read again. The configuration is neither: the memory map, the microprogram and the coprocessor of the emulator
+//! restoring the snapshot should be the same as of the one taking it.
+//!
+//! The file lists the non-zero words of the memory and the registers, the rest is zero. The segments are allocated
+//! while the file is parsed, so they may not be larger than the ones of [`MemoryMap::default`]:
+//!
+//! ```text
+//! segment text 400000 100000
+//! pc 400004
+//! instruction 400000 8c080000
+//! state Decode 34 0
+//! reg 8 0000002a
+//! mem 00400000 8c080000
+//! ```
+
+use std::fmt::{Display, Formatter};
+use crate::emulator::{Emulator, ExitStatus, REGISTERS_SIZE};
+use crate::emulator::memory::{MemoryMap, Segment};
+
+/// Saved state of an [`Emulator`], see [`Emulator::snapshot`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Snapshot {
+    map: MemoryMap,
+    memory: Vec<Vec<u8>>,
+    pc: u32,
+    instruction_address: u32,
+    current_instruction: u32,
+    /// Name of the microprogram state of the next cycle
+    state: String,
+    opcode: u8,
+    funct: u8,
+    registers: [i32; REGISTERS_SIZE],
+    alu_output: i32,
+    data: i32,
+    operand_a: i32,
+    operand_b: i32,
+    zero_flag: bool,
+    overflow_flag: bool,
+    cycles: u64,
+    instructions: u64,
+    /// Exit code of the finished program. Reaching the cycle limit is not saved, so the run may be resumed
+    exit_code: Option<i32>,
+    /// `BadVAddr`, `Status`, `Cause` and `EPC`
+    cp0: Option<[u32; 4]>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SnapshotError {
+    /// The snapshot was taken with another memory map
+    MemoryMapMismatch,
+    /// The microprogram of the emulator has no state of the snapshot
+    UnknownState(String),
+    /// The snapshot was taken with the coprocessor and the emulator has none, or the other way around
+    CoprocessorMismatch,
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::MemoryMapMismatch => write!(f, "The snapshot was taken with another memory map"),
+            SnapshotError::UnknownState(state) => write!(f, "The microprogram has no state {} of the snapshot", state),
+            SnapshotError::CoprocessorMismatch => write!(f, "The snapshot and the emulator differ in the coprocessor 0"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseSnapshotError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ParseSnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid snapshot file at line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseSnapshotError {}
+
+impl Emulator {
+    /// Saves the state of the emulation at the current cycle
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            map: self.map,
+            memory: self.memory.contents(),
+            pc: self.pc as u32,
+            instruction_address: self.instruction_address as u32,
+            current_instruction: self.current_instruction,
+            state: self.fsm.state_name().to_string(),
+            opcode: self.fsm.opcode,
+            funct: self.fsm.funct,
+            registers: self.registers.data,
+            alu_output: self.alu_output,
+            data: self.data,
+            operand_a: self.operand_a,
+            operand_b: self.operand_b,
+            zero_flag: self.alu.zero_flag,
+            overflow_flag: self.alu.overflow_flag,
+            cycles: self.cycles,
+            instructions: self.instructions,
+            exit_code: match self.status {
+                Some(ExitStatus::Exited(code)) => Some(code),
+                _ => None,
+            },
+            cp0: self.cp0.as_ref().map(|cp0| [cp0.bad_address(), cp0.status(), cp0.cause(), cp0.epc()]),
+        }
+    }
+
+    /// Continues the emulation from the snapshot. Nothing is changed when the snapshot does not fit the emulator
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        if snapshot.map != self.map {
+            return Err(SnapshotError::MemoryMapMismatch);
+        }
+        if snapshot.cp0.is_some() != self.cp0.is_some() {
+            return Err(SnapshotError::CoprocessorMismatch);
+        }
+        let state = self.fsm.program.state_id(&snapshot.state)
+            .ok_or_else(|| SnapshotError::UnknownState(snapshot.state.clone()))?;
+        self.memory.set_contents(&snapshot.memory);
+        self.pc = snapshot.pc as usize;
+        self.instruction_address = snapshot.instruction_address as usize;
+        self.current_instruction = snapshot.current_instruction;
+        self.fsm.current_state = state;
+        self.fsm.set_instruction(snapshot.opcode, snapshot.funct);
+        self.registers.data = snapshot.registers;
+        self.alu_output = snapshot.alu_output;
+        self.data = snapshot.data;
+        self.operand_a = snapshot.operand_a;
+        self.operand_b = snapshot.operand_b;
+        self.alu.zero_flag = snapshot.zero_flag;
+        self.alu.overflow_flag = snapshot.overflow_flag;
+        self.cycles = snapshot.cycles;
+        self.instructions = snapshot.instructions;
+        self.status = snapshot.exit_code.map(ExitStatus::Exited);
+        if let (Some(cp0), Some([bad_address, status, cause, epc])) = (&mut self.cp0, snapshot.cp0) {
+            cp0.restore(bad_address, status, cause, epc);
+        }
+        Ok(())
+    }
+}
+
+fn words(bytes: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    bytes.chunks(4).map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+}
+
+impl Snapshot {
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut result = String::new();
+        for (name, segment) in self.map.segments() {
+            result.push_str(&format!("segment {} {:x} {:x}\n", name, segment.base, segment.size));
+        }
+        result.push_str(&format!("pc {:x}\n", self.pc));
+        result.push_str(&format!("instruction {:x} {:08x}\n", self.instruction_address, self.current_instruction));
+        result.push_str(&format!("state {} {} {}\n", self.state, self.opcode, self.funct));
+        result.push_str(&format!(
+            "latches {:08x} {:08x} {:08x} {:08x}\n",
+            self.alu_output, self.data, self.operand_a, self.operand_b,
+        ));
+        result.push_str(&format!("flags {} {}\n", self.zero_flag as u8, self.overflow_flag as u8));
+        result.push_str(&format!("cycles {}\n", self.cycles));
+        result.push_str(&format!("instructions {}\n", self.instructions));
+        if let Some(code) = self.exit_code {
+            result.push_str(&format!("exited {}\n", code));
+        }
+        if let Some([bad_address, status, cause, epc]) = self.cp0 {
+            result.push_str(&format!("cp0 {:08x} {:08x} {:08x} {:08x}\n", bad_address, status, cause, epc));
+        }
+        for (id, value) in self.registers.iter().enumerate().filter(|(_, value)| **value != 0) {
+            result.push_str(&format!("reg {} {:08x}\n", id, value));
+        }
+        for ((_, segment), bytes) in self.map.segments().iter().zip(&self.memory) {
+            for (index, word) in words(bytes).enumerate().filter(|(_, word)| *word != 0) {
+                result.push_str(&format!("mem {:08x} {:08x}\n", segment.base as usize + index * 4, word));
+            }
+        }
+        result
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, ParseSnapshotError> {
+        let mut segments: Vec<(String, Segment)> = Vec::new();
+        let mut pc = None;
+        let mut instruction = None;
+        let mut state = None;
+        let mut latches = None;
+        let mut flags = None;
+        let mut cycles = None;
+        let mut instructions = None;
+        let mut exit_code = None;
+        let mut cp0 = None;
+        let mut registers = [0; REGISTERS_SIZE];
+        let mut words = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: &str| ParseSnapshotError { line: index + 1, message: message.to_string() };
+            let hex = |word: &str| u32::from_str_radix(word, 16).map_err(|_| error("invalid hexadecimal number"));
+            let number = |number: &str| number.parse::<u64>().map_err(|_| error("invalid number"));
+            let flag = |flag: &str| match flag {
+                "0" => Ok(false),
+                "1" => Ok(true),
+                _ => Err(error("expected 0 or 1")),
+            };
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.as_slice() {
+                [] => {}
+                ["segment", name, base, size] => {
+                    let size = hex(size)?;
+                    if MemoryMap::default().segments().iter().any(|(known, default)| known == name && size > default.size) {
+                        return Err(error("the segment is larger than the default one"));
+                    }
+                    segments.push((name.to_string(), Segment::new(hex(base)?, size)))
+                }
+                ["pc", address] => pc = Some(hex(address)?),
+                ["instruction", address, word] => instruction = Some((hex(address)?, hex(word)?)),
+                ["state", name, opcode, funct] => {
+                    let field = |field: &str| field.parse::<u8>().ok().filter(|field| *field < 64).ok_or_else(|| error("invalid opcode or funct"));
+                    state = Some((name.to_string(), field(opcode)?, field(funct)?))
+                }
+                ["latches", alu_output, data, operand_a, operand_b] => {
+                    latches = Some([hex(alu_output)? as i32, hex(data)? as i32, hex(operand_a)? as i32, hex(operand_b)? as i32])
+                }
+                ["flags", zero, overflow] => flags = Some((flag(zero)?, flag(overflow)?)),
+                ["cycles", count] => cycles = Some(number(count)?),
+                ["instructions", count] => instructions = Some(number(count)?),
+                ["exited", code] => exit_code = Some(code.parse::<i32>().map_err(|_| error("invalid exit code"))?),
+                ["cp0", bad_address, status, cause, epc] => cp0 = Some([hex(bad_address)?, hex(status)?, hex(cause)?, hex(epc)?]),
+                ["reg", id, value] => {
+                    let id = id.parse::<usize>().ok().filter(|id| (1..REGISTERS_SIZE).contains(id)).ok_or_else(|| error("invalid register"))?;
+                    registers[id] = hex(value)? as i32;
+                }
+                ["mem", address, word] => words.push((index + 1, hex(address)?, hex(word)?)),
+                _ => return Err(error("expected `segment`, `pc`, `instruction`, `state`, `latches`, `flags`, `cycles`, `instructions`, `exited`, `cp0`, `reg` or `mem` line")),
+            }
+        }
+
+        let missing = |name: &str| ParseSnapshotError { line: 1, message: format!("missing `{}` line", name) };
+        let segment = |name: &str| segments.iter().find(|(known, _)| known == name).map(|(_, segment)| *segment).ok_or_else(|| missing("segment"));
+        let map = MemoryMap { text: segment("text")?, data: segment("data")?, heap: segment("heap")?, stack: segment("stack")? };
+        map.validate().map_err(|error| ParseSnapshotError { line: 1, message: error.to_string() })?;
+        let mut memory: Vec<Vec<u8>> = map.segments().iter().map(|(_, segment)| vec![0; segment.size as usize]).collect();
+        for (line, address, word) in words {
+            let (index, segment) = map.segments().iter().map(|(_, segment)| *segment).enumerate()
+                .find(|(_, segment)| address.is_multiple_of(4) && segment.base <= address && address as u64 + 4 <= segment.end())
+                .ok_or_else(|| ParseSnapshotError { line, message: String::from("the word is outside of the memory map") })?;
+            let offset = (address - segment.base) as usize;
+            memory[index][offset..offset + 4].copy_from_slice(&word.to_be_bytes());
+        }
+        let (instruction_address, current_instruction) = instruction.ok_or_else(|| missing("instruction"))?;
+        let (state, opcode, funct) = state.ok_or_else(|| missing("state"))?;
+        let [alu_output, data, operand_a, operand_b] = latches.ok_or_else(|| missing("latches"))?;
+        let (zero_flag, overflow_flag) = flags.ok_or_else(|| missing("flags"))?;
+        Ok(Self {
+            map,
+            memory,
+            pc: pc.ok_or_else(|| missing("pc"))?,
+            instruction_address,
+            current_instruction,
+            state,
+            opcode,
+            funct,
+            registers,
+            alu_output,
+            data,
+            operand_a,
+            operand_b,
+            zero_flag,
+            overflow_flag,
+            cycles: cycles.ok_or_else(|| missing("cycles"))?,
+            instructions: instructions.ok_or_else(|| missing("instructions"))?,
+            exit_code,
+            cp0,
+        })
+    }
+}
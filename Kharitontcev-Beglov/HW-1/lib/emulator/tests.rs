@@ -0,0 +1,970 @@
+use super::*;
+use crate::binary::link::Linker;
+use crate::emulator::io::BufferInput;
+use crate::emulator::cp0::{Coprocessor0, ExceptionCode};
+use crate::emulator::memory::Segment;
+use crate::emulator::snapshot::{Snapshot, SnapshotError};
+
+const TEXT: u32 = Linker::DEFAULT_LOAD_ADDRESS;
+
+#[test]
+fn test_alu_addition() {
+    let mut alu = ALU::new();
+    assert_eq!(239 + 566, alu.perform_operation(239, 566, 32));
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn test_alu_add_negative() {
+    let mut alu = ALU::new();
+    assert_eq!(5 + -3, alu.perform_operation(5, -3, 32));
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn test_alu_add_zero() {
+    let mut alu = ALU::new();
+    assert_eq!(-2 + 2, alu.perform_operation(-2, 2, 32));
+    assert!(alu.get_zero_flag());
+}
+
+#[test]
+fn test_alu_overflow() {
+    let mut alu = ALU::new();
+    assert_eq!(i32::MIN, alu.perform_operation(i32::MAX, 1, 32));
+    assert!(!alu.get_zero_flag());
+    assert!(alu.get_overflow_flag());
+    assert_eq!(i32::MAX, alu.perform_operation(i32::MIN, 1, 34));
+    assert!(alu.get_overflow_flag());
+    assert_eq!(-2, alu.perform_operation(-1, -1, 32));
+    assert!(!alu.get_overflow_flag());
+}
+
+#[test]
+fn test_alu_unsigned() {
+    let mut alu = ALU::new();
+    assert_eq!(i32::MIN, alu.perform_operation(i32::MAX, 1, 33));
+    assert!(!alu.get_overflow_flag());
+    assert_eq!(i32::MAX, alu.perform_operation(i32::MIN, 1, 35));
+    assert!(!alu.get_overflow_flag());
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn test_alu_sub() {
+    let mut alu = ALU::new();
+    assert_eq!(5 - 3, alu.perform_operation(5, 3, 34));
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn test_alu_sub_neg() {
+    let mut alu = ALU::new();
+    assert_eq!(2 - 5, alu.perform_operation(2, 5, 34));
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn alu_sub_zero() {
+    let mut alu = ALU::new();
+    assert_eq!(5 - 5, alu.perform_operation(5, 5, 34));
+    assert!(alu.get_zero_flag());
+}
+
+#[test]
+fn alu_sub_overflow() {
+    let mut alu = ALU::new();
+    assert_eq!(i32::MAX, alu.perform_operation(i32::MIN, 1, 34));
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn alu_and_positive() {
+    let mut alu = ALU::new();
+    assert_eq!(7 & 5, alu.perform_operation(7, 5, 36));
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn alu_and_negative() {
+    let mut alu = ALU::new();
+    assert_eq!(-7 & -5, alu.perform_operation(-7, -5, 36));
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn alu_and_different_signs() {
+    let mut alu = ALU::new();
+    assert_eq!(-7 & 5, alu.perform_operation(-7, 5, 36));
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn alu_and_zero() {
+    let mut alu = ALU::new();
+    assert_eq!(2 & 4, alu.perform_operation(2, 4, 36));
+    assert!(alu.get_zero_flag());
+}
+
+#[test]
+fn alu_or_positive() {
+    let mut alu = ALU::new();
+    assert_eq!(7 | 5, alu.perform_operation(7, 5, 37));
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn alu_or_negative() {
+    let mut alu = ALU::new();
+    assert_eq!(-7 | -5, alu.perform_operation(-6, -5, 37));
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn alu_or_different_signs() {
+    let mut alu = ALU::new();
+    assert_eq!(-7 | 5, alu.perform_operation(-7, 5, 37));
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn alu_or_zero() {
+    let mut alu = ALU::new();
+    assert_eq!(0, alu.perform_operation(0, 0, 37));
+    assert!(alu.get_zero_flag());
+}
+
+#[test]
+fn alu_nor_positive() {
+    let mut alu = ALU::new();
+    assert_eq!(!(7 | 5), alu.perform_operation(7, 5, 39));
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn alu_nor_negative() {
+    let mut alu = ALU::new();
+    assert_eq!(!(-7 | -5), alu.perform_operation(-6, -5, 39));
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn alu_nor_different_signs() {
+    let mut alu = ALU::new();
+    assert_eq!(!(-7 | 5), alu.perform_operation(-7, 5, 39));
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn alu_reset() {
+    let mut alu = ALU::new();
+    alu.perform_operation(0, 0, 32);
+    assert!(alu.get_zero_flag());
+    alu.reset();
+    assert!(!alu.get_zero_flag());
+}
+
+#[test]
+fn registers_default_zero() {
+    let regs = Registers::new();
+    for i in 0..32 {
+        assert_eq!(0, regs.get_value(i));
+    }
+}
+
+#[test]
+#[should_panic]
+fn registers_zero_set() {
+    let mut regs = Registers::new();
+    regs.set_value(0, 0x5051);
+}
+
+#[test]
+#[should_panic]
+fn registers_too_big() {
+    let mut regs = Registers::new();
+    regs.set_value(239, 1);
+}
+
+#[test]
+fn registers_set() {
+    let mut regs = Registers::new();
+    for i in 1..32 {
+        regs.set_value(i, 1 << i);
+    }
+    assert_eq!(0, regs.get_value(0));
+    for i in 1..32 {
+        assert_eq!(1 << i, regs.get_value(i));
+    }
+    for i in 1..32 {
+        regs.set_value(i, 0);
+    }
+    for i in 1..32 {
+        assert_eq!(0, regs.get_value(i));
+    }
+    for i in (1..32).rev() {
+        regs.set_value(i, 1 << i);
+    }
+    assert_eq!(0, regs.get_value(0));
+    for i in (1..32).rev() {
+        assert_eq!(1 << i, regs.get_value(i));
+    }
+}
+
+#[test]
+fn registers_reset() {
+    let mut regs = Registers::new();
+    for i in 1..32 {
+        regs.set_value(i, 1 << i);
+    }
+    assert_eq!(0, regs.get_value(0));
+    for i in 1..32 {
+        assert_eq!(1 << i, regs.get_value(i));
+    }
+    for i in 1..32 {
+        regs.set_value(i, 0);
+    }
+    regs.reset();
+    for i in 0..32 {
+        assert_eq!(0, regs.get_value(i));
+    }
+}
+
+fn memory(data: Vec<u8>) -> Memory {
+    Memory::new(&MemoryMap::default(), Vec::new(), data).unwrap()
+}
+
+/// Word addresses of the data segment
+fn data_words() -> impl Iterator<Item = u32> {
+    let data = MemoryMap::default().data;
+    (data.base..data.end() as u32).step_by(4)
+}
+
+#[test]
+fn memory_initial() {
+    let memory = memory(Vec::new());
+    for i in data_words() {
+        assert_eq!(Some(0), memory.get_word_from_position(i));
+    }
+    let memory = self::memory(239i32.to_be_bytes().to_vec());
+    for i in data_words() {
+        assert_eq!(Some(if i == 0 { 239 } else { 0 }), memory.get_word_from_position(i));
+    }
+}
+
+#[test]
+fn test_set() {
+    let mut memory = memory(Vec::new());
+    memory.set_word_at_position(0, -1);
+    assert_eq!(Some(-1), memory.get_word_from_position(0));
+    assert_eq!(Some(-256), memory.get_word_from_position(1)); // overlapping works fine
+}
+
+#[test]
+fn memory_reset() {
+    let mut inital_memory = memory(Vec::new());
+    for i in data_words() {
+        assert_eq!(Some(0), inital_memory.get_word_from_position(i));
+    }
+    for i in data_words() {
+        inital_memory.set_word_at_position(i, -1);
+    }
+    for i in data_words() {
+        assert_eq!(Some(-1), inital_memory.get_word_from_position(i));
+    }
+    inital_memory.reset();
+    for i in data_words() {
+        assert_eq!(Some(0), inital_memory.get_word_from_position(i));
+    }
+}
+
+#[test]
+fn memory_segments() {
+    let map = MemoryMap::default();
+    let memory = Memory::new(&map, 7i32.to_be_bytes().to_vec(), Vec::new()).unwrap();
+    assert_eq!(Some(7), memory.get_word_from_position(map.text.base));
+    // The heap and the stack follow the data, so a word may be accessed at their boundary
+    assert_eq!(Some(0), memory.get_word_from_position(map.stack.end() as u32 - 4));
+    assert_eq!(map.data.end(), map.heap.base as u64);
+}
+
+#[test]
+fn memory_outside_of_map() {
+    let mut memory = memory(Vec::new());
+    assert_eq!(None, memory.get_word_from_position(0x10_0000));
+    // The word crosses the end of the stack segment
+    assert_eq!(None, memory.get_word_from_position(0xf_fffe));
+    assert_eq!(None, memory.get_string_from_position(0x10_0000));
+    memory.set_word_at_position(0x10_0000, 1);
+    assert_eq!(None, memory.get_word_from_position(0x10_0000));
+}
+
+#[test]
+fn memory_map_errors() {
+    let map = MemoryMap::default();
+    assert_eq!(Ok(()), map.validate());
+    let overlapping = MemoryMap { heap: Segment::new(0x8000, 0x1000), ..map };
+    assert_eq!(Err(MemoryMapError::Overlap("data", "heap")), overlapping.validate());
+    let unaligned = MemoryMap { text: Segment::new(0x40_0002, 0x100), ..map };
+    assert_eq!(Err(MemoryMapError::UnalignedSegment("text")), unaligned.validate());
+    let unaligned_size = MemoryMap { heap: Segment::new(0x1_0000, 0x7_0002), ..map };
+    assert_eq!(Err(MemoryMapError::UnalignedSize("heap")), unaligned_size.validate());
+    let outside = MemoryMap { stack: Segment::new(0xffff_0000, 0x1_0004), ..map };
+    assert_eq!(Err(MemoryMapError::OutOfAddressSpace("stack")), outside.validate());
+    let small = MemoryMap { data: Segment::new(0, 4), ..map };
+    assert_eq!(
+        Some(MemoryMapError::TooLarge { segment: "data", size: 8 }),
+        Memory::new(&small, Vec::new(), vec![0; 8]).err(),
+    );
+}
+
+fn test_fetch_decode(fsm: &mut FSM, opcode: u8, funct: u8) {
+    assert_eq!("Fetch", fsm.state_name());
+    let fetch = fsm.get_decision();
+    assert!(!fetch.iord);
+    assert!(!fetch.alu_src_a_reg);
+    assert_eq!(1, fetch.alu_source_b);
+    assert_eq!(32, fetch.alu_control);
+    assert_eq!(0, fetch.pc_source);
+    assert!(fetch.ir_write);
+    assert!(fetch.pc_write);
+    fsm.set_instruction(opcode, funct);
+
+    assert_eq!("Decode", fsm.state_name());
+    let decode = fsm.get_decision();
+    assert!(!decode.alu_src_a_reg);
+    assert_eq!(3, decode.alu_source_b);
+    assert_eq!(32, decode.alu_control);
+}
+
+#[test]
+fn fsm_j_type() {
+    let mut fsm = FSM::new(Microprogram::default());
+    test_fetch_decode(&mut fsm, 2, 0);
+    assert_eq!("JType", fsm.state_name());
+    let j = fsm.get_decision();
+    assert_eq!(2, j.pc_source);
+    assert!(j.pc_write);
+    assert_eq!("Fetch", fsm.state_name());
+}
+
+#[test]
+fn fsm_jal() {
+    let mut fsm = FSM::new(Microprogram::default());
+    test_fetch_decode(&mut fsm, 3, 0);
+    assert_eq!("JumpAndLink", fsm.state_name());
+    let jal = fsm.get_decision();
+    assert_eq!(2, jal.pc_source);
+    assert!(jal.pc_write);
+    assert!(jal.reg_write);
+    assert!(jal.link);
+    assert_eq!("Fetch", fsm.state_name());
+}
+
+#[test]
+fn fsm_jr() {
+    let mut fsm = FSM::new(Microprogram::default());
+    test_fetch_decode(&mut fsm, 0, 8);
+    assert_eq!("JumpRegister", fsm.state_name());
+    let jr = fsm.get_decision();
+    assert!(jr.alu_src_a_reg);
+    assert_eq!(0, jr.alu_source_b);
+    assert_eq!(32, jr.alu_control);
+    assert_eq!(0, jr.pc_source);
+    assert!(jr.pc_write);
+    assert!(!jr.reg_write);
+    assert_eq!("Fetch", fsm.state_name());
+}
+
+#[test]
+fn fsm_beq() {
+    let mut fsm = FSM::new(Microprogram::default());
+    test_fetch_decode(&mut fsm, 4, 0);
+    assert_eq!("BranchEqual", fsm.state_name());
+    let b = fsm.get_decision();
+    assert!(b.alu_src_a_reg);
+    assert_eq!(0, b.alu_source_b);
+    assert_eq!(34, b.alu_control);
+    assert_eq!(1, b.pc_source);
+    assert!(b.branch);
+    assert!(!b.negate_zero);
+
+    assert_eq!("Fetch", fsm.state_name());
+}
+
+#[test]
+fn fsm_bne() {
+    let mut fsm = FSM::new(Microprogram::default());
+    test_fetch_decode(&mut fsm, 5, 0);
+    assert_eq!("BranchNotEqual", fsm.state_name());
+    let b = fsm.get_decision();
+    assert!(b.alu_src_a_reg);
+    assert_eq!(0, b.alu_source_b);
+    assert_eq!(34, b.alu_control);
+    assert_eq!(1, b.pc_source);
+    assert!(b.branch);
+    assert!(b.negate_zero);
+
+    assert_eq!("Fetch", fsm.state_name());
+}
+
+#[test]
+fn fsm_sw() {
+    let mut fsm = FSM::new(Microprogram::default());
+    test_fetch_decode(&mut fsm, 43, 0);
+    assert_eq!("ITypeAddressCompute", fsm.state_name());
+    let memory_compute = fsm.get_decision();
+    assert!(memory_compute.alu_src_a_reg);
+    assert_eq!(2, memory_compute.alu_source_b);
+    assert_eq!(32, memory_compute.alu_control);
+
+    assert_eq!("ITypeMemoryWrite", fsm.state_name());
+    let memory_write = fsm.get_decision();
+    assert!(memory_write.iord);
+    assert!(memory_write.mem_write);
+
+    assert_eq!("Fetch", fsm.state_name());
+}
+
+#[test]
+fn fsm_lw() {
+    let mut fsm = FSM::new(Microprogram::default());
+    test_fetch_decode(&mut fsm, 34, 0);
+    assert_eq!("ITypeAddressCompute", fsm.state_name());
+    let memory_compute = fsm.get_decision();
+    assert!(memory_compute.alu_src_a_reg);
+    assert_eq!(2, memory_compute.alu_source_b);
+    assert_eq!(32, memory_compute.alu_control);
+
+    assert_eq!("ITypeMemoryRead", fsm.state_name());
+    let memory_read = fsm.get_decision();
+    assert!(memory_read.iord);
+
+    assert_eq!("ITypeReadWriteback", fsm.state_name());
+    let memory_writeback = fsm.get_decision();
+    assert!(!memory_writeback.reg_dst);
+    assert!(memory_writeback.mem_to_reg);
+    assert!(memory_writeback.reg_write);
+
+    assert_eq!("Fetch", fsm.state_name());
+}
+
+#[test]
+fn fsm_r_type() {
+    let mut fsm = FSM::new(Microprogram::default());
+    for funct in [32u8, 34, 36, 37, 39] {
+        test_fetch_decode(&mut fsm, 0, funct);
+
+        assert_eq!("RTypeExecute", fsm.state_name());
+        let execute = fsm.get_decision();
+        assert!(execute.alu_src_a_reg);
+        assert_eq!(0, execute.alu_source_b);
+        assert_eq!(funct, execute.alu_control);
+
+        assert_eq!("RTypeALUWriteBack", fsm.state_name());
+        let writeback = fsm.get_decision();
+        assert!(writeback.reg_dst);
+        assert!(!writeback.mem_to_reg);
+        assert!(writeback.reg_write);
+    }
+}
+
+fn emulator(code: Vec<u8>, memory: Vec<u8>) -> Emulator {
+    Emulator::new(code, memory, Box::new(BufferOutput::new()), Box::new(BufferInput::default()))
+}
+
+#[test]
+fn emulator_statistics() {
+    let lw = (34u32 << 26) | (8 << 16); // lw $8, 0($0)
+    let add = (8u32 << 21) | (8 << 16) | (9 << 11) | 32; // add $9, $8, $8
+    let code: Vec<u8> = [lw, add].iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    let mut emulator = emulator(code, 21i32.to_be_bytes().to_vec());
+    while !emulator.clock() {}
+    assert_eq!(42, emulator.registers.get_value(9));
+    assert_eq!(2, emulator.instructions());
+    assert_eq!(5 + 4, emulator.cycles());
+}
+
+#[test]
+fn emulator_backward_branch() {
+    let lw = |rt: u32, imm: u32| (34u32 << 26) | (rt << 16) | imm;
+    let sub = (9u32 << 21) | (10 << 16) | (9 << 11) | 34; // sub $9, $9, $10
+    let add = (8u32 << 21) | (10 << 16) | (8 << 11) | 32; // add $8, $8, $10
+    let bne = (5u32 << 26) | (9 << 21) | 0xfffd; // bne $9, $0, -3
+    // Counts $9 down from 3, a word far from the start is loaded with the offset above 0xff
+    let code: Vec<u8> = [lw(9, 0), lw(10, 0x104), add, sub, bne]
+        .iter()
+        .flat_map(|instr| instr.to_be_bytes())
+        .collect();
+    let mut memory = vec![0; 0x108];
+    memory[0..4].copy_from_slice(&3i32.to_be_bytes());
+    memory[0x104..0x108].copy_from_slice(&1i32.to_be_bytes());
+    let mut emulator = emulator(code, memory);
+    while !emulator.clock() {}
+    assert_eq!(3, emulator.registers.get_value(8));
+    assert_eq!(0, emulator.registers.get_value(9));
+}
+
+#[test]
+fn emulator_jump_uses_all_address_bits() {
+    // j 0x8000004, the highest bit of the 26-bit field selects the upper half of the 256 MB region
+    let code = ((2u32 << 26) | 0x2000001).to_be_bytes().to_vec();
+    let map = MemoryMap { text: Segment::new(0x800_0000, 0x100), ..MemoryMap::default() };
+    let output = Box::new(BufferOutput::new());
+    let mut emulator = Emulator::with_memory_map(code, vec![0; 4], map, output, Box::new(BufferInput::default())).unwrap();
+    while !emulator.clock() {}
+    assert_eq!(0x8000004, emulator.pc);
+}
+
+#[test]
+fn emulator_call_and_return() {
+    let jal = (3u32 << 26) | ((TEXT + 12) >> 2); // jal 12 from the start of the code
+    let lw = (34u32 << 26) | (8 << 16); // lw $8, 0($0)
+    let j = (2u32 << 26) | ((TEXT + 20) >> 2); // j 20 from the start of the code
+    let jr = 31u32 << 21 | 8; // jr $31
+    // The routine at 12 loads $8 and returns to the jump over it
+    let code: Vec<u8> = [jal, j, 0, lw, jr].iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    let mut emulator = emulator(code, 7i32.to_be_bytes().to_vec());
+    while !emulator.clock() {}
+    assert_eq!(7, emulator.registers.get_value(8));
+    assert_eq!(TEXT as i32 + 4, emulator.registers.get_value(31));
+    assert_eq!(TEXT as usize + 20, emulator.pc);
+    assert_eq!(4, emulator.instructions());
+}
+
+#[test]
+fn emulator_prints_into_output() {
+    let lw = (34u32 << 26) | (8 << 16); // lw $8, 0($0)
+    let print = 8u32 << 21; // print $8
+    let printc = (8u32 << 21) | 1; // printc $8
+    let prints = (63u32 << 26) | 4; // prints 4($0)
+    let code: Vec<u8> = [lw, print, printc, prints].iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    let mut memory = 955i32.to_be_bytes().to_vec();
+    memory.extend(b"ok\n\0");
+    let output = BufferOutput::new();
+    let mut emulator = Emulator::new(code, memory, Box::new(output.clone()), Box::new(BufferInput::default()));
+    while !emulator.clock() {}
+    assert_eq!("955\nλok\n", output.text());
+}
+
+#[test]
+fn emulator_run_to_completion() {
+    let lw = (34u32 << 26) | (8 << 16); // lw $8, 0($0)
+    let add = (8u32 << 21) | (8 << 16) | (9 << 11) | 32; // add $9, $8, $8
+    let print = 9u32 << 21; // print $9
+    let code: Vec<u8> = [lw, add, print].iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    let completion = Emulator::run_to_completion(code, 21i32.to_be_bytes().to_vec(), Box::new(BufferInput::default()), None);
+    assert_eq!("42\n", completion.output);
+    assert_eq!(ExitStatus::Exited(0), completion.status);
+    assert_eq!(3, completion.instructions);
+    assert_eq!(5 + 4 + 1, completion.cycles);
+}
+
+#[test]
+fn emulator_halt() {
+    let lw = (34u32 << 26) | (8 << 16); // lw $8, 0($0)
+    let halt = (8u32 << 21) | 13; // halt $8
+    let print = 8u32 << 21; // print $8
+    let code: Vec<u8> = [lw, halt, print].iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    let completion = Emulator::run_to_completion(code, 3i32.to_be_bytes().to_vec(), Box::new(BufferInput::default()), None);
+    assert_eq!(ExitStatus::Exited(3), completion.status);
+    assert_eq!("", completion.output);
+    assert_eq!(2, completion.instructions);
+}
+
+#[test]
+fn emulator_cycle_limit() {
+    let j = (2u32 << 26) | (TEXT >> 2); // j to itself
+    let mut emulator = emulator(j.to_be_bytes().to_vec(), vec![0; 4]).with_cycle_limit(100);
+    while !emulator.clock() {}
+    assert_eq!(Some(ExitStatus::CycleLimitExceeded(100)), emulator.status());
+    assert_eq!(100, emulator.cycles());
+    // The program stays stopped
+    assert!(emulator.clock());
+    assert_eq!(100, emulator.cycles());
+}
+
+#[test]
+fn emulator_syscalls() {
+    let lw = |rt: u32, imm: u32| (34u32 << 26) | (rt << 16) | imm;
+    let syscall = 12u32;
+    let mov_v0_to_a0 = (2u32 << 21) | (4 << 11) | 37; // or $4, $2, $0
+    let code: Vec<u8> = [
+        lw(2, 0), lw(4, 4), syscall, // print int -5
+        lw(2, 8), lw(4, 12), syscall, // print char 955
+        lw(2, 16), syscall, mov_v0_to_a0, lw(2, 0), syscall, // read int and print it
+        lw(2, 20), lw(4, 24), syscall, // print string at 28
+        lw(2, 32), lw(4, 4), syscall, // exit with -5
+        lw(2, 0), syscall,
+    ].iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    let mut memory: Vec<u8> = [1, -5, 11, 955, 5, 4, 28].iter().flat_map(|word: &i32| word.to_be_bytes()).collect();
+    memory.extend(b"hi\n\0");
+    memory.extend(17i32.to_be_bytes());
+    let completion = Emulator::run_to_completion(code, memory, Box::new(BufferInput::new(b" 42 \n7\n")), None);
+    assert_eq!("-5λ42hi\n", completion.output);
+    assert_eq!(ExitStatus::Exited(-5), completion.status);
+}
+
+#[test]
+#[should_panic(expected = "Unknown syscall service 3")]
+fn emulator_unknown_syscall() {
+    let lw = (34u32 << 26) | (2 << 16); // lw $2, 0($0)
+    let code: Vec<u8> = [lw, 12].iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    Emulator::run_to_completion(code, 3i32.to_be_bytes().to_vec(), Box::new(BufferInput::default()), None);
+}
+
+#[test]
+fn emulator_read_invalid_int() {
+    let lw = (34u32 << 26) | (2 << 16); // lw $2, 0($0)
+    let code: Vec<u8> = [lw, 12, lw, 12].iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    for (input, error) in [(&b"x\n"[..], InputError::InvalidInteger), (b"", InputError::EndOfInput)] {
+        let completion = Emulator::run_to_completion(code.clone(), 5i32.to_be_bytes().to_vec(), Box::new(BufferInput::new(input)), None);
+        assert_eq!(ExitStatus::InputError { pc: TEXT + 4, error }, completion.status);
+    }
+    // The first read succeeds
+    let completion = Emulator::run_to_completion(code, 5i32.to_be_bytes().to_vec(), Box::new(BufferInput::new(b"1\n")), None);
+    assert_eq!(ExitStatus::InputError { pc: TEXT + 12, error: InputError::EndOfInput }, completion.status);
+}
+
+#[test]
+fn emulator_reads_own_code() {
+    let lw = |rs: u32, rt: u32| (34u32 << 26) | (rs << 21) | (rt << 16); // lw $rt, 0($rs)
+    let print = 9u32 << 21; // print $9
+    // $8 gets the address of the code from the data, then $9 gets the first instruction
+    let code: Vec<u8> = [lw(0, 8), lw(8, 9), print].iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    let completion = Emulator::run_to_completion(code, TEXT.to_be_bytes().to_vec(), Box::new(BufferInput::default()), None);
+    assert_eq!(format!("{}\n", lw(0, 8) as i32), completion.output);
+}
+
+#[test]
+fn emulator_memory_map() {
+    let map = MemoryMap {
+        text: Segment::new(0x1000, 0x100),
+        data: Segment::new(0, 0x100),
+        heap: Segment::new(0x100, 0x100),
+        stack: Segment::new(0x2000, 0x1000),
+    };
+    let lw = (34u32 << 26) | (8 << 16); // lw $8, 0($0)
+    let sw = (43u32 << 26) | (29 << 21) | (8 << 16) | 0xfffc; // sw $8, -4($29)
+    let code: Vec<u8> = [lw, sw].iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    let output = Box::new(BufferOutput::new());
+    let mut emulator = Emulator::with_memory_map(code, 5i32.to_be_bytes().to_vec(), map, output, Box::new(BufferInput::default())).unwrap();
+    assert_eq!(0x1000, emulator.pc());
+    assert_eq!(0x3000, emulator.register(29));
+    while !emulator.clock() {}
+    assert_eq!(Some(ExitStatus::Exited(0)), emulator.status());
+    assert_eq!(Some(5), emulator.read_word(0x2ffc));
+    assert_eq!(0x1008, emulator.pc());
+}
+
+#[test]
+fn emulator_jump_outside_of_map() {
+    let j = (2u32 << 26) | (0x20_0000 >> 2); // j 0x200000, between the stack and the text segments
+    let mut emulator = emulator(j.to_be_bytes().to_vec(), Vec::new());
+    while !emulator.clock() {}
+    assert_eq!(Some(ExitStatus::AddressError { pc: 0x20_0000, address: 0x20_0000 }), emulator.status());
+    assert_eq!("Address 0x200000 is outside of the memory map", emulator.status().unwrap().to_string());
+}
+
+#[test]
+fn emulator_access_outside_of_map() {
+    let lw = (34u32 << 26) | (8 << 16) | 0x8000; // lw $8, -0x8000($0)
+    let sw = (43u32 << 26) | (8 << 16) | 0x8000; // sw $8, -0x8000($0)
+    let prints = (63u32 << 26) | 0x8000; // prints -0x8000($0)
+    for instr in [lw, sw, prints] {
+        let completion = Emulator::run_to_completion(instr.to_be_bytes().to_vec(), Vec::new(), Box::new(BufferInput::default()), None);
+        assert_eq!(ExitStatus::AddressError { pc: TEXT, address: 0xffff_8000 }, completion.status);
+    }
+}
+
+/// Address of the exception handler in the coprocessor tests
+const HANDLER: u32 = TEXT + 0x100;
+
+fn cop0(instr: Cop0) -> u32 {
+    crate::binary::instructions::transform_to_bytes(&crate::binary::instructions::Instr::C0(instr))
+}
+
+/// The main code at the start, padded with `halt $0`, and the handler at [`HANDLER`]
+fn coprocessor_program(main: &[u32], handler: &[u32]) -> Vec<u8> {
+    let mut words = main.to_vec();
+    words.resize(((HANDLER - TEXT) / 4) as usize, RType::HALT as u32);
+    words.extend(handler);
+    words.iter().flat_map(|instr| instr.to_be_bytes()).collect()
+}
+
+/// Handler continuing after the faulting instruction, the data should start with 4
+fn skipping_handler() -> Vec<u32> {
+    vec![
+        cop0(Cop0::Mfc0 { rt: 26, rd: Coprocessor0::EPC }),
+        (34u32 << 26) | (27 << 16), // lw $27, 0($0)
+        (26u32 << 21) | (27 << 16) | (26 << 11) | 33, // addu $26, $26, $27
+        cop0(Cop0::Mtc0 { rt: 26, rd: Coprocessor0::EPC }),
+        cop0(Cop0::Eret),
+    ]
+}
+
+fn run_with_coprocessor(code: Vec<u8>, data: &[i32], cp0: Coprocessor0) -> (String, Emulator) {
+    let memory = data.iter().flat_map(|word| word.to_be_bytes()).collect();
+    let output = BufferOutput::new();
+    let mut emulator = Emulator::new(code, memory, Box::new(output.clone()), Box::new(BufferInput::default()))
+        .with_coprocessor(cp0)
+        .with_cycle_limit(100_000);
+    while !emulator.clock() {}
+    assert_eq!(Some(ExitStatus::Exited(0)), emulator.status());
+    (output.text(), emulator)
+}
+
+#[test]
+fn coprocessor_reserved_instruction() {
+    let print = |rs: u32| rs << 21;
+    let main = [
+        1u32 << 26, // unknown opcode
+        cop0(Cop0::Mfc0 { rt: 8, rd: Coprocessor0::CAUSE }),
+        print(8),
+        cop0(Cop0::Mfc0 { rt: 8, rd: Coprocessor0::EPC }),
+        print(8),
+    ];
+    let (output, emulator) = run_with_coprocessor(coprocessor_program(&main, &skipping_handler()), &[4], Coprocessor0::new(HANDLER));
+    assert_eq!(format!("{}\n{}\n", (ExceptionCode::ReservedInstruction as u32) << 2, TEXT + 4), output);
+    assert_eq!(0, emulator.coprocessor().unwrap().status() & Coprocessor0::EXL);
+}
+
+#[test]
+fn coprocessor_overflow() {
+    let lw = |rt: u32, imm: u32| (34u32 << 26) | (rt << 16) | imm;
+    let main = [
+        lw(8, 4),
+        (8u32 << 21) | (8 << 16) | (9 << 11) | 32, // add $9, $8, $8
+        (8u32 << 21) | (8 << 16) | (10 << 11) | 33, // addu $10, $8, $8
+        (10u32 << 21) | (8 << 16) | (11 << 11) | 34, // sub $11, $10, $8
+        9u32 << 21, // print $9
+        10u32 << 21, // print $10
+        11u32 << 21, // print $11
+        cop0(Cop0::Mfc0 { rt: 8, rd: Coprocessor0::CAUSE }),
+        8u32 << 21, // print $8
+    ];
+    let (output, emulator) = run_with_coprocessor(coprocessor_program(&main, &skipping_handler()), &[4, i32::MAX], Coprocessor0::new(HANDLER));
+    // The overflowing results are not written
+    assert_eq!(format!("0\n-2\n0\n{}\n", (ExceptionCode::Overflow as u32) << 2), output);
+    assert_eq!(TEXT + 16, emulator.coprocessor().unwrap().epc());
+}
+
+#[test]
+fn coprocessor_misaligned_access() {
+    let lw = |rs: u32, rt: u32, imm: u32| (34u32 << 26) | (rs << 21) | (rt << 16) | imm;
+    let main = [
+        lw(0, 8, 4),
+        lw(8, 9, 0), // lw $9, 0($8), address 2
+        cop0(Cop0::Mfc0 { rt: 10, rd: Coprocessor0::CAUSE }),
+        (43u32 << 26) | (8 << 21) | (8 << 16) | 4, // sw $8, 4($8), address 6
+        cop0(Cop0::Mfc0 { rt: 11, rd: Coprocessor0::CAUSE }),
+        cop0(Cop0::Mfc0 { rt: 12, rd: Coprocessor0::BAD_VADDR }),
+        10u32 << 21, // print $10
+        11u32 << 21, // print $11
+        12u32 << 21, // print $12
+        9u32 << 21, // print $9
+    ];
+    let (output, emulator) = run_with_coprocessor(coprocessor_program(&main, &skipping_handler()), &[4, 2], Coprocessor0::new(HANDLER));
+    let (load, store) = (ExceptionCode::AddressLoad as u32, ExceptionCode::AddressStore as u32);
+    assert_eq!(format!("{}\n{}\n6\n0\n", load << 2, store << 2), output);
+    assert_eq!(Some(2), emulator.read_word(4));
+}
+
+#[test]
+fn coprocessor_unmapped_access() {
+    let main = [
+        (34u32 << 26) | (9 << 16) | 0x8000, // lw $9, -0x8000($0)
+        cop0(Cop0::Mfc0 { rt: 10, rd: Coprocessor0::CAUSE }),
+        (63u32 << 26) | 0x8000, // prints -0x8000($0)
+        cop0(Cop0::Mfc0 { rt: 11, rd: Coprocessor0::CAUSE }),
+        (43u32 << 26) | (9 << 16) | 0x8000, // sw $9, -0x8000($0)
+        cop0(Cop0::Mfc0 { rt: 12, rd: Coprocessor0::CAUSE }),
+        cop0(Cop0::Mfc0 { rt: 13, rd: Coprocessor0::BAD_VADDR }),
+        10u32 << 21, // print $10
+        11u32 << 21, // print $11
+        12u32 << 21, // print $12
+        13u32 << 21, // print $13
+    ];
+    let (output, _) = run_with_coprocessor(coprocessor_program(&main, &skipping_handler()), &[4], Coprocessor0::new(HANDLER));
+    let (load, store) = (ExceptionCode::AddressLoad as u32, ExceptionCode::AddressStore as u32);
+    assert_eq!(format!("{}\n{}\n{}\n{}\n", load << 2, load << 2, store << 2, 0xffff_8000u32 as i32), output);
+}
+
+#[test]
+fn coprocessor_syscall() {
+    let main = [
+        (34u32 << 26) | (2 << 16), // lw $2, 0($0), the print string service
+        RType::SYSCALL as u32,
+        cop0(Cop0::Mfc0 { rt: 8, rd: Coprocessor0::CAUSE }),
+        8u32 << 21, // print $8
+    ];
+    let (output, emulator) = run_with_coprocessor(coprocessor_program(&main, &skipping_handler()), &[4], Coprocessor0::new(HANDLER));
+    assert_eq!(format!("{}\n", (ExceptionCode::Syscall as u32) << 2), output);
+    assert_eq!(ExceptionCode::Syscall as u32, emulator.coprocessor().unwrap().exception_code());
+}
+
+/// Counts down from 100 with the timer interrupt counting in `$16`, returns the printed count and the interrupts
+fn timer_program(status: i32, period: u64) -> (String, Emulator) {
+    let lw = |rt: u32, imm: u32| (34u32 << 26) | (rt << 16) | imm;
+    let main = [
+        lw(17, 4),
+        lw(8, 8),
+        cop0(Cop0::Mtc0 { rt: 8, rd: Coprocessor0::STATUS }),
+        lw(9, 12),
+        (9u32 << 21) | (17 << 16) | (9 << 11) | 35, // subu $9, $9, $17
+        (5u32 << 26) | (9 << 21) | 0xfffe, // bne $9, $0, -2
+        9u32 << 21, // print $9
+        16u32 << 21, // print $16
+    ];
+    let handler = [
+        (16u32 << 21) | (17 << 16) | (16 << 11) | 33, // addu $16, $16, $17
+        cop0(Cop0::Mtc0 { rt: 0, rd: Coprocessor0::CAUSE }),
+        cop0(Cop0::Eret),
+    ];
+    let cp0 = Coprocessor0::new(HANDLER).with_timer(period);
+    run_with_coprocessor(coprocessor_program(&main, &handler), &[4, 1, status, 100], cp0)
+}
+
+#[test]
+fn coprocessor_timer_interrupt() {
+    let enabled = (Coprocessor0::TIMER_LINE | Coprocessor0::IE) as i32;
+    let (output, emulator) = timer_program(enabled, 50);
+    let interrupts: u64 = output.lines().nth(1).unwrap().parse().unwrap();
+    assert_eq!("0", output.lines().next().unwrap());
+    // The handler is shorter than the period, so every period ends with an interrupt
+    assert_eq!(emulator.cycles() / 50, interrupts);
+    assert_eq!(ExceptionCode::Interrupt as u32, emulator.coprocessor().unwrap().exception_code());
+    // The runs are the same
+    assert_eq!(output, timer_program(enabled, 50).0);
+    // Disabled interrupts or the masked line are not taken
+    assert_eq!("0\n0\n", timer_program(0, 50).0);
+    assert_eq!("0\n0\n", timer_program(Coprocessor0::IE as i32, 50).0);
+}
+
+#[test]
+#[should_panic(expected = "Invalid opcode 1")]
+fn emulator_invalid_opcode_without_coprocessor() {
+    let mut emulator = emulator((1u32 << 26).to_be_bytes().to_vec(), Vec::new());
+    while !emulator.clock() {}
+}
+
+#[test]
+fn emulator_overflow_without_coprocessor() {
+    let lw = (34u32 << 26) | (8 << 16); // lw $8, 0($0)
+    let addu = (8u32 << 21) | (8 << 16) | (9 << 11) | 33; // addu $9, $8, $8
+    let add = (8u32 << 21) | (8 << 16) | (9 << 11) | 32; // add $9, $8, $8
+    let code: Vec<u8> = [lw, addu, add].iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    let mut emulator = emulator(code, i32::MAX.to_be_bytes().to_vec());
+    for _ in 0..5 + 4 {
+        emulator.clock();
+    }
+    assert_eq!(-2, emulator.registers.get_value(9));
+    while !emulator.clock() {}
+    // The program stops at `add`, which writes nothing
+    assert_eq!(Some(ExitStatus::Overflow { pc: TEXT + 8 }), emulator.status());
+    assert_eq!(-2, emulator.registers.get_value(9));
+}
+
+#[test]
+fn emulator_with_microprogram() {
+    // Opcode 1 goes back to the fetch right after the decode, so it does nothing
+    let program = format!("{}\ndispatch opcode 1 -> Fetch", Microprogram::DEFAULT);
+    let program = Microprogram::from_text(&program).unwrap();
+    assert_eq!(Ok(()), program.validate());
+    let lw = (34u32 << 26) | (8 << 16); // lw $8, 0($0)
+    let print = 8u32 << 21; // print $8
+    let code: Vec<u8> = [1u32 << 26, lw, print].iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    let output = BufferOutput::new();
+    let mut emulator = Emulator::new(code, 7i32.to_be_bytes().to_vec(), Box::new(output.clone()), Box::new(BufferInput::default()))
+        .with_microprogram(program);
+    while !emulator.clock() {}
+    assert_eq!("7\n", output.text());
+    assert_eq!(2 + 5 + 1, emulator.cycles());
+}
+
+#[test]
+#[should_panic(expected = "Invalid opcode 43!")]
+fn emulator_microprogram_without_store() {
+    let program = Microprogram::DEFAULT.replace("dispatch opcode 43 -> ITypeAddressCompute", "");
+    let sw = (43u32 << 26) | (8 << 16); // sw $8, 0($0)
+    let mut emulator = emulator(sw.to_be_bytes().to_vec(), vec![0; 4]).with_microprogram(Microprogram::from_text(&program).unwrap());
+    while !emulator.clock() {}
+}
+
+/// Doubles the loaded word and stores it, three times
+fn snapshot_program() -> (Vec<u8>, Vec<u8>) {
+    let lw = (34u32 << 26) | (8 << 16); // lw $8, 0($0)
+    let addu = (8u32 << 21) | (8 << 16) | (8 << 11) | 33; // addu $8, $8, $8
+    let sw = (43u32 << 26) | (8 << 16); // sw $8, 0($0)
+    let code = [lw, addu, sw, lw, addu, sw, lw, addu, sw].iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    (code, 5i32.to_be_bytes().to_vec())
+}
+
+#[test]
+fn emulator_snapshot_rollback() {
+    let (code, memory) = snapshot_program();
+    let mut emulator = emulator(code, memory);
+    for _ in 0..17 {
+        emulator.clock();
+    }
+    // In the middle of the load of the second iteration
+    let snapshot = emulator.snapshot();
+    assert_eq!(17, snapshot.cycles());
+    while !emulator.clock() {}
+    assert_eq!(Some(40), emulator.read_word(0));
+    let finished = emulator.snapshot();
+
+    emulator.restore(&snapshot).unwrap();
+    assert_eq!(snapshot, emulator.snapshot());
+    assert_eq!(None, emulator.status());
+    assert_eq!(Some(10), emulator.read_word(0));
+    while !emulator.clock() {}
+    assert_eq!(finished, emulator.snapshot());
+    assert_eq!(Some(ExitStatus::Exited(0)), emulator.status());
+}
+
+#[test]
+fn emulator_snapshot_file() {
+    let (code, memory) = snapshot_program();
+    let mut emulator = emulator(code.clone(), memory.clone()).with_coprocessor(Coprocessor0::new(TEXT).with_timer(1000));
+    // Before the memory write of the second store
+    for _ in 0..13 + 5 + 4 + 3 {
+        emulator.clock();
+    }
+    let text = emulator.snapshot().to_text();
+    assert!(text.contains("state ITypeMemoryWrite 43 0\n"), "{}", text);
+    assert!(text.contains("mem 00000000 0000000a\n"), "{}", text);
+    let snapshot = Snapshot::from_text(&text).unwrap();
+    assert_eq!(emulator.snapshot(), snapshot);
+
+    // A fresh emulator resumes from the file
+    let mut resumed = self::emulator(code, memory).with_coprocessor(Coprocessor0::new(TEXT).with_timer(1000));
+    resumed.restore(&snapshot).unwrap();
+    while !emulator.clock() {}
+    while !resumed.clock() {}
+    assert_eq!(emulator.snapshot(), resumed.snapshot());
+    assert_eq!(emulator.instructions(), resumed.instructions());
+}
+
+#[test]
+fn emulator_snapshot_errors() {
+    let (code, memory) = snapshot_program();
+    let snapshot = emulator(code.clone(), memory.clone()).snapshot();
+    let map = MemoryMap { stack: Segment::new(0x8_0000, 0x4_0000), ..MemoryMap::default() };
+    let mut other = Emulator::with_memory_map(code.clone(), memory.clone(), map, Box::new(BufferOutput::new()), Box::new(BufferInput::default())).unwrap();
+    assert_eq!(Err(SnapshotError::MemoryMapMismatch), other.restore(&snapshot));
+    let mut with_coprocessor = emulator(code.clone(), memory.clone()).with_coprocessor(Coprocessor0::new(TEXT));
+    assert_eq!(Err(SnapshotError::CoprocessorMismatch), with_coprocessor.restore(&snapshot));
+    let renamed = Microprogram::from_text(&Microprogram::DEFAULT.replace("Fetch", "InstructionFetch")).unwrap();
+    let mut renamed = emulator(code, memory).with_microprogram(renamed);
+    assert_eq!(Err(SnapshotError::UnknownState(String::from("Fetch"))), renamed.restore(&snapshot));
+
+    let text = snapshot.to_text();
+    assert_eq!("missing `pc` line", Snapshot::from_text(&text.replace("pc 400000\n", "")).unwrap_err().message);
+    let outside = format!("{}mem 00100000 00000001\n", text);
+    assert_eq!(text.lines().count() + 1, Snapshot::from_text(&outside).unwrap_err().line);
+    let unaligned = Snapshot::from_text(&text.replace("segment heap 10000 70000", "segment heap 10000 6fffe")).unwrap_err();
+    assert_eq!("The size of the heap segment is not a multiple of the word size", unaligned.message);
+    let huge = Snapshot::from_text(&text.replace("segment stack 80000 80000", "segment stack 80000 ffffffff")).unwrap_err();
+    assert_eq!((4, String::from("the segment is larger than the default one")), (huge.line, huge.message));
+}
@@ -0,0 +1,173 @@
+use crate::fuzz::Rng;
+use crate::parser::ast::{Block, Expr, Ident, Ops, PrefixOps, Spanned, Stmt};
+
+#[derive(Clone, Debug)]
+pub struct GeneratorConfig {
+    /// Maximal number of statements in a single block
+    pub max_statements: usize,
+    /// Maximal nesting of `if` and `while` statements
+    pub max_depth: usize,
+    pub max_expr_depth: usize,
+    /// Every loop runs at most this number of iterations
+    pub max_loop_iterations: i32,
+    /// Number of variables assigned by the program, apart from the loop counters
+    pub variables: usize,
+    /// Number of distinct literals in the program, the compiler keeps every literal in the memory
+    pub literals: usize,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            max_statements: 5,
+            max_depth: 3,
+            max_expr_depth: 3,
+            max_loop_iterations: 4,
+            variables: 4,
+            literals: 12,
+        }
+    }
+}
+
+const VARIABLE_NAMES: [&str; 8] = ["a", "b", "c", "d", "e", "f", "g", "h"];
+const COUNTER_NAMES: [&str; 4] = ["ca", "cb", "cc", "cd"];
+const INTERESTING_LITERALS: [i32; 7] = [1, 4, 255, 256, 65535, 65536, i32::MAX];
+
+/// Generates random terminating Klang programs.
+///
+/// Every loop is controlled by its own counter `cX = N; while (cX) { ...; cX = cX - 1 }`, which is never assigned
+/// anywhere else, so the number of iterations is bounded. The program ends with printing all variables.
+pub struct ProgramGenerator {
+    rng: Rng,
+    config: GeneratorConfig,
+    literals: Vec<i32>,
+}
+
+impl ProgramGenerator {
+    pub fn new(seed: u64, config: GeneratorConfig) -> Self {
+        assert!(config.variables <= VARIABLE_NAMES.len(), "At most {} variables are supported", VARIABLE_NAMES.len());
+        assert!(config.max_depth <= COUNTER_NAMES.len(), "At most {} nested statements are supported", COUNTER_NAMES.len());
+        Self { rng: Rng::new(seed), config, literals: Vec::new() }
+    }
+
+    fn variable(&mut self) -> Ident {
+        let name = VARIABLE_NAMES[self.rng.below(self.config.variables)];
+        Ident(name.to_string())
+    }
+
+    fn random_literal(&mut self) -> i32 {
+        match self.rng.below(10) {
+            0..=5 => self.rng.below(10) as i32,
+            6 | 7 => self.rng.below(256) as i32,
+            8 => (self.rng.next_u64() & i32::MAX as u64) as i32,
+            _ => *self.rng.choose(&INTERESTING_LITERALS),
+        }
+    }
+
+    fn literal(&mut self) -> i32 {
+        if self.literals.len() < self.config.literals {
+            let literal = self.random_literal();
+            self.literals.push(literal);
+            literal
+        } else {
+            *self.rng.choose(&self.literals)
+        }
+    }
+
+    fn expr(&mut self, depth: usize, loop_depth: usize) -> Expr {
+        if depth >= self.config.max_expr_depth || self.rng.chance(30) {
+            return match self.rng.below(10) {
+                // Loop counters are readable, but never assigned
+                0 if loop_depth > 0 => Expr::Var(Ident(COUNTER_NAMES[self.rng.below(loop_depth)].to_string())),
+                0..=5 => Expr::Var(self.variable()),
+                _ => Expr::IntLiteral(self.literal()),
+            };
+        }
+        if self.rng.chance(15) {
+            return Expr::PrefixOperation(PrefixOps::BitwiseNot, Box::new(self.expr(depth + 1, loop_depth)));
+        }
+        let op = *self.rng.choose(&[Ops::Add, Ops::Sub, Ops::BitwiseAnd, Ops::BitwiseOr]);
+        Expr::InfixOperation(
+            Box::new(self.expr(depth + 1, loop_depth)),
+            op,
+            Box::new(self.expr(depth + 1, loop_depth)),
+        )
+    }
+
+    fn block(&mut self, depth: usize, loop_depth: usize) -> Block {
+        let statements = self.rng.below(self.config.max_statements + 1);
+        let mut block = Vec::new();
+        for _ in 0..statements {
+            self.stmt(depth, loop_depth, &mut block);
+        }
+        block
+    }
+
+    fn stmt(&mut self, depth: usize, loop_depth: usize, block: &mut Block) {
+        let compound_allowed = depth < self.config.max_depth;
+        let stmt = match self.rng.below(100) {
+            0..=39 => Stmt::VarAssign(self.variable(), self.expr(0, loop_depth)),
+            40..=64 => Stmt::Print(self.expr(0, loop_depth)),
+            65..=79 if compound_allowed => Stmt::If {
+                condition: self.expr(0, loop_depth),
+                true_branch: Box::new(self.block(depth + 1, loop_depth)),
+                false_branch: if self.rng.chance(50) {
+                    Some(Box::new(self.block(depth + 1, loop_depth)))
+                } else {
+                    None
+                },
+            },
+            80..=94 if compound_allowed => {
+                let counter = Ident(COUNTER_NAMES[loop_depth].to_string());
+                let iterations = self.rng.below(self.config.max_loop_iterations as usize + 1) as i32;
+                block.push(Spanned::new(Stmt::VarAssign(counter.clone(), Expr::IntLiteral(iterations)), Default::default()));
+                let mut body = self.block(depth + 1, loop_depth + 1);
+                let decrement = Expr::InfixOperation(
+                    Box::new(Expr::Var(counter.clone())),
+                    Ops::Sub,
+                    Box::new(Expr::IntLiteral(1)),
+                );
+                body.push(Spanned::new(Stmt::VarAssign(counter.clone(), decrement), Default::default()));
+                Stmt::While(Expr::Var(counter), Box::new(body))
+            }
+            _ => Stmt::VarDeclaration(vec![self.variable()]),
+        };
+        block.push(Spanned::new(stmt, Default::default()))
+    }
+
+    pub fn generate(&mut self) -> Block {
+        self.literals.clear();
+        let mut program = self.block(0, 0);
+        for name in &VARIABLE_NAMES[..self.config.variables] {
+            program.push(Spanned::new(Stmt::Print(Expr::Var(Ident(name.to_string()))), Default::default()));
+        }
+        program
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::interpreter::AstInterpreter;
+    use super::*;
+
+    #[test]
+    fn test_programs_terminate() {
+        let config = GeneratorConfig::default();
+        let variables = config.variables;
+        // Loops are nested at most 3 times, so the programs are small
+        let step_limit = 100_000;
+        for seed in 0..200 {
+            let program = ProgramGenerator::new(seed, config.clone()).generate();
+            let mut interpreter = AstInterpreter::with_step_limit(step_limit);
+            interpreter.run(&program).unwrap_or_else(|e| panic!("Program #{} failed: {}", seed, e));
+            assert!(interpreter.output().len() >= variables);
+        }
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let first = ProgramGenerator::new(42, GeneratorConfig::default()).generate();
+        let second = ProgramGenerator::new(42, GeneratorConfig::default()).generate();
+        assert_eq!(first, second);
+    }
+}
@@ -0,0 +1,38 @@
+//! Random Klang programs for the differential testing of the compiler against the reference interpreter
+
+pub mod generator;
+pub mod shrink;
+
+/// Small deterministic xorshift generator, so a failing program could be reproduced from its seed
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Zero state is a fixed point of xorshift
+        Self { state: seed.wrapping_mul(0x9e3779b97f4a7c15) | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// Uniform number in `0..bound`
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    /// Returns true with the probability of `percent`%
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
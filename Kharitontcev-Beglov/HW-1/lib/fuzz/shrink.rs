@@ -0,0 +1,195 @@
+use crate::parser::ast::{Block, Expr, Spanned, Stmt};
+
+fn expr_candidates(expr: &Expr) -> Vec<Expr> {
+    match expr {
+        Expr::IntLiteral(0) => vec![],
+        Expr::IntLiteral(1) => vec![Expr::IntLiteral(0)],
+        Expr::IntLiteral(x) => {
+            let mut result = vec![Expr::IntLiteral(0), Expr::IntLiteral(1)];
+            if x / 2 > 1 {
+                result.push(Expr::IntLiteral(x / 2))
+            }
+            result
+        }
+        Expr::Var(_) => vec![Expr::IntLiteral(0)],
+        Expr::InfixOperation(lhs, op, rhs) => {
+            let mut result = vec![lhs.as_ref().clone(), rhs.as_ref().clone(), Expr::IntLiteral(0)];
+            for lhs in expr_candidates(lhs) {
+                result.push(Expr::InfixOperation(Box::new(lhs), *op, rhs.clone()))
+            }
+            for rhs in expr_candidates(rhs) {
+                result.push(Expr::InfixOperation(lhs.clone(), *op, Box::new(rhs)))
+            }
+            result
+        }
+        Expr::PrefixOperation(op, inner) => {
+            let mut result = vec![inner.as_ref().clone(), Expr::IntLiteral(0)];
+            for inner in expr_candidates(inner) {
+                result.push(Expr::PrefixOperation(*op, Box::new(inner)))
+            }
+            result
+        }
+        Expr::Call(routine, args) => {
+            let mut result = args.clone();
+            result.push(Expr::IntLiteral(0));
+            for (index, arg) in args.iter().enumerate() {
+                for arg in expr_candidates(arg) {
+                    let mut args = args.clone();
+                    args[index] = arg;
+                    result.push(Expr::Call(*routine, args))
+                }
+            }
+            result
+        }
+    }
+}
+
+fn stmt_candidates(stmt: &Stmt) -> Vec<Stmt> {
+    match stmt {
+        Stmt::VarDeclaration(names) if names.len() > 1 => (0..names.len())
+            .map(|i| {
+                let mut names = names.clone();
+                names.remove(i);
+                Stmt::VarDeclaration(names)
+            })
+            .collect(),
+        Stmt::VarDeclaration(_) => vec![],
+        Stmt::VarAssign(id, expr) => expr_candidates(expr).into_iter()
+            .map(|expr| Stmt::VarAssign(id.clone(), expr))
+            .collect(),
+        Stmt::Print(expr) => expr_candidates(expr).into_iter().map(Stmt::Print).collect(),
+        Stmt::PrintChar(expr) => expr_candidates(expr).into_iter().map(Stmt::PrintChar).collect(),
+        Stmt::PrintString(value) if !value.is_empty() => vec![Stmt::PrintString(String::new())],
+        Stmt::PrintString(_) => vec![],
+        Stmt::If { condition, true_branch, false_branch } => {
+            let mut result = Vec::new();
+            if false_branch.is_some() {
+                result.push(Stmt::If { condition: condition.clone(), true_branch: true_branch.clone(), false_branch: None })
+            }
+            for condition in expr_candidates(condition) {
+                result.push(Stmt::If { condition, true_branch: true_branch.clone(), false_branch: false_branch.clone() })
+            }
+            for true_branch in block_candidates(true_branch) {
+                result.push(Stmt::If { condition: condition.clone(), true_branch: Box::new(true_branch), false_branch: false_branch.clone() })
+            }
+            if let Some(false_branch) = false_branch {
+                for false_branch in block_candidates(false_branch) {
+                    result.push(Stmt::If { condition: condition.clone(), true_branch: true_branch.clone(), false_branch: Some(Box::new(false_branch)) })
+                }
+            }
+            result
+        }
+        Stmt::While(condition, body) => {
+            let mut result: Vec<Stmt> = expr_candidates(condition).into_iter()
+                .map(|condition| Stmt::While(condition, body.clone()))
+                .collect();
+            for body in block_candidates(body) {
+                result.push(Stmt::While(condition.clone(), Box::new(body)))
+            }
+            result
+        }
+    }
+}
+
+fn block_candidates(block: &Block) -> Vec<Block> {
+    let mut result = Vec::new();
+    let splice = |i: usize, inner: &Block| {
+        let mut spliced = block[..i].to_vec();
+        spliced.extend_from_slice(inner);
+        spliced.extend_from_slice(&block[i + 1..]);
+        spliced
+    };
+    // Larger reductions go first
+    for i in 0..block.len() {
+        let mut removed = block.clone();
+        removed.remove(i);
+        result.push(removed);
+        match &block[i].node {
+            Stmt::If { true_branch, false_branch, .. } => {
+                result.push(splice(i, true_branch));
+                if let Some(false_branch) = false_branch {
+                    result.push(splice(i, false_branch));
+                }
+            }
+            Stmt::While(_, body) => result.push(splice(i, body)),
+            _ => {}
+        }
+    }
+    for i in 0..block.len() {
+        for stmt in stmt_candidates(&block[i].node) {
+            let mut replaced = block.clone();
+            replaced[i] = Spanned::new(stmt, block[i].span);
+            result.push(replaced);
+        }
+    }
+    result
+}
+
+/// Greedily reduces the program while `is_failing` holds for it.
+///
+/// Statements are removed or replaced by their nested blocks, expressions are replaced by their subexpressions and
+/// literals are decreased. Every step makes the program strictly smaller, so the process terminates. The reduced
+/// programs might not terminate, `is_failing` is responsible for rejecting them.
+pub fn shrink<F: FnMut(&Block) -> bool>(program: &Block, mut is_failing: F) -> Block {
+    let mut current = program.clone();
+    // Candidates before the last successful one have been rejected already, so the search continues from it
+    let mut start = 0;
+    'reduce: loop {
+        let candidates = block_candidates(&current);
+        for (index, candidate) in candidates.iter().enumerate().skip(start) {
+            if is_failing(candidate) {
+                current = candidate.clone();
+                start = index;
+                continue 'reduce;
+            }
+        }
+        if start == 0 {
+            return current;
+        }
+        start = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fuzz::generator::{GeneratorConfig, ProgramGenerator};
+    use crate::parser::ast::Ident;
+    use crate::parser::interpreter::AstInterpreter;
+    use crate::parser::printer::program_to_source;
+    use super::*;
+
+    fn prints_large_value(program: &Block) -> bool {
+        let mut interpreter = AstInterpreter::with_step_limit(10_000);
+        interpreter.run(program).is_ok() && interpreter.output().iter().any(|x| *x > 100)
+    }
+
+    #[test]
+    fn test_shrink_to_minimal() {
+        let seed = (0..)
+            .find(|seed| prints_large_value(&ProgramGenerator::new(*seed, GeneratorConfig::default()).generate()))
+            .unwrap();
+        let program = ProgramGenerator::new(seed, GeneratorConfig::default()).generate();
+        let shrunk = shrink(&program, prints_large_value);
+        assert!(prints_large_value(&shrunk));
+        // Either a print of a literal or an assignment of it and a print of the variable
+        assert!(shrunk.len() <= 2, "{}", program_to_source(&shrunk));
+    }
+
+    #[test]
+    fn test_shrink_keeps_needed_statements() {
+        let a = || Ident(String::from("a"));
+        let program: Block = vec![
+            Stmt::Print(Expr::IntLiteral(3)),
+            Stmt::VarAssign(a(), Expr::IntLiteral(7)),
+            Stmt::Print(Expr::IntLiteral(5)),
+            Stmt::Print(Expr::Var(a())),
+        ].into_iter().map(|stmt| Spanned::new(stmt, Default::default())).collect();
+        let prints_seven = |program: &Block| {
+            let mut interpreter = AstInterpreter::with_step_limit(100);
+            interpreter.run(program).is_ok() && interpreter.output().contains(&7)
+        };
+        let shrunk = shrink(&program, prints_seven);
+        let expected = vec![Stmt::VarAssign(a(), Expr::IntLiteral(7)), Stmt::Print(Expr::Var(a()))];
+        assert_eq!(expected, shrunk.into_iter().map(|stmt| stmt.node).collect::<Vec<_>>());
+    }
+}
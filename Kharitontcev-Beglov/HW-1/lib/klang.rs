@@ -2,5 +2,8 @@ pub mod parser;
 pub mod lexer;
 pub mod stack_machine;
 pub mod binary;
+pub mod fuzz;
+pub mod ssa;
+pub mod emulator;
 
 extern crate nom;
\ No newline at end of file
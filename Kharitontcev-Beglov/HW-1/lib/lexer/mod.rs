@@ -6,14 +6,14 @@ use tokens::*;
 use nom::*;
 use nom::branch::alt;
 use nom::combinator::{map, map_res, recognize};
-use nom::bytes::complete::{tag, take};
+use nom::bytes::complete::{is_not, tag, take};
 use nom::character::complete::{alpha1, i32};
 use nom::error::ParseError;
 use nom::multi::many0;
 use nom::sequence::{delimited, pair};
 macro_rules! syntax {
     ($func_name: ident, $tag_string: literal, $output_token: expr) => {
-        fn $func_name<'a>(s: &'a [u8]) -> IResult<&[u8], Token> {
+        fn $func_name(s: &[u8]) -> IResult<&[u8], Token> {
             map(tag($tag_string), |_| $output_token)(s)
         }
     };
@@ -74,7 +74,11 @@ pub fn lex_reserved_ident(input: &[u8]) -> IResult<&[u8], Token> {
                 "else" => Token::ElseKeyword,
                 "while" => Token::WhileKeyword,
                 "print" => Token::PrintKeyword,
+                "printc" => Token::PrintCharKeyword,
+                "prints" => Token::PrintStringKeyword,
                 "var" => Token::VarKeyword,
+                "import" => Token::ImportKeyword,
+                "export" => Token::ExportKeyword,
                 _ => Token::Ident(syntax.to_string()),
             })
         },
@@ -85,6 +89,70 @@ pub fn lex_int_literal(input: &[u8]) -> IResult<&[u8], Token> {
     map(i32, Token::IntLiteral)(input)
 }
 
+/// Escape sequences of the string and character literals
+const ESCAPES: [(char, char); 6] = [('n', '\n'), ('t', '\t'), ('r', '\r'), ('0', '\0'), ('\\', '\\'), ('"', '"')];
+
+/// Replaces the escape sequences with the characters, `\'` is an escaped quote as well
+pub fn unescape(literal: &str) -> Option<String> {
+    let mut result = String::new();
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let escaped = chars.next()?;
+        match ESCAPES.iter().find(|(name, _)| *name == escaped) {
+            Some((_, c)) => result.push(*c),
+            None if escaped == '\'' => result.push('\''),
+            None => return None,
+        }
+    }
+    Some(result)
+}
+
+/// Inverse of [`unescape`] for the double-quoted strings
+pub fn escape(value: &str) -> String {
+    let mut result = String::new();
+    for c in value.chars() {
+        match ESCAPES.iter().find(|(_, escaped)| *escaped == c) {
+            Some((name, _)) => {
+                result.push('\\');
+                result.push(*name)
+            }
+            None => result.push(c),
+        }
+    }
+    result
+}
+
+/// Characters of a quoted literal up to the closing quote, an escaped quote does not close it
+fn quoted_content<'a>(quote: &'static str) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], &'a [u8]> {
+    let stop = if quote == "\"" { "\"\\\n" } else { "'\\\n" };
+    delimited(tag(quote), recognize(many0(alt((is_not(stop), recognize(pair(tag("\\"), take(1usize))))))), tag(quote))
+}
+
+/// Double-quoted string on a single line with the escape sequences `\n`, `\t`, `\r`, `\0`, `\\` and `\"`
+pub fn lex_string_literal(input: &[u8]) -> IResult<&[u8], Token> {
+    map_res(quoted_content("\""), |s| {
+        let value = complete_byte_slice_str_from_utf8(s).map_err(|_| "invalid UTF-8")?;
+        unescape(&value).map(Token::StringLiteral).ok_or("invalid escape sequence")
+    })(input)
+}
+
+/// Single character in single quotes, possibly escaped
+pub fn lex_char_literal(input: &[u8]) -> IResult<&[u8], Token> {
+    map_res(quoted_content("'"), |s| {
+        let value = complete_byte_slice_str_from_utf8(s).map_err(|_| "invalid UTF-8")?;
+        let value = unescape(&value).ok_or("invalid escape sequence")?;
+        let mut chars = value.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(Token::CharLiteral(c)),
+            _ => Err("expected a single character"),
+        }
+    })(input)
+}
+
 fn lex_illegal(input: &[u8]) -> IResult<&[u8], Token> {
     map(take(1usize), |_| Token::Illegal)(input)
 }
@@ -95,6 +163,8 @@ fn lex_token(input: &[u8]) -> IResult<&[u8], Token> {
         lex_punct,
         lex_reserved_ident,
         lex_int_literal,
+        lex_string_literal,
+        lex_char_literal,
         lex_illegal,
     ))(input)
 }
@@ -108,16 +178,39 @@ fn multispace0<T, E: ParseError<T>>(input: T) -> IResult<T, T, E>
         !(c == ' ' || c == '\t' || c == '\r')
     })
 }
-fn lex_tokens(input: &[u8]) -> IResult<&[u8], Vec<Token>> {
-    many0(delimited(multispace0, lex_token, multispace0))(input)
+fn lex_tokens(input: &[u8]) -> IResult<&[u8], Vec<(Token, usize)>> {
+    let with_offset = |i| map(lex_token, |token| (token, input.offset(i)))(i);
+    many0(delimited(multispace0, with_offset, multispace0))(input)
+}
+
+fn offset_to_span(input: &[u8], offset: usize) -> Span {
+    let before = &input[..offset];
+    let line_start = before.iter().rposition(|c| *c == b'\n').map_or(0, |p| p + 1);
+    Span {
+        line: before.iter().filter(|c| **c == b'\n').count() + 1,
+        column: offset - line_start + 1,
+    }
 }
 
 pub struct Lexer;
 
 impl Lexer {
     pub fn lex_tokens(bytes: &[u8]) -> IResult<&[u8], Vec<Token>> {
-        lex_tokens(bytes)
-            .map(|(slice, result)| (slice, [&result[..], &vec![Token::EOF][..]].concat()))
+        Self::lex_tokens_with_spans(bytes)
+            .map(|(slice, (tokens, _))| (slice, tokens))
+    }
+
+    /// Lexes the tokens and additionally returns the source position of each of them
+    pub fn lex_tokens_with_spans(bytes: &[u8]) -> IResult<&[u8], (Vec<Token>, Vec<Span>)> {
+        lex_tokens(bytes).map(|(slice, result)| {
+            let (mut tokens, offsets): (Vec<Token>, Vec<usize>) = result.into_iter().unzip();
+            let mut spans: Vec<Span> = offsets.into_iter()
+                .map(|offset| offset_to_span(bytes, offset))
+                .collect();
+            tokens.push(Token::EOF);
+            spans.push(offset_to_span(bytes, bytes.len() - slice.len()));
+            (slice, (tokens, spans))
+        })
     }
 }
 
@@ -212,4 +305,73 @@ mod tests {
         
         assert_eq!(result, expected_results);
     }
+
+    #[test]
+    fn test_module_header() {
+        let input = "import \"lib/util.k\"\nexport total".as_bytes();
+        let (_, result) = Lexer::lex_tokens(input).unwrap();
+        let expected = vec![
+            Token::ImportKeyword,
+            Token::StringLiteral(String::from("lib/util.k")),
+            Token::EOL,
+            Token::ExportKeyword,
+            Token::Ident(String::from("total")),
+            Token::EOF,
+        ];
+        assert_eq!(expected, result);
+        let (_, result) = Lexer::lex_tokens("\"unterminated\n\"".as_bytes()).unwrap();
+        assert_eq!(Token::Illegal, result[0]);
+    }
+
+    #[test]
+    fn test_text_literals() {
+        let input = "prints \"a\\\"b\\n\"; printc('\\'') printc('x')".as_bytes();
+        let (_, result) = Lexer::lex_tokens(input).unwrap();
+        let expected = vec![
+            Token::PrintStringKeyword,
+            Token::StringLiteral(String::from("a\"b\n")),
+            Token::Semicolon,
+            Token::PrintCharKeyword,
+            Token::LeftBrace,
+            Token::CharLiteral('\''),
+            Token::RightBrace,
+            Token::PrintCharKeyword,
+            Token::LeftBrace,
+            Token::CharLiteral('x'),
+            Token::RightBrace,
+            Token::EOF,
+        ];
+        assert_eq!(expected, result);
+        assert_eq!("a\\\"b\\n", escape("a\"b\n"));
+        for invalid in ["\"\\q\"", "'ab'", "''"] {
+            let (_, result) = Lexer::lex_tokens(invalid.as_bytes()).unwrap();
+            assert_eq!(Token::Illegal, result[0], "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_spans() {
+        let input = "{\n  a = 10\n\tprint(a)\n}".as_bytes();
+        let (_, (tokens, spans)) = Lexer::lex_tokens_with_spans(input).unwrap();
+        assert_eq!(tokens.len(), spans.len());
+        let expected = vec![
+            (Token::OpenParenthesis, 1, 1),
+            (Token::EOL, 1, 2),
+            (Token::Ident(String::from("a")), 2, 3),
+            (Token::Assign, 2, 5),
+            (Token::IntLiteral(10), 2, 7),
+            (Token::EOL, 2, 9),
+            (Token::PrintKeyword, 3, 2),
+            (Token::LeftBrace, 3, 7),
+            (Token::Ident(String::from("a")), 3, 8),
+            (Token::RightBrace, 3, 9),
+            (Token::EOL, 3, 10),
+            (Token::CloseParenthesis, 4, 1),
+            (Token::EOF, 4, 2),
+        ];
+        let actual: Vec<_> = tokens.into_iter().zip(spans)
+            .map(|(token, span)| (token, span.line, span.column))
+            .collect();
+        assert_eq!(expected, actual);
+    }
 }
\ No newline at end of file
@@ -1,3 +1,4 @@
+use std::fmt::{Display, Formatter};
 use std::iter::Enumerate;
 use std::ops::{Range, RangeFrom, RangeFull, RangeTo};
 use nom::{InputIter, InputLength, InputTake, Needed, Slice};
@@ -7,6 +8,8 @@ pub enum Token {
     EOF,
     IntLiteral(i32),
     Ident(String),
+    StringLiteral(String),
+    CharLiteral(char),
     Assign,
     Plus,
     Minus,
@@ -24,25 +27,58 @@ pub enum Token {
     ElseKeyword,
     WhileKeyword,
     PrintKeyword,
+    PrintCharKeyword,
+    PrintStringKeyword,
     VarKeyword,
+    ImportKeyword,
+    ExportKeyword,
     Illegal
 }
+/// Position of a token in the source code, both line and column are 1-based
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 #[repr(C)]
 pub struct Tokens<'a> {
     pub tok: &'a [Token],
+    // Either empty or has the same length as `tok`
+    pub spans: &'a [Span],
     pub start: usize,
     pub end: usize
 }
 
 impl<'a> Tokens<'a> {
     pub fn new(vec: &'a [Token]) -> Self {
+        Self::with_spans(vec, &[])
+    }
+
+    pub fn with_spans(vec: &'a [Token], spans: &'a [Span]) -> Self {
         Tokens {
             tok: vec,
+            spans,
             start: 0,
             end: vec.len()
         }
     }
+
+    /// Span of the first token, default span if the tokens were lexed without positions
+    pub fn span(&self) -> Span {
+        self.spans.first().copied().unwrap_or_default()
+    }
+
+    fn spans_slice(&self, range: Range<usize>) -> &'a [Span] {
+        if self.spans.is_empty() { self.spans } else { &self.spans[range] }
+    }
 }
 
 impl<'a> InputLength for Tokens<'a>  {
@@ -56,6 +92,7 @@ impl<'a> InputTake for Tokens<'a> {
     fn take(&self, count: usize) -> Self {
         Tokens {
             tok: &self.tok[0..count],
+            spans: self.spans_slice(0..count),
             start: 0,
             end: count,
         }
@@ -66,11 +103,13 @@ impl<'a> InputTake for Tokens<'a> {
         let (prefix, suffix) = self.tok.split_at(count);
         let first = Tokens {
             tok: prefix,
+            spans: self.spans_slice(0..count),
             start: 0,
             end: prefix.len(),
         };
         let second = Tokens {
             tok: suffix,
+            spans: self.spans_slice(count..self.tok.len()),
             start: 0,
             end: suffix.len(),
         };
@@ -90,6 +129,7 @@ impl<'a> Slice<Range<usize>> for Tokens<'a> {
     fn slice(&self, range: Range<usize>) -> Self {
         Tokens {
             tok: self.tok.slice(range.clone()),
+            spans: self.spans_slice(range.clone()),
             start: self.start + range.start,
             end: self.start + range.end,
         }
@@ -115,6 +155,7 @@ impl<'a> Slice<RangeFull> for Tokens<'a> {
     fn slice(&self, _: RangeFull) -> Self {
         Tokens {
             tok: self.tok,
+            spans: self.spans,
             start: self.start,
             end: self.end,
         }
@@ -1,7 +1,31 @@
+use crate::lexer::tokens::Span;
+
 #[derive(Eq, PartialEq, Clone, Debug, Hash)]
 pub struct Ident(pub String);
 
-pub type Block = Vec<Stmt>;
+/// AST node together with the position in the source code where it starts
+#[derive(PartialEq, Clone, Debug)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Self { node, span }
+    }
+}
+
+pub type Block = Vec<Spanned<Stmt>>;
+
+/// Source file compiled separately: the header with the imported files and the exported variables, then the program
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct Module {
+    /// Paths of the imported files relative to the importing one
+    pub imports: Vec<String>,
+    pub exports: Vec<Ident>,
+    pub body: Block,
+}
 
 #[derive(PartialEq, Clone, Debug)]
 pub enum Stmt {
@@ -10,6 +34,10 @@ pub enum Stmt {
     If { condition: Expr, true_branch: Box<Block>, false_branch: Option<Box<Block>> },
     While(Expr, Box<Block>),
     Print(Expr),
+    /// Prints the character with the code of the value, without a line break
+    PrintChar(Expr),
+    /// Prints the string as is, without a line break
+    PrintString(String),
 }
 
 #[derive(PartialEq, Clone, Debug)]
@@ -18,9 +46,11 @@ pub enum Expr {
     Var(Ident),
     InfixOperation(Box<Expr>, Ops, Box<Expr>),
     PrefixOperation(PrefixOps, Box<Expr>),
+    /// Call of a routine of the runtime library
+    Call(Routine, Vec<Expr>),
 }
 
-#[derive(PartialEq, Eq, Ord, PartialOrd, Copy, Clone, Debug)]
+#[derive(PartialEq, Eq, Ord, PartialOrd, Copy, Clone, Debug, Hash)]
 pub enum Ops {
     Add,
     Sub,
@@ -29,12 +59,88 @@ pub enum Ops {
     BitwiseNor
 }
 
-#[derive(PartialEq, Clone, Debug, Ord, PartialOrd, Eq)]
+impl Ops {
+    /// Computes the operation the same way the emulator ALU does, i.e. with wrapping arithmetics
+    pub fn apply(&self, lhs: i32, rhs: i32) -> i32 {
+        match self {
+            Ops::Add => lhs.wrapping_add(rhs),
+            Ops::Sub => lhs.wrapping_sub(rhs),
+            Ops::BitwiseAnd => lhs & rhs,
+            Ops::BitwiseOr => lhs | rhs,
+            Ops::BitwiseNor => !(lhs | rhs),
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Ord, PartialOrd, Eq, Hash)]
 pub enum PrefixOps {
     BitwiseNot,
     UnaryMinus
 }
 
+impl PrefixOps {
+    pub fn apply(&self, value: i32) -> i32 {
+        match self {
+            PrefixOps::BitwiseNot => !value,
+            PrefixOps::UnaryMinus => value.wrapping_neg(),
+        }
+    }
+}
+
+/// Routine of the runtime library, called from Klang by its name, e.g. `mul(a, b)`
+#[derive(PartialEq, Copy, Clone, Debug, Ord, PartialOrd, Eq, Hash)]
+pub enum Routine {
+    Mul,
+    Div,
+    Mod,
+    Abs,
+    Min,
+    Max,
+}
+
+impl Routine {
+    pub const ALL: [Routine; 6] = [Routine::Mul, Routine::Div, Routine::Mod, Routine::Abs, Routine::Min, Routine::Max];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|routine| routine.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Routine::Mul => "mul",
+            Routine::Div => "div",
+            Routine::Mod => "mod",
+            Routine::Abs => "abs",
+            Routine::Min => "min",
+            Routine::Max => "max",
+        }
+    }
+
+    /// Number of the arguments
+    pub fn arity(&self) -> usize {
+        match self {
+            Routine::Abs => 1,
+            _ => 2,
+        }
+    }
+
+    /// Computes the routine the same way the runtime library does: arithmetics wraps,
+    /// division by zero gives -1 and the remainder is the dividend then
+    pub fn apply(&self, args: &[i32]) -> i32 {
+        match (self, args) {
+            (Routine::Mul, [lhs, rhs]) => lhs.wrapping_mul(*rhs),
+            (Routine::Div, [_, 0]) => -1,
+            (Routine::Div, [lhs, rhs]) => lhs.wrapping_div(*rhs),
+            (Routine::Mod, [lhs, 0]) => *lhs,
+            (Routine::Mod, [lhs, rhs]) => lhs.wrapping_rem(*rhs),
+            (Routine::Abs, [value]) => value.wrapping_abs(),
+            (Routine::Min, [lhs, rhs]) => *lhs.min(rhs),
+            (Routine::Max, [lhs, rhs]) => *lhs.max(rhs),
+            _ => panic!("{} takes {} arguments, got {}", self.name(), self.arity(), args.len()),
+        }
+    }
+}
+
 #[derive(PartialEq, PartialOrd, Debug, Clone)]
 pub enum Precedence {
     Lowest,
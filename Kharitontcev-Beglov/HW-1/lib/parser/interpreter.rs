@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use crate::parser::ast::{Block, Expr, Ident, Stmt};
+
+/// Character printed by `printc` for the value, the emulator prints the same one
+pub fn output_char(value: i32) -> char {
+    char::from_u32(value as u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// Text printed by `prints`, the string is stored zero-terminated, so it ends at the first `\0`
+pub fn output_string(value: &str) -> &str {
+    value.split('\0').next().unwrap_or_default()
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InterpretError {
+    StepLimitExceeded,
+}
+
+impl Display for InterpretError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterpretError::StepLimitExceeded => write!(f, "Step limit exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for InterpretError {}
+
+/// Tree-walking interpreter of the Klang AST, the reference semantics for the compiled code.
+///
+/// Variables are zero until assigned, arithmetics wraps around as in the emulator ALU.
+#[derive(Clone, Debug, Default)]
+pub struct AstInterpreter {
+    variables: HashMap<Ident, i32>,
+    output: Vec<i32>,
+    text: String,
+    steps: usize,
+    step_limit: Option<usize>,
+}
+
+impl AstInterpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the number of executed statements and loop iterations
+    pub fn with_step_limit(step_limit: usize) -> Self {
+        Self { step_limit: Some(step_limit), ..Self::default() }
+    }
+
+    fn step(&mut self) -> Result<(), InterpretError> {
+        self.steps += 1;
+        match self.step_limit {
+            Some(limit) if self.steps > limit => Err(InterpretError::StepLimitExceeded),
+            _ => Ok(())
+        }
+    }
+
+    pub fn eval(&self, expr: &Expr) -> i32 {
+        match expr {
+            Expr::IntLiteral(x) => *x,
+            Expr::Var(id) => self.variable(id),
+            Expr::InfixOperation(lhs, op, rhs) => op.apply(self.eval(lhs), self.eval(rhs)),
+            Expr::PrefixOperation(op, expr) => op.apply(self.eval(expr)),
+            Expr::Call(routine, args) => {
+                let args: Vec<i32> = args.iter().map(|arg| self.eval(arg)).collect();
+                routine.apply(&args)
+            }
+        }
+    }
+
+    fn run_stmt(&mut self, stmt: &Stmt) -> Result<(), InterpretError> {
+        self.step()?;
+        match stmt {
+            Stmt::VarDeclaration(_) => {}
+            Stmt::VarAssign(id, expr) => {
+                let value = self.eval(expr);
+                self.variables.insert(id.clone(), value);
+            }
+            Stmt::If { condition, true_branch, false_branch } => {
+                if self.eval(condition) != 0 {
+                    self.run(true_branch)?
+                } else if let Some(false_branch) = false_branch {
+                    self.run(false_branch)?
+                }
+            }
+            Stmt::While(condition, body) => {
+                while self.eval(condition) != 0 {
+                    self.run(body)?;
+                    self.step()?
+                }
+            }
+            Stmt::Print(expr) => {
+                let value = self.eval(expr);
+                self.output.push(value);
+                self.text.push_str(&format!("{}\n", value))
+            }
+            Stmt::PrintChar(expr) => {
+                let value = self.eval(expr);
+                self.text.push(output_char(value))
+            }
+            Stmt::PrintString(value) => self.text.push_str(output_string(value)),
+        }
+        Ok(())
+    }
+
+    pub fn run(&mut self, program: &Block) -> Result<(), InterpretError> {
+        for stmt in program {
+            self.run_stmt(&stmt.node)?
+        }
+        Ok(())
+    }
+
+    /// Values printed by the program so far
+    pub fn output(&self) -> &[i32] {
+        &self.output
+    }
+
+    /// Whole text printed by the program so far, including the characters and the strings
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn variable(&self, id: &Ident) -> i32 {
+        self.variables.get(id).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_source;
+    use super::*;
+
+    #[test]
+    fn test_fib() {
+        let program = parse_source("{
+            n = 6; a = 1; b = 1
+            while (n) { print(b); c = a + b; b = a; a = c; n = n - 1 }
+        }");
+        let mut interpreter = AstInterpreter::new();
+        interpreter.run(&program).unwrap();
+        assert_eq!(&[1, 1, 2, 3, 5, 8], interpreter.output());
+        assert_eq!(21, interpreter.variable(&Ident(String::from("a"))));
+    }
+
+    #[test]
+    fn test_wrapping_and_branches() {
+        let program = parse_source("{
+            x = 2147483647 + 1; print(x)
+            if (x & 0) { print(1) } else { print(~x) }
+        }");
+        let mut interpreter = AstInterpreter::new();
+        interpreter.run(&program).unwrap();
+        assert_eq!(&[i32::MIN, i32::MAX], interpreter.output());
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let program = parse_source("{ x = 1; while (x) { print(x) } }");
+        let mut interpreter = AstInterpreter::with_step_limit(50);
+        assert_eq!(Err(InterpretError::StepLimitExceeded), interpreter.run(&program));
+        assert!(!interpreter.output().is_empty());
+    }
+}
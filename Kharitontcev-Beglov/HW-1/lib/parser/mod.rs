@@ -1,4 +1,7 @@
 pub mod ast;
+pub mod interpreter;
+pub mod printer;
+pub mod simplify;
 
 use nom::branch::alt;
 use nom::bytes::complete::take;
@@ -27,6 +30,19 @@ fn parse_literal(tokens: Tokens) -> IResult<Tokens, Expr> {
     } else {
         match t1.tok[0].clone() {
             Token::IntLiteral(x) => Ok((i1, Expr::IntLiteral(x))),
+            Token::CharLiteral(c) => Ok((i1, Expr::IntLiteral(c as i32))),
+            _ => Err(Err::Error(Error::new(tokens, ErrorKind::Tag)))
+        }
+    }
+}
+
+fn parse_string_literal(tokens: Tokens) -> IResult<Tokens, String> {
+    let (remaining, token) = take(1usize)(tokens)?;
+    if token.tok.is_empty() {
+        Err(Err::Error(Error::new(tokens, ErrorKind::Tag)))
+    } else {
+        match token.tok[0].clone() {
+            Token::StringLiteral(value) => Ok((remaining, value)),
             _ => Err(Err::Error(Error::new(tokens, ErrorKind::Tag)))
         }
     }
@@ -55,7 +71,11 @@ tag_token!(if_tag, Token::IfKeyword);
 tag_token!(else_tag, Token::ElseKeyword);
 tag_token!(while_tag, Token::WhileKeyword);
 tag_token!(print_tag, Token::PrintKeyword);
+tag_token!(print_char_tag, Token::PrintCharKeyword);
+tag_token!(print_string_tag, Token::PrintStringKeyword);
 tag_token!(var_tag, Token::VarKeyword);
+tag_token!(import_tag, Token::ImportKeyword);
+tag_token!(export_tag, Token::ExportKeyword);
 tag_token!(eof_tag, Token::EOF);
 
 fn infix_op(t: &Token) -> (Precedence, Option<Ops>) {
@@ -72,12 +92,27 @@ fn parse_atom_expr(input: Tokens) -> IResult<Tokens, Expr> {
     alt((
         parse_prefix_expr,
         parse_literal,
+        parse_call_expr,
         parse_ident_expr,
         delimited(lbrace_tag, parse_full_expr, rbrace_tag)
     ))(input)
 }
 
 
+/// Call of a runtime routine with the right number of arguments
+fn parse_call_expr(input: Tokens) -> IResult<Tokens, Expr> {
+    let (remaining, (name, _, args, _)) = tuple((
+        parse_ident,
+        lbrace_tag,
+        separated_list0(comma_tag, parse_full_expr),
+        rbrace_tag,
+    ))(input)?;
+    match Routine::from_name(&name.0) {
+        Some(routine) if routine.arity() == args.len() => Ok((remaining, Expr::Call(routine, args))),
+        _ => Err(Err::Error(error_position!(input, ErrorKind::Verify))),
+    }
+}
+
 fn parse_ident_expr(tokens: Tokens) -> IResult<Tokens, Expr> {
     map(parse_ident, Expr::Var)(tokens)
 }
@@ -171,10 +206,7 @@ fn parse_if(input: Tokens) -> IResult<Tokens, Stmt> {
             Stmt::If {
                 condition,
                 true_branch: Box::new(true_block),
-                false_branch: match else_block {
-                    None => None,
-                    Some((_, else_block)) => Some(Box::new(else_block))
-                },
+                false_branch: else_block.map(|(_, else_block)| Box::new(else_block)),
             }
         })(input)
 }
@@ -204,14 +236,33 @@ fn parse_print(input: Tokens) -> IResult<Tokens, Stmt> {
     )(input)
 }
 
-fn parse_stmt(input: Tokens) -> IResult<Tokens, Stmt> {
-    alt((
+fn parse_print_char(input: Tokens) -> IResult<Tokens, Stmt> {
+    map(
+        tuple((
+            print_char_tag,
+            lbrace_tag,
+            parse_full_expr,
+            rbrace_tag
+        )),
+        |(_, _, expr, _)| Stmt::PrintChar(expr)
+    )(input)
+}
+
+fn parse_print_string(input: Tokens) -> IResult<Tokens, Stmt> {
+    map(tuple((print_string_tag, parse_string_literal)), |(_, value)| Stmt::PrintString(value))(input)
+}
+
+fn parse_stmt(input: Tokens) -> IResult<Tokens, Spanned<Stmt>> {
+    let span = input.span();
+    map(alt((
         parse_var_declaration,
         parse_var_assign,
         parse_if,
         parse_while,
-        parse_print
-    ))(input)
+        parse_print,
+        parse_print_char,
+        parse_print_string
+    )), move |stmt| Spanned::new(stmt, span))(input)
 }
 
 fn stmt_separator(input: Tokens) -> IResult<Tokens, Tokens> {
@@ -227,16 +278,58 @@ fn parse_block(input: Tokens) -> IResult<Tokens, Block> {
     )(input)
 }
 
+/// Line of the module header
+enum HeaderItem {
+    Import(String),
+    Export(Vec<Ident>),
+}
+
+fn parse_header_item(input: Tokens) -> IResult<Tokens, HeaderItem> {
+    alt((
+        map(tuple((import_tag, parse_string_literal)), |(_, path)| HeaderItem::Import(path)),
+        map(tuple((export_tag, separated_list1(comma_tag, parse_ident))), |(_, list)| HeaderItem::Export(list)),
+    ))(input)
+}
+
+fn parse_module(input: Tokens) -> IResult<Tokens, Module> {
+    map(
+        tuple((many0(stmt_separator), many0(terminated(parse_header_item, many1(stmt_separator))), parse_block)),
+        |(_, header, body)| {
+            let mut module = Module { body, ..Module::default() };
+            for item in header {
+                match item {
+                    HeaderItem::Import(path) => module.imports.push(path),
+                    HeaderItem::Export(list) => module.exports.extend(list),
+                }
+            }
+            module
+        },
+    )(input)
+}
+
+/// Lexes and parses the program with the statement positions, panics if it is invalid
+#[cfg(test)]
+pub(crate) fn parse_source(source: &str) -> Block {
+    let (_, (lexed, spans)) = crate::lexer::Lexer::lex_tokens_with_spans(source.as_bytes()).unwrap();
+    let (_, parsed) = Parser::parse(Tokens::with_spans(&lexed, &spans)).unwrap();
+    parsed
+}
+
 pub struct Parser;
 impl Parser {
     pub fn parse(tokens: Tokens) -> IResult<Tokens, Block> {
         terminated(parse_block, eof_tag)(tokens)
     }
+
+    /// Parses a program which may start with `import "<path>"` and `export <names>` lines
+    pub fn parse_module(tokens: Tokens) -> IResult<Tokens, Module> {
+        terminated(parse_module, eof_tag)(tokens)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::lexer::{Lexer, tokens::Tokens};
+    use crate::lexer::{Lexer, tokens::{Span, Tokens}};
     use crate::parser::ast::PrefixOps::BitwiseNot;
     use super::*;
 
@@ -255,4 +348,61 @@ mod tests {
             )),
         ))
     }
+
+    #[test]
+    fn test_calls() {
+        let parse = |input: &str| {
+            let (_, lexed) = Lexer::lex_tokens(input.as_bytes()).unwrap();
+            parse_full_expr(Tokens::new(&lexed)).map(|(_, expr)| expr).ok()
+        };
+        assert_eq!(
+            Some(Expr::Call(Routine::Max, vec![Expr::Call(Routine::Abs, vec![Expr::Var(Ident(String::from("a")))]), Expr::IntLiteral(2)])),
+            parse("max(abs(a), 2)")
+        );
+        // Unknown routines and wrong numbers of arguments are not calls
+        assert_eq!(Some(Expr::Var(Ident(String::from("foo")))), parse("foo(1)"));
+        assert_eq!(Some(Expr::Var(Ident(String::from("abs")))), parse("abs(1, 2)"));
+    }
+
+    #[test]
+    fn test_text_output() {
+        let (_, lexed) = Lexer::lex_tokens("{ prints \"x = \"; printc('a' + 1)\nprint(x) }".as_bytes()).unwrap();
+        let (_, block) = Parser::parse(Tokens::new(&lexed)).unwrap();
+        let statements: Vec<Stmt> = block.into_iter().map(|stmt| stmt.node).collect();
+        assert_eq!(vec![
+            Stmt::PrintString(String::from("x = ")),
+            Stmt::PrintChar(InfixOperation(Box::new(Expr::IntLiteral(97)), Ops::Add, Box::new(Expr::IntLiteral(1)))),
+            Stmt::Print(Expr::Var(Ident(String::from("x")))),
+        ], statements);
+    }
+
+    #[test]
+    fn test_module() {
+        let input = "import \"util.k\"\nexport a, b; import \"io.k\"\n\n{ a = 1 }".as_bytes();
+        let (_, lexed) = Lexer::lex_tokens(input).unwrap();
+        let (_, module) = Parser::parse_module(Tokens::new(&lexed)).unwrap();
+        assert_eq!(vec![String::from("util.k"), String::from("io.k")], module.imports);
+        assert_eq!(vec![Ident(String::from("a")), Ident(String::from("b"))], module.exports);
+        assert_eq!(1, module.body.len());
+        // A plain program is a module without the header
+        let (_, lexed) = Lexer::lex_tokens("{ print(1) }".as_bytes()).unwrap();
+        let (_, module) = Parser::parse_module(Tokens::new(&lexed)).unwrap();
+        assert_eq!((0, 0, 1), (module.imports.len(), module.exports.len(), module.body.len()));
+        // The header is followed by a line break
+        let (_, lexed) = Lexer::lex_tokens("export a { a = 1 }".as_bytes()).unwrap();
+        assert!(Parser::parse_module(Tokens::new(&lexed)).is_err());
+    }
+
+    #[test]
+    fn test_statement_spans() {
+        let input = "{\n  a = 1\n  while (a) {\n    print(a); a = a - 1\n  }\n}".as_bytes();
+        let (_, (lexed, spans)) = Lexer::lex_tokens_with_spans(input).unwrap();
+        let (_, parsed) = Parser::parse(Tokens::with_spans(&lexed, &spans)).unwrap();
+        assert_eq!(2, parsed.len());
+        assert_eq!(Span { line: 2, column: 3 }, parsed[0].span);
+        assert_eq!(Span { line: 3, column: 3 }, parsed[1].span);
+        let Stmt::While(_, body) = &parsed[1].node else { panic!("Expected while, got {:?}", parsed[1]) };
+        let body_spans: Vec<Span> = body.iter().map(|stmt| stmt.span).collect();
+        assert_eq!(vec![Span { line: 4, column: 5 }, Span { line: 4, column: 15 }], body_spans);
+    }
 }
\ No newline at end of file
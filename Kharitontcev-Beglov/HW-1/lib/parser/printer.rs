@@ -0,0 +1,158 @@
+use std::fmt::{Display, Formatter};
+use crate::lexer::escape;
+use crate::parser::ast::{Block, Expr, Ops, PrefixOps, Stmt};
+
+impl Display for Ops {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ops::Add => write!(f, "+"),
+            Ops::Sub => write!(f, "-"),
+            Ops::BitwiseAnd => write!(f, "&"),
+            Ops::BitwiseOr => write!(f, "|"),
+            // There is no syntax for nor, but it could be expressed as ~(a | b)
+            Ops::BitwiseNor => write!(f, "~|"),
+        }
+    }
+}
+
+fn fmt_operand(expr: &Expr, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match expr {
+        Expr::InfixOperation(_, _, _) => write!(f, "({})", expr),
+        _ => write!(f, "{}", expr),
+    }
+}
+
+/// Prints the expression in the Klang syntax, nested operations are always put into braces
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // Only non-negative literals could be lexed
+            Expr::IntLiteral(i32::MIN) => write!(f, "((0 - {}) - 1)", i32::MAX),
+            Expr::IntLiteral(x) if *x < 0 => write!(f, "(0 - {})", -x),
+            Expr::IntLiteral(x) => write!(f, "{}", x),
+            Expr::Var(id) => write!(f, "{}", id.0),
+            Expr::InfixOperation(lhs, Ops::BitwiseNor, rhs) => {
+                write!(f, "~(")?;
+                fmt_operand(lhs, f)?;
+                write!(f, " | ")?;
+                fmt_operand(rhs, f)?;
+                write!(f, ")")
+            }
+            Expr::InfixOperation(lhs, op, rhs) => {
+                fmt_operand(lhs, f)?;
+                write!(f, " {} ", op)?;
+                fmt_operand(rhs, f)
+            }
+            Expr::PrefixOperation(PrefixOps::BitwiseNot, expr) => {
+                write!(f, "~")?;
+                fmt_operand(expr, f)
+            }
+            Expr::PrefixOperation(PrefixOps::UnaryMinus, expr) => {
+                write!(f, "(0 - ")?;
+                fmt_operand(expr, f)?;
+                write!(f, ")")
+            }
+            Expr::Call(routine, args) => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "{}({})", routine.name(), args.join(", "))
+            }
+        }
+    }
+}
+
+fn print_block(block: &Block, indent: usize, result: &mut String) {
+    result.push_str("{\n");
+    for stmt in block {
+        result.push_str(&"    ".repeat(indent + 1));
+        print_stmt(&stmt.node, indent + 1, result);
+        result.push('\n');
+    }
+    result.push_str(&"    ".repeat(indent));
+    result.push('}');
+}
+
+fn print_stmt(stmt: &Stmt, indent: usize, result: &mut String) {
+    match stmt {
+        Stmt::VarDeclaration(names) => {
+            let names: Vec<&str> = names.iter().map(|id| id.0.as_str()).collect();
+            result.push_str(&format!("var {}", names.join(", ")))
+        }
+        Stmt::VarAssign(id, expr) => result.push_str(&format!("{} = {}", id.0, expr)),
+        Stmt::If { condition, true_branch, false_branch } => {
+            result.push_str(&format!("if ({}) ", condition));
+            print_block(true_branch, indent, result);
+            if let Some(false_branch) = false_branch {
+                result.push_str(" else ");
+                print_block(false_branch, indent, result);
+            }
+        }
+        Stmt::While(condition, body) => {
+            result.push_str(&format!("while ({}) ", condition));
+            print_block(body, indent, result);
+        }
+        Stmt::Print(expr) => result.push_str(&format!("print({})", expr)),
+        Stmt::PrintChar(expr) => result.push_str(&format!("printc({})", expr)),
+        Stmt::PrintString(value) => result.push_str(&format!("prints \"{}\"", escape(value))),
+    }
+}
+
+/// Prints the program back into the Klang source code, which parses into the same AST
+pub fn program_to_source(program: &Block) -> String {
+    let mut result = String::new();
+    print_block(program, 0, &mut result);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::ast::{Ident, Spanned};
+    use crate::parser::parse_source;
+    use super::*;
+
+    fn strip_spans(block: Block) -> Block {
+        block.into_iter().map(|stmt| Spanned::new(match stmt.node {
+            Stmt::If { condition, true_branch, false_branch } => Stmt::If {
+                condition,
+                true_branch: Box::new(strip_spans(*true_branch)),
+                false_branch: false_branch.map(|block| Box::new(strip_spans(*block))),
+            },
+            Stmt::While(condition, body) => Stmt::While(condition, Box::new(strip_spans(*body))),
+            stmt => stmt
+        }, Default::default())).collect()
+    }
+
+    fn parse(source: &str) -> Block {
+        strip_spans(parse_source(source))
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let source = "{
+    var a, b
+    a = ~1 & (3 + 4)
+    b = (a - 1) - (a | 2)
+    if (a & b) {
+        print(a)
+    } else {
+        while (b) {
+            b = b - 1
+        }
+    }
+    if (a) {
+    }
+}";
+        let program = parse(source);
+        assert_eq!(source, program_to_source(&program));
+        assert_eq!(program, parse(&program_to_source(&program)));
+    }
+
+    #[test]
+    fn test_operations_without_syntax() {
+        let a = || Box::new(Expr::Var(Ident(String::from("a"))));
+        let nor = Expr::InfixOperation(a(), Ops::BitwiseNor, Box::new(Expr::IntLiteral(-5)));
+        assert_eq!("~(a | (0 - 5))", nor.to_string());
+        let minus = Expr::PrefixOperation(PrefixOps::UnaryMinus, a());
+        assert_eq!("(0 - a)", minus.to_string());
+        assert_eq!("((0 - 2147483647) - 1)", Expr::IntLiteral(i32::MIN).to_string());
+    }
+}
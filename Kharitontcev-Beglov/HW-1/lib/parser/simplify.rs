@@ -0,0 +1,182 @@
+//! Constant folding and algebraic simplification of the AST.
+//!
+//! Klang expressions have no side effects, so any subexpression could be dropped or replaced with its value. The only
+//! exception is the checked arithmetic, where `+`, `-` and unary minus stop the program on the overflow: such
+//! operations are folded only when they don't overflow and are never dropped.
+
+use crate::binary::instructions::Arithmetic;
+use crate::parser::ast::{Block, Expr, Ops, PrefixOps, Spanned, Stmt};
+
+/// Whether evaluating the expression may stop the program
+fn may_trap(expr: &Expr, arithmetic: Arithmetic) -> bool {
+    match expr {
+        Expr::InfixOperation(lhs, op, rhs) => {
+            arithmetic.may_trap(op) || may_trap(lhs, arithmetic) || may_trap(rhs, arithmetic)
+        }
+        Expr::PrefixOperation(op, expr) => arithmetic.may_trap_prefix(op) || may_trap(expr, arithmetic),
+        Expr::Call(_, args) => args.iter().any(|arg| may_trap(arg, arithmetic)),
+        _ => false,
+    }
+}
+
+fn simplify_infix(lhs: Expr, op: Ops, rhs: Expr, arithmetic: Arithmetic) -> Expr {
+    let droppable = |expr: &Expr| !may_trap(expr, arithmetic);
+    match (lhs, op, rhs) {
+        (Expr::IntLiteral(lhs), op, Expr::IntLiteral(rhs)) if arithmetic.apply(&op, lhs, rhs).is_some() => {
+            Expr::IntLiteral(arithmetic.apply(&op, lhs, rhs).unwrap())
+        }
+        (x, Ops::Add | Ops::Sub | Ops::BitwiseOr, Expr::IntLiteral(0)) => x,
+        (Expr::IntLiteral(0), Ops::Add | Ops::BitwiseOr, x) => x,
+        (Expr::IntLiteral(-1), Ops::BitwiseAnd, x) | (x, Ops::BitwiseAnd, Expr::IntLiteral(-1)) => x,
+        (x, Ops::BitwiseAnd, Expr::IntLiteral(0)) | (Expr::IntLiteral(0), Ops::BitwiseAnd, x) if droppable(&x) => Expr::IntLiteral(0),
+        (x, Ops::BitwiseOr, Expr::IntLiteral(-1)) | (Expr::IntLiteral(-1), Ops::BitwiseOr, x) if droppable(&x) => Expr::IntLiteral(-1),
+        (lhs, Ops::Sub, rhs) if lhs == rhs && droppable(&lhs) => Expr::IntLiteral(0),
+        (lhs, Ops::BitwiseAnd | Ops::BitwiseOr, rhs) if lhs == rhs => lhs,
+        (lhs, op, rhs) => Expr::InfixOperation(Box::new(lhs), op, Box::new(rhs)),
+    }
+}
+
+fn simplify_prefix(op: PrefixOps, expr: Expr, arithmetic: Arithmetic) -> Expr {
+    match (op, expr) {
+        (op, Expr::IntLiteral(x)) if arithmetic.apply_prefix(&op, x).is_some() => {
+            Expr::IntLiteral(arithmetic.apply_prefix(&op, x).unwrap())
+        }
+        (PrefixOps::BitwiseNot, Expr::PrefixOperation(PrefixOps::BitwiseNot, x)) => *x,
+        // The inner negation of the minimal value overflows
+        (PrefixOps::UnaryMinus, Expr::PrefixOperation(PrefixOps::UnaryMinus, x)) if !arithmetic.may_trap_prefix(&op) => *x,
+        (op, expr) => Expr::PrefixOperation(op, Box::new(expr)),
+    }
+}
+
+/// Folds constant subexpressions with the same semantics as the compiled code and applies identities
+/// such as `x + 0 = x`, `x & 0 = 0` and `x - x = 0`
+pub fn simplify_expr(expr: Expr, arithmetic: Arithmetic) -> Expr {
+    match expr {
+        Expr::InfixOperation(lhs, op, rhs) => {
+            simplify_infix(simplify_expr(*lhs, arithmetic), op, simplify_expr(*rhs, arithmetic), arithmetic)
+        }
+        Expr::PrefixOperation(op, expr) => simplify_prefix(op, simplify_expr(*expr, arithmetic), arithmetic),
+        Expr::Call(routine, args) => {
+            let args: Vec<Expr> = args.into_iter().map(|arg| simplify_expr(arg, arithmetic)).collect();
+            let constants: Option<Vec<i32>> = args.iter()
+                .map(|arg| if let Expr::IntLiteral(x) = arg { Some(*x) } else { None })
+                .collect();
+            match constants {
+                Some(constants) => Expr::IntLiteral(routine.apply(&constants)),
+                None => Expr::Call(routine, args),
+            }
+        }
+        expr => expr,
+    }
+}
+
+fn simplify_stmt(stmt: Spanned<Stmt>, arithmetic: Arithmetic, result: &mut Block) {
+    let span = stmt.span;
+    let stmt = match stmt.node {
+        Stmt::VarAssign(id, expr) => Stmt::VarAssign(id, simplify_expr(expr, arithmetic)),
+        Stmt::Print(expr) => Stmt::Print(simplify_expr(expr, arithmetic)),
+        Stmt::PrintChar(expr) => Stmt::PrintChar(simplify_expr(expr, arithmetic)),
+        Stmt::If { condition, true_branch, false_branch } => match simplify_expr(condition, arithmetic) {
+            // There are no scopes, so the taken branch is just put in place of the statement
+            Expr::IntLiteral(0) => {
+                if let Some(false_branch) = false_branch {
+                    result.append(&mut simplify_program(*false_branch, arithmetic));
+                }
+                return;
+            }
+            Expr::IntLiteral(_) => {
+                result.append(&mut simplify_program(*true_branch, arithmetic));
+                return;
+            }
+            condition => Stmt::If {
+                condition,
+                true_branch: Box::new(simplify_program(*true_branch, arithmetic)),
+                false_branch: false_branch.map(|block| Box::new(simplify_program(*block, arithmetic))),
+            },
+        },
+        Stmt::While(condition, body) => match simplify_expr(condition, arithmetic) {
+            Expr::IntLiteral(0) => return,
+            condition => Stmt::While(condition, Box::new(simplify_program(*body, arithmetic))),
+        },
+        stmt => stmt,
+    };
+    result.push(Spanned::new(stmt, span))
+}
+
+/// Simplifies all expressions of the program and resolves `if` and `while` statements with constant conditions
+pub fn simplify_program(program: Block, arithmetic: Arithmetic) -> Block {
+    let mut result = Vec::with_capacity(program.len());
+    for stmt in program {
+        simplify_stmt(stmt, arithmetic, &mut result);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fuzz::generator::{GeneratorConfig, ProgramGenerator};
+    use crate::parser::ast::Ident;
+    use crate::parser::interpreter::AstInterpreter;
+    use crate::parser::parse_source;
+    use crate::parser::printer::program_to_source;
+    use super::*;
+
+    fn simplified(source: &str) -> String {
+        program_to_source(&simplify_program(parse_source(source), Arithmetic::Wrapping))
+    }
+
+    fn simplified_checked(source: &str) -> String {
+        program_to_source(&simplify_program(parse_source(source), Arithmetic::Checked))
+    }
+
+    #[test]
+    fn test_folding() {
+        assert_eq!("{\n    print(7)\n}", simplified("{ print(~0 & (3 + 4)) }"));
+        assert_eq!("{\n    print(((0 - 2147483647) - 1))\n}", simplified("{ print(2147483647 + 1) }"));
+        assert_eq!("{\n    x = 5 + a\n}", simplified("{ x = (2 + 3) + (a | 0) }"));
+        assert_eq!("{\n    x = max(a, (0 - 3))\n}", simplified("{ x = max(a | 0, mul(div(7, 2), 0 - 1)) }"));
+    }
+
+    #[test]
+    fn test_identities() {
+        assert_eq!("{\n    x = a\n    y = 0\n    z = 0\n    w = a\n}", simplified("{ x = 0 + (a - 0); y = b & 0; z = (a + b) - (a + b); w = ~~a }"));
+        assert_eq!("{\n    x = a - b\n}", simplified("{ x = a - b }"));
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        // The overflowing operations are kept to stop the program
+        assert_eq!("{\n    print(2147483647 + 1)\n}", simplified_checked("{ print(2147483647 + 1) }"));
+        assert_eq!("{\n    x = (0 - 2) - 2147483647\n}", simplified_checked("{ x = (0 - 2) - 2147483647 }"));
+        assert_eq!("{\n    x = 7\n}", simplified_checked("{ x = (2147483647 - 2147483640) | 0 }"));
+        let source = "{ x = (a + b) - (a + b); y = (a + 1) & 0; z = ~~a; w = b & 0 }";
+        assert_eq!("{\n    x = (a + b) - (a + b)\n    y = (a + 1) & 0\n    z = a\n    w = 0\n}", simplified_checked(source));
+        let negation = vec![Spanned::new(
+            Stmt::Print(Expr::PrefixOperation(PrefixOps::UnaryMinus, Box::new(Expr::PrefixOperation(
+                PrefixOps::UnaryMinus,
+                Box::new(Expr::Var(Ident(String::from("a")))),
+            )))),
+            Default::default(),
+        )];
+        assert_eq!(negation, simplify_program(negation.clone(), Arithmetic::Checked));
+    }
+
+    #[test]
+    fn test_constant_conditions() {
+        let source = "{ if (1 - 1) { print(1) } else { print(2) }; if (3 & 1) { print(3) }; while (a & 0) { print(4) }; if (0) { print(5) } }";
+        assert_eq!("{\n    print(2)\n    print(3)\n}", simplified(source));
+        assert_eq!("{\n    while (1) {\n        print(a)\n    }\n}", simplified("{ while (2 - 1) { print(a + 0) } }"));
+    }
+
+    #[test]
+    fn test_generated_programs() {
+        for seed in 0..200 {
+            let program = ProgramGenerator::new(seed, GeneratorConfig::default()).generate();
+            let mut expected = AstInterpreter::new();
+            expected.run(&program).unwrap();
+            let mut actual = AstInterpreter::new();
+            actual.run(&simplify_program(program, Arithmetic::Wrapping)).unwrap();
+            assert_eq!(expected.output(), actual.output(), "Program #{}", seed);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use crate::parser::interpreter::{output_char, output_string};
+use crate::ssa::{Function, Instruction, Operation, Terminator, Value};
+use crate::stack_machine::cfg::BlockId;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InterpretError {
+    StepLimitExceeded,
+    UndefinedValue(Value),
+    MissingPhiInput(Value, BlockId),
+}
+
+impl Display for InterpretError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterpretError::StepLimitExceeded => write!(f, "Step limit exceeded"),
+            InterpretError::UndefinedValue(value) => write!(f, "Value {} is used before the definition", value),
+            InterpretError::MissingPhiInput(value, block) => write!(f, "Phi {} has no input from b{}", value, block),
+        }
+    }
+}
+
+impl std::error::Error for InterpretError {}
+
+/// Interpreter of the SSA form, used to check that the optimizations keep the behaviour of the program.
+///
+/// A step is an executed block, so the limit bounds the number of loop iterations.
+#[derive(Clone, Debug, Default)]
+pub struct SsaInterpreter {
+    values: HashMap<Value, i32>,
+    output: Vec<i32>,
+    text: String,
+    steps: usize,
+    step_limit: Option<usize>,
+}
+
+impl SsaInterpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_step_limit(step_limit: usize) -> Self {
+        Self { step_limit: Some(step_limit), ..Self::default() }
+    }
+
+    fn value(&self, value: Value) -> Result<i32, InterpretError> {
+        self.values.get(&value).copied().ok_or(InterpretError::UndefinedValue(value))
+    }
+
+    fn evaluate(&self, operation: &Operation) -> Result<i32, InterpretError> {
+        Ok(match operation {
+            Operation::Const(x) => *x,
+            Operation::Binary(op, lhs, rhs) => op.apply(self.value(*lhs)?, self.value(*rhs)?),
+            Operation::Unary(op, value) => op.apply(self.value(*value)?),
+            Operation::Call(routine, args) => {
+                let args = args.iter().map(|arg| self.value(*arg)).collect::<Result<Vec<_>, _>>()?;
+                routine.apply(&args)
+            }
+        })
+    }
+
+    pub fn run(&mut self, function: &Function) -> Result<(), InterpretError> {
+        let mut previous = None;
+        let mut current = Function::ENTRY;
+        loop {
+            self.steps += 1;
+            if self.step_limit.is_some_and(|limit| self.steps > limit) {
+                return Err(InterpretError::StepLimitExceeded);
+            }
+            let block = &function.blocks[current];
+            // All phis read the values before any of them is assigned
+            let mut phi_values = Vec::new();
+            for phi in &block.phis {
+                let input = phi.incoming.iter()
+                    .find(|(predecessor, _)| Some(*predecessor) == previous)
+                    .ok_or(InterpretError::MissingPhiInput(phi.value, previous.unwrap_or(current)))?;
+                phi_values.push((phi.value, self.value(input.1)?));
+            }
+            self.values.extend(phi_values);
+            for instruction in &block.instructions {
+                match instruction {
+                    Instruction::Assign(value, operation) => {
+                        let result = self.evaluate(operation)?;
+                        self.values.insert(*value, result);
+                    }
+                    Instruction::Print(value) => {
+                        let value = self.value(*value)?;
+                        self.output.push(value);
+                        self.text.push_str(&format!("{}\n", value))
+                    }
+                    Instruction::PrintChar(value) => {
+                        let value = self.value(*value)?;
+                        self.text.push(output_char(value))
+                    }
+                    Instruction::PrintString(value) => self.text.push_str(output_string(value)),
+                    Instruction::Location(_) => {}
+                }
+            }
+            previous = Some(current);
+            current = match block.terminator {
+                Terminator::Jump(target) => target,
+                Terminator::Branch(value, not_zero, zero) => if self.value(value)? != 0 { not_zero } else { zero },
+                Terminator::Return => return Ok(()),
+            };
+        }
+    }
+
+    /// Values printed by the program so far
+    pub fn output(&self) -> &[i32] {
+        &self.output
+    }
+
+    /// Whole text printed by the program so far, including the characters and the strings
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
@@ -0,0 +1,299 @@
+//! Lowering of the AST into the SSA form.
+//!
+//! The control flow of Klang is structured, so the phis are placed directly: after an `if` for the variables
+//! having different values in the branches, and in the loop header for the variables assigned in the body.
+//! All variables start with a single zero constant in the entry block.
+
+use std::collections::HashMap;
+use crate::lexer::tokens::Span;
+use crate::parser::ast::{Block, Expr, Ident, Stmt};
+use crate::ssa::{Function, Instruction, Operation, Phi, Terminator, Value};
+use crate::stack_machine::cfg::BlockId;
+
+struct Lowering {
+    function: Function,
+    current: BlockId,
+    // Variables in the order of the first appearance, so the phis are created deterministically
+    order: Vec<Ident>,
+    variables: HashMap<Ident, Value>,
+}
+
+fn collect_expr_variables(expr: &Expr, result: &mut Vec<Ident>) {
+    match expr {
+        Expr::IntLiteral(_) => {}
+        Expr::Var(id) => if !result.contains(id) {
+            result.push(id.clone())
+        },
+        Expr::InfixOperation(lhs, _, rhs) => {
+            collect_expr_variables(lhs, result);
+            collect_expr_variables(rhs, result);
+        }
+        Expr::PrefixOperation(_, expr) => collect_expr_variables(expr, result),
+        Expr::Call(_, args) => args.iter().for_each(|arg| collect_expr_variables(arg, result)),
+    }
+}
+
+/// Variables read or assigned in the block, with `only_assigned` just the assigned ones
+fn collect_variables(block: &Block, only_assigned: bool, result: &mut Vec<Ident>) {
+    for stmt in block {
+        match &stmt.node {
+            Stmt::VarDeclaration(_) => {}
+            Stmt::VarAssign(id, expr) => {
+                if !only_assigned {
+                    collect_expr_variables(expr, result);
+                }
+                if !result.contains(id) {
+                    result.push(id.clone());
+                }
+            }
+            Stmt::If { condition, true_branch, false_branch } => {
+                if !only_assigned {
+                    collect_expr_variables(condition, result);
+                }
+                collect_variables(true_branch, only_assigned, result);
+                if let Some(false_branch) = false_branch {
+                    collect_variables(false_branch, only_assigned, result);
+                }
+            }
+            Stmt::While(condition, body) => {
+                if !only_assigned {
+                    collect_expr_variables(condition, result);
+                }
+                collect_variables(body, only_assigned, result);
+            }
+            Stmt::Print(expr) | Stmt::PrintChar(expr) => if !only_assigned {
+                collect_expr_variables(expr, result)
+            },
+            Stmt::PrintString(_) => {}
+        }
+    }
+}
+
+impl Lowering {
+    fn emit(&mut self, instruction: Instruction) {
+        self.function.blocks[self.current].instructions.push(instruction)
+    }
+
+    fn assign(&mut self, operation: Operation) -> Value {
+        let value = self.function.new_value();
+        self.emit(Instruction::Assign(value, operation));
+        value
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        self.function.blocks[self.current].terminator = terminator
+    }
+
+    fn lower_expr(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::IntLiteral(x) => self.assign(Operation::Const(*x)),
+            Expr::Var(id) => self.variables[id],
+            Expr::InfixOperation(lhs, op, rhs) => {
+                let lhs = self.lower_expr(lhs);
+                let rhs = self.lower_expr(rhs);
+                self.assign(Operation::Binary(*op, lhs, rhs))
+            }
+            Expr::PrefixOperation(op, expr) => {
+                let value = self.lower_expr(expr);
+                self.assign(Operation::Unary(*op, value))
+            }
+            Expr::Call(routine, args) => {
+                let args = args.iter().map(|arg| self.lower_expr(arg)).collect();
+                self.assign(Operation::Call(*routine, args))
+            }
+        }
+    }
+
+    fn lower_if(&mut self, condition: &Expr, true_branch: &Block, false_branch: Option<&Block>) {
+        let condition = self.lower_expr(condition);
+        let before = self.current;
+        let true_block = self.function.new_block();
+        let false_block = false_branch.map(|_| self.function.new_block());
+        let merge = self.function.new_block();
+        self.terminate(Terminator::Branch(condition, true_block, false_block.unwrap_or(merge)));
+        let variables_before = self.variables.clone();
+
+        self.current = true_block;
+        self.lower_block(true_branch);
+        self.terminate(Terminator::Jump(merge));
+        let true_end = self.current;
+        let true_variables = std::mem::replace(&mut self.variables, variables_before);
+
+        let false_end = match (false_block, false_branch) {
+            (Some(false_block), Some(false_branch)) => {
+                self.current = false_block;
+                self.lower_block(false_branch);
+                self.terminate(Terminator::Jump(merge));
+                self.current
+            }
+            _ => before,
+        };
+
+        self.current = merge;
+        for id in self.order.clone() {
+            let (true_value, false_value) = (true_variables[&id], self.variables[&id]);
+            if true_value != false_value {
+                let value = self.function.new_value();
+                self.function.blocks[merge].phis.push(Phi {
+                    value,
+                    incoming: vec![(true_end, true_value), (false_end, false_value)],
+                });
+                self.variables.insert(id, value);
+            }
+        }
+    }
+
+    fn lower_while(&mut self, condition: &Expr, body: &Block, span: Span) {
+        let preheader = self.current;
+        let header = self.function.new_block();
+        let body_block = self.function.new_block();
+        let exit = self.function.new_block();
+        self.terminate(Terminator::Jump(header));
+
+        let mut assigned = Vec::new();
+        collect_variables(body, true, &mut assigned);
+        let mut loop_phis = Vec::new();
+        for id in self.order.clone().into_iter().filter(|id| assigned.contains(id)) {
+            let value = self.function.new_value();
+            self.function.blocks[header].phis.push(Phi { value, incoming: vec![(preheader, self.variables[&id])] });
+            self.variables.insert(id.clone(), value);
+            loop_phis.push(id);
+        }
+
+        self.current = header;
+        self.emit(Instruction::Location(span));
+        let condition = self.lower_expr(condition);
+        self.terminate(Terminator::Branch(condition, body_block, exit));
+        let header_variables = self.variables.clone();
+
+        self.current = body_block;
+        self.lower_block(body);
+        self.terminate(Terminator::Jump(header));
+        let latch = self.current;
+        for (phi, id) in self.function.blocks[header].phis.iter_mut().zip(&loop_phis) {
+            phi.incoming.push((latch, self.variables[id]));
+        }
+
+        self.variables = header_variables;
+        self.current = exit;
+    }
+
+    fn lower_stmt(&mut self, stmt: &Stmt, span: Span) {
+        match stmt {
+            Stmt::VarDeclaration(_) => {}
+            Stmt::VarAssign(id, expr) => {
+                self.emit(Instruction::Location(span));
+                let value = self.lower_expr(expr);
+                self.variables.insert(id.clone(), value);
+            }
+            Stmt::Print(expr) => {
+                self.emit(Instruction::Location(span));
+                let value = self.lower_expr(expr);
+                self.emit(Instruction::Print(value));
+            }
+            Stmt::PrintChar(expr) => {
+                self.emit(Instruction::Location(span));
+                let value = self.lower_expr(expr);
+                self.emit(Instruction::PrintChar(value));
+            }
+            Stmt::PrintString(value) => {
+                self.emit(Instruction::Location(span));
+                self.emit(Instruction::PrintString(value.clone()));
+            }
+            Stmt::If { condition, true_branch, false_branch } => {
+                self.emit(Instruction::Location(span));
+                self.lower_if(condition, true_branch, false_branch.as_deref());
+            }
+            Stmt::While(condition, body) => {
+                self.emit(Instruction::Location(span));
+                self.lower_while(condition, body, span);
+            }
+        }
+    }
+
+    fn lower_block(&mut self, block: &Block) {
+        for stmt in block {
+            self.lower_stmt(&stmt.node, stmt.span)
+        }
+    }
+}
+
+/// Builds the SSA form of the program, the result is not optimized
+pub fn lower_program(program: &Block) -> Function {
+    let mut order = Vec::new();
+    collect_variables(program, false, &mut order);
+    let mut lowering = Lowering {
+        function: Function::new(),
+        current: Function::ENTRY,
+        order,
+        variables: HashMap::new(),
+    };
+    if !lowering.order.is_empty() {
+        let zero = lowering.assign(Operation::Const(0));
+        lowering.variables = lowering.order.iter().map(|id| (id.clone(), zero)).collect();
+    }
+    lowering.lower_block(program);
+    lowering.terminate(Terminator::Return);
+    lowering.function
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_source;
+    use super::*;
+
+    fn lower(source: &str) -> String {
+        lower_program(&parse_source(source)).to_string()
+    }
+
+    #[test]
+    fn test_if() {
+        assert_eq!("\
+b0:
+    v0 = const 0
+    loc 1:3
+    v1 = const 1
+    loc 1:10
+    branch v0 b1 b2
+b1:
+    loc 1:19
+    v2 = const 2
+    jump b2
+b2:
+    v3 = phi [b1: v2] [b0: v1]
+    loc 1:28
+    v4 = add v3 v0
+    print v4
+    return
+", lower("{ a = 1; if (c) { a = 2 }; print(a + c) }"));
+    }
+
+    #[test]
+    fn test_while() {
+        assert_eq!("\
+b0:
+    v0 = const 0
+    loc 1:3
+    v1 = const 3
+    loc 1:10
+    jump b1
+b1:
+    v2 = phi [b0: v1] [b2: v7]
+    v3 = phi [b0: v0] [b2: v5]
+    loc 1:10
+    branch v2 b2 b3
+b2:
+    loc 1:22
+    v4 = const 1
+    v5 = add v3 v4
+    loc 1:33
+    v6 = const 1
+    v7 = sub v2 v6
+    jump b1
+b3:
+    loc 1:46
+    print v3
+    return
+", lower("{ n = 3; while (n) { s = s + 1; n = n - 1 }; print(s) }"));
+    }
+}
@@ -0,0 +1,334 @@
+//! Three-address SSA intermediate representation between the AST and the binary code.
+//!
+//! A function is a list of basic blocks, the first one is the entry. Every virtual register is assigned exactly
+//! once, either by an instruction or by a phi node at the start of a block. Klang has no scopes and no functions,
+//! so the whole program is a single function, and the variables read before an assignment are zero.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use crate::binary::instructions::Arithmetic;
+use crate::lexer::escape;
+use crate::lexer::tokens::Span;
+use crate::parser::ast::{Ops, PrefixOps, Routine};
+use crate::stack_machine::cfg::{reverse_postorder, BlockId, Dominators};
+
+pub mod lower;
+pub mod optimize;
+pub mod interpreter;
+
+/// Virtual register
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct Value(pub usize);
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+/// Computation of a value, equal operations on equal values give equal results. The only side effect is the stop
+/// of the program on the overflow with the checked arithmetic
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Operation {
+    Const(i32),
+    Binary(Ops, Value, Value),
+    Unary(PrefixOps, Value),
+    /// The runtime routines have no side effects, so a call is an operation as well
+    Call(Routine, Vec<Value>),
+}
+
+impl Operation {
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            Operation::Const(_) => vec![],
+            Operation::Binary(_, lhs, rhs) => vec![*lhs, *rhs],
+            Operation::Unary(_, value) => vec![*value],
+            Operation::Call(_, args) => args.clone(),
+        }
+    }
+
+    /// Whether the operation may stop the program, see [`Arithmetic::may_trap`]
+    pub fn may_trap(&self, arithmetic: Arithmetic) -> bool {
+        match self {
+            Operation::Binary(op, _, _) => arithmetic.may_trap(op),
+            Operation::Unary(op, _) => arithmetic.may_trap_prefix(op),
+            Operation::Const(_) | Operation::Call(_, _) => false,
+        }
+    }
+
+    fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Operation::Const(_) => vec![],
+            Operation::Binary(_, lhs, rhs) => vec![lhs, rhs],
+            Operation::Unary(_, value) => vec![value],
+            Operation::Call(_, args) => args.iter_mut().collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Instruction {
+    Assign(Value, Operation),
+    Print(Value),
+    PrintChar(Value),
+    PrintString(String),
+    /// Marks the start of the code of the statement at the position
+    Location(Span),
+}
+
+/// Value equal to the incoming value of the predecessor the control came from
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Phi {
+    pub value: Value,
+    pub incoming: Vec<(BlockId, Value)>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// Goes to the first block if the value is not zero and to the second one otherwise
+    Branch(Value, BlockId, BlockId),
+    Return,
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch(_, not_zero, zero) => vec![*not_zero, *zero],
+            Terminator::Return => vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BasicBlock {
+    pub phis: Vec<Phi>,
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+impl BasicBlock {
+    pub fn new() -> Self {
+        Self { phis: Vec::new(), instructions: Vec::new(), terminator: Terminator::Return }
+    }
+}
+
+impl Default for BasicBlock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Function {
+    pub blocks: Vec<BasicBlock>,
+    next_value: usize,
+}
+
+impl Default for Function {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Function {
+    pub const ENTRY: BlockId = 0;
+
+    /// Function with the empty entry block
+    pub fn new() -> Self {
+        Self { blocks: vec![BasicBlock::new()], next_value: 0 }
+    }
+
+    pub fn new_value(&mut self) -> Value {
+        let value = Value(self.next_value);
+        self.next_value += 1;
+        value
+    }
+
+    pub fn new_block(&mut self) -> BlockId {
+        self.blocks.push(BasicBlock::new());
+        self.blocks.len() - 1
+    }
+
+    pub fn successors(&self) -> Vec<Vec<BlockId>> {
+        self.blocks.iter().map(|block| block.terminator.successors()).collect()
+    }
+
+    /// Predecessors of every block, phis have exactly one incoming value for each of them
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut result: Vec<Vec<BlockId>> = vec![Vec::new(); self.blocks.len()];
+        for (id, block) in self.blocks.iter().enumerate() {
+            for successor in block.terminator.successors() {
+                if !result[successor].contains(&id) {
+                    result[successor].push(id);
+                }
+            }
+        }
+        result
+    }
+
+    /// Blocks reachable from the entry in the reverse postorder
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        reverse_postorder(Self::ENTRY, &self.successors())
+    }
+
+    pub fn dominators(&self) -> Dominators {
+        Dominators::compute(&self.reverse_postorder(), &self.predecessors())
+    }
+
+    /// Block containing the phi or the instruction assigning every value
+    pub fn definitions(&self) -> HashMap<Value, BlockId> {
+        let mut result = HashMap::new();
+        for (id, block) in self.blocks.iter().enumerate() {
+            for phi in &block.phis {
+                result.insert(phi.value, id);
+            }
+            for instruction in &block.instructions {
+                if let Instruction::Assign(value, _) = instruction {
+                    result.insert(*value, id);
+                }
+            }
+        }
+        result
+    }
+
+    /// Replaces all uses of the values with their replacements, chains of replacements are followed
+    pub fn replace_uses(&mut self, replacements: &HashMap<Value, Value>) {
+        if replacements.is_empty() {
+            return;
+        }
+        let resolve = |value: &mut Value| {
+            while let Some(replacement) = replacements.get(value) {
+                *value = *replacement;
+            }
+        };
+        for block in &mut self.blocks {
+            for phi in &mut block.phis {
+                phi.incoming.iter_mut().for_each(|(_, value)| resolve(value));
+            }
+            for instruction in &mut block.instructions {
+                match instruction {
+                    Instruction::Assign(_, operation) => operation.operands_mut().into_iter().for_each(resolve),
+                    Instruction::Print(value) | Instruction::PrintChar(value) => resolve(value),
+                    Instruction::PrintString(_) | Instruction::Location(_) => {}
+                }
+            }
+            if let Terminator::Branch(value, _, _) = &mut block.terminator {
+                resolve(value)
+            }
+        }
+    }
+
+    /// Removes the blocks unreachable from the entry and the phi inputs coming from them, blocks are renumbered
+    pub fn remove_unreachable_blocks(&mut self) {
+        let mut order = self.reverse_postorder();
+        order.sort();
+        if order.len() == self.blocks.len() {
+            return;
+        }
+        let mut new_ids = vec![None; self.blocks.len()];
+        for (new_id, old_id) in order.iter().enumerate() {
+            new_ids[*old_id] = Some(new_id);
+        }
+        let blocks = std::mem::take(&mut self.blocks);
+        self.blocks = blocks.into_iter()
+            .enumerate()
+            .filter(|(id, _)| new_ids[*id].is_some())
+            .map(|(_, mut block)| {
+                for phi in &mut block.phis {
+                    phi.incoming.retain(|(predecessor, _)| new_ids[*predecessor].is_some());
+                    phi.incoming.iter_mut().for_each(|(predecessor, _)| *predecessor = new_ids[*predecessor].unwrap());
+                }
+                block.terminator = match block.terminator {
+                    Terminator::Jump(target) => Terminator::Jump(new_ids[target].unwrap()),
+                    Terminator::Branch(value, not_zero, zero) => {
+                        Terminator::Branch(value, new_ids[not_zero].unwrap(), new_ids[zero].unwrap())
+                    }
+                    Terminator::Return => Terminator::Return,
+                };
+                block
+            })
+            .collect();
+    }
+
+    /// Splits the edges going from a block with several successors into a block with phis.
+    ///
+    /// After that the phi inputs can be copied at the end of the predecessor without affecting other successors.
+    pub fn split_critical_edges(&mut self) {
+        for id in 0..self.blocks.len() {
+            let Terminator::Branch(value, not_zero, zero) = self.blocks[id].terminator else { continue };
+            if not_zero == zero {
+                self.blocks[id].terminator = Terminator::Jump(zero);
+                continue;
+            }
+            let split = |function: &mut Self, target: BlockId| {
+                if function.blocks[target].phis.is_empty() {
+                    return target;
+                }
+                let middle = function.new_block();
+                function.blocks[middle].terminator = Terminator::Jump(target);
+                for phi in &mut function.blocks[target].phis {
+                    if let Some(input) = phi.incoming.iter_mut().find(|(predecessor, _)| *predecessor == id) {
+                        input.0 = middle;
+                    }
+                }
+                middle
+            };
+            let not_zero = split(self, not_zero);
+            let zero = split(self, zero);
+            self.blocks[id].terminator = Terminator::Branch(value, not_zero, zero);
+        }
+    }
+}
+
+fn operation_to_text(operation: &Operation) -> String {
+    match operation {
+        Operation::Const(x) => format!("const {}", x),
+        Operation::Binary(op, lhs, rhs) => {
+            let name = match op {
+                Ops::Add => "add",
+                Ops::Sub => "sub",
+                Ops::BitwiseAnd => "and",
+                Ops::BitwiseOr => "or",
+                Ops::BitwiseNor => "nor",
+            };
+            format!("{} {} {}", name, lhs, rhs)
+        }
+        Operation::Unary(PrefixOps::BitwiseNot, value) => format!("not {}", value),
+        Operation::Unary(PrefixOps::UnaryMinus, value) => format!("neg {}", value),
+        Operation::Call(routine, args) => {
+            let args: Vec<String> = args.iter().map(Value::to_string).collect();
+            format!("call {} {}", routine.name(), args.join(" "))
+        }
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (id, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "b{}:", id)?;
+            for phi in &block.phis {
+                let incoming: Vec<String> = phi.incoming.iter()
+                    .map(|(predecessor, value)| format!("[b{}: {}]", predecessor, value))
+                    .collect();
+                writeln!(f, "    {} = phi {}", phi.value, incoming.join(" "))?;
+            }
+            for instruction in &block.instructions {
+                match instruction {
+                    Instruction::Assign(value, operation) => writeln!(f, "    {} = {}", value, operation_to_text(operation))?,
+                    Instruction::Print(value) => writeln!(f, "    print {}", value)?,
+                    Instruction::PrintChar(value) => writeln!(f, "    printc {}", value)?,
+                    Instruction::PrintString(value) => writeln!(f, "    prints \"{}\"", escape(value))?,
+                    Instruction::Location(span) => writeln!(f, "    loc {}", span)?,
+                }
+            }
+            match block.terminator {
+                Terminator::Jump(target) => writeln!(f, "    jump b{}", target)?,
+                Terminator::Branch(value, not_zero, zero) => writeln!(f, "    branch {} b{} b{}", value, not_zero, zero)?,
+                Terminator::Return => writeln!(f, "    return")?,
+            }
+        }
+        Ok(())
+    }
+}
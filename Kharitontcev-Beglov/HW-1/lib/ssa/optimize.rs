@@ -0,0 +1,352 @@
+//! Optimizations of the SSA form.
+//!
+//! Every pass keeps the function in the SSA form, [`optimize`] runs them until none of them changes anything.
+//! Operations have no side effects, so they may be removed, merged and moved freely as long as their operands stay
+//! available. With the checked arithmetic the operations which may overflow stop the program: they are folded only
+//! without the overflow, never removed and never moved out of a loop.
+
+use std::collections::{HashMap, HashSet};
+use crate::binary::instructions::Arithmetic;
+use crate::parser::ast::Ops;
+use crate::ssa::{Function, Instruction, Operation, Terminator, Value};
+use crate::stack_machine::cfg::BlockId;
+
+/// Removes the phis which select the same value from all predecessors, ignoring the phi itself
+pub fn remove_trivial_phis(function: &mut Function) {
+    let mut replacements = HashMap::new();
+    for block in &mut function.blocks {
+        block.phis.retain(|phi| {
+            let mut inputs = phi.incoming.iter().map(|(_, value)| *value).filter(|value| *value != phi.value);
+            let Some(first) = inputs.next() else { return true };
+            if inputs.all(|value| value == first) {
+                replacements.insert(phi.value, first);
+                return false;
+            }
+            true
+        });
+    }
+    function.replace_uses(&replacements);
+}
+
+/// Evaluates the operations with constant operands and removes the branches on constants
+pub fn propagate_constants(function: &mut Function, arithmetic: Arithmetic) {
+    let mut constants: HashMap<Value, i32> = HashMap::new();
+    for id in function.reverse_postorder() {
+        for instruction in &mut function.blocks[id].instructions {
+            let Instruction::Assign(value, operation) = instruction else { continue };
+            let folded = match operation {
+                Operation::Const(x) => Some(*x),
+                Operation::Binary(op, lhs, rhs) => match (constants.get(lhs), constants.get(rhs)) {
+                    (Some(lhs), Some(rhs)) => arithmetic.apply(op, *lhs, *rhs),
+                    _ => None,
+                },
+                Operation::Unary(op, operand) => constants.get(operand).and_then(|x| arithmetic.apply_prefix(op, *x)),
+                Operation::Call(routine, args) => args.iter()
+                    .map(|arg| constants.get(arg).copied())
+                    .collect::<Option<Vec<i32>>>()
+                    .map(|args| routine.apply(&args)),
+            };
+            if let Some(x) = folded {
+                *operation = Operation::Const(x);
+                constants.insert(*value, x);
+            }
+        }
+        let Terminator::Branch(condition, not_zero, zero) = function.blocks[id].terminator else { continue };
+        let Some(condition) = constants.get(&condition) else { continue };
+        let (taken, skipped) = if *condition != 0 { (not_zero, zero) } else { (zero, not_zero) };
+        function.blocks[id].terminator = Terminator::Jump(taken);
+        if taken != skipped {
+            for phi in &mut function.blocks[skipped].phis {
+                phi.incoming.retain(|(predecessor, _)| *predecessor != id);
+            }
+        }
+    }
+    function.remove_unreachable_blocks();
+}
+
+/// Appends a block to its only predecessor if the predecessor has no other successors
+pub fn merge_blocks(function: &mut Function) {
+    loop {
+        let predecessors = function.predecessors();
+        let merged = (0..function.blocks.len()).find_map(|id| match function.blocks[id].terminator {
+            Terminator::Jump(next) if next != id && next != Function::ENTRY && predecessors[next] == [id] => Some((id, next)),
+            _ => None,
+        });
+        let Some((id, next)) = merged else { break };
+        let next_block = std::mem::take(&mut function.blocks[next]);
+        // A phi with the single predecessor is just its input
+        let replacements = next_block.phis.iter().map(|phi| (phi.value, phi.incoming[0].1)).collect();
+        let block = &mut function.blocks[id];
+        block.instructions.extend(next_block.instructions);
+        block.terminator = next_block.terminator;
+        for successor in block.terminator.successors() {
+            for phi in &mut function.blocks[successor].phis {
+                phi.incoming.iter_mut()
+                    .filter(|(predecessor, _)| *predecessor == next)
+                    .for_each(|(predecessor, _)| *predecessor = id);
+            }
+        }
+        function.replace_uses(&replacements);
+        function.remove_unreachable_blocks();
+    }
+}
+
+/// Operands of the commutative operations are ordered, so `a + b` and `b + a` are the same expression
+fn canonical(operation: Operation) -> Operation {
+    match operation {
+        Operation::Binary(op, lhs, rhs) if op != Ops::Sub && lhs > rhs => Operation::Binary(op, rhs, lhs),
+        operation => operation,
+    }
+}
+
+/// Replaces an operation with the equal one computed in a dominating block
+pub fn eliminate_common_subexpressions(function: &mut Function) {
+    let dominators = function.dominators();
+    let mut available: Vec<HashMap<Operation, Value>> = vec![HashMap::new(); function.blocks.len()];
+    let mut replacements: HashMap<Value, Value> = HashMap::new();
+    // The immediate dominator always precedes the block in the reverse postorder
+    for id in function.reverse_postorder() {
+        let mut table = dominators.immediate_dominator(id).map(|idom| available[idom].clone()).unwrap_or_default();
+        function.blocks[id].instructions.retain_mut(|instruction| {
+            let Instruction::Assign(value, operation) = instruction else { return true };
+            for operand in operation.operands_mut() {
+                if let Some(replacement) = replacements.get(operand) {
+                    *operand = *replacement;
+                }
+            }
+            *operation = canonical(operation.clone());
+            match table.get(operation) {
+                Some(existing) => {
+                    replacements.insert(*value, *existing);
+                    false
+                }
+                None => {
+                    table.insert(operation.clone(), *value);
+                    true
+                }
+            }
+        });
+        available[id] = table;
+    }
+    function.replace_uses(&replacements);
+}
+
+/// Removes the operations and phis whose values never reach a print, a branch or an operation which may trap
+pub fn eliminate_dead_code(function: &mut Function, arithmetic: Arithmetic) {
+    let mut operands: HashMap<Value, Vec<Value>> = HashMap::new();
+    let mut worklist = Vec::new();
+    for block in &function.blocks {
+        for phi in &block.phis {
+            operands.insert(phi.value, phi.incoming.iter().map(|(_, value)| *value).collect());
+        }
+        for instruction in &block.instructions {
+            match instruction {
+                Instruction::Assign(value, operation) => {
+                    operands.insert(*value, operation.operands());
+                    if operation.may_trap(arithmetic) {
+                        worklist.push(*value);
+                    }
+                }
+                Instruction::Print(value) | Instruction::PrintChar(value) => worklist.push(*value),
+                Instruction::PrintString(_) | Instruction::Location(_) => {}
+            }
+        }
+        if let Terminator::Branch(condition, _, _) = block.terminator {
+            worklist.push(condition);
+        }
+    }
+    let mut live = HashSet::new();
+    while let Some(value) = worklist.pop() {
+        if live.insert(value) {
+            worklist.extend(operands.get(&value).into_iter().flatten());
+        }
+    }
+    for block in &mut function.blocks {
+        block.phis.retain(|phi| live.contains(&phi.value));
+        block.instructions.retain(|instruction| match instruction {
+            Instruction::Assign(value, _) => live.contains(value),
+            _ => true,
+        });
+    }
+}
+
+/// Natural loops of the function: the header and the blocks reaching its back edges without passing the header
+fn natural_loops(function: &Function) -> Vec<(BlockId, HashSet<BlockId>)> {
+    let dominators = function.dominators();
+    let predecessors = function.predecessors();
+    let mut loops: Vec<(BlockId, HashSet<BlockId>)> = Vec::new();
+    for id in function.reverse_postorder() {
+        let latches: Vec<BlockId> = predecessors[id].iter()
+            .filter(|predecessor| dominators.dominates(id, **predecessor))
+            .copied()
+            .collect();
+        if latches.is_empty() {
+            continue;
+        }
+        let mut body = HashSet::from([id]);
+        let mut worklist = latches;
+        while let Some(block) = worklist.pop() {
+            if body.insert(block) {
+                worklist.extend(&predecessors[block]);
+            }
+        }
+        loops.push((id, body));
+    }
+    loops
+}
+
+/// Moves the operations whose operands are defined outside a loop into the block preceding the loop.
+///
+/// Only the loops entered from a single block, which has no other successors, are handled. The operations which may
+/// trap stay in the loop, it may run no iterations or print before them.
+pub fn hoist_loop_invariants(function: &mut Function, arithmetic: Arithmetic) {
+    let order = function.reverse_postorder();
+    let predecessors = function.predecessors();
+    let mut definitions = function.definitions();
+    for (header, body) in natural_loops(function) {
+        let outside: Vec<BlockId> = predecessors[header].iter().filter(|block| !body.contains(block)).copied().collect();
+        let [preheader] = outside[..] else { continue };
+        if function.blocks[preheader].terminator != Terminator::Jump(header) {
+            continue;
+        }
+        let mut hoisted = Vec::new();
+        for &id in order.iter().filter(|id| body.contains(id)) {
+            function.blocks[id].instructions.retain(|instruction| {
+                let Instruction::Assign(value, operation) = instruction else { return true };
+                let invariant = !operation.may_trap(arithmetic)
+                    && operation.operands().iter().all(|operand| !body.contains(&definitions[operand]));
+                if invariant {
+                    definitions.insert(*value, preheader);
+                    hoisted.push(instruction.clone());
+                }
+                !invariant
+            });
+        }
+        function.blocks[preheader].instructions.extend(hoisted);
+    }
+}
+
+pub fn optimize(function: &mut Function, arithmetic: Arithmetic) {
+    loop {
+        let before = function.clone();
+        remove_trivial_phis(function);
+        propagate_constants(function, arithmetic);
+        merge_blocks(function);
+        eliminate_common_subexpressions(function);
+        hoist_loop_invariants(function, arithmetic);
+        eliminate_dead_code(function, arithmetic);
+        if *function == before {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fuzz::generator::{GeneratorConfig, ProgramGenerator};
+    use crate::parser::parse_source;
+    use crate::parser::interpreter::AstInterpreter;
+    use crate::ssa::interpreter::SsaInterpreter;
+    use crate::ssa::lower::lower_program;
+    use super::*;
+
+    fn lower(source: &str) -> Function {
+        lower_program(&parse_source(source))
+    }
+
+    fn without_locations(function: &Function) -> String {
+        function.to_string().lines().filter(|line| !line.contains("loc")).map(|line| format!("{}\n", line)).collect()
+    }
+
+    fn output(function: &Function) -> Vec<i32> {
+        let mut interpreter = SsaInterpreter::with_step_limit(1_000_000);
+        interpreter.run(function).unwrap();
+        interpreter.output().to_vec()
+    }
+
+    #[test]
+    fn test_constant_propagation() {
+        let mut function = lower("{ a = 2; b = a + 3; if (b - 5) { print(1) } else { print(b) } }");
+        optimize(&mut function, Arithmetic::Wrapping);
+        assert_eq!("\
+b0:
+    v3 = const 5
+    print v3
+    return
+", without_locations(&function));
+    }
+
+    #[test]
+    fn test_common_subexpressions() {
+        let mut function = lower("{ print(a + b); if (c) { print(b + a) }; print((a + b) & c) }");
+        eliminate_common_subexpressions(&mut function);
+        eliminate_dead_code(&mut function, Arithmetic::Wrapping);
+        let additions = function.to_string().matches("add").count();
+        assert_eq!(1, additions);
+        assert_eq!(output(&lower("{ print(a + b); if (c) { print(b + a) }; print((a + b) & c) }")), output(&function));
+    }
+
+    #[test]
+    fn test_dead_code() {
+        let mut function = lower("{ a = 1; b = a + 2; a = b - 1; c = 7; print(c) }");
+        eliminate_dead_code(&mut function, Arithmetic::Wrapping);
+        assert_eq!("\
+b0:
+    v6 = const 7
+    print v6
+    return
+", without_locations(&function));
+    }
+
+    #[test]
+    fn test_loop_invariants() {
+        let mut function = lower("{ a = 5; n = 3; while (n) { print(a & ~n); print(a - 1); n = n - 1 } }");
+        optimize(&mut function, Arithmetic::Wrapping);
+        // The constants, including the folded `a - 1`, are computed before the loop, `~n`, `&` and `n - 1` are left
+        let header = function.blocks.iter().position(|block| !block.phis.is_empty()).unwrap();
+        let body: String = function.blocks[header..].iter()
+            .flat_map(|block| &block.instructions)
+            .filter(|instruction| matches!(instruction, Instruction::Assign(_, _)))
+            .map(|instruction| format!("{:?}\n", instruction))
+            .collect();
+        assert!(!body.contains("Const"), "{}", function);
+        assert_eq!(3, body.lines().count(), "{}", function);
+        assert_eq!(vec![4, 4, 5, 4, 4, 4], output(&function));
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        // The overflowing addition is neither folded nor removed, the one without the overflow is folded
+        let mut function = lower("{ a = 2147483647; b = a + 1; c = a - 7; print(c) }");
+        optimize(&mut function, Arithmetic::Checked);
+        assert_eq!("\
+b0:
+    v1 = const 2147483647
+    v2 = const 1
+    v3 = add v1 v2
+    v5 = const 2147483640
+    print v5
+    return
+", without_locations(&function));
+        // The invariant addition stays in the loop, after the print of the first iteration
+        let mut function = lower("{ a = 2147483647; n = 3; while (n) { print(n); b = a + 1; n = n - 1 } }");
+        optimize(&mut function, Arithmetic::Checked);
+        let header = function.blocks.iter().position(|block| !block.phis.is_empty()).unwrap();
+        assert!(function.blocks[header..].iter().any(|block| block.instructions.iter().any(|instruction| {
+            matches!(instruction, Instruction::Assign(_, Operation::Binary(Ops::Add, _, _)))
+        })), "{}", function);
+    }
+
+    #[test]
+    fn test_generated_programs() {
+        for seed in 0..200 {
+            let block = ProgramGenerator::new(seed, GeneratorConfig::default()).generate();
+            let mut expected = AstInterpreter::with_step_limit(1_000_000);
+            expected.run(&block).unwrap();
+            let mut function = lower_program(&block);
+            assert_eq!(expected.output(), output(&function), "seed {}", seed);
+            optimize(&mut function, Arithmetic::Wrapping);
+            assert_eq!(expected.output(), output(&function), "seed {}\n{}", seed, function);
+        }
+    }
+}
@@ -0,0 +1,467 @@
+//! Control-flow graph of the stack machine code and a generic dataflow solver over it.
+//!
+//! The code is split into basic blocks before every `Label` and after every jump. Blocks keep the order of the
+//! program, the first block is the entry.
+
+use std::collections::{HashMap, VecDeque};
+use crate::stack_machine::sm::{Condition, Label, StackCommand};
+
+pub type BlockId = usize;
+
+/// Commands `start..end` of the program, which are always executed together
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize,
+    pub successors: Vec<BlockId>,
+    pub predecessors: Vec<BlockId>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ControlFlowGraph<'a> {
+    program: &'a [StackCommand],
+    blocks: Vec<BasicBlock>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// Dataflow analysis solved by [`ControlFlowGraph::solve`].
+///
+/// Facts form a semilattice with `join`, the transfer function is applied to a single command. For backward
+/// analyses the fact after the command is transferred into the fact before it.
+pub trait Dataflow {
+    type Fact: Clone + Eq;
+
+    const DIRECTION: Direction;
+
+    /// Fact at the entry of the program for forward analyses, at the exits for backward ones
+    fn boundary(&self) -> Self::Fact;
+
+    /// Fact for the blocks which are not reached yet
+    fn initial(&self) -> Self::Fact;
+
+    fn join(&self, lhs: &Self::Fact, rhs: &Self::Fact) -> Self::Fact;
+
+    fn transfer(&self, command: &StackCommand, fact: Self::Fact) -> Self::Fact;
+}
+
+/// Facts before and after every block, in the order of the program regardless of the direction
+#[derive(Clone, Debug)]
+pub struct DataflowResult<F> {
+    pub block_in: Vec<F>,
+    pub block_out: Vec<F>,
+}
+
+/// Facts before and after every command of the program
+#[derive(Clone, Debug)]
+pub struct CommandFacts<F> {
+    pub before: Vec<F>,
+    pub after: Vec<F>,
+}
+
+/// Immediate dominators of the blocks reachable from the entry
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Dominators {
+    // The entry is its own immediate dominator, unreachable blocks have none
+    idom: Vec<Option<BlockId>>,
+}
+
+impl Dominators {
+    /// Computes the dominator tree with the Cooper–Harvey–Kennedy algorithm.
+    ///
+    /// `order` is the reverse postorder of the reachable blocks starting with the entry, `predecessors` are
+    /// indexed by the block.
+    pub fn compute(order: &[BlockId], predecessors: &[Vec<BlockId>]) -> Self {
+        let mut position = vec![usize::MAX; predecessors.len()];
+        for (index, block) in order.iter().enumerate() {
+            position[*block] = index;
+        }
+        let mut idom: Vec<Option<BlockId>> = vec![None; predecessors.len()];
+        idom[order[0]] = Some(order[0]);
+        let intersect = |idom: &[Option<BlockId>], mut a: BlockId, mut b: BlockId| {
+            while a != b {
+                while position[a] > position[b] {
+                    a = idom[a].unwrap();
+                }
+                while position[b] > position[a] {
+                    b = idom[b].unwrap();
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in order.iter().skip(1) {
+                let new_idom = predecessors[block].iter()
+                    .filter(|predecessor| idom[**predecessor].is_some())
+                    .copied()
+                    .reduce(|a, b| intersect(&idom, a, b));
+                if new_idom.is_some() && idom[block] != new_idom {
+                    idom[block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        Self { idom }
+    }
+
+    pub fn immediate_dominator(&self, block: BlockId) -> Option<BlockId> {
+        self.idom[block].filter(|idom| *idom != block)
+    }
+
+    /// Whether every path from the entry to `block` goes through `dominator`
+    pub fn dominates(&self, dominator: BlockId, block: BlockId) -> bool {
+        if self.idom[block].is_none() {
+            return false;
+        }
+        let mut current = block;
+        loop {
+            if current == dominator {
+                return true;
+            }
+            match self.immediate_dominator(current) {
+                Some(idom) => current = idom,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Blocks reachable from `entry` in the reverse postorder, `successors` are indexed by the block
+pub fn reverse_postorder(entry: BlockId, successors: &[Vec<BlockId>]) -> Vec<BlockId> {
+    let mut visited = vec![false; successors.len()];
+    let mut postorder = Vec::new();
+    // Explicit stack of (block, index of the next successor to visit)
+    let mut stack = vec![(entry, 0)];
+    visited[entry] = true;
+    while let Some((block, next)) = stack.pop() {
+        match successors[block].get(next) {
+            Some(&successor) => {
+                stack.push((block, next + 1));
+                if !visited[successor] {
+                    visited[successor] = true;
+                    stack.push((successor, 0));
+                }
+            }
+            None => postorder.push(block),
+        }
+    }
+    postorder.reverse();
+    postorder
+}
+
+fn is_jump(command: &StackCommand) -> bool {
+    matches!(command, StackCommand::Jmp(_) | StackCommand::ConditionalJump(_, _))
+}
+
+impl<'a> ControlFlowGraph<'a> {
+    pub fn build(program: &'a [StackCommand]) -> Self {
+        let mut starts = vec![0];
+        for (index, command) in program.iter().enumerate() {
+            match command {
+                StackCommand::Label(_) if index > 0 => starts.push(index),
+                _ if is_jump(command) && index + 1 < program.len() => starts.push(index + 1),
+                _ => {}
+            }
+        }
+        starts.dedup();
+        let mut blocks: Vec<BasicBlock> = starts.iter()
+            .enumerate()
+            .map(|(id, start)| BasicBlock {
+                start: *start,
+                end: starts.get(id + 1).copied().unwrap_or(program.len()),
+                successors: Vec::new(),
+                predecessors: Vec::new(),
+            })
+            .collect();
+        let labels: HashMap<Label, BlockId> = blocks.iter()
+            .enumerate()
+            .filter_map(|(id, block)| match program.get(block.start) {
+                Some(StackCommand::Label(label)) => Some((*label, id)),
+                _ => None
+            })
+            .collect();
+        for id in 0..blocks.len() {
+            let next = Some(id + 1).filter(|next| *next < blocks.len());
+            let last = blocks[id].end.checked_sub(1).filter(|last| *last >= blocks[id].start).map(|last| &program[last]);
+            let successors: Vec<BlockId> = match last {
+                Some(StackCommand::Jmp(label)) => labels.get(label).copied().into_iter().collect(),
+                Some(StackCommand::ConditionalJump(_, label)) => next.into_iter().chain(labels.get(label).copied()).collect(),
+                _ => next.into_iter().collect(),
+            };
+            for successor in &successors {
+                if !blocks[*successor].predecessors.contains(&id) {
+                    blocks[*successor].predecessors.push(id);
+                }
+            }
+            blocks[id].successors = successors;
+            blocks[id].successors.dedup();
+        }
+        Self { program, blocks }
+    }
+
+    pub fn program(&self) -> &'a [StackCommand] {
+        self.program
+    }
+
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    pub fn block(&self, id: BlockId) -> &BasicBlock {
+        &self.blocks[id]
+    }
+
+    pub fn entry(&self) -> BlockId {
+        0
+    }
+
+    pub fn commands(&self, id: BlockId) -> &'a [StackCommand] {
+        let block = &self.blocks[id];
+        &self.program[block.start..block.end]
+    }
+
+    /// Block containing the command at the index
+    pub fn block_of(&self, index: usize) -> BlockId {
+        self.blocks.partition_point(|block| block.start <= index) - 1
+    }
+
+    fn successors(&self) -> Vec<Vec<BlockId>> {
+        self.blocks.iter().map(|block| block.successors.clone()).collect()
+    }
+
+    /// Blocks reachable from the entry in the reverse postorder
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        reverse_postorder(self.entry(), &self.successors())
+    }
+
+    pub fn dominators(&self) -> Dominators {
+        let predecessors: Vec<Vec<BlockId>> = self.blocks.iter().map(|block| block.predecessors.clone()).collect();
+        Dominators::compute(&self.reverse_postorder(), &predecessors)
+    }
+
+    fn transfer_block<D: Dataflow>(&self, analysis: &D, id: BlockId, fact: D::Fact) -> D::Fact {
+        let commands = self.commands(id);
+        match D::DIRECTION {
+            Direction::Forward => commands.iter().fold(fact, |fact, command| analysis.transfer(command, fact)),
+            Direction::Backward => commands.iter().rev().fold(fact, |fact, command| analysis.transfer(command, fact)),
+        }
+    }
+
+    /// Solves the analysis with the worklist algorithm
+    pub fn solve<D: Dataflow>(&self, analysis: &D) -> DataflowResult<D::Fact> {
+        let count = self.blocks.len();
+        // Facts flowing into the block along the direction of the analysis, and out of it
+        let mut incoming = vec![analysis.initial(); count];
+        let mut outgoing = vec![analysis.initial(); count];
+        let mut worklist: VecDeque<BlockId> = match D::DIRECTION {
+            Direction::Forward => (0..count).collect(),
+            Direction::Backward => (0..count).rev().collect(),
+        };
+        let mut queued = vec![true; count];
+        while let Some(id) = worklist.pop_front() {
+            queued[id] = false;
+            let block = &self.blocks[id];
+            let (sources, targets) = match D::DIRECTION {
+                Direction::Forward => (&block.predecessors, &block.successors),
+                Direction::Backward => (&block.successors, &block.predecessors),
+            };
+            let is_boundary = match D::DIRECTION {
+                Direction::Forward => id == self.entry(),
+                Direction::Backward => block.successors.is_empty(),
+            };
+            let boundary = if is_boundary { Some(analysis.boundary()) } else { None };
+            incoming[id] = boundary.into_iter()
+                .chain(sources.iter().map(|source| outgoing[*source].clone()))
+                .reduce(|lhs, rhs| analysis.join(&lhs, &rhs))
+                .unwrap_or_else(|| analysis.initial());
+            let fact = self.transfer_block(analysis, id, incoming[id].clone());
+            if fact != outgoing[id] {
+                outgoing[id] = fact;
+                for target in targets {
+                    if !queued[*target] {
+                        queued[*target] = true;
+                        worklist.push_back(*target);
+                    }
+                }
+            }
+        }
+        match D::DIRECTION {
+            Direction::Forward => DataflowResult { block_in: incoming, block_out: outgoing },
+            Direction::Backward => DataflowResult { block_in: outgoing, block_out: incoming },
+        }
+    }
+
+    /// Solves the analysis and replays the transfer function inside the blocks
+    pub fn solve_commands<D: Dataflow>(&self, analysis: &D) -> CommandFacts<D::Fact> {
+        let result = self.solve(analysis);
+        let mut before = vec![analysis.initial(); self.program.len()];
+        let mut after = vec![analysis.initial(); self.program.len()];
+        for (id, block) in self.blocks.iter().enumerate() {
+            match D::DIRECTION {
+                Direction::Forward => {
+                    let mut fact = result.block_in[id].clone();
+                    for index in block.start..block.end {
+                        before[index] = fact.clone();
+                        fact = analysis.transfer(&self.program[index], fact);
+                        after[index] = fact.clone();
+                    }
+                }
+                Direction::Backward => {
+                    let mut fact = result.block_out[id].clone();
+                    for index in (block.start..block.end).rev() {
+                        after[index] = fact.clone();
+                        fact = analysis.transfer(&self.program[index], fact);
+                        before[index] = fact.clone();
+                    }
+                }
+            }
+        }
+        CommandFacts { before, after }
+    }
+
+    /// Graphviz representation of the graph, jump edges are labeled with the condition
+    pub fn to_dot(&self) -> String {
+        let mut result = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+        for (id, _) in self.blocks.iter().enumerate() {
+            let mut label = format!("B{}\\l", id);
+            for command in self.commands(id) {
+                match command {
+                    StackCommand::Label(_) => label.push_str(&format!("{}\\l", command)),
+                    _ => label.push_str(&format!("    {}\\l", command)),
+                }
+            }
+            result.push_str(&format!("    b{} [label=\"{}\"];\n", id, label));
+        }
+        for (id, block) in self.blocks.iter().enumerate() {
+            let last = self.commands(id).last();
+            for successor in &block.successors {
+                let jump_label = match last {
+                    Some(StackCommand::ConditionalJump(condition, label)) if self.blocks[*successor].start < self.program.len()
+                        && self.program[self.blocks[*successor].start] == StackCommand::Label(*label) => match condition {
+                        Condition::EqualsZero => " [label=\"zero\"]",
+                        Condition::NotEqualsZero => " [label=\"not zero\"]",
+                    },
+                    _ => "",
+                };
+                result.push_str(&format!("    b{} -> b{}{};\n", id, successor, jump_label));
+            }
+        }
+        result.push_str("}\n");
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use crate::parser::ast::Ident;
+    use crate::stack_machine::text::parse_program;
+    use super::*;
+
+    const WHILE: &str = "
+        const 3
+        store c
+        jmp L1
+    L0:
+        load c
+        jz L2
+        load c
+        print
+    L2:
+        load c
+        const 1
+        op sub
+        store c
+    L1:
+        load c
+        jnz L0
+        const 0
+        print
+    ";
+
+    #[test]
+    fn test_blocks() {
+        let program = parse_program(WHILE).unwrap();
+        let cfg = ControlFlowGraph::build(&program);
+        let ranges: Vec<(usize, usize)> = cfg.blocks().iter().map(|block| (block.start, block.end)).collect();
+        assert_eq!(vec![(0, 3), (3, 6), (6, 8), (8, 13), (13, 16), (16, 18)], ranges);
+        let successors: Vec<&[BlockId]> = cfg.blocks().iter().map(|block| block.successors.as_slice()).collect();
+        assert_eq!(vec![&[4][..], &[2, 3], &[3], &[4], &[5, 1], &[]], successors);
+        assert_eq!(vec![1, 2], cfg.block(3).predecessors);
+        assert_eq!(4, cfg.block_of(14));
+    }
+
+    #[test]
+    fn test_dominators() {
+        let program = parse_program(WHILE).unwrap();
+        let dominators = ControlFlowGraph::build(&program).dominators();
+        let idoms: Vec<Option<BlockId>> = (0..6).map(|block| dominators.immediate_dominator(block)).collect();
+        assert_eq!(vec![None, Some(4), Some(1), Some(1), Some(0), Some(4)], idoms);
+        assert!(dominators.dominates(4, 2));
+        assert!(!dominators.dominates(2, 3));
+
+        let program = parse_program("jmp L0\nconst 1\nprint\nL0:\nconst 2\nprint").unwrap();
+        let dominators = ControlFlowGraph::build(&program).dominators();
+        assert!(!dominators.dominates(0, 1));
+        assert_eq!(None, dominators.immediate_dominator(1));
+    }
+
+    /// Variables which may be assigned before the command
+    struct MayBeAssigned;
+
+    impl Dataflow for MayBeAssigned {
+        type Fact = HashSet<Ident>;
+        const DIRECTION: Direction = Direction::Forward;
+
+        fn boundary(&self) -> Self::Fact {
+            HashSet::new()
+        }
+
+        fn initial(&self) -> Self::Fact {
+            HashSet::new()
+        }
+
+        fn join(&self, lhs: &Self::Fact, rhs: &Self::Fact) -> Self::Fact {
+            lhs.union(rhs).cloned().collect()
+        }
+
+        fn transfer(&self, command: &StackCommand, mut fact: Self::Fact) -> Self::Fact {
+            if let StackCommand::Store(id) = command {
+                fact.insert(id.clone());
+            }
+            fact
+        }
+    }
+
+    #[test]
+    fn test_forward_analysis() {
+        let program = parse_program("L0:\nload a\njz L1\nconst 1\nstore b\njmp L0\nL1:\nconst 2\nstore c").unwrap();
+        let cfg = ControlFlowGraph::build(&program);
+        let facts = cfg.solve_commands(&MayBeAssigned);
+        // `b` is assigned on the second iteration
+        assert_eq!(HashSet::from([Ident(String::from("b"))]), facts.before[1]);
+        assert_eq!(HashSet::from([Ident(String::from("b")), Ident(String::from("c"))]), facts.after[8]);
+    }
+
+    #[test]
+    fn test_dot() {
+        let program = parse_program("load a\njz L0\nconst 1\nprint\nL0:").unwrap();
+        let dot = ControlFlowGraph::build(&program).to_dot();
+        assert_eq!("digraph cfg {
+    node [shape=box, fontname=\"monospace\"];
+    b0 [label=\"B0\\l    load a\\l    jz L0\\l\"];
+    b1 [label=\"B1\\l    const 1\\l    print\\l\"];
+    b2 [label=\"B2\\lL0:\\l\"];
+    b0 -> b1;
+    b0 -> b2 [label=\"zero\"];
+    b1 -> b2;
+}
+", dot);
+    }
+}
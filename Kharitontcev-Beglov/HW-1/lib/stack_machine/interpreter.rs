@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use crate::parser::ast::Ident;
+use crate::parser::interpreter::{output_char, output_string};
+use crate::stack_machine::sm::{Condition, Label, StackCommand};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InterpretError {
+    /// The command at the index required more values than the stack contains
+    StackUnderflow(usize),
+    UnknownLabel(Label),
+    StepLimitExceeded,
+}
+
+impl Display for InterpretError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterpretError::StackUnderflow(index) => write!(f, "Stack underflow at command #{}", index),
+            InterpretError::UnknownLabel(label) => write!(f, "Jump to unknown label {}", label),
+            InterpretError::StepLimitExceeded => write!(f, "Step limit exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for InterpretError {}
+
+/// Reference interpreter of the stack machine commands.
+///
+/// Variables are zero until assigned, the same as in the compiled code, where the memory is zeroed.
+#[derive(Clone, Debug, Default)]
+pub struct SMInterpreter {
+    stack: Vec<i32>,
+    variables: HashMap<Ident, i32>,
+    output: Vec<i32>,
+    text: String,
+    step_limit: Option<usize>,
+}
+
+impl SMInterpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the number of executed commands, so non-terminating programs are reported as errors
+    pub fn with_step_limit(step_limit: usize) -> Self {
+        Self { step_limit: Some(step_limit), ..Self::default() }
+    }
+
+    fn pop(&mut self, index: usize) -> Result<i32, InterpretError> {
+        self.stack.pop().ok_or(InterpretError::StackUnderflow(index))
+    }
+
+    fn collect_labels(program: &[StackCommand]) -> HashMap<Label, usize> {
+        program.iter()
+            .enumerate()
+            .filter_map(|(index, cmd)| match cmd {
+                StackCommand::Label(label) => Some((*label, index)),
+                _ => None
+            })
+            .collect()
+    }
+
+    pub fn run(&mut self, program: &[StackCommand]) -> Result<(), InterpretError> {
+        let labels = Self::collect_labels(program);
+        let jump = |label: &Label| labels.get(label).copied().ok_or(InterpretError::UnknownLabel(*label));
+        let mut pc = 0;
+        let mut steps = 0;
+        while pc < program.len() {
+            steps += 1;
+            if self.step_limit.is_some_and(|limit| steps > limit) {
+                return Err(InterpretError::StepLimitExceeded);
+            }
+            let mut next = pc + 1;
+            match &program[pc] {
+                StackCommand::Print => {
+                    let value = self.pop(pc)?;
+                    self.output.push(value);
+                    self.text.push_str(&format!("{}\n", value))
+                }
+                StackCommand::PrintChar => {
+                    let value = self.pop(pc)?;
+                    self.text.push(output_char(value))
+                }
+                StackCommand::PrintString(value) => self.text.push_str(output_string(value)),
+                StackCommand::Op(op) => {
+                    let rhs = self.pop(pc)?;
+                    let lhs = self.pop(pc)?;
+                    self.stack.push(op.apply(lhs, rhs))
+                }
+                StackCommand::Load(id) => self.stack.push(self.variable(id)),
+                StackCommand::Store(id) => {
+                    let value = self.pop(pc)?;
+                    self.variables.insert(id.clone(), value);
+                }
+                StackCommand::Const(x) => self.stack.push(*x),
+                StackCommand::Dup => {
+                    let value = *self.stack.last().ok_or(InterpretError::StackUnderflow(pc))?;
+                    self.stack.push(value)
+                }
+                StackCommand::Call(routine) => {
+                    let at = self.stack.len().checked_sub(routine.arity()).ok_or(InterpretError::StackUnderflow(pc))?;
+                    let result = routine.apply(&self.stack[at..]);
+                    self.stack.truncate(at);
+                    self.stack.push(result)
+                }
+                StackCommand::Label(_) | StackCommand::Location(_) => {}
+                StackCommand::Jmp(label) => next = jump(label)?,
+                StackCommand::ConditionalJump(condition, label) => {
+                    let value = self.pop(pc)?;
+                    let taken = match condition {
+                        Condition::EqualsZero => value == 0,
+                        Condition::NotEqualsZero => value != 0,
+                    };
+                    if taken {
+                        next = jump(label)?
+                    }
+                }
+            }
+            pc = next;
+        }
+        Ok(())
+    }
+
+    /// Values printed by the program so far
+    pub fn output(&self) -> &[i32] {
+        &self.output
+    }
+
+    /// Whole text printed by the program so far, including the characters and the strings
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn variable(&self, id: &Ident) -> i32 {
+        self.variables.get(id).copied().unwrap_or(0)
+    }
+
+    pub fn stack(&self) -> &[i32] {
+        &self.stack
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::ast::Ops;
+    use super::*;
+
+    fn ident(name: &str) -> Ident {
+        Ident(name.to_string())
+    }
+
+    #[test]
+    fn test_operands_order() {
+        let program = vec![
+            StackCommand::Const(5),
+            StackCommand::Const(7),
+            StackCommand::Op(Ops::Sub),
+            StackCommand::Print,
+        ];
+        let mut interpreter = SMInterpreter::new();
+        interpreter.run(&program).unwrap();
+        assert_eq!(&[-2], interpreter.output());
+        assert!(interpreter.stack().is_empty());
+    }
+
+    #[test]
+    fn test_loop() {
+        // x = 3; while (x) { print(x); x = x - 1 }
+        let start = Label { id: 0 };
+        let condition = Label { id: 1 };
+        let program = vec![
+            StackCommand::Const(3),
+            StackCommand::Store(ident("x")),
+            StackCommand::Jmp(condition),
+            StackCommand::Label(start),
+            StackCommand::Load(ident("x")),
+            StackCommand::Print,
+            StackCommand::Load(ident("x")),
+            StackCommand::Const(1),
+            StackCommand::Op(Ops::Sub),
+            StackCommand::Store(ident("x")),
+            StackCommand::Label(condition),
+            StackCommand::Load(ident("x")),
+            StackCommand::ConditionalJump(Condition::NotEqualsZero, start),
+        ];
+        let mut interpreter = SMInterpreter::new();
+        interpreter.run(&program).unwrap();
+        assert_eq!(&[3, 2, 1], interpreter.output());
+        assert_eq!(0, interpreter.variable(&ident("x")));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut interpreter = SMInterpreter::new();
+        assert_eq!(Err(InterpretError::StackUnderflow(1)), interpreter.run(&[StackCommand::Const(1), StackCommand::Op(Ops::Add)]));
+
+        let mut interpreter = SMInterpreter::new();
+        let label = Label { id: 42 };
+        assert_eq!(Err(InterpretError::UnknownLabel(label)), interpreter.run(&[StackCommand::Jmp(label)]));
+
+        let mut interpreter = SMInterpreter::with_step_limit(100);
+        let program = [StackCommand::Label(label), StackCommand::Jmp(label)];
+        assert_eq!(Err(InterpretError::StepLimitExceeded), interpreter.run(&program));
+    }
+}
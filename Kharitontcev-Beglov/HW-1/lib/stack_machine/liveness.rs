@@ -0,0 +1,148 @@
+//! Liveness analysis of the variables in the stack machine code.
+//!
+//! A variable is live before a command if its current value may be loaded afterward. Variables read before
+//! any assignment are live at the start of the program.
+
+use std::collections::{HashMap, HashSet};
+use crate::parser::ast::Ident;
+use crate::stack_machine::cfg::{ControlFlowGraph, Dataflow, Direction};
+use crate::stack_machine::sm::StackCommand;
+
+/// Range of the commands where the variable is live or accessed, in the order of the program
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LiveInterval {
+    pub variable: Ident,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Liveness {
+    live_in: Vec<HashSet<Ident>>,
+    live_out: Vec<HashSet<Ident>>,
+}
+
+/// Backward dataflow analysis computing the live variables
+pub struct LiveVariables;
+
+impl Dataflow for LiveVariables {
+    type Fact = HashSet<Ident>;
+    const DIRECTION: Direction = Direction::Backward;
+
+    fn boundary(&self) -> Self::Fact {
+        HashSet::new()
+    }
+
+    fn initial(&self) -> Self::Fact {
+        HashSet::new()
+    }
+
+    fn join(&self, lhs: &Self::Fact, rhs: &Self::Fact) -> Self::Fact {
+        lhs.union(rhs).cloned().collect()
+    }
+
+    fn transfer(&self, command: &StackCommand, mut fact: Self::Fact) -> Self::Fact {
+        match command {
+            StackCommand::Load(id) => { fact.insert(id.clone()); }
+            StackCommand::Store(id) => { fact.remove(id); }
+            _ => {}
+        }
+        fact
+    }
+}
+
+impl Liveness {
+    pub fn analyze(program: &[StackCommand]) -> Self {
+        let facts = ControlFlowGraph::build(program).solve_commands(&LiveVariables);
+        Self { live_in: facts.before, live_out: facts.after }
+    }
+
+    /// Variables live before the command at the index
+    pub fn live_in(&self, index: usize) -> &HashSet<Ident> {
+        &self.live_in[index]
+    }
+
+    /// Variables live after the command at the index
+    pub fn live_out(&self, index: usize) -> &HashSet<Ident> {
+        &self.live_out[index]
+    }
+
+    /// Live intervals of all variables of the program sorted by the start.
+    ///
+    /// Stores of dead values are included, so every variable of the program has an interval.
+    pub fn intervals(&self, program: &[StackCommand]) -> Vec<LiveInterval> {
+        let mut ranges: HashMap<&Ident, (usize, usize)> = HashMap::new();
+        let mut extend = |variable, index| {
+            let range = ranges.entry(variable).or_insert((index, index));
+            range.0 = range.0.min(index);
+            range.1 = range.1.max(index);
+        };
+        for (index, cmd) in program.iter().enumerate() {
+            for variable in self.live_in[index].iter().chain(&self.live_out[index]) {
+                extend(variable, index);
+            }
+            if let StackCommand::Load(id) | StackCommand::Store(id) = cmd {
+                extend(id, index);
+            }
+        }
+        let mut result: Vec<LiveInterval> = ranges.into_iter()
+            .map(|(variable, (start, end))| LiveInterval { variable: variable.clone(), start, end })
+            .collect();
+        result.sort_by(|a, b| (a.start, a.end, &a.variable.0).cmp(&(b.start, b.end, &b.variable.0)));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stack_machine::text::parse_program;
+    use super::*;
+
+    fn ident(name: &str) -> Ident {
+        Ident(name.to_string())
+    }
+
+    #[test]
+    fn test_straight_line() {
+        let program = parse_program("const 1\nstore a\nload a\nstore b\nload a\nprint\nconst 2\nstore a").unwrap();
+        let liveness = Liveness::analyze(&program);
+        assert!(liveness.live_in(0).is_empty());
+        assert_eq!(&HashSet::from([ident("a")]), liveness.live_out(1));
+        // `b` is never read
+        assert_eq!(&HashSet::from([ident("a")]), liveness.live_out(3));
+        assert!(liveness.live_out(4).is_empty());
+        assert_eq!(vec![
+            LiveInterval { variable: ident("a"), start: 1, end: 7 },
+            LiveInterval { variable: ident("b"), start: 3, end: 3 },
+        ], liveness.intervals(&program));
+    }
+
+    #[test]
+    fn test_loop() {
+        // The counter is live in the whole loop, `x` is read before the assignment
+        let program = parse_program("
+            const 3
+            store c
+            jmp L1
+        L0:
+            load x
+            print
+            load c
+            const 1
+            op sub
+            store c
+        L1:
+            load c
+            jnz L0
+        ").unwrap();
+        let liveness = Liveness::analyze(&program);
+        assert_eq!(&HashSet::from([ident("x")]), liveness.live_in(0));
+        assert_eq!(&HashSet::from([ident("c"), ident("x")]), liveness.live_in(3));
+        // Both are read again after jumping back
+        assert_eq!(&HashSet::from([ident("c"), ident("x")]), liveness.live_out(12));
+        assert_eq!(vec![
+            LiveInterval { variable: ident("x"), start: 0, end: 12 },
+            LiveInterval { variable: ident("c"), start: 1, end: 12 },
+        ], liveness.intervals(&program));
+    }
+}
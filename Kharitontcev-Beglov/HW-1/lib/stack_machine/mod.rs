@@ -1,2 +1,8 @@
 pub mod sm;
-pub mod transform;
\ No newline at end of file
+pub mod transform;
+pub mod interpreter;
+pub mod text;
+pub mod peephole;
+pub mod liveness;
+pub mod cfg;
+pub mod warnings;
@@ -0,0 +1,246 @@
+//! Peephole optimizations of the stack machine code.
+//!
+//! Every rule keeps the printed values and the final values of the variables, the rules are applied until
+//! none of them changes the program. `Location` markers never prevent a rule, but code between them may move.
+
+use std::collections::{HashMap, HashSet};
+use crate::parser::ast::Ops;
+use crate::stack_machine::sm::{Condition, Label, StackCommand};
+
+fn is_marker(cmd: &StackCommand) -> bool {
+    matches!(cmd, StackCommand::Label(_) | StackCommand::Location(_))
+}
+
+fn jump_target(cmd: &StackCommand) -> Option<Label> {
+    match cmd {
+        StackCommand::Jmp(label) | StackCommand::ConditionalJump(_, label) => Some(*label),
+        _ => None
+    }
+}
+
+/// Index of the next command after `index` which is not a `Location` marker
+fn next_command(program: &[StackCommand], index: usize) -> Option<usize> {
+    (index + 1..program.len()).find(|&i| !matches!(program[i], StackCommand::Location(_)))
+}
+
+/// `Const c; jz L` either always jumps or never does
+fn fold_constant_jumps(program: Vec<StackCommand>) -> Vec<StackCommand> {
+    let mut result: Vec<StackCommand> = Vec::with_capacity(program.len());
+    for cmd in program {
+        if let (StackCommand::ConditionalJump(condition, label), Some(StackCommand::Const(value))) = (&cmd, result.last()) {
+            let taken = match condition {
+                Condition::EqualsZero => *value == 0,
+                Condition::NotEqualsZero => *value != 0,
+            };
+            let label = *label;
+            result.pop();
+            if taken {
+                result.push(StackCommand::Jmp(label));
+            }
+            continue;
+        }
+        result.push(cmd);
+    }
+    result
+}
+
+/// `x + 0`, `x - 0` and `x | 0` are `x`
+fn remove_neutral_operations(program: Vec<StackCommand>) -> Vec<StackCommand> {
+    let mut result: Vec<StackCommand> = Vec::with_capacity(program.len());
+    for cmd in program {
+        if let (StackCommand::Op(Ops::Add | Ops::Sub | Ops::BitwiseOr), Some(StackCommand::Const(0))) = (&cmd, result.last()) {
+            result.pop();
+            continue;
+        }
+        result.push(cmd);
+    }
+    result
+}
+
+/// `Store x; Load x` is `Dup; Store x`, the value is not read back from the memory
+fn forward_stores(mut program: Vec<StackCommand>) -> Vec<StackCommand> {
+    let mut index = 0;
+    while index < program.len() {
+        if let StackCommand::Store(id) = &program[index] {
+            if let Some(next) = next_command(&program, index) {
+                if program[next] == StackCommand::Load(id.clone()) {
+                    program.remove(next);
+                    program.insert(index, StackCommand::Dup);
+                    index += 1;
+                }
+            }
+        }
+        index += 1;
+    }
+    program
+}
+
+/// Jumps to a label followed by `Jmp L` go directly to `L`
+fn thread_jumps(mut program: Vec<StackCommand>) -> Vec<StackCommand> {
+    let mut forwards = HashMap::new();
+    for (index, cmd) in program.iter().enumerate() {
+        if let StackCommand::Label(label) = cmd {
+            if let Some(StackCommand::Jmp(target)) = program[index..].iter().find(|cmd| !is_marker(cmd)) {
+                forwards.insert(*label, *target);
+            }
+        }
+    }
+    let resolve = |label: Label| {
+        // Loops like `L: Jmp L` are kept as they are
+        let mut visited = HashSet::from([label]);
+        let mut current = label;
+        while let Some(&next) = forwards.get(&current) {
+            if !visited.insert(next) {
+                return label;
+            }
+            current = next;
+        }
+        current
+    };
+    for cmd in program.iter_mut() {
+        match cmd {
+            StackCommand::Jmp(label) | StackCommand::ConditionalJump(_, label) => *label = resolve(*label),
+            _ => {}
+        }
+    }
+    program
+}
+
+/// `Jmp L` followed only by labels up to `L` does nothing
+fn remove_jumps_to_next(program: Vec<StackCommand>) -> Vec<StackCommand> {
+    let mut result = Vec::with_capacity(program.len());
+    for (index, cmd) in program.iter().enumerate() {
+        if let StackCommand::Jmp(label) = cmd {
+            let falls_through = program[index + 1..].iter()
+                .take_while(|cmd| is_marker(cmd))
+                .any(|cmd| *cmd == StackCommand::Label(*label));
+            if falls_through {
+                continue;
+            }
+        }
+        result.push(cmd.clone());
+    }
+    result
+}
+
+/// Code after an unconditional jump is not reachable until the next label
+fn remove_unreachable(program: Vec<StackCommand>) -> Vec<StackCommand> {
+    let mut result = Vec::with_capacity(program.len());
+    let mut reachable = true;
+    for cmd in program {
+        if let StackCommand::Label(_) = cmd {
+            reachable = true;
+        }
+        if reachable {
+            if let StackCommand::Jmp(_) = cmd {
+                reachable = false;
+            }
+            result.push(cmd);
+        }
+    }
+    result
+}
+
+fn remove_unused_labels(program: Vec<StackCommand>) -> Vec<StackCommand> {
+    let used: HashSet<Label> = program.iter().filter_map(jump_target).collect();
+    program.into_iter()
+        .filter(|cmd| match cmd {
+            StackCommand::Label(label) => used.contains(label),
+            _ => true
+        })
+        .collect()
+}
+
+pub fn optimize(mut program: Vec<StackCommand>) -> Vec<StackCommand> {
+    loop {
+        let before = program.clone();
+        program = fold_constant_jumps(program);
+        program = remove_neutral_operations(program);
+        program = forward_stores(program);
+        program = thread_jumps(program);
+        program = remove_jumps_to_next(program);
+        program = remove_unreachable(program);
+        program = remove_unused_labels(program);
+        if program == before {
+            return program;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fuzz::generator::{GeneratorConfig, ProgramGenerator};
+    use crate::parser::ast::Ident;
+    use crate::parser::parse_source;
+    use crate::stack_machine::interpreter::SMInterpreter;
+    use crate::stack_machine::text::parse_program;
+    use crate::stack_machine::transform::AstTransformer;
+    use super::*;
+
+    fn sm(text: &str) -> Vec<StackCommand> {
+        parse_program(text).unwrap()
+    }
+
+    fn compile(source: &str) -> Vec<StackCommand> {
+        AstTransformer::new().transform_ast_to_sm(parse_source(source))
+    }
+
+    fn assert_equivalent(program: &[StackCommand], optimized: &[StackCommand]) {
+        let mut expected = SMInterpreter::with_step_limit(1_000_000);
+        expected.run(program).unwrap();
+        let mut actual = SMInterpreter::with_step_limit(1_000_000);
+        actual.run(optimized).unwrap();
+        assert_eq!(expected.output(), actual.output());
+        for id in ["a", "b", "c", "d"] {
+            let id = Ident(id.to_string());
+            assert_eq!(expected.variable(&id), actual.variable(&id));
+        }
+    }
+
+    #[test]
+    fn test_store_load_forwarding() {
+        let program = sm("const 1\nstore a\nloc 2:5\nload a\nprint");
+        assert_eq!(sm("const 1\ndup\nstore a\nloc 2:5\nprint"), optimize(program));
+        // Another path may reach the load
+        let program = sm("const 1\nstore a\nL0:\nload a\nprint\njmp L0");
+        assert_eq!(program, optimize(program.clone()));
+    }
+
+    #[test]
+    fn test_jumps() {
+        let program = sm("jmp L0\nconst 1\nprint\nL0:\njz L1\nL1:\njmp L2\nL2:\nload a\njnz L1\nconst 2\nprint");
+        assert_eq!(sm("jz L2\nL2:\nload a\njnz L2\nconst 2\nprint"), optimize(program));
+        let program = sm("L0:\njmp L0");
+        assert_eq!(program, optimize(program.clone()));
+    }
+
+    #[test]
+    fn test_constant_conditions_and_neutral_operations() {
+        let program = compile("{ if (1) { print(a + 0) } else { print(2) }; while (0) { print(3) } }");
+        let optimized = optimize(program.clone());
+        assert_eq!(
+            vec![StackCommand::Load(Ident(String::from("a"))), StackCommand::Print],
+            optimized.into_iter().filter(|cmd| !is_marker(cmd)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_if_without_else() {
+        let program = compile("{ a = 3; if (a) { b = a }; print(b) }");
+        let optimized = optimize(program.clone());
+        assert!(!optimized.iter().any(|cmd| matches!(cmd, StackCommand::Jmp(_))));
+        assert!(optimized.contains(&StackCommand::Dup));
+        assert_equivalent(&program, &optimized);
+    }
+
+    #[test]
+    fn test_generated_programs() {
+        for seed in 0..200 {
+            let block = ProgramGenerator::new(seed, GeneratorConfig::default()).generate();
+            let program = AstTransformer::new().transform_ast_to_sm(block);
+            let optimized = optimize(program.clone());
+            assert!(optimized.len() <= program.len());
+            assert_equivalent(&program, &optimized);
+        }
+    }
+}
@@ -1,16 +1,27 @@
 use std::fmt::{Display, Formatter};
-use crate::parser::ast::{Ident, Ops};
+use crate::lexer::tokens::Span;
+use crate::parser::ast::{Ident, Ops, Routine};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum StackCommand {
     Print,
+    // Pops the code of the character to print
+    PrintChar,
+    // Prints the string literal, which is stored in the data
+    PrintString(String),
     Op(Ops),
     Load(Ident),
     Store(Ident),
     Const(i32),
+    // Pushes a copy of the top of the stack
+    Dup,
+    // Pops the arguments of the runtime routine, the last one is on the top, and pushes the result
+    Call(Routine),
     Label(Label),
     Jmp(Label),
     ConditionalJump(Condition, Label),
+    // Marks the start of the code of a statement at the given source position. Compiles to nothing
+    Location(Span),
 }
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct Label {
@@ -19,11 +30,11 @@ pub struct Label {
 
 impl Display for Label {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "LABEL {}:", self.id)
+        write!(f, "L{}", self.id)
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Condition {
     EqualsZero,
     NotEqualsZero,
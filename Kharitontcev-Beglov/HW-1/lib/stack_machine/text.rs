@@ -0,0 +1,216 @@
+//! Textual representation of the stack machine commands, one command per line:
+//!
+//! ```text
+//! loc 2:5
+//! const 5
+//! load x
+//! op add
+//! call mul
+//! prints "x = "
+//! store x
+//! jz L3
+//! L3:
+//! ```
+//!
+//! Empty lines and comments starting with `#` are ignored by the parser.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use crate::lexer::{escape, unescape};
+use crate::lexer::tokens::Span;
+use crate::parser::ast::{Ident, Ops, Routine};
+use crate::stack_machine::sm::{Condition, Label, StackCommand};
+
+fn op_name(op: &Ops) -> &'static str {
+    match op {
+        Ops::Add => "add",
+        Ops::Sub => "sub",
+        Ops::BitwiseAnd => "and",
+        Ops::BitwiseOr => "or",
+        Ops::BitwiseNor => "nor",
+    }
+}
+
+impl Display for StackCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StackCommand::Print => write!(f, "print"),
+            StackCommand::PrintChar => write!(f, "printc"),
+            StackCommand::PrintString(value) => write!(f, "prints \"{}\"", escape(value)),
+            StackCommand::Op(op) => write!(f, "op {}", op_name(op)),
+            StackCommand::Load(id) => write!(f, "load {}", id.0),
+            StackCommand::Store(id) => write!(f, "store {}", id.0),
+            StackCommand::Const(x) => write!(f, "const {}", x),
+            StackCommand::Dup => write!(f, "dup"),
+            StackCommand::Call(routine) => write!(f, "call {}", routine.name()),
+            StackCommand::Label(label) => write!(f, "{}:", label),
+            StackCommand::Jmp(label) => write!(f, "jmp {}", label),
+            StackCommand::ConditionalJump(Condition::EqualsZero, label) => write!(f, "jz {}", label),
+            StackCommand::ConditionalJump(Condition::NotEqualsZero, label) => write!(f, "jnz {}", label),
+            StackCommand::Location(span) => write!(f, "loc {}", span),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseSMError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ParseSMError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid stack machine code at line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseSMError {}
+
+fn parse_label(text: &str) -> Result<Label, String> {
+    text.strip_prefix('L')
+        .and_then(|id| id.parse().ok())
+        .map(|id| Label { id })
+        .ok_or_else(|| format!("invalid label `{}`, expected L<number>", text))
+}
+
+fn parse_ident(text: &str) -> Result<Ident, String> {
+    if !text.is_empty() && text.chars().all(|c| c.is_ascii_alphabetic() || c == '_') {
+        Ok(Ident(text.to_string()))
+    } else {
+        Err(format!("invalid variable name `{}`", text))
+    }
+}
+
+fn parse_op(text: &str) -> Result<Ops, String> {
+    [Ops::Add, Ops::Sub, Ops::BitwiseAnd, Ops::BitwiseOr, Ops::BitwiseNor].into_iter()
+        .find(|op| op_name(op) == text)
+        .ok_or_else(|| format!("unknown operation `{}`", text))
+}
+
+fn parse_span(text: &str) -> Result<Span, String> {
+    let error = || format!("invalid location `{}`, expected <line>:<column>", text);
+    let (line, column) = text.split_once(':').ok_or_else(error)?;
+    Ok(Span {
+        line: line.parse().map_err(|_| error())?,
+        column: column.parse().map_err(|_| error())?,
+    })
+}
+
+impl FromStr for StackCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(literal) = s.trim().strip_prefix("prints ") {
+            return literal.trim().strip_prefix('"')
+                .and_then(|literal| literal.strip_suffix('"'))
+                .and_then(unescape)
+                .map(StackCommand::PrintString)
+                .ok_or_else(|| format!("invalid string `{}`", literal.trim()));
+        }
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        match parts.as_slice() {
+            ["print"] => Ok(StackCommand::Print),
+            ["printc"] => Ok(StackCommand::PrintChar),
+            ["op", op] => parse_op(op).map(StackCommand::Op),
+            ["load", id] => parse_ident(id).map(StackCommand::Load),
+            ["store", id] => parse_ident(id).map(StackCommand::Store),
+            ["const", x] => x.parse()
+                .map(StackCommand::Const)
+                .map_err(|_| format!("invalid constant `{}`", x)),
+            ["dup"] => Ok(StackCommand::Dup),
+            ["call", name] => Routine::from_name(name)
+                .map(StackCommand::Call)
+                .ok_or_else(|| format!("unknown routine `{}`", name)),
+            ["jmp", label] => parse_label(label).map(StackCommand::Jmp),
+            ["jz", label] => parse_label(label).map(|l| StackCommand::ConditionalJump(Condition::EqualsZero, l)),
+            ["jnz", label] => parse_label(label).map(|l| StackCommand::ConditionalJump(Condition::NotEqualsZero, l)),
+            ["loc", span] => parse_span(span).map(StackCommand::Location),
+            [label] if label.ends_with(':') => parse_label(&label[..label.len() - 1]).map(StackCommand::Label),
+            _ => Err(format!("unknown command `{}`", s.trim())),
+        }
+    }
+}
+
+pub fn program_to_text(program: &[StackCommand]) -> String {
+    let mut result = String::new();
+    for command in program {
+        match command {
+            StackCommand::Label(_) => {}
+            _ => result.push_str("    "),
+        }
+        result.push_str(&command.to_string());
+        result.push('\n');
+    }
+    result
+}
+
+/// Line without the comment, `#` inside a string literal does not start one
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '#' if !in_string => return &line[..index],
+            '"' if !escaped => in_string = !in_string,
+            _ => {}
+        }
+        escaped = in_string && c == '\\' && !escaped;
+    }
+    line
+}
+
+pub fn parse_program(text: &str) -> Result<Vec<StackCommand>, ParseSMError> {
+    let mut result = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let command = line.parse().map_err(|message| ParseSMError { line: index + 1, message })?;
+        result.push(command);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let program = vec![
+            StackCommand::Location(Span { line: 2, column: 5 }),
+            StackCommand::Const(-5),
+            StackCommand::Load(Ident(String::from("x"))),
+            StackCommand::Op(Ops::BitwiseNor),
+            StackCommand::Dup,
+            StackCommand::Call(Routine::Max),
+            StackCommand::Store(Ident(String::from("x"))),
+            StackCommand::ConditionalJump(Condition::EqualsZero, Label { id: 3 }),
+            StackCommand::ConditionalJump(Condition::NotEqualsZero, Label { id: 3 }),
+            StackCommand::Jmp(Label { id: 3 }),
+            StackCommand::Label(Label { id: 3 }),
+            StackCommand::Print,
+            StackCommand::PrintChar,
+            StackCommand::PrintString(String::from("# \"a\"\n")),
+        ];
+        let text = program_to_text(&program);
+        assert_eq!("    loc 2:5\n    const -5\n    load x\n    op nor\n    dup\n    call max\n    store x\n    jz L3\n    jnz L3\n    jmp L3\nL3:\n    print\n    printc\n    prints \"# \\\"a\\\"\\n\"\n", text);
+        assert_eq!(program, parse_program(&text).unwrap());
+    }
+
+    #[test]
+    fn test_comments_and_errors() {
+        let program = parse_program("# countdown\n\nconst 1 # one\nprint\n").unwrap();
+        assert_eq!(vec![StackCommand::Const(1), StackCommand::Print], program);
+
+        let error = parse_program("const 1\nop mul\n").unwrap_err();
+        assert_eq!(2, error.line);
+        assert!(parse_program("jmp 3").is_err());
+        assert!(parse_program("load x1").is_err());
+        assert!(parse_program("const").is_err());
+        assert!(parse_program("call pow").is_err());
+        assert!(parse_program("prints \"a").is_err());
+        assert_eq!(vec![StackCommand::PrintString(String::from("\\"))], parse_program("prints \"\\\\\" # \"").unwrap());
+    }
+}
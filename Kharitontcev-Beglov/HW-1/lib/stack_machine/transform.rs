@@ -1,7 +1,8 @@
 use std::collections::{LinkedList};
-use crate::parser::ast::{Block, Expr, Ops, PrefixOps, Stmt};
+use crate::parser::ast::{Block, Expr, Ops, PrefixOps, Spanned, Stmt};
 use crate::stack_machine::sm::{Condition, Label, StackCommand};
 
+#[derive(Default)]
 pub struct AstTransformer {
     last_id: i32,
 }
@@ -18,7 +19,7 @@ impl AstTransformer {
     }
     fn transform_expr_to_sm(&self, expr: &Expr) -> LinkedList<StackCommand> {
         match expr {
-            Expr::IntLiteral(n) => LinkedList::from([StackCommand::Const(n.clone())]),
+            Expr::IntLiteral(n) => LinkedList::from([StackCommand::Const(*n)]),
             Expr::Var(x) => LinkedList::from([StackCommand::Load(x.clone())]),
             Expr::InfixOperation(left, op, right) => {
                 let mut left = self.transform_expr_to_sm(left.as_ref());
@@ -35,26 +36,37 @@ impl AstTransformer {
                         left
                     },
                     PrefixOps::UnaryMinus => {
-                        let mut left = self.transform_expr_to_sm(expr.as_ref());
-                        left.push_back(StackCommand::Const(0));
+                        let mut left = LinkedList::from([StackCommand::Const(0)]);
+                        left.append(&mut self.transform_expr_to_sm(expr.as_ref()));
                         left.push_back(StackCommand::Op(Ops::Sub));
                         left
                     }
                 }
             }
+            Expr::Call(routine, args) => {
+                let mut result = LinkedList::new();
+                for arg in args {
+                    result.append(&mut self.transform_expr_to_sm(arg));
+                }
+                result.push_back(StackCommand::Call(*routine));
+                result
+            }
         }
     }
 
-    fn transform_stmt(&mut self, label: Label, stmt: &Stmt) -> (bool, LinkedList<StackCommand>) {
-        match stmt {
+    fn transform_stmt(&mut self, label: Label, stmt: &Spanned<Stmt>) -> (bool, LinkedList<StackCommand>) {
+        let location = StackCommand::Location(stmt.span);
+        match &stmt.node {
             Stmt::VarDeclaration(_) => (false, LinkedList::new()),
             Stmt::VarAssign(id, expr) => {
-                let mut expr_cmds = self.transform_expr_to_sm(expr);
+                let mut expr_cmds = LinkedList::from([location]);
+                expr_cmds.append(&mut self.transform_expr_to_sm(expr));
                 expr_cmds.push_back(StackCommand::Store(id.clone()));
                 (false, expr_cmds)
             }
             Stmt::If { condition, true_branch, false_branch } => {
-                let mut condition_code = self.transform_expr_to_sm(condition);
+                let mut condition_code = LinkedList::from([location]);
+                condition_code.append(&mut self.transform_expr_to_sm(condition));
                 let false_label = self.generate_label();
                 condition_code.push_back(StackCommand::ConditionalJump(Condition::EqualsZero, false_label));
                 let (_, mut true_code) = self.transform_block_to_sm(label, true_branch.as_ref());
@@ -70,6 +82,7 @@ impl AstTransformer {
                 let start_body_label = self.generate_label();
                 let after_body_label = self.generate_label();
                 let mut result = LinkedList::from([
+                    location.clone(),
                     StackCommand::Jmp(after_body_label),
                     StackCommand::Label(start_body_label)
                 ]);
@@ -77,15 +90,24 @@ impl AstTransformer {
                 let mut condition_code = self.transform_expr_to_sm(condition);
                 result.append(&mut body_code);
                 result.push_back(StackCommand::Label(after_body_label));
+                result.push_back(location);
                 result.append(&mut condition_code);
                 result.push_back(StackCommand::ConditionalJump(Condition::NotEqualsZero, start_body_label));
                 (false, result)
             }
             Stmt::Print(expr) => {
-                let mut result = self.transform_expr_to_sm(expr);
+                let mut result = LinkedList::from([location]);
+                result.append(&mut self.transform_expr_to_sm(expr));
                 result.push_back(StackCommand::Print);
                 (false, result)
             }
+            Stmt::PrintChar(expr) => {
+                let mut result = LinkedList::from([location]);
+                result.append(&mut self.transform_expr_to_sm(expr));
+                result.push_back(StackCommand::PrintChar);
+                (false, result)
+            }
+            Stmt::PrintString(value) => (false, LinkedList::from([location, StackCommand::PrintString(value.clone())])),
         }
     }
 
@@ -94,9 +116,9 @@ impl AstTransformer {
             return (false, LinkedList::new())
         }
         let mut result = LinkedList::new();
-        for i in 0..(program.len() - 1) {
+        for stmt in &program[..program.len() - 1] {
             let after_stmt = self.generate_label();
-            let (lab_used, mut code) = self.transform_stmt(after_stmt, &program[i]);
+            let (lab_used, mut code) = self.transform_stmt(after_stmt, stmt);
             result.append(&mut code);
             if lab_used {
                 result.push_back(StackCommand::Label(after_stmt));
@@ -0,0 +1,125 @@
+//! Diagnostics of the stack machine code based on the dataflow analyses.
+
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use crate::lexer::tokens::Span;
+use crate::parser::ast::Ident;
+use crate::stack_machine::cfg::{ControlFlowGraph, Dataflow, Direction};
+use crate::stack_machine::sm::StackCommand;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Warning {
+    /// Position of the statement, if the code has `Location` markers
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "warning at line {}: {}", span, self.message),
+            None => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+/// Forward analysis of the variables assigned on every path, `None` stands for the code which is not reached
+struct DefinitelyAssigned<'a> {
+    /// Variables assigned before the program starts
+    assigned: &'a HashSet<Ident>,
+}
+
+impl Dataflow for DefinitelyAssigned<'_> {
+    type Fact = Option<HashSet<Ident>>;
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self) -> Self::Fact {
+        Some(self.assigned.clone())
+    }
+
+    fn initial(&self) -> Self::Fact {
+        None
+    }
+
+    fn join(&self, lhs: &Self::Fact, rhs: &Self::Fact) -> Self::Fact {
+        match (lhs, rhs) {
+            (Some(lhs), Some(rhs)) => Some(lhs.intersection(rhs).cloned().collect()),
+            (Some(fact), None) | (None, Some(fact)) => Some(fact.clone()),
+            (None, None) => None,
+        }
+    }
+
+    fn transfer(&self, command: &StackCommand, fact: Self::Fact) -> Self::Fact {
+        fact.map(|mut assigned| {
+            if let StackCommand::Store(id) = command {
+                assigned.insert(id.clone());
+            }
+            assigned
+        })
+    }
+}
+
+/// Reports loads of the variables which are not assigned on some path to them, such variables are read as zero
+pub fn uninitialized_reads(program: &[StackCommand]) -> Vec<Warning> {
+    uninitialized_reads_with(program, &HashSet::new())
+}
+
+/// Same as [`uninitialized_reads`], the `assigned` variables are set before the program, e.g. imported from other modules
+pub fn uninitialized_reads_with(program: &[StackCommand], assigned: &HashSet<Ident>) -> Vec<Warning> {
+    let facts = ControlFlowGraph::build(program).solve_commands(&DefinitelyAssigned { assigned });
+    let mut span = None;
+    let mut result: Vec<Warning> = Vec::new();
+    for (index, command) in program.iter().enumerate() {
+        match command {
+            StackCommand::Location(location) => span = Some(*location),
+            StackCommand::Load(id) => {
+                let Some(assigned) = &facts.before[index] else { continue };
+                let warning = Warning { span, message: format!("variable `{}` may be read before assignment", id.0) };
+                if !assigned.contains(id) && !result.contains(&warning) {
+                    result.push(warning);
+                }
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_source;
+    use crate::stack_machine::transform::AstTransformer;
+    use super::*;
+
+    fn warnings(source: &str) -> Vec<String> {
+        let program = AstTransformer::new().transform_ast_to_sm(parse_source(source));
+        uninitialized_reads(&program).iter().map(|warning| warning.to_string()).collect()
+    }
+
+    #[test]
+    fn test_uninitialized_reads() {
+        let source = "{
+    a = 1
+    if (a) {
+        b = a
+    }
+    print(b)
+    print(a + c)
+    while (a) {
+        d = a
+        a = 0
+    }
+    print(d)
+}";
+        assert_eq!(vec![
+            "warning at line 6:5: variable `b` may be read before assignment",
+            "warning at line 7:5: variable `c` may be read before assignment",
+            "warning at line 12:5: variable `d` may be read before assignment",
+        ], warnings(source));
+    }
+
+    #[test]
+    fn test_no_warnings() {
+        assert!(warnings("{ a = 1; if (a) { b = 1 } else { b = 2 }; print(b); while (a) { a = a - 1 } }").is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+extern crate klang_lib;
+
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use clap::Parser;
+use klang_lib::binary::object::{link_objects, Object};
+
+#[derive(Parser)]
+#[command(version, about = "Links the object files of Klang modules into the code and the memory for the emulator", long_about = None)]
+struct Cli {
+    /// Object files produced by `compiler --emit object`, the imported modules run before the importing ones
+    #[arg(required = true, value_name = "OBJECT_FILE")]
+    objects: Vec<PathBuf>,
+
+    /// Output file for the linked code
+    #[arg(short, long, value_name = "CODE_BINARY")]
+    code: PathBuf,
+
+    /// Output file for the linked memory
+    #[arg(short, long, value_name = "MEMORY_BINARY")]
+    memory: PathBuf,
+}
+
+fn read_checks(file: &Path) {
+    if !file.exists() {
+        panic!("File {} is not exists", file.to_str().unwrap())
+    }
+    if !file.is_file() {
+        panic!("File {} is not a file", file.to_str().unwrap())
+    }
+}
+
+fn read_object(file: &Path) -> io::Result<Object> {
+    read_checks(file);
+    let text = std::fs::read_to_string(file)?;
+    Object::from_text(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", file.display(), e)))
+}
+
+fn main() -> io::Result<()> {
+    let cli = Cli::parse();
+    let objects = cli.objects.iter().map(|path| read_object(path)).collect::<io::Result<Vec<_>>>()?;
+    let (memory, code) = match link_objects(&objects) {
+        Ok(result) => result,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            std::process::exit(1)
+        }
+    };
+    File::create(&cli.code)?.write_all(&code)?;
+    File::create(&cli.memory)?.write_all(&memory)?;
+    Ok(())
+}
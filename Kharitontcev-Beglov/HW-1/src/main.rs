@@ -1,57 +1,278 @@
+use std::collections::HashSet;
 use std::fs::File;
 use std::io;
 use std::io::{Read, Write};
-use std::path::PathBuf;
-use clap::Parser;
+use std::path::{Path, PathBuf};
+use clap::{CommandFactory, Parser, ValueEnum};
+use clap::error::ErrorKind;
+use klang_lib::binary::debug_info::DebugInfo;
+use klang_lib::binary::instructions::Arithmetic;
+use klang_lib::binary::object::ModuleInterface;
+use klang_lib::binary::register::RegisterTransformer;
+use klang_lib::binary::ssa::SsaTransformer;
 use klang_lib::binary::SMTransformer;
 use klang_lib::lexer::Lexer;
 use klang_lib::lexer::tokens::Tokens;
+use klang_lib::parser::ast::{Ident, Module};
+use klang_lib::parser::simplify::simplify_program;
+use klang_lib::ssa;
+use klang_lib::ssa::lower::lower_program;
+use klang_lib::stack_machine::cfg::ControlFlowGraph;
+use klang_lib::stack_machine::peephole;
+use klang_lib::stack_machine::sm::StackCommand;
+use klang_lib::stack_machine::text::{parse_program, program_to_text};
 use klang_lib::stack_machine::transform::AstTransformer;
+use klang_lib::stack_machine::warnings::uninitialized_reads_with;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum Emit {
+    /// Binary code and memory for the emulator
+    Binary,
+    /// Textual stack machine code
+    Sm,
+    /// Control-flow graph of the stack machine code in the Graphviz DOT format
+    Cfg,
+    /// Textual SSA form, only with `--backend ssa`
+    Ssa,
+    /// Relocatable object file of the module for `klang-ld`, only with the stack backend
+    Object,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum Backend {
+    /// Compiles through the stack machine code, all intermediate values are kept in the memory stack
+    Stack,
+    /// Compiles the AST directly, expressions are evaluated in registers
+    Register,
+    /// Compiles through the SSA form, which is optimized with -O1, all values are kept in registers while they fit
+    Ssa,
+}
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    /// Set the source code file to compile
+    /// Set the source code file to compile. Files with `.sm` extension are read as the textual stack machine code
     #[arg(short, long, value_name = "SOURCE_CODE_FILE")]
     input: PathBuf,
     
     /// Output file for the compiled code
     #[arg(short, long, value_name = "CODE_BINARY")]
-    code: PathBuf,
+    code: Option<PathBuf>,
     
     /// Output file for the compiled memory
     #[arg(short, long, value_name = "MEMORY_BINARY")]
-    memory: PathBuf
+    memory: Option<PathBuf>,
+
+    /// Kind of the compiler output
+    #[arg(long, value_enum, default_value_t = Emit::Binary)]
+    emit: Emit,
+
+    /// Output file for `--emit sm`, `--emit cfg`, `--emit ssa` and `--emit object`, the output is printed into stdout if not set
+    #[arg(short, long, value_name = "OUTPUT_FILE")]
+    output: Option<PathBuf>,
+
+    /// Output file for the debug information (statement positions and variable addresses)
+    #[arg(short, long, value_name = "DEBUG_INFO")]
+    debug_info: Option<PathBuf>,
+
+    /// Code generator producing the binary
+    #[arg(long, value_enum, default_value_t = Backend::Stack)]
+    backend: Backend,
+
+    /// Optimization level: 0 disables optimizations, 1 enables constant folding, the peephole optimizer of the stack machine code,
+    /// keeping variables in registers and the optimizations of the SSA form
+    #[arg(short = 'O', value_name = "LEVEL", default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=1))]
+    opt_level: u8,
+
+    /// Print with the MARS-compatible `syscall` services instead of the print pseudo-instructions, with the stack backend only
+    #[arg(long)]
+    syscalls: bool,
+
+    /// Compile `+`, `-` and unary minus with `add` and `sub`, which stop the program on the signed overflow,
+    /// instead of the wrapping `addu` and `subu`
+    #[arg(long)]
+    checked_arithmetic: bool,
 }
 
-fn read_checks(file: &PathBuf) {
+fn read_checks(file: &Path) {
     if !file.exists() {
-        panic!("File {} is not exists", file.as_path().to_str().unwrap())
+        panic!("File {} is not exists", file.to_str().unwrap())
     }
     if !file.is_file() {
-        panic!("File {} is not a file", file.as_path().to_str().unwrap())
+        panic!("File {} is not a file", file.to_str().unwrap())
+    }
+}
+
+fn parse_source(source_code: &[u8]) -> Module {
+    let (left, (lexed, spans)) = Lexer::lex_tokens_with_spans(source_code).unwrap();
+    if !left.is_empty() {
+        panic!("Not all source code parsed!");
+    }
+    let (_, parsed) = klang_lib::parser::Parser::parse_module(Tokens::with_spans(&lexed, &spans)).unwrap();
+    parsed
+}
+
+fn module_name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().into_owned()
+}
+
+/// Reads the imported files, relative to the input one, to know the variables they export
+fn module_interface(input: &Path, module: &Module) -> io::Result<ModuleInterface> {
+    let mut interface = ModuleInterface {
+        name: module_name(input),
+        exports: module.exports.clone(),
+        ..ModuleInterface::default()
+    };
+    for import in &module.imports {
+        let path = input.parent().unwrap_or(Path::new("")).join(import);
+        read_checks(&path);
+        let mut source_code_buffer = Vec::new();
+        File::open(&path)?.read_to_end(&mut source_code_buffer)?;
+        interface.externals.extend(parse_source(&source_code_buffer).exports);
+        interface.imports.push(module_name(&path));
+    }
+    Ok(interface)
+}
+
+fn report_warnings(stack_machine: &[StackCommand], externals: &HashSet<Ident>) {
+    for warning in uninitialized_reads_with(stack_machine, externals) {
+        eprintln!("{}", warning);
     }
 }
 
+fn write_text(cli: &Cli, text: &str) -> io::Result<()> {
+    match &cli.output {
+        Some(output) => File::create(output)?.write_all(text.as_bytes()),
+        None => io::stdout().write_all(text.as_bytes()),
+    }
+}
+
+fn write_binary(cli: &Cli, memory: &[u8], code: &[u8], debug_info: &DebugInfo) -> io::Result<()> {
+    let (Some(code_path), Some(memory_path)) = (&cli.code, &cli.memory) else {
+        Cli::command()
+            .error(ErrorKind::MissingRequiredArgument, "--code and --memory are required to emit the binary")
+            .exit()
+    };
+    let mut code_file = File::create(code_path)?;
+    code_file.write_all(code)?;
+    let mut memory_file = File::create(memory_path)?;
+    memory_file.write_all(memory)?;
+    if let Some(debug_info_path) = &cli.debug_info {
+        let mut debug_info_file = File::create(debug_info_path)?;
+        debug_info_file.write_all(debug_info.to_text().as_bytes())?;
+    }
+    Ok(())
+}
+
+fn arithmetic(cli: &Cli) -> Arithmetic {
+    if cli.checked_arithmetic { Arithmetic::Checked } else { Arithmetic::Wrapping }
+}
+
+fn stack_machine_transformer(cli: &Cli) -> SMTransformer {
+    let transformer = if cli.opt_level >= 1 {
+        SMTransformer::with_variable_registers()
+    } else {
+        SMTransformer::new()
+    };
+    let transformer = transformer.with_arithmetic(arithmetic(cli));
+    if cli.syscalls { transformer.with_syscalls() } else { transformer }
+}
+
 fn main() -> io::Result<()> {
-    let mut ast_transformer = AstTransformer::new();
-    let mut stack_machine_transformer = SMTransformer::new();
-    
     let cli = Cli::parse();
     read_checks(&cli.input);
-    let mut input_file = File::open(cli.input)?;
+    let is_sm_input = cli.input.extension().is_some_and(|ext| ext == "sm");
+    let mut input_file = File::open(&cli.input)?;
     let mut source_code_buffer = Vec::new();
     input_file.read_to_end(&mut source_code_buffer)?;
-    let (left, lexed) = Lexer::lex_tokens(source_code_buffer.as_slice()).unwrap();
-    if !left.is_empty() {
-        panic!("Not all source code parsed!");
+
+    if cli.emit == Emit::Object && cli.backend != Backend::Stack {
+        Cli::command()
+            .error(ErrorKind::ArgumentConflict, "object files are compiled with the stack backend only")
+            .exit()
     }
-    let (_, parsed) = klang_lib::parser::Parser::parse(Tokens::new(&lexed)).unwrap();
-    let stack_machine = ast_transformer.transform_ast_to_sm(parsed);
-    let (memory, code) = stack_machine_transformer.transform_program(&stack_machine);
-    
-    let mut code_file = File::create(cli.code)?;
-    code_file.write_all(&code)?;
-    let mut memory_file = File::create(cli.memory)?;
-    memory_file.write_all(&memory)
+    if cli.syscalls && (cli.backend != Backend::Stack || cli.emit == Emit::Object) {
+        Cli::command()
+            .error(ErrorKind::ArgumentConflict, "syscalls are used by the stack backend for whole programs only, not for object files")
+            .exit()
+    }
+    if cli.backend == Backend::Register && (is_sm_input || cli.emit != Emit::Binary) {
+        Cli::command()
+            .error(ErrorKind::ArgumentConflict, "the register backend compiles Klang source code without the stack machine code")
+            .exit()
+    }
+    let ssa_conflict = match cli.backend {
+        Backend::Ssa => is_sm_input || !matches!(cli.emit, Emit::Binary | Emit::Ssa),
+        _ => cli.emit == Emit::Ssa,
+    };
+    if ssa_conflict {
+        Cli::command()
+            .error(ErrorKind::ArgumentConflict, "the SSA form is built from Klang source code with `--backend ssa` only")
+            .exit()
+    }
+
+    let mut interface = ModuleInterface { name: module_name(&cli.input), ..ModuleInterface::default() };
+    let mut stack_machine = if is_sm_input {
+        let text = String::from_utf8(source_code_buffer)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let stack_machine = parse_program(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        report_warnings(&stack_machine, &interface.externals);
+        stack_machine
+    } else {
+        let module = parse_source(&source_code_buffer);
+        if !module.imports.is_empty() && cli.emit == Emit::Binary {
+            Cli::command()
+                .error(ErrorKind::ArgumentConflict, "modules with imports are compiled with `--emit object` and linked with `klang-ld`")
+                .exit()
+        }
+        interface = module_interface(&cli.input, &module)?;
+        let mut program = module.body;
+        // Warnings are computed before the simplification, which removes the reads in the branches it resolves
+        let unsimplified = AstTransformer::new().transform_ast_to_sm(program.clone());
+        report_warnings(&unsimplified, &interface.externals);
+        if cli.opt_level >= 1 {
+            program = simplify_program(program, arithmetic(&cli));
+        }
+        if cli.backend == Backend::Register {
+            let mut register_transformer = RegisterTransformer::new().with_arithmetic(arithmetic(&cli));
+            let (memory, code) = register_transformer.transform_program(&program)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            return write_binary(&cli, &memory, &code, register_transformer.debug_info());
+        }
+        if cli.backend == Backend::Ssa {
+            let mut function = lower_program(&program);
+            if cli.opt_level >= 1 {
+                ssa::optimize::optimize(&mut function, arithmetic(&cli));
+            }
+            if cli.emit == Emit::Ssa {
+                return write_text(&cli, &function.to_string());
+            }
+            let mut ssa_transformer = SsaTransformer::new().with_arithmetic(arithmetic(&cli));
+            let (memory, code) = ssa_transformer.transform_program(&function)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            return write_binary(&cli, &memory, &code, ssa_transformer.debug_info());
+        }
+        if cli.opt_level >= 1 { AstTransformer::new().transform_ast_to_sm(program) } else { unsimplified }
+    };
+    if cli.opt_level >= 1 {
+        stack_machine = peephole::optimize(stack_machine);
+    }
+
+    if cli.emit == Emit::Object {
+        let object = stack_machine_transformer(&cli).transform_object(&stack_machine, &interface)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        return write_text(&cli, &object.to_text());
+    }
+    if cli.emit != Emit::Binary {
+        let text = match cli.emit {
+            Emit::Cfg => ControlFlowGraph::build(&stack_machine).to_dot(),
+            _ => program_to_text(&stack_machine),
+        };
+        return write_text(&cli, &text);
+    }
+
+    let mut stack_machine_transformer = stack_machine_transformer(&cli);
+    let (memory, code) = stack_machine_transformer.transform_program(&stack_machine)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    write_binary(&cli, &memory, &code, stack_machine_transformer.debug_info())
 }
@@ -0,0 +1,126 @@
+//! Checks the conditional jumps of the stack backend: close targets are reached with a single PC-relative branch,
+//! targets out of the 16-bit offset range fall back to an inverted branch over a jump. Jumps across large programs
+//! of all the backends should land on the instructions or right after the code. The data offsets of `lw` and `sw`
+//! are limited to 16 bits as well, larger data is a compile error.
+
+mod common;
+
+use std::time::Duration;
+use klang_lib::binary::link::Linker;
+use klang_lib::binary::SMTransformer;
+use klang_lib::lexer::tokens::Span;
+use klang_lib::parser::ast::{Block, Expr, Spanned, Stmt};
+use klang_lib::stack_machine::sm::StackCommand;
+use klang_lib::stack_machine::transform::AstTransformer;
+
+use common::{Backend, BACKENDS};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Numbers of the J-type instructions and of the branches in the code
+fn count_jumps(code: &[u8]) -> (usize, usize) {
+    let opcodes: Vec<u32> = code.chunks(4).map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()) >> 26).collect();
+    (opcodes.iter().filter(|opcode| **opcode == 2).count(), opcodes.iter().filter(|opcode| matches!(opcode, 4 | 5)).count())
+}
+
+/// Offsets of the targets of the J-type instructions from the start of the code
+fn jump_targets(code: &[u8]) -> Vec<usize> {
+    code.chunks(4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        .filter(|instr| instr >> 26 == 2)
+        .map(|instr| ((instr & 0x3ffffff) << 2) as usize - Linker::DEFAULT_LOAD_ADDRESS as usize)
+        .collect()
+}
+
+fn compile(source: &str) -> (Vec<StackCommand>, Vec<u8>, Vec<u8>) {
+    let program = common::parse(source.as_bytes()).unwrap();
+    let stack_machine = AstTransformer::new().transform_ast_to_sm(program);
+    let (memory, code) = SMTransformer::new().transform_program(&stack_machine).unwrap();
+    (stack_machine, memory, code)
+}
+
+fn count_commands(program: &[StackCommand], predicate: fn(&StackCommand) -> bool) -> usize {
+    program.iter().filter(|cmd| predicate(cmd)).count()
+}
+
+#[test]
+fn short_branches() {
+    let (program, memory, code) = compile("{ n = 5; while (n) { if (n & 1) { print(n) } else { print(0 - n) }; n = n - 1 } }");
+    let (jumps, branches) = count_jumps(&code);
+    assert_eq!(count_commands(&program, |cmd| matches!(cmd, StackCommand::Jmp(_))), jumps);
+    assert_eq!(count_commands(&program, |cmd| matches!(cmd, StackCommand::ConditionalJump(_, _))), branches);
+    assert_eq!(Ok(vec![5, -4, 3, -2, 1]), common::run_emulator(&memory, &code, TIMEOUT));
+}
+
+#[test]
+fn long_branches() {
+    // Each statement takes more than 20 instructions, so the body is out of the branch range
+    let body = "b = b + 1\n".repeat(2000);
+    let source = format!("{{ n = 2; while (n) {{ if (n - 1) {{ {} }}; n = n - 1 }}; print(b) }}", body);
+    let (program, memory, code) = compile(&source);
+    let (jumps, branches) = count_jumps(&code);
+    let conditional_jumps = count_commands(&program, |cmd| matches!(cmd, StackCommand::ConditionalJump(_, _)));
+    // Both the jump over the `if` body and the jump back to the loop start are long
+    assert_eq!(conditional_jumps, branches);
+    assert_eq!(count_commands(&program, |cmd| matches!(cmd, StackCommand::Jmp(_))) + 2, jumps);
+    assert_eq!(Ok(vec![2000]), common::run_emulator(&memory, &code, TIMEOUT));
+}
+
+#[test]
+fn many_constants() {
+    // The offsets of the constants do not fit into 8 bits
+    let source = format!("{{ {} }}", (1..=100).map(|x| format!("print({})", x * 3)).collect::<Vec<_>>().join("\n"));
+    let (_, memory, code) = compile(&source);
+    let expected: Vec<i32> = (1..=100).map(|x| x * 3).collect();
+    assert_eq!(Ok(expected), common::run_emulator(&memory, &code, TIMEOUT));
+}
+
+#[test]
+fn data_offset_boundary() {
+    // Length of the printed string which puts the last constant at the offset 0x7ffc. The stack and the register
+    // backends store 4, the string and two offsets of the layout, the SSA backend stores the string and one offset
+    for (backend, length) in [(Backend::Stack, 0x7ff0), (Backend::Register, 0x7ff0), (Backend::Ssa, 0x7ff8)] {
+        let text = "a".repeat(length);
+        let program = common::parse(format!("{{ prints \"{}\" }}", text).as_bytes()).unwrap();
+        let (memory, code) = common::compile_with(program, 0, backend);
+        assert_eq!(0x8000, memory.len(), "{:?}", backend);
+        assert_eq!(Ok(text), common::run_emulator_text(&memory, &code, TIMEOUT), "{:?}", backend);
+
+        let program = common::parse(format!("{{ prints \"{}\" }}", "a".repeat(length + 4)).as_bytes()).unwrap();
+        assert_eq!(
+            Err(String::from("Data offset 0x8000 does not fit into the 16-bit offset")),
+            common::try_compile_with(program, 0, backend).map(|_| ()),
+            "{:?}", backend
+        );
+    }
+}
+
+#[test]
+fn too_many_constants() {
+    // `{ print(1); ...; print(9000) }`, built directly since parsing so many statements takes long
+    let program: Block = (1..=9000)
+        .map(|x| Spanned::new(Stmt::Print(Expr::IntLiteral(x)), Span { line: 1, column: 1 }))
+        .collect();
+    for backend in BACKENDS {
+        for opt_level in [0, 1] {
+            let result = common::try_compile_with(program.clone(), opt_level, backend);
+            assert!(result.is_err_and(|e| e.contains("16-bit offset")), "{:?} -O{}", backend, opt_level);
+        }
+    }
+}
+
+#[test]
+fn far_jumps() {
+    let body = "b = b + n\n".repeat(3000);
+    let source = format!("{{ n = 3; while (n) {{ if (n & 1) {{ {} }} else {{ print(b) }}; n = n - 1 }}; print(b) }}", body);
+    let program = common::parse(source.as_bytes()).unwrap();
+    for backend in BACKENDS {
+        for opt_level in [0, 1] {
+            let (memory, code) = common::compile_with(program.clone(), opt_level, backend);
+            let targets = jump_targets(&code);
+            assert!(!targets.is_empty());
+            assert!(targets.iter().all(|target| *target <= code.len()), "{:?} -O{}: {:?}", backend, opt_level, targets);
+            assert_eq!(Ok(vec![9000, 12000]), common::run_emulator(&memory, &code, TIMEOUT), "{:?} -O{}", backend, opt_level);
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Helpers shared by the integration tests: compilation of Klang programs and running them in the emulator binary
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use klang_lib::binary::instructions::Arithmetic;
+use klang_lib::binary::register::RegisterTransformer;
+use klang_lib::binary::ssa::SsaTransformer;
+use klang_lib::binary::SMTransformer;
+use klang_lib::lexer::Lexer;
+use klang_lib::lexer::tokens::Tokens;
+use klang_lib::parser::ast::Block;
+use klang_lib::parser::Parser;
+use klang_lib::parser::simplify::simplify_program;
+use klang_lib::ssa::lower::lower_program;
+use klang_lib::ssa::optimize::optimize;
+use klang_lib::stack_machine::peephole;
+use klang_lib::stack_machine::transform::AstTransformer;
+
+pub fn parse(source: &[u8]) -> Result<Block, String> {
+    let (left, (lexed, spans)) = Lexer::lex_tokens_with_spans(source).map_err(|e| e.to_string())?;
+    if !left.is_empty() {
+        return Err(String::from("Not all source code lexed"));
+    }
+    let (_, parsed) = Parser::parse(Tokens::with_spans(&lexed, &spans)).map_err(|e| match e {
+        nom::Err::Error(e) | nom::Err::Failure(e) => format!("Syntax error at {}", e.input.span()),
+        nom::Err::Incomplete(_) => String::from("Unexpected end of the source code"),
+    })?;
+    Ok(parsed)
+}
+
+/// Code generator of the compiler, as `--backend` option
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Backend {
+    Stack,
+    Register,
+    Ssa,
+}
+
+pub const BACKENDS: [Backend; 3] = [Backend::Stack, Backend::Register, Backend::Ssa];
+
+/// Compiles the program and returns the memory and code binaries
+pub fn compile(program: Block) -> (Vec<u8>, Vec<u8>) {
+    compile_with(program, 0, Backend::Stack)
+}
+
+/// Compiles the program as `compiler -O<opt_level> --backend <backend>` does
+pub fn compile_with(program: Block, opt_level: u8, backend: Backend) -> (Vec<u8>, Vec<u8>) {
+    try_compile_with(program, opt_level, backend).unwrap()
+}
+
+/// Same as `compile_with`, but the link errors are returned
+pub fn try_compile_with(program: Block, opt_level: u8, backend: Backend) -> Result<(Vec<u8>, Vec<u8>), String> {
+    try_compile_with_arithmetic(program, opt_level, backend, Arithmetic::default())
+}
+
+pub fn try_compile_with_arithmetic(
+    mut program: Block,
+    opt_level: u8,
+    backend: Backend,
+    arithmetic: Arithmetic,
+) -> Result<(Vec<u8>, Vec<u8>), String> {
+    if opt_level >= 1 {
+        program = simplify_program(program, arithmetic);
+    }
+    let result = match backend {
+        Backend::Register => RegisterTransformer::new().with_arithmetic(arithmetic).transform_program(&program),
+        Backend::Ssa => {
+            let mut function = lower_program(&program);
+            if opt_level >= 1 {
+                optimize(&mut function, arithmetic);
+            }
+            SsaTransformer::new().with_arithmetic(arithmetic).transform_program(&function)
+        }
+        Backend::Stack => {
+            let mut stack_machine = AstTransformer::new().transform_ast_to_sm(program);
+            if opt_level >= 1 {
+                stack_machine = peephole::optimize(stack_machine);
+                SMTransformer::with_variable_registers().with_arithmetic(arithmetic).transform_program(&stack_machine)
+            } else {
+                SMTransformer::new().with_arithmetic(arithmetic).transform_program(&stack_machine)
+            }
+        }
+    };
+    result.map_err(|e| e.to_string())
+}
+
+pub fn fresh_dir(name: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+        .join(name)
+        .join(COUNTER.fetch_add(1, Ordering::Relaxed).to_string());
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn parse_output(output: &str) -> Result<Vec<i32>, String> {
+    output.lines()
+        .map(|line| line.trim().parse().map_err(|_| format!("Unexpected output line {:?}", line)))
+        .collect()
+}
+
+/// Statistics printed by the emulator with `--stats`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct EmulatorStats {
+    pub cycles: u64,
+    pub instructions: u64,
+}
+
+fn read_in_background<R: Read + Send + 'static>(mut reader: R) -> thread::JoinHandle<std::io::Result<String>> {
+    thread::spawn(move || {
+        let mut output = String::new();
+        reader.read_to_string(&mut output).map(|_| output)
+    })
+}
+
+/// Runs the `mips_emulator` binary with extra arguments and the input, returns its stdout and stderr.
+/// The process is killed if it runs longer than `timeout`, the error of a failed process has its stderr
+fn run_emulator_process(memory: &[u8], code: &[u8], args: &[&str], input: &[u8], timeout: Duration) -> Result<(String, String), String> {
+    let dir = fresh_dir("emulator");
+    let code_path = dir.join("program.code");
+    let memory_path = dir.join("program.mem");
+    fs::write(&code_path, code).unwrap();
+    fs::write(&memory_path, memory).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mips_emulator"))
+        .arg("-c").arg(&code_path)
+        .arg("-m").arg(&memory_path)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The emulator may not read the whole input, then writing the rest of it fails, which is fine
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    thread::spawn(move || stdin.write_all(&input));
+    let stdout = read_in_background(child.stdout.take().unwrap());
+    let stderr = read_in_background(child.stderr.take().unwrap());
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            child.wait().unwrap();
+            return Err(format!("Emulator timed out after {:?}", timeout));
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+    let output = stdout.join().unwrap().map_err(|e| e.to_string())?;
+    let errors = stderr.join().unwrap().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("Emulator failed with {}\n{}", status, errors));
+    }
+    Ok((output, errors))
+}
+
+/// Runs the `mips_emulator` binary, the process is killed if it runs longer than `timeout`
+pub fn run_emulator(memory: &[u8], code: &[u8], timeout: Duration) -> Result<Vec<i32>, String> {
+    let (output, _) = run_emulator_process(memory, code, &[], &[], timeout)?;
+    parse_output(&output)
+}
+
+/// Runs the `mips_emulator` binary and returns the printed text as is
+pub fn run_emulator_text(memory: &[u8], code: &[u8], timeout: Duration) -> Result<String, String> {
+    run_emulator_with_input(memory, code, &[], timeout)
+}
+
+/// Runs the `mips_emulator` binary with the input in its stdin and returns the printed text as is
+pub fn run_emulator_with_input(memory: &[u8], code: &[u8], input: &[u8], timeout: Duration) -> Result<String, String> {
+    run_emulator_process(memory, code, &[], input, timeout).map(|(output, _)| output)
+}
+
+/// Runs the `mips_emulator` binary with `--stats`
+pub fn run_emulator_with_stats(memory: &[u8], code: &[u8], timeout: Duration) -> Result<(Vec<i32>, EmulatorStats), String> {
+    let (output, errors) = run_emulator_process(memory, code, &["--stats"], &[], timeout)?;
+    let stat = |name: &str| errors.lines()
+        .find_map(|line| line.strip_prefix(name))
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or_else(|| format!("No {:?} in the emulator stderr", name));
+    let stats = EmulatorStats { cycles: stat("Cycles:")?, instructions: stat("Instructions:")? };
+    Ok((parse_output(&output)?, stats))
+}
+
+/// All files with the extension in the directory, sorted by name
+pub fn files_with_extension(dir: &Path, extension: &str) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+    let mut result: Vec<PathBuf> = entries
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .collect();
+    result.sort();
+    result
+}
+
+pub fn tests_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+/// Parsed programs in the test directories with their paths and the expected output from the `.ans` files
+pub fn test_programs(dirs: &[&str]) -> Vec<(PathBuf, Block, Vec<i32>)> {
+    dirs.iter()
+        .flat_map(|dir| files_with_extension(&tests_dir().join(dir), "klang"))
+        .map(|path| {
+            let program = parse(&fs::read(&path).unwrap()).unwrap();
+            let expected = parse_output(&fs::read_to_string(path.with_extension("ans")).unwrap()).unwrap();
+            (path, program, expected)
+        })
+        .collect()
+}
+
+/// Runs the programs in `simple` and `complex` compiled with both backends, checks their output and returns the
+/// statistics of both runs for each program
+pub fn compare_backends(opt_level: u8, first: Backend, second: Backend, timeout: Duration) -> Vec<(PathBuf, EmulatorStats, EmulatorStats)> {
+    test_programs(&["simple", "complex"]).into_iter()
+        .map(|(path, program, expected)| {
+            let (memory, code) = compile_with(program.clone(), opt_level, first);
+            let (first_output, first_stats) = run_emulator_with_stats(&memory, &code, timeout).unwrap();
+            let (memory, code) = compile_with(program, opt_level, second);
+            let (second_output, second_stats) = run_emulator_with_stats(&memory, &code, timeout).unwrap();
+            assert_eq!(expected, first_output, "{:?} backend output of {}", first, path.display());
+            assert_eq!(expected, second_output, "{:?} backend output of {}", second, path.display());
+            (path, first_stats, second_stats)
+        })
+        .collect()
+}
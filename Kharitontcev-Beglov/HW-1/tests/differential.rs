@@ -0,0 +1,97 @@
+//! Differential testing of the compiler: random programs are compiled and run in the emulator, the output is compared
+//! with the AST interpreter. A failing program is shrunk and saved into `tests/regressions`, which are checked by
+//! the `regressions` test afterward. Every program is compiled by all backends, both without and with optimizations.
+//!
+//! The number of programs and the first seed could be set with `KLANG_FUZZ_ITERATIONS` and `KLANG_FUZZ_SEED`.
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::time::Duration;
+use klang_lib::fuzz::generator::{GeneratorConfig, ProgramGenerator};
+use klang_lib::fuzz::shrink::shrink;
+use klang_lib::parser::ast::Block;
+use klang_lib::parser::interpreter::AstInterpreter;
+use klang_lib::parser::printer::program_to_source;
+use common::{Backend, BACKENDS};
+
+const STEP_LIMIT: usize = 100_000;
+const EMULATOR_TIMEOUT: Duration = Duration::from_secs(10);
+const OPT_LEVELS: [u8; 2] = [0, 1];
+
+/// All combinations of the compiler options which are checked
+fn configurations() -> impl Iterator<Item=(u8, Backend)> {
+    OPT_LEVELS.into_iter().flat_map(|level| BACKENDS.into_iter().map(move |backend| (level, backend)))
+}
+
+fn env_or(name: &str, default: u64) -> u64 {
+    env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+/// Output of the reference interpreter, `None` if the program does not terminate in time
+fn reference_output(program: &Block) -> Option<Vec<i32>> {
+    let mut interpreter = AstInterpreter::with_step_limit(STEP_LIMIT);
+    interpreter.run(program).ok().map(|_| interpreter.output().to_vec())
+}
+
+/// Goes through the whole pipeline starting from the source code, as the `compiler` binary does
+fn compiled_output(program: &Block, opt_level: u8, backend: Backend) -> Result<Vec<i32>, String> {
+    let source = program_to_source(program);
+    let parsed = common::parse(source.as_bytes())?;
+    let (memory, code) = common::compile_with(parsed, opt_level, backend);
+    common::run_emulator(&memory, &code, EMULATOR_TIMEOUT)
+}
+
+fn is_failing(program: &Block) -> bool {
+    match reference_output(program) {
+        None => false,
+        Some(expected) => configurations().any(|(level, backend)| compiled_output(program, level, backend) != Ok(expected.clone())),
+    }
+}
+
+fn save_regression(name: &str, program: &Block) -> String {
+    let dir = common::tests_dir().join("regressions");
+    fs::create_dir_all(&dir).unwrap();
+    let expected = reference_output(program).unwrap();
+    let answer: Vec<String> = expected.iter().map(|x| x.to_string()).collect();
+    fs::write(dir.join(format!("{}.klang", name)), program_to_source(program)).unwrap();
+    fs::write(dir.join(format!("{}.ans", name)), answer.join("\n") + "\n").unwrap();
+    dir.join(format!("{}.klang", name)).display().to_string()
+}
+
+#[test]
+fn compiler_agrees_with_interpreter() {
+    let iterations = env_or("KLANG_FUZZ_ITERATIONS", 100);
+    let first_seed = env_or("KLANG_FUZZ_SEED", 0);
+    let mut failures = Vec::new();
+    for seed in first_seed..first_seed + iterations {
+        // Every other program uses more variables than there are registers for them
+        let config = GeneratorConfig { variables: if seed % 2 == 0 { 4 } else { 8 }, ..GeneratorConfig::default() };
+        let program = ProgramGenerator::new(seed, config).generate();
+        if !is_failing(&program) {
+            continue;
+        }
+        let shrunk = shrink(&program, is_failing);
+        let path = save_regression(&format!("fuzz_{}", seed), &shrunk);
+        let outputs: Vec<_> = configurations().map(|(level, backend)| compiled_output(&shrunk, level, backend)).collect();
+        failures.push(format!("seed {}: {:?}, saved into {}", seed, outputs, path));
+    }
+    assert!(failures.is_empty(), "Compiled programs differ from the interpreter:\n{}", failures.join("\n"));
+}
+
+#[test]
+fn regressions() {
+    for path in common::files_with_extension(&common::tests_dir().join("regressions"), "klang") {
+        let program = common::parse(&fs::read(&path).unwrap()).unwrap();
+        let expected = common::parse_output(&fs::read_to_string(path.with_extension("ans")).unwrap()).unwrap();
+        assert_eq!(Some(expected.clone()), reference_output(&program), "Interpreter output of {}", path.display());
+        for (level, backend) in configurations() {
+            assert_eq!(
+                Ok(expected.clone()),
+                compiled_output(&program, level, backend),
+                "Compiled output of {} with -O{} by {:?} backend", path.display(), level, backend
+            );
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Exit status of `mips_emulator`: the exit code of `halt` becomes the status of the process,
+//! and a program exceeding `--cycle-limit`, overflowing, accessing an address outside of the memory map or failing to
+//! read its input is stopped with an error. Also the memory map the coprocessor, the microcode, the state diagram and the snapshot options.
+
+mod common;
+
+use std::fs;
+use std::process::{Command, Output};
+use klang_lib::binary::instructions::{Cop0, Instr, IType, JType, RType, transform_to_bytes};
+use klang_lib::binary::link::Linker;
+use klang_lib::emulator::microcode::Microprogram;
+
+fn run(code: &[Instr], memory: &[u8], args: &[&str]) -> Output {
+    let dir = common::fresh_dir("emulator_cli");
+    let code: Vec<u8> = code.iter().flat_map(|instr| transform_to_bytes(instr).to_be_bytes()).collect();
+    fs::write(dir.join("program.code"), code).unwrap();
+    fs::write(dir.join("program.mem"), memory).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_mips_emulator"))
+        .arg("-c").arg(dir.join("program.code"))
+        .arg("-m").arg(dir.join("program.mem"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn exit_code() {
+    let code = [
+        Instr::I(IType::Lw { rs: 0, rt: 8, imm: 0 }),
+        Instr::R(RType { rs: 8, rt: 0, rd: 0, funct: 0 }),
+        Instr::R(RType::halt(8)),
+        Instr::R(RType { rs: 0, rt: 0, rd: 0, funct: 0 }),
+    ];
+    let output = run(&code, &42i32.to_be_bytes(), &[]);
+    assert_eq!(Some(42), output.status.code());
+    assert_eq!("42\n", String::from_utf8_lossy(&output.stdout));
+
+    // Running past the end exits with zero
+    let output = run(&code[..2], &42i32.to_be_bytes(), &[]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn cycle_limit() {
+    let code = [Instr::J(JType::Jmp { address: Linker::DEFAULT_LOAD_ADDRESS >> 2 })];
+    let output = run(&code, &[0; 4], &["--cycle-limit", "1000"]);
+    assert_eq!(Some(124), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Cycle limit of 1000 exceeded at pc=0x400000"));
+}
+
+#[test]
+fn overflow() {
+    let code = [
+        Instr::I(IType::Lw { rs: 0, rt: 8, imm: 0 }),
+        Instr::R(RType { rs: 8, rt: 8, rd: 9, funct: RType::ADD }),
+    ];
+    let output = run(&code, &i32::MAX.to_be_bytes(), &[]);
+    assert_eq!(Some(136), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Arithmetic overflow at pc=0x400004"));
+}
+
+#[test]
+fn invalid_input() {
+    // Reads an integer with the service 5
+    let code = [Instr::I(IType::Lw { rs: 0, rt: 2, imm: 0 }), Instr::R(RType::syscall())];
+    let input = common::fresh_dir("emulator_cli").join("input.txt");
+    fs::write(&input, "x\n").unwrap();
+    let output = run(&code, &5i32.to_be_bytes(), &["--input", input.to_str().unwrap()]);
+    assert_eq!(Some(65), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid integer in the input at pc=0x400004"));
+}
+
+#[test]
+fn address_error() {
+    let code = [Instr::I(IType::Lw { rs: 0, rt: 8, imm: 0x8000 })];
+    let output = run(&code, &[0; 4], &[]);
+    assert_eq!(Some(139), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Address 0xffff8000 is outside of the memory map at pc=0x400000"));
+}
+
+#[test]
+fn memory_map() {
+    let code = [Instr::J(JType::Jmp { address: 0x20_0000 >> 2 })];
+    let output = run(&code, &[0; 4], &["--cycle-limit", "100", "--text-base", "0x200000"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Cycle limit of 100 exceeded at pc=0x200000"));
+
+    let output = run(&code, &[0; 4], &["--text-base", "0"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("The text and data segments overlap"));
+}
+
+#[test]
+fn exception_handler() {
+    // The handler at the third instruction prints the cause of the syscall exception
+    let code = [
+        Instr::R(RType::syscall()),
+        Instr::R(RType::halt(0)),
+        Instr::C0(Cop0::Mfc0 { rt: 8, rd: 13 }),
+        Instr::R(RType { rs: 8, rt: 0, rd: 0, funct: 0 }),
+    ];
+    let handler = (Linker::DEFAULT_LOAD_ADDRESS + 8).to_string();
+    let output = run(&code, &[0; 4], &["--exception-handler", &handler, "--timer", "1000"]);
+    assert!(output.status.success());
+    assert_eq!("32\n", String::from_utf8_lossy(&output.stdout));
+
+    // Without the coprocessor $2 = 0 is an unknown service
+    let output = run(&code, &[0; 4], &[]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown syscall service 0"));
+    let output = run(&code, &[0; 4], &["--timer", "1000"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn microcode() {
+    let dir = common::fresh_dir("emulator_cli_microcode");
+    let code = [Instr::I(IType::Lw { rs: 0, rt: 8, imm: 0 }), Instr::R(RType { rs: 8, rt: 0, rd: 0, funct: 0 })];
+    let valid = dir.join("valid.txt");
+    fs::write(&valid, Microprogram::DEFAULT).unwrap();
+    let output = run(&code, &42i32.to_be_bytes(), &["--microcode", valid.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!("42\n", String::from_utf8_lossy(&output.stdout));
+
+    // Without the branches the states are unreachable
+    let invalid = dir.join("invalid.txt");
+    let text: String = Microprogram::DEFAULT.lines().filter(|line| !line.contains("-> Branch")).map(|line| format!("{}\n", line)).collect();
+    fs::write(&invalid, text).unwrap();
+    let output = run(&code, &42i32.to_be_bytes(), &["--microcode", invalid.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("The state BranchEqual is unreachable"), "{}", stderr);
+    assert!(stderr.contains("The state BranchNotEqual is unreachable"), "{}", stderr);
+}
+
+#[test]
+fn dump_fsm() {
+    let dir = common::fresh_dir("emulator_cli_dump_fsm");
+    let dot = dir.join("fsm.dot");
+    let output = Command::new(env!("CARGO_BIN_EXE_mips_emulator")).arg("--dump-fsm").arg(&dot).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(Microprogram::default().to_dot(), fs::read_to_string(&dot).unwrap());
+
+    // The diagram of the loaded microcode, the program still runs
+    let microcode = dir.join("microcode.txt");
+    fs::write(&microcode, format!("{}\ndispatch opcode 1 -> Fetch", Microprogram::DEFAULT)).unwrap();
+    let code = [Instr::I(IType::Lw { rs: 0, rt: 8, imm: 0 }), Instr::R(RType { rs: 8, rt: 0, rd: 0, funct: 0 })];
+    let output = run(&code, &42i32.to_be_bytes(), &["--microcode", microcode.to_str().unwrap(), "--dump-fsm", dot.to_str().unwrap()]);
+    assert_eq!("42\n", String::from_utf8_lossy(&output.stdout));
+    assert!(fs::read_to_string(&dot).unwrap().contains("\"Decode\" -> \"Fetch\" [label=\"opcode 1\"];"));
+}
+
+#[test]
+fn snapshots() {
+    let dir = common::fresh_dir("emulator_cli_snapshots");
+    let snapshot = dir.join("snapshot.txt");
+    let snapshot = snapshot.to_str().unwrap();
+    // Prints 1 and then 2 with the halt in between
+    let code = [
+        Instr::I(IType::Lw { rs: 0, rt: 8, imm: 0 }),
+        Instr::R(RType { rs: 8, rt: 0, rd: 0, funct: 0 }),
+        Instr::R(RType { rs: 8, rt: 8, rd: 8, funct: RType::ADDU }),
+        Instr::R(RType { rs: 8, rt: 0, rd: 0, funct: 0 }),
+        Instr::R(RType::halt(8)),
+    ];
+    let output = run(&code, &1i32.to_be_bytes(), &["--cycle-limit", "7", "--save-snapshot", snapshot]);
+    assert_eq!(Some(124), output.status.code());
+    assert_eq!("1\n", String::from_utf8_lossy(&output.stdout));
+    assert!(fs::read_to_string(snapshot).unwrap().contains("cycles 7\n"));
+
+    let output = run(&code, &1i32.to_be_bytes(), &["--load-snapshot", snapshot, "--stats"]);
+    assert_eq!(Some(2), output.status.code());
+    assert_eq!("2\n", String::from_utf8_lossy(&output.stdout));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Cycles: 12"));
+
+    let output = run(&code, &1i32.to_be_bytes(), &["--load-snapshot", snapshot, "--exception-handler", "0"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("differ in the coprocessor 0"));
+}
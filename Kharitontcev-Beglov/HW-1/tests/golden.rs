@@ -0,0 +1,163 @@
+//! Golden end-to-end tests: every `X.klang` under `tests` with a sibling `X.ans` is compiled by all the backends,
+//! with and without the optimizations, and run in the emulator with `X.stdin` as the input if it exists.
+//! The printed text must be exactly `X.ans` and the exit code zero. A program with `X.err` instead must fail
+//! to compile or to run (exit with another code, overflow, fail to read the input or exceed the cycle limit) with the
+//! error containing the text of `X.err`.
+//!
+//! The runner has no test harness: `cargo test --test golden -- --bless` writes the output of the stack backend
+//! into the expectations instead of comparing, other arguments select the programs with the path containing them.
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use klang_lib::emulator::{Emulator, ExitStatus};
+use klang_lib::emulator::io::BufferInput;
+
+use common::BACKENDS;
+
+const CYCLE_LIMIT: u64 = 10_000_000;
+
+/// Programs in these directories import each other, they are compiled separately in `tests/modules.rs`
+const SEPARATELY_COMPILED: [&str; 1] = ["modules"];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Outcome {
+    Output(String),
+    Error(String),
+}
+
+impl Outcome {
+    /// Whether the outcome agrees with the expected one, the errors are expected to contain the text
+    fn matches(&self, expected: &Outcome) -> bool {
+        match (self, expected) {
+            (Outcome::Output(actual), Outcome::Output(expected)) => actual == expected,
+            (Outcome::Error(actual), Outcome::Error(expected)) => actual.contains(expected.trim()),
+            _ => false,
+        }
+    }
+}
+
+fn sources(dir: &Path, result: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            if !SEPARATELY_COMPILED.iter().any(|name| path.file_name().is_some_and(|file| file == *name)) {
+                sources(&path, result);
+            }
+        } else if path.extension().is_some_and(|ext| ext == "klang") {
+            result.push(path);
+        }
+    }
+}
+
+fn expected(source: &Path) -> Option<Outcome> {
+    if let Ok(output) = fs::read_to_string(source.with_extension("ans")) {
+        return Some(Outcome::Output(output));
+    }
+    fs::read_to_string(source.with_extension("err")).ok().map(Outcome::Error)
+}
+
+fn emulate(memory: Vec<u8>, code: Vec<u8>, input: &[u8]) -> Outcome {
+    let input = BufferInput::new(input);
+    let completion = Emulator::run_to_completion(code, memory, Box::new(input), Some(CYCLE_LIMIT));
+    match completion.status {
+        ExitStatus::Exited(0) => Outcome::Output(completion.output),
+        status => Outcome::Error(status.to_string()),
+    }
+}
+
+/// Outcomes of the program for every backend and optimization level, the first one is of the stack backend at `-O0`
+fn run(source: &Path) -> Vec<(String, Outcome)> {
+    let program = match common::parse(&fs::read(source).unwrap()) {
+        Ok(program) => program,
+        Err(error) => return vec![(String::from("parser"), Outcome::Error(error))],
+    };
+    let input = fs::read(source.with_extension("stdin")).unwrap_or_default();
+    let mut result = Vec::new();
+    for backend in BACKENDS {
+        for opt_level in [0, 1] {
+            let outcome = match common::try_compile_with(program.clone(), opt_level, backend) {
+                Ok((memory, code)) => emulate(memory, code, &input),
+                Err(error) => Outcome::Error(error),
+            };
+            result.push((format!("{:?} backend with -O{}", backend, opt_level), outcome));
+        }
+    }
+    result
+}
+
+/// Lines of both texts side by side from the first different one
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.split_inclusive('\n').collect();
+    let actual: Vec<&str> = actual.split_inclusive('\n').collect();
+    let first = expected.iter().zip(&actual).take_while(|(lhs, rhs)| lhs == rhs).count();
+    let mut result = format!("first difference at line {}\n", first + 1);
+    expected[first..].iter().for_each(|line| result.push_str(&format!("  - {:?}\n", line)));
+    actual[first..].iter().for_each(|line| result.push_str(&format!("  + {:?}\n", line)));
+    result
+}
+
+fn describe(expected: &Outcome, actual: &Outcome) -> String {
+    match (expected, actual) {
+        (Outcome::Output(expected), Outcome::Output(actual)) => diff(expected, actual),
+        (Outcome::Error(expected), Outcome::Error(actual)) => format!("expected an error with {:?}, got:\n{}\n", expected.trim(), actual),
+        (Outcome::Output(_), Outcome::Error(actual)) => format!("unexpected error:\n{}\n", actual),
+        (Outcome::Error(expected), Outcome::Output(actual)) => format!("expected an error with {:?}, printed:\n{}\n", expected.trim(), actual),
+    }
+}
+
+fn bless(source: &Path, outcome: &Outcome) {
+    let (path, other, text) = match outcome {
+        Outcome::Output(output) => (source.with_extension("ans"), source.with_extension("err"), output.clone()),
+        Outcome::Error(error) => (source.with_extension("err"), source.with_extension("ans"), format!("{}\n", error.trim())),
+    };
+    fs::write(path, text).unwrap();
+    if other.exists() {
+        fs::remove_file(other).unwrap();
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let blessing = args.iter().any(|arg| arg == "--bless");
+    let filters: Vec<&String> = args.iter().filter(|arg| !arg.starts_with('-')).collect();
+
+    let mut all = Vec::new();
+    sources(&common::tests_dir(), &mut all);
+    let tests_dir = common::tests_dir();
+    let mut failures = Vec::new();
+    let mut checked = 0;
+    for source in all {
+        let name = source.strip_prefix(&tests_dir).unwrap().display().to_string();
+        if !filters.is_empty() && !filters.iter().any(|filter| name.contains(filter.as_str())) {
+            continue;
+        }
+        let Some(mut expected) = expected(&source) else { continue };
+        let outcomes = run(&source);
+        if blessing && !outcomes[0].1.matches(&expected) {
+            bless(&source, &outcomes[0].1);
+            println!("blessed {}", name);
+            expected = outcomes[0].1.clone();
+        }
+        checked += 1;
+        let mismatches: Vec<String> = outcomes.iter()
+            .filter(|(_, outcome)| !outcome.matches(&expected))
+            .map(|(configuration, outcome)| format!("{}, {}: {}", name, configuration, describe(&expected, outcome)))
+            .collect();
+        println!("golden {} ... {}", name, if mismatches.is_empty() { "ok" } else { "FAILED" });
+        failures.extend(mismatches);
+    }
+
+    for failure in &failures {
+        println!("\n{}", failure);
+    }
+    println!("\n{} programs checked, {} mismatches", checked, failures.len());
+    if checked == 0 && filters.is_empty() {
+        println!("No golden tests found");
+        return ExitCode::FAILURE;
+    }
+    if failures.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
@@ -0,0 +1,68 @@
+//! Runs the Klang test programs with the AST interpreter, the stack machine interpreter and the emulator,
+//! and checks that all of them print the same values.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use klang_lib::lexer::tokens::Span;
+use klang_lib::parser::ast::{Expr, Ident, Ops, PrefixOps, Spanned, Stmt};
+use klang_lib::parser::interpreter::AstInterpreter;
+use klang_lib::stack_machine::interpreter::SMInterpreter;
+use klang_lib::stack_machine::transform::AstTransformer;
+
+fn check_program(source: &Path) {
+    let program = common::parse(&fs::read(source).unwrap()).unwrap();
+
+    let mut ast_interpreter = AstInterpreter::new();
+    ast_interpreter.run(&program).unwrap();
+
+    let stack_machine = AstTransformer::new().transform_ast_to_sm(program.clone());
+    let mut sm_interpreter = SMInterpreter::new();
+    sm_interpreter.run(&stack_machine).unwrap();
+
+    let (memory, code) = common::compile(program.clone());
+    let emulator_output = common::run_emulator(&memory, &code, Duration::from_secs(10)).unwrap();
+
+    let (memory, code) = common::compile_with(program, 1, common::Backend::Stack);
+    let optimized_output = common::run_emulator(&memory, &code, Duration::from_secs(10)).unwrap();
+
+    let expected = common::parse_output(&fs::read_to_string(source.with_extension("ans")).unwrap()).unwrap();
+    assert_eq!(expected, ast_interpreter.output(), "AST interpreter output of {}", source.display());
+    assert_eq!(expected, sm_interpreter.output(), "SM interpreter output of {}", source.display());
+    assert_eq!(expected, emulator_output, "Emulator output of {}", source.display());
+    assert_eq!(expected, optimized_output, "Emulator output of {} with -O1", source.display());
+}
+
+#[test]
+fn interpreters_agree_with_emulator() {
+    let mut checked = 0;
+    for dir in ["simple", "complex"] {
+        for path in common::files_with_extension(&common::tests_dir().join(dir), "klang") {
+            check_program(&path);
+            checked += 1;
+        }
+    }
+    assert!(checked > 0);
+}
+
+#[test]
+fn unary_minus() {
+    // The parser has no unary minus, so the program is built from the AST, as the fuzzer does
+    let span = Span { line: 1, column: 1 };
+    let negate = |expr: Expr| Expr::PrefixOperation(PrefixOps::UnaryMinus, Box::new(expr));
+    let a = Ident(String::from("a"));
+    let program = vec![
+        Spanned::new(Stmt::VarAssign(a.clone(), Expr::IntLiteral(5)), span),
+        Spanned::new(Stmt::Print(negate(Expr::Var(a))), span),
+        Spanned::new(Stmt::Print(Expr::InfixOperation(Box::new(negate(Expr::IntLiteral(2))), Ops::Sub, Box::new(Expr::IntLiteral(1)))), span),
+    ];
+    let stack_machine = AstTransformer::new().transform_ast_to_sm(program.clone());
+    let mut sm_interpreter = SMInterpreter::new();
+    sm_interpreter.run(&stack_machine).unwrap();
+    assert_eq!(vec![-5, -3], sm_interpreter.output());
+
+    let (memory, code) = common::compile(program);
+    assert_eq!(Ok(vec![-5, -3]), common::run_emulator(&memory, &code, Duration::from_secs(10)));
+}
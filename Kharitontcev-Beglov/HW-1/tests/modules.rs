@@ -0,0 +1,68 @@
+//! Separate compilation: the modules in `tests/modules` are compiled into object files one by one,
+//! linked with `klang-ld` and run in the emulator. `report.klang` imports the other modules, so it must print `report.ans`.
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn module_path(name: &str) -> PathBuf {
+    common::tests_dir().join("modules").join(name)
+}
+
+fn compile_object(source: &Path, dir: &Path, opt_level: u8) -> PathBuf {
+    let object = dir.join(source.with_extension("o").file_name().unwrap());
+    let output = Command::new(env!("CARGO_BIN_EXE_compiler"))
+        .arg("-i").arg(source)
+        .arg(format!("-O{}", opt_level))
+        .arg("--emit").arg("object")
+        .arg("-o").arg(&object)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "Compilation of {} failed: {}", source.display(), String::from_utf8_lossy(&output.stderr));
+    object
+}
+
+fn link(objects: &[PathBuf], dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_klang-ld"))
+        .args(objects)
+        .arg("-c").arg(dir.join("program.code"))
+        .arg("-m").arg(dir.join("program.mem"))
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn separate_compilation() {
+    let expected = common::parse_output(&fs::read_to_string(module_path("report.ans")).unwrap()).unwrap();
+    for opt_level in [0, 1] {
+        let dir = common::fresh_dir("modules");
+        // The imported modules run first whatever the order of the objects is
+        let objects: Vec<PathBuf> = ["report.klang", "main.klang", "util.klang"].iter()
+            .map(|name| compile_object(&module_path(name), &dir, opt_level))
+            .collect();
+        let output = link(&objects, &dir);
+        assert!(output.status.success(), "Linking failed: {}", String::from_utf8_lossy(&output.stderr));
+        let memory = fs::read(dir.join("program.mem")).unwrap();
+        let code = fs::read(dir.join("program.code")).unwrap();
+        assert_eq!(Ok(expected.clone()), common::run_emulator(&memory, &code, TIMEOUT), "Output with -O{}", opt_level);
+    }
+}
+
+#[test]
+fn link_errors() {
+    let dir = common::fresh_dir("modules");
+    let [util, main, duplicate] = ["util.klang", "main.klang", "duplicate.klang"].map(|name| compile_object(&module_path(name), &dir, 0));
+
+    let output = link(std::slice::from_ref(&main), &dir);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Module util.klang imported by main.klang is not linked"));
+
+    let output = link(&[util, main, duplicate], &dir);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Variable total is exported by both"));
+}
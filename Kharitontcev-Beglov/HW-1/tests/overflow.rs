@@ -0,0 +1,59 @@
+//! Signed overflow of the compiled arithmetic: it wraps around by default, as in the interpreters, and stops the
+//! emulation with `Arithmetic::Checked`, also when the optimizations see the constant operands or the unused result.
+
+mod common;
+
+use klang_lib::binary::instructions::Arithmetic;
+use klang_lib::emulator::{Emulator, ExitStatus};
+use klang_lib::emulator::io::BufferInput;
+
+use common::BACKENDS;
+
+const ADDITION: &str = "{ x = 2147483640; n = 10; while (n) { x = x + 1; n = n - 1 }; print(x) }";
+const NEGATION: &str = "{ x = 0 - 2147483647 - 1; n = 1; while (n) { x = 0 - x; n = n - 1 }; print(x) }";
+const SUBTRACTION: &str = "{ x = 0 - 2147483640; n = 10; while (n) { x = x - 1; n = n - 1 }; print(x) }";
+const CONSTANT_ADDITION: &str = "{ print(2147483647 + 1) }";
+const CONSTANT_SUBTRACTION: &str = "{ x = 0 - 2147483647; print(x - 2) }";
+const UNUSED_ADDITION: &str = "{ a = 2147483647; b = a + 1; print(7) }";
+
+/// The printed text, or the status of the stopped emulation
+fn run(source: &str, opt_level: u8, backend: common::Backend, arithmetic: Arithmetic) -> Result<String, ExitStatus> {
+    let program = common::parse(source.as_bytes()).unwrap();
+    let (memory, code) = common::try_compile_with_arithmetic(program, opt_level, backend, arithmetic).unwrap();
+    let completion = Emulator::run_to_completion(code, memory, Box::new(BufferInput::default()), None);
+    match completion.status {
+        ExitStatus::Exited(0) => Ok(completion.output),
+        status => Err(status),
+    }
+}
+
+#[test]
+fn wrapping_arithmetic() {
+    for backend in BACKENDS {
+        for opt_level in [0, 1] {
+            let configuration = format!("{:?} -O{}", backend, opt_level);
+            assert_eq!(Ok(String::from("-2147483646\n")), run(ADDITION, opt_level, backend, Arithmetic::Wrapping), "{}", configuration);
+            assert_eq!(Ok(String::from("-2147483648\n")), run(NEGATION, opt_level, backend, Arithmetic::Wrapping), "{}", configuration);
+            assert_eq!(Ok(String::from("2147483646\n")), run(SUBTRACTION, opt_level, backend, Arithmetic::Wrapping), "{}", configuration);
+            assert_eq!(Ok(String::from("-2147483648\n")), run(CONSTANT_ADDITION, opt_level, backend, Arithmetic::Wrapping), "{}", configuration);
+            assert_eq!(Ok(String::from("2147483647\n")), run(CONSTANT_SUBTRACTION, opt_level, backend, Arithmetic::Wrapping), "{}", configuration);
+            assert_eq!(Ok(String::from("7\n")), run(UNUSED_ADDITION, opt_level, backend, Arithmetic::Wrapping), "{}", configuration);
+        }
+    }
+}
+
+#[test]
+fn checked_arithmetic() {
+    for backend in BACKENDS {
+        for opt_level in [0, 1] {
+            for source in [ADDITION, NEGATION, SUBTRACTION, CONSTANT_ADDITION, CONSTANT_SUBTRACTION, UNUSED_ADDITION] {
+                let result = run(source, opt_level, backend, Arithmetic::Checked);
+                let configuration = format!("{:?} -O{}: {}", backend, opt_level, source);
+                assert!(matches!(result, Err(ExitStatus::Overflow { .. })), "{}: {:?}", configuration, result);
+            }
+            // Without the overflow the result is the same
+            let source = "{ x = 5; y = 0 - 7; print(x + y + x) }";
+            assert_eq!(Ok(String::from("3\n")), run(source, opt_level, backend, Arithmetic::Checked));
+        }
+    }
+}
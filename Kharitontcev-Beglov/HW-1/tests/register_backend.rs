@@ -0,0 +1,30 @@
+//! Checks the register backend on the test programs: the output is the same as with the stack backend,
+//! while fewer cycles are executed. Spilling is checked with only two registers for the expressions.
+
+mod common;
+
+use std::time::Duration;
+use klang_lib::binary::register::RegisterTransformer;
+use common::Backend;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[test]
+fn register_backend_is_faster() {
+    let results = common::compare_backends(0, Backend::Stack, Backend::Register, TIMEOUT);
+    for (path, stack_stats, register_stats) in &results {
+        assert!(
+            register_stats.cycles < stack_stats.cycles,
+            "{}: {:?} with registers, {:?} with the stack", path.display(), register_stats, stack_stats
+        );
+    }
+    assert!(!results.is_empty());
+}
+
+#[test]
+fn spilling() {
+    for (path, program, expected) in common::test_programs(&["complex"]) {
+        let (memory, code) = RegisterTransformer::with_register_limit(2).transform_program(&program).unwrap();
+        assert_eq!(Ok(expected), common::run_emulator(&memory, &code, TIMEOUT), "Output of {}", path.display());
+    }
+}
@@ -0,0 +1,74 @@
+//! Runtime routines in the emulator: every routine is called with the edge cases of the arguments by all the
+//! backends, and the printed results should match `Routine::apply`.
+
+mod common;
+
+use std::time::Duration;
+use klang_lib::parser::ast::Routine;
+
+use common::BACKENDS;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+const ARGUMENTS: [i32; 12] = [0, 1, -1, 2, 7, -7, 13, 100, -123456, 65536, i32::MAX, i32::MIN];
+
+/// Klang expression of the number, there are no negative literals
+fn literal(x: i32) -> String {
+    match x {
+        i32::MIN => String::from("((0 - 2147483647) - 1)"),
+        x if x < 0 => format!("(0 - {})", -x),
+        x => x.to_string(),
+    }
+}
+
+/// Calls the routine on the values of the variables, so the calls are not folded at `-O1`
+fn calls(routine: Routine) -> (String, Vec<i32>) {
+    let pairs: Vec<Vec<i32>> = match routine.arity() {
+        1 => ARGUMENTS.iter().map(|x| vec![*x]).collect(),
+        _ => ARGUMENTS.iter().flat_map(|lhs| ARGUMENTS.iter().map(|rhs| vec![*lhs, *rhs])).collect(),
+    };
+    let mut source = String::new();
+    for args in &pairs {
+        let names = ["a", "b"];
+        for (name, x) in names.iter().zip(args) {
+            source.push_str(&format!("{} = {}\n", name, literal(*x)));
+        }
+        source.push_str(&format!("print({}({}))\n", routine.name(), names[..args.len()].join(", ")));
+    }
+    (source, pairs.iter().map(|args| routine.apply(args)).collect())
+}
+
+#[test]
+fn routines() {
+    for routine in Routine::ALL {
+        let (body, expected) = calls(routine);
+        let program = common::parse(format!("{{\n{}}}", body).as_bytes()).unwrap();
+        for backend in BACKENDS {
+            let (memory, code) = common::compile_with(program.clone(), 0, backend);
+            assert_eq!(Ok(expected.clone()), common::run_emulator(&memory, &code, TIMEOUT), "{} with {:?}", routine.name(), backend);
+        }
+    }
+}
+
+#[test]
+fn nested_calls_keep_intermediate_values() {
+    // The factorial and the digits of it, the calls are inside expressions with other pending operands
+    let source = "{
+        n = 1; f = 1
+        while (n - 13) { f = mul(f, n) + 0; n = n + 1 }
+        print(f)
+        s = 0
+        while (f) { s = s + (mod(f, 10) + (abs(0 - 1) - max(min(f, 1), 1))); f = div(f, 10) }
+        print(s)
+        print(n + mul(n + mul(2, n), abs(n - mul(n, 3))) - min(max(n, div(100, n)), 5))
+    }";
+    let program = common::parse(source.as_bytes()).unwrap();
+    let n = 13;
+    let expected = vec![479001600, 27, n + (n + 2 * n) * (3 * n - n) - 5];
+    for backend in BACKENDS {
+        for opt_level in [0, 1] {
+            let (memory, code) = common::compile_with(program.clone(), opt_level, backend);
+            assert_eq!(Ok(expected.clone()), common::run_emulator(&memory, &code, TIMEOUT), "{:?} at -O{}", backend, opt_level);
+        }
+    }
+}
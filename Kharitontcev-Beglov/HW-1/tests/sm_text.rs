@@ -0,0 +1,49 @@
+//! Golden tests of the textual stack machine code: `X.klang` must be compiled into exactly `X.sm`,
+//! hand-written `X.sm` without the Klang source must print `X.ans` when compiled and run.
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+use std::time::Duration;
+use klang_lib::binary::SMTransformer;
+use klang_lib::stack_machine::interpreter::SMInterpreter;
+use klang_lib::stack_machine::text::parse_program;
+
+#[test]
+fn emitted_sm_matches_golden() {
+    let sm_dir = common::tests_dir().join("sm");
+    let sources = common::files_with_extension(&sm_dir, "klang");
+    assert!(!sources.is_empty());
+    for source in sources {
+        let output = Command::new(env!("CARGO_BIN_EXE_compiler"))
+            .arg("-i").arg(&source)
+            .arg("--emit").arg("sm")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "Compilation of {} failed", source.display());
+        let expected = fs::read_to_string(source.with_extension("sm")).unwrap();
+        assert_eq!(expected, String::from_utf8(output.stdout).unwrap(), "SM code of {}", source.display());
+    }
+}
+
+#[test]
+fn handwritten_sm_runs() {
+    let sm_dir = common::tests_dir().join("sm");
+    let programs: Vec<_> = common::files_with_extension(&sm_dir, "sm").into_iter()
+        .filter(|path| !path.with_extension("klang").exists())
+        .collect();
+    assert!(!programs.is_empty());
+    for path in programs {
+        let program = parse_program(&fs::read_to_string(&path).unwrap()).unwrap();
+        let expected = common::parse_output(&fs::read_to_string(path.with_extension("ans")).unwrap()).unwrap();
+
+        let mut interpreter = SMInterpreter::new();
+        interpreter.run(&program).unwrap();
+        assert_eq!(expected, interpreter.output(), "SM interpreter output of {}", path.display());
+
+        let (memory, code) = SMTransformer::new().transform_program(&program).unwrap();
+        let output = common::run_emulator(&memory, &code, Duration::from_secs(10)).unwrap();
+        assert_eq!(expected, output, "Emulator output of {}", path.display());
+    }
+}
@@ -0,0 +1,39 @@
+//! Checks the SSA backend on the test programs: with -O1 the output is the same as with the register backend,
+//! while no more cycles are executed. Spilling is checked with a single register for the values.
+
+mod common;
+
+use std::time::Duration;
+use klang_lib::binary::instructions::Arithmetic;
+use klang_lib::binary::ssa::SsaTransformer;
+use klang_lib::ssa::lower::lower_program;
+use klang_lib::ssa::optimize::optimize;
+use common::Backend;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[test]
+fn ssa_backend_is_faster() {
+    let results = common::compare_backends(1, Backend::Register, Backend::Ssa, TIMEOUT);
+    for (path, register_stats, ssa_stats) in &results {
+        assert!(
+            ssa_stats.cycles <= register_stats.cycles,
+            "{}: {:?} with SSA, {:?} with the register backend", path.display(), ssa_stats, register_stats
+        );
+    }
+    assert!(!results.is_empty());
+}
+
+#[test]
+fn spilling() {
+    for (path, program, expected) in common::test_programs(&["complex"]) {
+        let mut function = lower_program(&program);
+        for optimized in [false, true] {
+            if optimized {
+                optimize(&mut function, Arithmetic::Wrapping);
+            }
+            let (memory, code) = SsaTransformer::with_register_limit(1).transform_program(&function).unwrap();
+            assert_eq!(Ok(expected.clone()), common::run_emulator(&memory, &code, TIMEOUT), "Output of {}", path.display());
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Characters and strings in the emulator: the text printed by every backend should be the same as the text
+//! printed by the AST interpreter, including the strings with escapes and the characters outside of ASCII.
+//! The programs run in the emulator in-process, the binary is checked with an object file and an output file.
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+use std::time::Duration;
+use klang_lib::binary::SMTransformer;
+use klang_lib::emulator::{Emulator, ExitStatus};
+use klang_lib::emulator::io::BufferInput;
+use klang_lib::parser::interpreter::AstInterpreter;
+use klang_lib::stack_machine::transform::AstTransformer;
+
+use common::BACKENDS;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const CYCLE_LIMIT: u64 = 10_000_000;
+
+const SOURCE: &str = r#"{
+    prints "Squares:\n"
+    i = 1
+    while (i - 4) {
+        prints "\t"
+        print(mul(i, i))
+        i = i + 1
+    }
+    prints "abc"
+    prints "abcd"
+    prints ""
+    printc('\n')
+    c = 'a'
+    while ('e' - c) { printc(c); printc(c - 32); c = c + 1 }
+    printc(10)
+    printc(955); printc('"'); printc(0 - 1); printc('\\')
+    prints "\"quoted\" é\n"
+    prints "Squares:\n"
+    prints "end\0hidden"
+}"#;
+
+fn expected() -> String {
+    let program = common::parse(SOURCE.as_bytes()).unwrap();
+    let mut interpreter = AstInterpreter::new();
+    interpreter.run(&program).unwrap();
+    interpreter.text().to_string()
+}
+
+#[test]
+fn printed_text() {
+    let expected = expected();
+    assert!(expected.starts_with("Squares:\n\t1\n\t4\n\t9\nabcabcd\naAbBcCdD\nλ\"\u{FFFD}\\"));
+    assert!(expected.ends_with("Squares:\nend"));
+    let program = common::parse(SOURCE.as_bytes()).unwrap();
+    for backend in BACKENDS {
+        for opt_level in [0, 1] {
+            let (memory, code) = common::compile_with(program.clone(), opt_level, backend);
+            let completion = Emulator::run_to_completion(code, memory, Box::new(BufferInput::default()), Some(CYCLE_LIMIT));
+            assert_eq!(expected, completion.output, "{:?} with -O{}", backend, opt_level);
+        }
+    }
+}
+
+#[test]
+fn printed_text_from_object() {
+    // The addresses of the strings are relocated when the object is linked
+    let dir = common::fresh_dir("text_output");
+    let source = dir.join("text.klang");
+    fs::write(&source, SOURCE).unwrap();
+    let object = dir.join("text.o");
+    let output = Command::new(env!("CARGO_BIN_EXE_compiler"))
+        .arg("-i").arg(&source)
+        .arg("--emit").arg("object")
+        .arg("-o").arg(&object)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "Compilation failed: {}", String::from_utf8_lossy(&output.stderr));
+    let output = Command::new(env!("CARGO_BIN_EXE_klang-ld"))
+        .arg(&object)
+        .arg("-c").arg(dir.join("program.code"))
+        .arg("-m").arg(dir.join("program.mem"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "Linking failed: {}", String::from_utf8_lossy(&output.stderr));
+    let memory = fs::read(dir.join("program.mem")).unwrap();
+    let code = fs::read(dir.join("program.code")).unwrap();
+    assert_eq!(Ok(expected()), common::run_emulator_text(&memory, &code, TIMEOUT));
+}
+
+#[test]
+fn printed_text_into_file() {
+    let dir = common::fresh_dir("text_output");
+    let program = common::parse(SOURCE.as_bytes()).unwrap();
+    let (memory, code) = common::compile(program);
+    fs::write(dir.join("program.code"), code).unwrap();
+    fs::write(dir.join("program.mem"), memory).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_mips_emulator"))
+        .arg("-c").arg(dir.join("program.code"))
+        .arg("-m").arg(dir.join("program.mem"))
+        .arg("-o").arg(dir.join("program.out"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "Emulation failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stdout.is_empty());
+    assert_eq!(expected(), fs::read_to_string(dir.join("program.out")).unwrap());
+}
+
+#[test]
+fn printed_text_with_syscalls() {
+    let expected = expected();
+    let program = common::parse(SOURCE.as_bytes()).unwrap();
+    let stack_machine = AstTransformer::new().transform_ast_to_sm(program);
+    for transformer in [SMTransformer::new(), SMTransformer::with_variable_registers()] {
+        let (memory, code) = transformer.with_syscalls().transform_program(&stack_machine).unwrap();
+        // Only `syscall` prints, there are no print pseudo-instructions
+        let words: Vec<u32> = code.chunks(4).map(|word| u32::from_be_bytes(word.try_into().unwrap())).collect();
+        assert!(!words.iter().any(|word| (word >> 26 == 0 && word & 0x3f <= 1) || word >> 26 == 63));
+        let completion = Emulator::run_to_completion(code, memory, Box::new(BufferInput::default()), Some(CYCLE_LIMIT));
+        assert_eq!(expected, completion.output);
+        assert_eq!(ExitStatus::Exited(0), completion.status);
+    }
+}